@@ -0,0 +1,154 @@
+use std::collections::HashSet;
+
+use crate::ast::{Ast, ast_common_prefix, ast_common_suffix,
+    ast_max_expansion_length, ast_min_expansion_length, ast_num_expansions};
+use crate::checksum::Checksum;
+use crate::error::BraceExpandError;
+use crate::parse_pattern;
+use crate::state_machines::{AstStateMachine, StateMachine};
+
+
+/// If the smaller pattern has at most this many expansions (and the
+/// larger one at most OVERLAP_MAX_SCAN), patterns_overlap() compares
+/// the actual expansion sets instead of just guessing.
+const OVERLAP_MAX_SET_SIZE: usize = 1_000_000;
+const OVERLAP_MAX_SCAN: usize = 10_000_000;
+
+
+pub(crate) fn for_each_expansion(ast: &Ast, f: impl FnMut(&str) -> bool) {
+    for_each_expansion_with_checksum(ast, None, f);
+}
+
+
+fn for_each_expansion_with_checksum(ast: &Ast, checksum: Option<Checksum>, mut f: impl FnMut(&str) -> bool) {
+    let mut sm = AstStateMachine::new(ast).with_checksum(checksum);
+    let mut s = String::new();
+    loop {
+        s.clear();
+        sm.fill(&mut s);
+        if !f(&s) || !sm.advance() {
+            break;
+        }
+    }
+}
+
+
+/// Determines whether any string is produced by both patterns.
+///
+/// This first compares the patterns structurally (common prefixes and
+/// suffixes, and length ranges), which can prove that they're disjoint
+/// without expanding them. If that's inconclusive and they're small
+/// enough, their expansions are compared directly. Otherwise, this
+/// conservatively returns true, so:
+///
+/// - false means the patterns definitely don't overlap
+/// - true means they *might* overlap (and definitely do if both are
+///   reasonably small)
+pub fn patterns_overlap(a: &str, b: &str, escape: bool) -> Result<bool, BraceExpandError> {
+    let (ast_a, checksum_a) = parse_pattern(a, escape)?;
+    let (ast_b, checksum_b) = parse_pattern(b, escape)?;
+
+    let num_a = ast_num_expansions(&ast_a);
+    let num_b = ast_num_expansions(&ast_b);
+    if num_a == 0 || num_b == 0 {
+        return Ok(false);
+    }
+
+    let length_range = |ast: &Ast, checksum: Option<Checksum>| (
+        ast_min_expansion_length(ast) + checksum.map_or(0, |checksum| checksum.min_len()),
+        ast_max_expansion_length(ast) + checksum.map_or(0, |checksum| checksum.max_len()),
+    );
+    let (min_a, max_a) = length_range(&ast_a, checksum_a);
+    let (min_b, max_b) = length_range(&ast_b, checksum_b);
+    if max_a < min_b || max_b < min_a {
+        return Ok(false);
+    }
+
+    let (prefix_a, prefix_b) = (ast_common_prefix(&ast_a), ast_common_prefix(&ast_b));
+    if !prefix_a.starts_with(&prefix_b) && !prefix_b.starts_with(&prefix_a) {
+        return Ok(false);
+    }
+
+    // (Checksums go after everything else, so the common suffixes only
+    // mean anything without them)
+    if checksum_a.is_none() && checksum_b.is_none() {
+        let (suffix_a, suffix_b) = (ast_common_suffix(&ast_a), ast_common_suffix(&ast_b));
+        if !suffix_a.ends_with(&suffix_b) && !suffix_b.ends_with(&suffix_a) {
+            return Ok(false);
+        }
+    }
+
+    let ((small, small_checksum, small_num), (large, large_checksum, large_num)) = if num_a <= num_b {
+        ((&ast_a, checksum_a, num_a), (&ast_b, checksum_b, num_b))
+    } else {
+        ((&ast_b, checksum_b, num_b), (&ast_a, checksum_a, num_a))
+    };
+
+    if small_num > OVERLAP_MAX_SET_SIZE || large_num > OVERLAP_MAX_SCAN {
+        return Ok(true);
+    }
+
+    let mut seen = HashSet::with_capacity(small_num);
+    for_each_expansion_with_checksum(small, small_checksum, |s| {
+        seen.insert(s.to_owned());
+        true
+    });
+
+    let mut found = false;
+    for_each_expansion_with_checksum(large, large_checksum, |s| {
+        found = seen.contains(s);
+        !found
+    });
+    Ok(found)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disjoint_prefixes() {
+        assert_eq!(patterns_overlap("a{b,c}", "x{b,c}", true), Ok(false));
+    }
+
+    #[test]
+    fn test_disjoint_suffixes() {
+        assert_eq!(patterns_overlap("{a,b}Fv", "{a,b}Fi", true), Ok(false));
+    }
+
+    #[test]
+    fn test_disjoint_lengths() {
+        assert_eq!(patterns_overlap("a{b,c}", "a{bb,cc}", true), Ok(false));
+    }
+
+    #[test]
+    fn test_disjoint_middles() {
+        assert_eq!(patterns_overlap("a{b,c}d", "a{e,f}d", true), Ok(false));
+    }
+
+    #[test]
+    fn test_overlapping() {
+        assert_eq!(patterns_overlap("a{b,c}", "{a,x}c", true), Ok(true));
+        assert_eq!(patterns_overlap("{a,b}c{e,f{g,h}}", "bcf{h,i}", true), Ok(true));
+        assert_eq!(patterns_overlap("abc", "abc", true), Ok(true));
+    }
+
+    #[test]
+    fn test_checksums() {
+        // "ab" + (97 + 98) % 10
+        assert_eq!(patterns_overlap("ab#", "ab5", true), Ok(true));
+        assert_eq!(patterns_overlap("ab#", "ab4", true), Ok(false));
+        assert_eq!(patterns_overlap("{a,b}x#", "{a,b}x#d", true), Ok(false));
+        assert_eq!(patterns_overlap("{a,b}x#", "bx{1..9}", true), Ok(true));
+        assert_eq!(patterns_overlap("{a,b}5#d", "b55", true), Ok(true));
+        // (The checksum makes these longer)
+        assert_eq!(patterns_overlap("abc#%1000", "abc5", true), Ok(false));
+        assert_eq!(patterns_overlap("abc#%1000", "abc294", true), Ok(true));
+    }
+
+    #[test]
+    fn test_parse_error() {
+        assert!(patterns_overlap("a}b", "abc", true).is_err());
+    }
+}
@@ -1,41 +1,64 @@
-use crate::tokenizer::Token;
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::error::BraceExpandError;
+use crate::lazy::LazyChoices;
+use crate::range::BraceRange;
+use crate::tokenizer::{escape_for_pattern, tokenize_with_offsets, Token};
 
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum AstItem {
     Leaf(String),
     Choices(Vec<Ast>),
+    /// A range like "{1..5}" or "{a..e}", which is iterated over directly
+    /// instead of being expanded into Choices
+    Range(BraceRange),
+    /// Alternatives that are read from a user-supplied source as they're
+    /// needed (only created programmatically, never by parsing)
+    Lazy(LazyChoices),
 }
 
-// The items in an Ast should always alternate between Leafs and Choices
-// (since consecutive Terms in a Pattern can and should be combined)
+// The items in an Ast should always alternate between Leafs and
+// Choices/Ranges (since consecutive Terms in a Pattern can and should be
+// combined)
 pub type Ast = Vec<AstItem>;
 
 
 /// Creates an AstItem::Choices from the start of the provided token
-/// slice, which should begin immediately after the OpenBrace. Stops
-/// when it reaches a CloseBrace.
+/// slice, which should begin immediately after the OpenBrace (and
+/// `offsets` should line up with it). Stops when it reaches a
+/// CloseBrace. If the tokens run out first, the brace is unbalanced,
+/// and `open_brace_offset` is reported as the offending brace.
+///
+/// If the braces contain just a range (like "{1..5}" or "{a..e}"), an
+/// AstItem::Range is created instead.
 ///
 /// Returns the AST item and the number of tokens that were consumed.
-fn choices_from_tokens_partial(tokens: &[Token]) -> (AstItem, usize) {
+fn choices_from_tokens_partial(tokens: &[Token], offsets: &[usize], open_brace_offset: usize) -> Result<(AstItem, usize), BraceExpandError> {
     let mut v = Vec::new();
 
     let mut i = 0;
-    while i < tokens.len() {
-        let (ast, ast_size) = ast_from_tokens_partial(&tokens[i..]);
+    loop {
+        let (ast, ast_size) = ast_from_tokens_partial(&tokens[i..], &offsets[i..])?;
         v.push(ast);
         i += ast_size;
 
-        if let Some(Token::CloseBrace) = tokens.get(i) {
-            break;
+        match tokens.get(i) {
+            Some(Token::CloseBrace) => break,
+            Some(Token::Comma) => i += 1,
+            _ => return Err(BraceExpandError::UnbalancedBrace(open_brace_offset)),
         }
+    }
 
-        if let Some(Token::Comma) = tokens.get(i) {
-            i += 1;
+    if let [alternative] = v.as_slice() {
+        if let [AstItem::Leaf(s)] = alternative.as_slice() {
+            if let Some(range) = BraceRange::parse(s) {
+                return Ok((AstItem::Range(range?), i));
+            }
         }
     }
 
-    (AstItem::Choices(v), i)
+    Ok((AstItem::Choices(v), i))
 }
 
 
@@ -43,14 +66,14 @@ fn choices_from_tokens_partial(tokens: &[Token]) -> (AstItem, usize) {
 /// Stops when it reaches a CloseBrace or Comma.
 ///
 /// Returns the AST and the number of tokens that were consumed.
-fn ast_from_tokens_partial(tokens: &[Token]) -> (Ast, usize) {
+fn ast_from_tokens_partial(tokens: &[Token], offsets: &[usize]) -> Result<(Ast, usize), BraceExpandError> {
     let mut pat = Ast::new();
 
     let mut i = 0;
     while i < tokens.len() {
         match &tokens[i] {
             Token::OpenBrace => {
-                let (new_item, new_item_size) = choices_from_tokens_partial(&tokens[i+1..]);
+                let (new_item, new_item_size) = choices_from_tokens_partial(&tokens[i+1..], &offsets[i+1..], offsets[i])?;
                 pat.push(new_item);
                 i += 1 + new_item_size;
             },
@@ -61,18 +84,42 @@ fn ast_from_tokens_partial(tokens: &[Token]) -> (Ast, usize) {
         i += 1;
     }
 
-    (pat, i)
+    Ok((pat, i))
 }
 
 
 /// Converts a slice of Tokens to an AST.
-pub fn ast_from_tokens(tokens: &[Token]) -> Result<Ast, String> {
-    let (ast, amt_consumed) = ast_from_tokens_partial(tokens);
+///
+/// Byte offsets in errors assume that the tokens were written without
+/// any backslash escapes. Use ast_from_pattern() to get exact offsets.
+pub(crate) fn ast_from_tokens(tokens: &[Token]) -> Result<Ast, BraceExpandError> {
+    let mut offsets = Vec::with_capacity(tokens.len());
+    let mut offset = 0;
+    for token in tokens {
+        offsets.push(offset);
+        offset += match token {
+            Token::Term(s) => s.len(),
+            _ => 1,
+        };
+    }
+    ast_from_tokens_with_offsets(tokens, &offsets)
+}
 
-    if amt_consumed < tokens.len() {
-        Err(format!("unexpected {:?} at position {}", tokens[amt_consumed], amt_consumed))
-    } else {
-        Ok(ast)
+
+/// Tokenizes a pattern and converts it to an AST.
+pub fn ast_from_pattern(pattern: &str, escape: bool) -> Result<Ast, BraceExpandError> {
+    let (tokens, offsets) = tokenize_with_offsets(pattern, escape);
+    ast_from_tokens_with_offsets(&tokens, &offsets)
+}
+
+
+pub(crate) fn ast_from_tokens_with_offsets(tokens: &[Token], offsets: &[usize]) -> Result<Ast, BraceExpandError> {
+    let (ast, amt_consumed) = ast_from_tokens_partial(tokens, offsets)?;
+
+    match tokens.get(amt_consumed) {
+        None => Ok(ast),
+        Some(Token::CloseBrace) => Err(BraceExpandError::UnbalancedBrace(offsets[amt_consumed])),
+        Some(token) => Err(BraceExpandError::UnexpectedToken(token.clone(), amt_consumed)),
     }
 }
 
@@ -82,6 +129,9 @@ fn ast_item_max_expansion_length(item: &AstItem) -> usize {
         AstItem::Leaf(s) => s.len(),
         AstItem::Choices(v) =>
             v.iter().map(ast_max_expansion_length).max().unwrap_or(0),
+        AstItem::Range(range) => range.max_len(),
+        AstItem::Lazy(choices) =>
+            choices.with_all(|v| v.iter().map(String::len).max().unwrap_or(0)),
     }
 }
 
@@ -93,18 +143,395 @@ pub fn ast_max_expansion_length(ast: &Ast) -> usize {
 }
 
 
-fn ast_item_num_expansions(item: &AstItem) -> usize {
+fn ast_item_min_expansion_length(item: &AstItem) -> usize {
     match item {
-        AstItem::Leaf(_) => 1,
+        AstItem::Leaf(s) => s.len(),
         AstItem::Choices(v) =>
-            v.iter().map(ast_num_expansions).sum(),
+            v.iter().map(ast_min_expansion_length).min().unwrap_or(0),
+        AstItem::Range(range) => range.min_len(),
+        AstItem::Lazy(choices) =>
+            choices.with_all(|v| v.iter().map(String::len).min().unwrap_or(0)),
     }
 }
 
 
-/// Calculates the total number of expansions this AST will evaluate to.
+/// Calculates the length of the shortest string this AST will evaluate
+/// to.
+pub fn ast_min_expansion_length(ast: &Ast) -> usize {
+    ast.iter().map(ast_item_min_expansion_length).sum()
+}
+
+
+fn ast_item_num_expansions_checked(item: &AstItem) -> Option<usize> {
+    match item {
+        AstItem::Leaf(_) => Some(1),
+        AstItem::Choices(v) =>
+            v.iter().try_fold(0usize, |total, ast| total.checked_add(ast_num_expansions_checked(ast)?)),
+        AstItem::Range(range) => usize::try_from(range.len()).ok(),
+        AstItem::Lazy(choices) => Some(choices.num_choices()),
+    }
+}
+
+
+/// Whether this AST has no expansions at all, which can only happen with
+/// a LazyChoices source that has no alternatives. Unlike counting the
+/// expansions, this only reads the first alternative of each source.
+pub fn ast_is_empty(ast: &Ast) -> bool {
+    ast.iter().any(|item| match item {
+        AstItem::Leaf(_) | AstItem::Range(_) => false,
+        AstItem::Choices(v) => v.iter().all(ast_is_empty),
+        AstItem::Lazy(choices) => choices.with_choice(0, |_| ()).is_none(),
+    })
+}
+
+
+pub(crate) fn ast_item_num_expansions(item: &AstItem) -> usize {
+    ast_item_num_expansions_checked(item).unwrap_or(usize::MAX)
+}
+
+
+/// Calculates the total number of expansions this AST will evaluate to,
+/// or None if that doesn't fit in a usize.
+pub fn ast_num_expansions_checked(ast: &Ast) -> Option<usize> {
+    ast.iter().try_fold(1usize, |total, item| total.checked_mul(ast_item_num_expansions_checked(item)?))
+}
+
+
+/// Calculates the total number of expansions this AST will evaluate to
+/// (saturating at usize::MAX).
 pub fn ast_num_expansions(ast: &Ast) -> usize {
-    ast.iter().map(ast_item_num_expansions).product()
+    ast_num_expansions_checked(ast).unwrap_or(usize::MAX)
+}
+
+
+/// Finds which of a set of alternatives the n-th expansion of them all
+/// comes from, given how many expansions each one has. Returns the
+/// alternative's index and the index of the expansion within it, or
+/// None if there are only n or fewer expansions in total.
+pub(crate) fn locate_choice(mut n: usize, counts: impl IntoIterator<Item = usize>) -> Option<(usize, usize)> {
+    for (i, count) in counts.into_iter().enumerate() {
+        if n < count {
+            return Some((i, n));
+        }
+        n -= count;
+    }
+    None
+}
+
+
+/// Splits the index of an expansion of a sequence of items into the
+/// index of each item's part of it, given how many expansions each item
+/// has. The last item varies fastest, so n is treated as a mixed-radix
+/// number with it as the least significant digit. Returns None if any
+/// item has no expansions.
+pub(crate) fn mixed_radix_digits(mut n: usize, counts: &[usize]) -> Option<Vec<usize>> {
+    let mut digits = vec![0; counts.len()];
+    for (digit, count) in digits.iter_mut().zip(counts).rev() {
+        if *count == 0 {
+            return None;
+        }
+        *digit = n % count;
+        n /= count;
+    }
+    Some(digits)
+}
+
+
+fn ast_item_nth_expansion(item: &AstItem, n: usize, out: &mut String) {
+    match item {
+        AstItem::Leaf(s) => out.push_str(s),
+        AstItem::Choices(v) => {
+            if let Some((i, n)) = locate_choice(n, v.iter().map(ast_num_expansions)) {
+                ast_nth_expansion(&v[i], n, out);
+            }
+        },
+        AstItem::Range(range) => range.for_each_byte(range.value(n as u64), |c| out.push(c as char)),
+        AstItem::Lazy(choices) => {
+            choices.with_choice(n, |s| out.push_str(s));
+        },
+    }
+}
+
+
+/// Appends the n-th expansion of this AST (in iteration order, counting
+/// from 0) to `out`, without generating any of the ones before it.
+/// Returns false if there are only n or fewer expansions.
+pub fn ast_nth_expansion(ast: &Ast, n: usize, out: &mut String) -> bool {
+    let counts: Vec<usize> = ast.iter().map(ast_item_num_expansions).collect();
+    let total = counts.iter().try_fold(1usize, |acc, count| acc.checked_mul(*count));
+    if total.is_some_and(|total| n >= total) {
+        return false;
+    }
+    let Some(indices) = mixed_radix_digits(n, &counts) else {
+        return false;
+    };
+
+    for (item, index) in ast.iter().zip(indices) {
+        ast_item_nth_expansion(item, index, out);
+    }
+    true
+}
+
+
+fn ast_item_length_distribution(item: &AstItem) -> BTreeMap<usize, usize> {
+    match item {
+        AstItem::Leaf(s) => BTreeMap::from([(s.len(), 1)]),
+        AstItem::Choices(v) => {
+            let mut dist = BTreeMap::new();
+            for (length, count) in v.iter().flat_map(ast_length_distribution) {
+                let entry = dist.entry(length).or_insert(0usize);
+                *entry = entry.saturating_add(count);
+            }
+            dist
+        },
+        AstItem::Range(range) => range.length_distribution(),
+        AstItem::Lazy(choices) => {
+            let mut dist = BTreeMap::new();
+            choices.with_all(|v| for s in v {
+                *dist.entry(s.len()).or_insert(0usize) += 1;
+            });
+            dist
+        },
+    }
+}
+
+
+/// Calculates how many expansions of each length this AST will evaluate
+/// to, as a map {length: count}. (Counts saturate at usize::MAX.)
+pub fn ast_length_distribution(ast: &Ast) -> BTreeMap<usize, usize> {
+    let mut dist: BTreeMap<usize, usize> = BTreeMap::from([(0, 1)]);
+    for item in ast {
+        let item_dist = ast_item_length_distribution(item);
+        let mut new_dist = BTreeMap::new();
+        for (length_a, count_a) in &dist {
+            for (length_b, count_b) in &item_dist {
+                let entry = new_dist.entry(length_a + length_b).or_insert(0usize);
+                *entry = entry.saturating_add(count_a.saturating_mul(*count_b));
+            }
+        }
+        dist = new_dist;
+    }
+    dist
+}
+
+
+/// Collects the set of bytes that can appear in this AST's expansions.
+pub fn ast_alphabet(ast: &Ast) -> BTreeSet<u8> {
+    let mut alphabet = BTreeSet::new();
+    for item in ast {
+        match item {
+            AstItem::Leaf(s) => alphabet.extend(s.bytes()),
+            AstItem::Choices(v) => {
+                for child in v {
+                    alphabet.extend(ast_alphabet(child));
+                }
+            },
+            AstItem::Range(range) => alphabet.extend(range.alphabet()),
+            AstItem::Lazy(choices) => choices.with_all(|v| for s in v {
+                alphabet.extend(s.bytes());
+            }),
+        }
+    }
+    alphabet
+}
+
+
+/// Longest common prefix of two strings (respecting char boundaries).
+fn common_prefix<'a>(a: &'a str, b: &str) -> &'a str {
+    let len = a.char_indices()
+        .zip(b.chars())
+        .find(|((_, ca), cb)| ca != cb)
+        .map_or(a.len().min(b.len()), |((i, _), _)| i);
+    &a[..len]
+}
+
+
+/// Longest common suffix of two strings (respecting char boundaries).
+fn common_suffix<'a>(a: &'a str, b: &str) -> &'a str {
+    let len = a.chars().rev()
+        .zip(b.chars().rev())
+        .take_while(|(ca, cb)| ca == cb)
+        .map(|(ca, _)| ca.len_utf8())
+        .sum::<usize>();
+    &a[a.len()-len..]
+}
+
+
+/// Shared implementation of ast_common_prefix() and ast_common_suffix().
+/// Returns the affix, and whether it's "complete" (i.e. it's the only
+/// string the AST evaluates to).
+fn ast_common_affix(ast: &Ast, suffix: bool) -> (String, bool) {
+    let mut affix = String::new();
+
+    let items: Box<dyn Iterator<Item = &AstItem>> = if suffix {
+        Box::new(ast.iter().rev())
+    } else {
+        Box::new(ast.iter())
+    };
+
+    for item in items {
+        let (item_affix, item_complete) = match item {
+            AstItem::Leaf(s) => (s.clone(), true),
+            AstItem::Choices(v) => {
+                let mut children = v.iter().map(|child| ast_common_affix(child, suffix));
+                if let Some(first) = children.next() {
+                    children.fold(first, |(acc, acc_complete), (child, child_complete)| {
+                        let shared = if suffix {
+                            common_suffix(&acc, &child)
+                        } else {
+                            common_prefix(&acc, &child)
+                        };
+                        let complete = acc_complete && child_complete && shared.len() == acc.len() && shared.len() == child.len();
+                        (shared.to_owned(), complete)
+                    })
+                } else {
+                    // No choices means no expansions at all, so we can
+                    // stop here (anything we say is vacuously true)
+                    (String::new(), false)
+                }
+            },
+            AstItem::Range(range) => {
+                let affix = if suffix { range.common_suffix() } else { range.common_prefix() };
+                (affix, range.len() == 1)
+            },
+            AstItem::Lazy(choices) => choices.with_all(|v| {
+                let Some((first, rest)) = v.split_first() else {
+                    return (String::new(), false);
+                };
+                let shared = rest.iter().fold(first.as_str(), |acc, s| {
+                    if suffix { common_suffix(acc, s) } else { common_prefix(acc, s) }
+                });
+                (shared.to_owned(), rest.iter().all(|s| s == first))
+            }),
+        };
+
+        if suffix {
+            affix.insert_str(0, &item_affix);
+        } else {
+            affix.push_str(&item_affix);
+        }
+
+        if !item_complete {
+            return (affix, false);
+        }
+    }
+
+    (affix, true)
+}
+
+
+/// Calculates the longest string that every expansion of this AST will
+/// begin with.
+pub fn ast_common_prefix(ast: &Ast) -> String {
+    ast_common_affix(ast, false).0
+}
+
+
+/// Calculates the longest string that every expansion of this AST will
+/// end with.
+pub fn ast_common_suffix(ast: &Ast) -> String {
+    ast_common_affix(ast, true).0
+}
+
+
+/// Appends pattern syntax for an AST item to `out`. See ast_to_pattern().
+fn ast_item_to_pattern(item: &AstItem, escape: bool, out: &mut String) {
+    match item {
+        AstItem::Leaf(s) if escape => out.push_str(&escape_for_pattern(s)),
+        AstItem::Leaf(s) => out.push_str(s),
+        // A group whose only alternative looks like a range (e.g. "{1..3}")
+        // would be parsed back as a range, so write its text on its own
+        AstItem::Choices(v) if matches!(v.as_slice(), [alt]
+                if matches!(alt.as_slice(), [AstItem::Leaf(s)] if BraceRange::parse(s).is_some())) => {
+            out.push_str(&ast_to_pattern(&v[0], escape));
+        },
+        AstItem::Choices(v) => {
+            out.push('{');
+            for (i, child) in v.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push_str(&ast_to_pattern(child, escape));
+            }
+            out.push('}');
+        },
+        AstItem::Range(range) => out.push_str(&format!("{{{range}}}")),
+        AstItem::Lazy(choices) => choices.with_all(|v| {
+            let alternatives: Vec<Ast> = v.iter().map(|s| vec![AstItem::Leaf(s.clone())]).collect();
+            ast_item_to_pattern(&AstItem::Choices(alternatives), escape, out);
+        }),
+    }
+}
+
+
+/// Converts an AST back into a pattern that parses to the same thing (with
+/// the same `escape` setting). Lazy choices are read in full and written
+/// out as normal ones.
+pub fn ast_to_pattern(ast: &Ast, escape: bool) -> String {
+    let mut out = String::new();
+    for item in ast {
+        ast_item_to_pattern(item, escape, &mut out);
+    }
+    out
+}
+
+
+/// Escapes a string for use in a quoted Graphviz DOT label.
+fn escape_dot_label(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+
+/// Adds nodes for an AST's items to a DOT graph, with edges to them from
+/// `parent`. `edge_label` is put on each of those edges, if given.
+/// Returns the next unused node ID.
+fn ast_to_dot_partial(ast: &Ast, parent: usize, edge_label: Option<usize>, mut next_id: usize, out: &mut String) -> usize {
+    let edge_attrs = edge_label.map_or(String::new(), |label| format!(" [label=\"{label}\"]"));
+
+    if ast.is_empty() {
+        // Empty alternative (like the second one in "{a,}")
+        out.push_str(&format!("    n{next_id} [label=\"\", shape=point];\n"));
+        out.push_str(&format!("    n{parent} -> n{next_id}{edge_attrs};\n"));
+        return next_id + 1;
+    }
+
+    for item in ast {
+        let id = next_id;
+        next_id += 1;
+        match item {
+            AstItem::Leaf(s) => {
+                out.push_str(&format!("    n{id} [label=\"{}\", shape=box];\n", escape_dot_label(s)));
+            },
+            AstItem::Choices(v) => {
+                out.push_str(&format!("    n{id} [label=\"{{}}\", shape=diamond];\n"));
+                for (i, child) in v.iter().enumerate() {
+                    next_id = ast_to_dot_partial(child, id, Some(i), next_id, out);
+                }
+            },
+            AstItem::Range(range) => {
+                out.push_str(&format!("    n{id} [label=\"{{{range}}}\", shape=hexagon];\n"));
+            },
+            AstItem::Lazy(_) => {
+                out.push_str(&format!("    n{id} [label=\"{{...}}\", shape=diamond, style=dashed];\n"));
+            },
+        }
+        out.push_str(&format!("    n{parent} -> n{id}{edge_attrs};\n"));
+    }
+
+    next_id
+}
+
+
+/// Renders an AST as a Graphviz DOT graph. Leafs are boxes, Choices
+/// are diamonds (dashed for Lazy ones, whose alternatives aren't shown)
+/// and Ranges are hexagons; the edges out of a Choices node are labeled
+/// with the index of the alternative they belong to, and the edges out
+/// of any node are in expansion order.
+pub fn ast_to_dot(ast: &Ast) -> String {
+    let mut out = String::from("digraph pattern {\n    n0 [label=\"pattern\", shape=ellipse];\n");
+    ast_to_dot_partial(ast, 0, None, 1, &mut out);
+    out.push_str("}\n");
+    out
 }
 
 
@@ -112,8 +539,13 @@ pub fn ast_num_expansions(ast: &Ast) -> usize {
 mod tests {
     use super::*;
 
+    use crate::range::RangeKind;
     use crate::tokenizer::tokenize;
 
+    fn dot_for(pattern: &str) -> String {
+        ast_to_dot(&ast_from_tokens(&tokenize(pattern, true)).unwrap())
+    }
+
     #[test]
     fn test_simple_expansion_in_middle_of_string() {
         let tokens = tokenize("a{b,c}d", true);
@@ -171,6 +603,23 @@ mod tests {
         ]));
     }
 
+    #[test]
+    fn test_unbalanced_braces() {
+        assert_eq!(ast_from_tokens(&tokenize("{a,b", true)), Err(BraceExpandError::UnbalancedBrace(0)));
+        assert_eq!(ast_from_tokens(&tokenize("{a,{b,c}", true)), Err(BraceExpandError::UnbalancedBrace(0)));
+        assert_eq!(ast_from_tokens(&tokenize("x{a,{b,c", true)), Err(BraceExpandError::UnbalancedBrace(4)));
+        assert_eq!(ast_from_tokens(&tokenize("a{", true)), Err(BraceExpandError::UnbalancedBrace(1)));
+        assert_eq!(ast_from_tokens(&tokenize("a}b", true)), Err(BraceExpandError::UnbalancedBrace(1)));
+        assert_eq!(ast_from_tokens(&tokenize("a,b", true)),
+            Err(BraceExpandError::UnexpectedToken(Token::Comma, 1)));
+
+        // Offsets count the backslashes in escapes
+        assert_eq!(ast_from_pattern("\\{\\}{a", true), Err(BraceExpandError::UnbalancedBrace(4)));
+
+        let ast = ast_from_pattern("{a,{b,{c,{d,{e,f}}}}}", true).unwrap();
+        assert_eq!(ast_num_expansions(&ast), 6);
+    }
+
     #[test]
     fn test_simple_max_expansion_length() {
         let tokens = tokenize("a{b,c}d", true);
@@ -206,10 +655,244 @@ mod tests {
         assert_eq!(ast_num_expansions(&ast), 6);
     }
 
+    #[test]
+    fn test_num_expansions_overflow() {
+        let ast = ast_from_tokens(&tokenize(&"{a,b}".repeat(64), true)).unwrap();
+        assert_eq!(ast_num_expansions_checked(&ast), None);
+        assert_eq!(ast_num_expansions(&ast), usize::MAX);
+
+        let ast = ast_from_tokens(&tokenize(&"{a,b}".repeat(63), true)).unwrap();
+        assert_eq!(ast_num_expansions_checked(&ast), Some(1 << 63));
+
+        // Overflowing inside a nested group
+        let ast = ast_from_tokens(&tokenize(&format!("{{x,{}}}", "{a,b}".repeat(64)), true)).unwrap();
+        assert_eq!(ast_num_expansions_checked(&ast), None);
+        // ...or in the sum of the alternatives
+        let ast = ast_from_tokens(&tokenize(&format!("{{{0},{0}}}", "{a,b}".repeat(62)), true)).unwrap();
+        assert_eq!(ast_num_expansions_checked(&ast), Some(1 << 63));
+        let ast = ast_from_tokens(&tokenize(&format!("{{{0},{0}}}", "{a,b}".repeat(63)), true)).unwrap();
+        assert_eq!(ast_num_expansions_checked(&ast), None);
+    }
+
     #[test]
     fn test_num_expansions_with_empty_terms() {
         let tokens = tokenize("a{,b,,c,}d{}", true);
         let ast = ast_from_tokens(&tokens).unwrap();
         assert_eq!(ast_num_expansions(&ast), 5);
     }
+
+    #[test]
+    fn test_length_distribution() {
+        let tokens = tokenize("{a,bb}{c,dd}", true);
+        let ast = ast_from_tokens(&tokens).unwrap();
+        assert_eq!(ast_length_distribution(&ast), BTreeMap::from([(2, 1), (3, 2), (4, 1)]));
+    }
+
+    #[test]
+    fn test_length_distribution_with_empty_terms() {
+        let tokens = tokenize("a{,b,,c,}d{}", true);
+        let ast = ast_from_tokens(&tokens).unwrap();
+        assert_eq!(ast_length_distribution(&ast), BTreeMap::from([(2, 3), (3, 2)]));
+    }
+
+    #[test]
+    fn test_min_expansion_length() {
+        let tokens = tokenize("a{bb,c}d{e,ff{g,}}", true);
+        let ast = ast_from_tokens(&tokens).unwrap();
+        assert_eq!(ast_min_expansion_length(&ast), 4);
+    }
+
+    #[test]
+    fn test_common_affixes() {
+        let tokens = tokenize("ab{cd,ce{f,g}}h{ij,kj}", true);
+        let ast = ast_from_tokens(&tokens).unwrap();
+        assert_eq!(ast_common_prefix(&ast), "abc");
+        assert_eq!(ast_common_suffix(&ast), "j");
+    }
+
+    #[test]
+    fn test_common_affixes_through_fixed_choices() {
+        let tokens = tokenize("a{b}{c,c}{d,e}{f}", true);
+        let ast = ast_from_tokens(&tokens).unwrap();
+        assert_eq!(ast_common_prefix(&ast), "abc");
+        assert_eq!(ast_common_suffix(&ast), "f");
+    }
+
+    #[test]
+    fn test_common_affixes_with_empty_terms() {
+        let tokens = tokenize("a{,b}c", true);
+        let ast = ast_from_tokens(&tokens).unwrap();
+        assert_eq!(ast_common_prefix(&ast), "a");
+        assert_eq!(ast_common_suffix(&ast), "c");
+    }
+
+    #[test]
+    fn test_alphabet() {
+        let tokens = tokenize("ab{c,d{e,}}a", true);
+        let ast = ast_from_tokens(&tokens).unwrap();
+        assert_eq!(ast_alphabet(&ast), BTreeSet::from([b'a', b'b', b'c', b'd', b'e']));
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let dot = dot_for("a{b,c}d");
+        assert!(dot.starts_with("digraph pattern {\n"));
+        assert!(dot.ends_with("}\n"));
+        // root, a, {}, b, c, d
+        assert_eq!(dot.matches("shape=").count(), 6);
+        // root -> a, {}, d; {} -> b, c
+        assert_eq!(dot.matches("-> ").count(), 5);
+        assert!(dot.contains("n2 -> n3 [label=\"0\"];"));
+        assert!(dot.contains("n2 -> n4 [label=\"1\"];"));
+    }
+
+    #[test]
+    fn test_to_dot_empty_alternative_and_escaping() {
+        let dot = dot_for("{\"x\\\\,}");
+        assert!(dot.contains("[label=\"\\\"x\\\\\", shape=box]"));
+        assert!(dot.contains("shape=point"));
+        assert_eq!(dot.matches("-> ").count(), 3);
+    }
+
+    #[test]
+    fn test_ranges() {
+        let ast = ast_from_tokens(&tokenize("a{1..3}b", true)).unwrap();
+        assert_eq!(ast, vec![
+            AstItem::Leaf("a".to_owned()),
+            AstItem::Range(BraceRange{start: 1, end: 3, step: 1, kind: RangeKind::Numeric{radix: 10, width: 0}}),
+            AstItem::Leaf("b".to_owned()),
+        ]);
+        assert_eq!(ast_num_expansions(&ast), 3);
+        assert_eq!(ast_max_expansion_length(&ast), 3);
+
+        let ast = ast_from_tokens(&tokenize("{3..1}", true)).unwrap();
+        assert_eq!(ast, vec![AstItem::Range(BraceRange{start: 3, end: 1, step: 1, kind: RangeKind::Numeric{radix: 10, width: 0}})]);
+        assert_eq!(ast_num_expansions(&ast), 3);
+
+        let ast = ast_from_tokens(&tokenize("x{8..1000}{a,b}", true)).unwrap();
+        assert_eq!(ast_num_expansions(&ast), 993 * 2);
+        assert_eq!(ast_max_expansion_length(&ast), 6);
+        assert_eq!(ast_min_expansion_length(&ast), 3);
+        assert_eq!(ast_length_distribution(&ast), BTreeMap::from([(3, 4), (4, 180), (5, 1800), (6, 2)]));
+    }
+
+    #[test]
+    fn test_stepped_ranges() {
+        let ast = ast_from_tokens(&tokenize("a{1..10..2}", true)).unwrap();
+        assert_eq!(ast_num_expansions(&ast), 5);
+        let ast = ast_from_tokens(&tokenize("{10..1..3}", true)).unwrap();
+        assert_eq!(ast_num_expansions(&ast), 4);
+        let ast = ast_from_tokens(&tokenize("{0..1000..7}", true)).unwrap();
+        assert_eq!(ast_num_expansions(&ast), 1000 / 7 + 1);
+        assert_eq!(ast_max_expansion_length(&ast), 3);
+
+        assert_eq!(ast_from_tokens(&tokenize("a{1..10..0}", true)),
+            Err(BraceExpandError::ZeroRangeStep("1..10..0".to_owned())));
+    }
+
+    #[test]
+    fn test_zero_padded_ranges() {
+        let ast = ast_from_tokens(&tokenize("a{01..10}", true)).unwrap();
+        assert_eq!(ast_max_expansion_length(&ast), 3);
+        assert_eq!(ast_min_expansion_length(&ast), 3);
+
+        let ast = ast_from_tokens(&tokenize("a{001..10}", true)).unwrap();
+        assert_eq!(ast, vec![
+            AstItem::Leaf("a".to_owned()),
+            AstItem::Range(BraceRange{start: 1, end: 10, step: 1, kind: RangeKind::Numeric{radix: 10, width: 3}}),
+        ]);
+        assert_eq!(ast_max_expansion_length(&ast), 4);
+        assert_eq!(ast_length_distribution(&ast), BTreeMap::from([(4, 10)]));
+
+        let ast = ast_from_tokens(&tokenize("a{1..10}", true)).unwrap();
+        assert_eq!(ast_max_expansion_length(&ast), 3);
+        assert_eq!(ast_min_expansion_length(&ast), 2);
+    }
+
+    #[test]
+    fn test_range_affixes_and_alphabet() {
+        let ast = ast_from_tokens(&tokenize("a{120..124}b", true)).unwrap();
+        assert_eq!(ast_common_prefix(&ast), "a12");
+        assert_eq!(ast_common_suffix(&ast), "b");
+        assert_eq!(ast_alphabet(&ast), BTreeSet::from(*b"ab01234"));
+
+        let ast = ast_from_tokens(&tokenize("a{7..7}b", true)).unwrap();
+        assert_eq!(ast_common_prefix(&ast), "a7b");
+    }
+
+    #[test]
+    fn test_range_to_dot() {
+        let dot = dot_for("a{0x0e..0x11}");
+        assert!(dot.contains("[label=\"{0x0e..0x11}\", shape=hexagon]"));
+        assert_eq!(dot.matches("shape=").count(), 3);
+    }
+
+    #[test]
+    fn test_to_pattern() {
+        for pattern in ["abc", "{a,b}c{e,f{g,h}}", "a{,b,}d{}", "x{3..1}{0x0e..0x10..2}{a..c}", "{1..x}"] {
+            let ast = ast_from_tokens(&tokenize(pattern, true)).unwrap();
+            assert_eq!(ast_to_pattern(&ast, true), pattern);
+            assert_eq!(ast_to_pattern(&ast, false), pattern);
+        }
+
+        let ast = ast_from_tokens(&tokenize("a\\{b\\}{x\\,y,z\\\\}", true)).unwrap();
+        assert_eq!(ast_to_pattern(&ast, true), "a\\{b\\}{x\\,y,z\\\\}");
+        assert_eq!(ast_from_tokens(&tokenize(&ast_to_pattern(&ast, true), true)).unwrap(), ast);
+
+        // Escaped braces around a range make it literal, and stay escaped
+        let ast = ast_from_tokens(&tokenize("\\{1..3\\}", true)).unwrap();
+        assert_eq!(ast, vec![AstItem::Leaf("{1..3}".to_owned())]);
+        assert_eq!(ast_to_pattern(&ast, true), "\\{1..3\\}");
+
+        // A literal comma in a leaf is re-escaped
+        let ast = vec![AstItem::Leaf("a,b".to_owned())];
+        assert_eq!(ast_to_pattern(&ast, true), "a\\,b");
+    }
+
+    #[test]
+    fn test_to_pattern_fixed_point() {
+        for pattern in [
+            "{a,b}c{e,f{g,h{i,{j,k}}}}",
+            "{,}{,,a}{a,}{}",
+            "a{,b{,c{,d}}}",
+            "\\{\\,\\}{\\,,\\\\}x",
+            "\\{1..3\\}{a..c..2}",
+            "é{ü,\\ß}",
+        ] {
+            let ast = ast_from_tokens(&tokenize(pattern, true)).unwrap();
+            let normalized = ast_to_pattern(&ast, true);
+            let reparsed = ast_from_tokens(&tokenize(&normalized, true)).unwrap();
+            assert_eq!(reparsed, ast, "{pattern}");
+            assert_eq!(ast_to_pattern(&reparsed, true), normalized, "{pattern}");
+        }
+    }
+
+    #[test]
+    fn test_nth_expansion() {
+        let pattern = "{a,b}c{e,f{g,h}}{1..3}{,x}";
+        let ast = ast_from_tokens(&tokenize(pattern, true)).unwrap();
+        let expected: Vec<String> = crate::brace_expand_iter(pattern, true).unwrap().collect();
+        assert_eq!(expected.len(), ast_num_expansions(&ast));
+
+        for (n, expansion) in expected.iter().enumerate() {
+            let mut s = String::new();
+            assert!(ast_nth_expansion(&ast, n, &mut s));
+            assert_eq!(&s, expansion);
+        }
+        assert!(!ast_nth_expansion(&ast, expected.len(), &mut String::new()));
+    }
+
+    #[test]
+    fn test_expansion_index_helpers() {
+        assert_eq!(locate_choice(0, [2, 3]), Some((0, 0)));
+        assert_eq!(locate_choice(4, [2, 3]), Some((1, 2)));
+        assert_eq!(locate_choice(5, [2, 3]), None);
+        assert_eq!(locate_choice(1, [0, 2]), Some((1, 1)));
+
+        // 7 = 1*4 + 1*2 + 1
+        assert_eq!(mixed_radix_digits(7, &[3, 2, 2]), Some(vec![1, 1, 1]));
+        assert_eq!(mixed_radix_digits(4, &[3, 2, 2]), Some(vec![1, 0, 0]));
+        assert_eq!(mixed_radix_digits(0, &[]), Some(vec![]));
+        assert_eq!(mixed_radix_digits(0, &[2, 0]), None);
+    }
 }
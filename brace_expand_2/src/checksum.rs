@@ -0,0 +1,175 @@
+use djb2_utils::hash_djb2;
+
+
+/// A checksum appended to the end of every expansion (written as a
+/// marker at the end of a pattern: see parse_checksum_marker()).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Checksum {
+    /// The sum of all the bytes, mod N, in decimal. (N = 0 is treated
+    /// as 1.) Written as "#%N", or just "#" for SumMod(10), which gives
+    /// one digit.
+    SumMod(u32),
+    /// The sum of just the decimal digits, mod 10, as one digit.
+    /// Written as "#d".
+    DigitSum,
+}
+
+impl Checksum {
+    /// Adds one byte to a running sum (which starts at 0). Both kinds of
+    /// checksum are sums, so they can be built up a piece at a time.
+    pub(crate) fn add_byte(&self, sum: u64, c: u8) -> u64 {
+        match self {
+            Self::SumMod(_) => sum + u64::from(c),
+            Self::DigitSum if c.is_ascii_digit() => sum + u64::from(c - b'0'),
+            Self::DigitSum => sum,
+        }
+    }
+
+    /// Turns a running sum into the checksum value.
+    pub(crate) fn finish(&self, sum: u64) -> u32 {
+        match self {
+            Self::SumMod(n) => (sum % u64::from((*n).max(1))) as u32,
+            Self::DigitSum => (sum % 10) as u32,
+        }
+    }
+
+    /// The checksum of a string (not including the checksum itself).
+    pub fn value(&self, s: &[u8]) -> u32 {
+        self.finish(s.iter().fold(0, |sum, c| self.add_byte(sum, *c)))
+    }
+
+    /// Hashes the digits that would be appended for a running sum,
+    /// continuing from the hash state of everything before them.
+    pub(crate) fn hash_appended(&self, sum: u64, state: u32) -> u32 {
+        let mut value = self.finish(sum);
+        let mut digits = [0u8; 10];
+        let mut start = digits.len();
+        loop {
+            start -= 1;
+            digits[start] = b'0' + (value % 10) as u8;
+            value /= 10;
+            if value == 0 {
+                break;
+            }
+        }
+        hash_djb2(&digits[start..], state)
+    }
+
+    /// Appends the checksum of `target[start..]` to `target`.
+    pub(crate) fn append(&self, target: &mut String, start: usize) {
+        let value = self.value(&target.as_bytes()[start..]);
+        target.push_str(&value.to_string());
+    }
+
+    /// Same as append(), but for bytes.
+    pub(crate) fn append_bytes(&self, target: &mut Vec<u8>, start: usize) {
+        let value = self.value(&target[start..]);
+        target.extend_from_slice(value.to_string().as_bytes());
+    }
+
+    /// The fewest bytes append() can add.
+    pub(crate) fn min_len(&self) -> usize {
+        1
+    }
+
+    /// The most bytes append() can add.
+    pub(crate) fn max_len(&self) -> usize {
+        match self {
+            Self::SumMod(n) => (n.max(&1) - 1).to_string().len(),
+            Self::DigitSum => 1,
+        }
+    }
+
+    /// The marker that parse_checksum_marker() reads as this checksum.
+    pub(crate) fn marker(&self) -> String {
+        match self {
+            Self::SumMod(10) => "#".to_owned(),
+            Self::SumMod(n) => format!("#%{n}"),
+            Self::DigitSum => "#d".to_owned(),
+        }
+    }
+}
+
+
+/// If a pattern ends with a checksum marker -- "#", "#%N" (for
+/// SumMod(N)) or "#d" (for DigitSum) -- returns the pattern without it,
+/// and the checksum. If `escape` is true, a backslash before the "#"
+/// makes it literal; otherwise, there's no way to end a pattern with a
+/// literal marker.
+pub(crate) fn parse_checksum_marker(pattern: &str, escape: bool) -> Option<(&str, Checksum)> {
+    let hash_pos = pattern.rfind('#')?;
+    let (rest, marker) = (&pattern[..hash_pos], &pattern[hash_pos + 1..]);
+    let checksum = match marker {
+        "" => Checksum::SumMod(10),
+        "d" => Checksum::DigitSum,
+        _ => {
+            let n = marker.strip_prefix('%')?;
+            if n.is_empty() || !n.bytes().all(|c| c.is_ascii_digit()) {
+                return None;
+            }
+            Checksum::SumMod(n.parse().ok()?)
+        },
+    };
+
+    let num_backslashes = rest.bytes().rev().take_while(|c| *c == b'\\').count();
+    if escape && num_backslashes % 2 == 1 {
+        None
+    } else {
+        Some((rest, checksum))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_values() {
+        // 97 + 98 + 99 = 294
+        assert_eq!(Checksum::SumMod(10).value(b"abc"), 4);
+        assert_eq!(Checksum::SumMod(256).value(b"abc"), 38);
+        assert_eq!(Checksum::SumMod(0).value(b"abc"), 0);
+        assert_eq!(Checksum::DigitSum.value(b"a9b8c"), 7);
+
+        assert_eq!(Checksum::SumMod(10).max_len(), 1);
+        assert_eq!(Checksum::SumMod(1000).max_len(), 3);
+        assert_eq!(Checksum::SumMod(1001).max_len(), 4);
+        assert_eq!(Checksum::SumMod(0).max_len(), 1);
+        assert_eq!(Checksum::DigitSum.min_len(), 1);
+
+        let mut s = "xyzabc".to_owned();
+        Checksum::SumMod(10).append(&mut s, 3);
+        assert_eq!(s, "xyzabc4");
+    }
+
+    #[test]
+    fn test_hash_appended() {
+        for checksum in [Checksum::SumMod(10), Checksum::SumMod(1000), Checksum::DigitSum] {
+            let mut s = "x1y2z".to_owned();
+            let sum = s.bytes().fold(0, |sum, c| checksum.add_byte(sum, c));
+            let state = hash_djb2(s.as_bytes(), 0x1505);
+            checksum.append(&mut s, 0);
+            assert_eq!(checksum.hash_appended(sum, state), hash_djb2(s.as_bytes(), 0x1505), "{s}");
+        }
+    }
+
+    #[test]
+    fn test_parse_checksum_marker() {
+        assert_eq!(parse_checksum_marker("abc#", true), Some(("abc", Checksum::SumMod(10))));
+        assert_eq!(parse_checksum_marker("abc#d", true), Some(("abc", Checksum::DigitSum)));
+        assert_eq!(parse_checksum_marker("a#bc#%256", true), Some(("a#bc", Checksum::SumMod(256))));
+        assert_eq!(parse_checksum_marker("abc", true), None);
+        assert_eq!(parse_checksum_marker("abc#x", true), None);
+        assert_eq!(parse_checksum_marker("abc#%", true), None);
+        assert_eq!(parse_checksum_marker("abc#%+5", true), None);
+        assert_eq!(parse_checksum_marker("abc\\#", true), None);
+        assert_eq!(parse_checksum_marker("abc\\#d", true), None);
+        assert_eq!(parse_checksum_marker("abc\\#", false), Some(("abc\\", Checksum::SumMod(10))));
+        assert_eq!(parse_checksum_marker("abc\\\\#", true), Some(("abc\\\\", Checksum::SumMod(10))));
+
+        for checksum in [Checksum::SumMod(10), Checksum::SumMod(7), Checksum::DigitSum] {
+            assert_eq!(parse_checksum_marker(&format!("x{}", checksum.marker()), true), Some(("x", checksum)));
+        }
+    }
+}
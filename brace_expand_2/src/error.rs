@@ -0,0 +1,38 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::tokenizer::Token;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BraceExpandError {
+    /// A token that isn't allowed at that point in the pattern, and its
+    /// index in the token list.
+    UnexpectedToken(Token, usize),
+    /// A brace without a partner (an unclosed "{" or a stray "}"), and
+    /// its byte offset in the pattern.
+    UnbalancedBrace(usize),
+    /// A numeric range whose bounds are written in different bases (like
+    /// "0x0..0b1"), and the text of the range.
+    MismatchedRangeBases(String),
+    /// A range with a step of zero (like "1..5..0"), and the text of the
+    /// range.
+    ZeroRangeStep(String),
+}
+
+impl fmt::Display for BraceExpandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnexpectedToken(token, position) =>
+                write!(f, "unexpected {token:?} at position {position}"),
+            Self::UnbalancedBrace(offset) =>
+                write!(f, "unbalanced brace at byte {offset}"),
+            Self::MismatchedRangeBases(range) =>
+                write!(f, "range \"{range}\" has bounds in different bases"),
+            Self::ZeroRangeStep(range) =>
+                write!(f, "range \"{range}\" has a step of zero"),
+        }
+    }
+}
+
+impl Error for BraceExpandError {}
@@ -0,0 +1,252 @@
+use std::collections::{HashMap, HashSet};
+
+use djb2_utils::{hash_djb2, invhash_djb2};
+
+use crate::analysis::for_each_expansion;
+use crate::ast::{Ast, AstItem, ast_item_num_expansions, ast_num_expansions};
+
+
+/// The result of find_expansions_with_hashes().
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct InverseSearchResult {
+    /// Every expansion whose hash is one of the targets, in order
+    pub matches: Vec<String>,
+    /// How many hashes were calculated along the way (forwards over
+    /// prefixes, and backwards over suffixes)
+    pub num_hashed: usize,
+}
+
+
+/// Everything that's still to come at some point in the search: the
+/// rest of the innermost sequence of items, then the rest of the one
+/// enclosing it, and so on. (A linked list on the stack, so that
+/// descending into a Choices item doesn't need to copy anything.)
+#[derive(Clone, Copy)]
+struct Continuation<'c, 'a> {
+    items: &'a [AstItem],
+    /// How many different prefixes lead to this point
+    num_prefixes: usize,
+    outer: Option<&'c Continuation<'c, 'a>>,
+}
+
+
+impl<'c, 'a> Continuation<'c, 'a> {
+    /// The next item, and what comes after it.
+    fn split_first(self) -> Option<(&'a AstItem, Self)> {
+        let mut cont = self;
+        loop {
+            if let Some((item, rest)) = cont.items.split_first() {
+                let num_prefixes = cont.num_prefixes.saturating_mul(ast_item_num_expansions(item));
+                return Some((item, Self{items: rest, num_prefixes, outer: cont.outer}));
+            }
+            cont = *cont.outer?;
+        }
+    }
+
+    fn items(self) -> impl Iterator<Item = &'a AstItem> + 'c {
+        std::iter::successors(Some(self), |cont| cont.outer.copied()).flat_map(|cont| cont.items.iter())
+    }
+
+    fn num_expansions(self) -> usize {
+        self.items().fold(1usize, |acc, item| acc.saturating_mul(ast_item_num_expansions(item)))
+    }
+
+    /// Identifies the position in the AST (two continuations with the
+    /// same key always expand the same way).
+    fn key(self) -> Vec<(usize, usize)> {
+        std::iter::successors(Some(self), |cont| cont.outer.copied())
+            .filter(|cont| !cont.items.is_empty())
+            .map(|cont| (cont.items.as_ptr() as usize, cont.items.len()))
+            .collect()
+    }
+}
+
+
+fn for_each_item_expansion(item: &AstItem, mut f: impl FnMut(&str)) {
+    match item {
+        AstItem::Leaf(s) => f(s),
+        _ if ast_item_num_expansions(item) == 0 => {},
+        _ => for_each_expansion(&vec![item.clone()], |s| {
+            f(s);
+            true
+        }),
+    }
+}
+
+
+struct InverseSearch<'t> {
+    targets: &'t HashSet<u32>,
+    /// For each continuation that's been cut at, the hash states it can
+    /// lead to a target from
+    required: HashMap<Vec<(usize, usize)>, HashSet<u32>>,
+    result: InverseSearchResult,
+}
+
+
+impl<'a> InverseSearch<'_> {
+    /// Fills in (if needed) the set of hash states from which `cont` can
+    /// reach a target, and returns its key in `required`. This un-hashes
+    /// one item at a time, so continuations that share an ending share
+    /// that part of the work.
+    fn required_states(&mut self, cont: Continuation<'_, 'a>) -> Vec<(usize, usize)> {
+        let key = cont.key();
+        if !self.required.contains_key(&key) {
+            let states = match cont.split_first() {
+                None => self.targets.clone(),
+                Some((item, rest)) => {
+                    let rest_key = self.required_states(rest);
+                    let rest_states = &self.required[&rest_key];
+                    let mut states = HashSet::new();
+                    let mut num_hashed = 0;
+                    for_each_item_expansion(item, |s| {
+                        states.extend(rest_states.iter().map(|state| invhash_djb2(s.as_bytes(), *state)));
+                        num_hashed += rest_states.len();
+                    });
+                    self.result.num_hashed += num_hashed;
+                    states
+                }
+            };
+            self.required.insert(key.clone(), states);
+        }
+        key
+    }
+
+    /// Adds the matches that `cont` leads to from `state`, which should
+    /// be one of its required states. Since the required states of
+    /// everything after each item are already known, this only follows
+    /// the expansions that actually lead to a target.
+    fn collect_matches(&mut self, cont: Continuation<'_, 'a>, state: u32, prefix: &mut String) {
+        let Some((item, rest)) = cont.split_first() else {
+            self.result.matches.push(prefix.clone());
+            return;
+        };
+        let rest_key = self.required_states(rest);
+        let mut next_states = Vec::new();
+        for_each_item_expansion(item, |s| {
+            let next_state = hash_djb2(s.as_bytes(), state);
+            if self.required[&rest_key].contains(&next_state) {
+                next_states.push((s.to_owned(), next_state));
+            }
+        });
+        self.result.num_hashed += ast_item_num_expansions(item);
+        for (s, next_state) in next_states {
+            let len = prefix.len();
+            prefix.push_str(&s);
+            self.collect_matches(rest, next_state, prefix);
+            prefix.truncate(len);
+        }
+    }
+
+    /// Hashes forwards through the next item of `cont`, descending into
+    /// Choices alternatives, until the rest is cheap enough to un-hash
+    /// backwards. From then on, only states that can still reach a
+    /// target are followed.
+    fn visit(&mut self, cont: Continuation<'_, 'a>, state: u32, prefix: &mut String) {
+        let Some((item, rest)) = cont.split_first() else {
+            if self.targets.contains(&state) {
+                self.result.matches.push(prefix.clone());
+            }
+            return;
+        };
+
+        // Un-hashing the rest from every target costs about this
+        // much, once; going one item further costs about as much as the
+        // right-hand side, and more after that. (It's always better to
+        // look inside Choices first, though, since their alternatives
+        // can be cut separately.)
+        if !matches!(item, AstItem::Choices(_))
+                && self.targets.len().saturating_mul(cont.num_expansions()) <= rest.num_prefixes {
+            let key = self.required_states(cont);
+            if self.required[&key].contains(&state) {
+                self.collect_matches(cont, state, prefix);
+            }
+            return;
+        }
+
+        if let AstItem::Choices(alternatives) = item {
+            for alternative in alternatives {
+                let inner = Continuation{items: alternative, num_prefixes: cont.num_prefixes, outer: Some(&rest)};
+                self.visit(inner, state, prefix);
+            }
+        } else {
+            for_each_item_expansion(item, |s| {
+                self.result.num_hashed += 1;
+                let len = prefix.len();
+                prefix.push_str(s);
+                self.visit(rest, hash_djb2(s.as_bytes(), state), prefix);
+                prefix.truncate(len);
+            });
+        }
+    }
+}
+
+
+/// Finds every expansion of the AST whose djb2 hash (from `seed`) is one
+/// of `targets`, without hashing every expansion.
+///
+/// This is a meet-in-the-middle search: the beginning of the pattern is
+/// hashed forwards, descending into nested alternatives as it goes.
+/// Wherever the rest of the pattern (often a fixed, known suffix) gets
+/// small enough, it's un-hashed backwards from each target with
+/// invhash_djb2() instead, giving the set of hash states that can still
+/// reach a target from there. Whole subtrees of expansions whose state
+/// isn't in that set are skipped without being visited.
+pub fn find_expansions_with_hashes(ast: &Ast, seed: u32, targets: &HashSet<u32>) -> InverseSearchResult {
+    let mut search = InverseSearch{targets, required: HashMap::new(), result: InverseSearchResult::default()};
+    if targets.is_empty() || ast_num_expansions(ast) == 0 {
+        return search.result;
+    }
+
+    search.visit(Continuation{items: ast, num_prefixes: 1, outer: None}, seed, &mut String::new());
+    search.result
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ast::ast_from_pattern;
+
+    /// Finds the matches by hashing every expansion, for comparison.
+    fn brute_force(ast: &Ast, seed: u32, targets: &HashSet<u32>) -> Vec<String> {
+        let mut matches = Vec::new();
+        for_each_expansion(ast, |s| {
+            if targets.contains(&hash_djb2(s.as_bytes(), seed)) {
+                matches.push(s.to_owned());
+            }
+            true
+        });
+        matches
+    }
+
+    #[test]
+    fn test_matches_brute_force() {
+        for (pattern, target_names) in [
+            ("{a,b,c}{d,e}__10dWmActor_cFv", vec!["be__10dWmActor_cFv", "ad__10dWmActor_cFv"]),
+            ("x{1..20}y{1..20}", vec!["x3y17", "x20y20", "nope"]),
+            ("{a,{b,c}d}{,e}", vec!["cde", "a"]),
+            ("{x{a..z}{a..z}__Fv,y{1..9}}z", vec!["xcat__Fvz", "y7z"]),
+            ("plain", vec!["plain"]),
+            ("", vec![""]),
+        ] {
+            let ast = ast_from_pattern(pattern, true).unwrap();
+            let targets: HashSet<u32> = target_names.iter().map(|s| hash_djb2(s.as_bytes(), 0x1505)).collect();
+            let result = find_expansions_with_hashes(&ast, 0x1505, &targets);
+            assert_eq!(result.matches, brute_force(&ast, 0x1505, &targets), "{pattern}");
+        }
+
+        let ast = ast_from_pattern("{a,b}", true).unwrap();
+        assert_eq!(find_expansions_with_hashes(&ast, 0x1505, &HashSet::new()), InverseSearchResult::default());
+    }
+
+    #[test]
+    fn test_prunes_nested_subtrees() {
+        // (Just one top-level item, so this has to split inside it)
+        let ast = ast_from_pattern("{x{a..z}{a..z}{a..z}__Fv,y}", true).unwrap();
+        let targets = HashSet::from([hash_djb2(b"xcat__Fv", 0x1505)]);
+        let result = find_expansions_with_hashes(&ast, 0x1505, &targets);
+        assert_eq!(result.matches, vec!["xcat__Fv"]);
+        assert!(result.num_hashed < ast_num_expansions(&ast) / 4, "{}", result.num_hashed);
+    }
+}
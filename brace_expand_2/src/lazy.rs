@@ -0,0 +1,190 @@
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+
+/// Something that produces alternatives for a LazyChoices, one at a
+/// time. Any `FnMut() -> Option<String>` closure is one.
+pub trait ChoiceSource {
+    /// The next alternative, or None once there are no more.
+    fn next_choice(&mut self) -> Option<String>;
+
+    /// The total number of alternatives, if it's known in advance. This
+    /// lets the number of expansions be calculated without reading the
+    /// whole source, so it must be accurate.
+    fn num_choices(&self) -> Option<usize> {
+        None
+    }
+}
+
+impl<F: FnMut() -> Option<String>> ChoiceSource for F {
+    fn next_choice(&mut self) -> Option<String> {
+        self()
+    }
+}
+
+
+/// A ChoiceSource with a number of alternatives given by the user.
+struct CountedChoiceSource<S: ChoiceSource> {
+    source: S,
+    count: usize,
+}
+
+impl<S: ChoiceSource> ChoiceSource for CountedChoiceSource<S> {
+    fn next_choice(&mut self) -> Option<String> {
+        self.source.next_choice()
+    }
+
+    fn num_choices(&self) -> Option<usize> {
+        Some(self.count)
+    }
+}
+
+
+struct LazyChoicesState {
+    /// None once the source has run out
+    source: Option<Box<dyn ChoiceSource + Send>>,
+    /// Every alternative read from the source so far
+    cache: Vec<String>,
+    num_choices: Option<usize>,
+}
+
+impl LazyChoicesState {
+    /// Reads from the source until there are more than `i` alternatives
+    /// cached, or it runs out.
+    fn read_through(&mut self, i: usize) {
+        while self.cache.len() <= i {
+            let Some(source) = &mut self.source else {
+                return;
+            };
+            match source.next_choice() {
+                Some(choice) => self.cache.push(choice),
+                None => self.source = None,
+            }
+        }
+    }
+
+    fn read_all(&mut self) {
+        self.read_through(usize::MAX);
+    }
+}
+
+
+/// A set of alternatives (like "{a,b,c}") that are read from a
+/// ChoiceSource as they're needed, instead of being known up front.
+/// Alternatives are cached once read, since each one is needed again
+/// whenever anything before it in the pattern changes.
+///
+/// Clones share the same source and cache, even across threads. That's
+/// why sources have to be Send: a pattern's expansions can be split
+/// into ranges that are searched on different threads (see
+/// BraceExpandIterator::split_ranges()), and each thread's state
+/// machine reads from the same LazyChoices.
+///
+/// Calculating most properties of a pattern (its alphabet, maximum
+/// length, etc.) reads the whole source; the number of expansions does
+/// too, unless the source reports how many alternatives it has.
+#[derive(Clone)]
+pub struct LazyChoices(Arc<Mutex<LazyChoicesState>>);
+
+impl LazyChoices {
+    pub fn new(source: impl ChoiceSource + Send + 'static) -> Self {
+        let num_choices = source.num_choices();
+        Self(Arc::new(Mutex::new(LazyChoicesState{
+            source: Some(Box::new(source)),
+            cache: Vec::new(),
+            num_choices,
+        })))
+    }
+
+    /// Like new(), but with the number of alternatives given explicitly
+    /// (which must be accurate). Useful for closures.
+    pub fn with_count(source: impl ChoiceSource + Send + 'static, count: usize) -> Self {
+        Self::new(CountedChoiceSource{source, count})
+    }
+
+    fn state(&self) -> MutexGuard<'_, LazyChoicesState> {
+        // The state is always left consistent, even if a source panics
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Calls `f` with the i-th alternative, reading it from the source
+    /// if needed. Returns None if there aren't that many alternatives.
+    pub(crate) fn with_choice<R>(&self, i: usize, f: impl FnOnce(&str) -> R) -> Option<R> {
+        let mut state = self.state();
+        state.read_through(i);
+        state.cache.get(i).map(|s| f(s))
+    }
+
+    /// Calls `f` with every alternative, reading the whole source.
+    pub(crate) fn with_all<R>(&self, f: impl FnOnce(&[String]) -> R) -> R {
+        let mut state = self.state();
+        state.read_all();
+        f(&state.cache)
+    }
+
+    /// The number of alternatives. This reads the whole source, unless
+    /// it reported the number in advance.
+    pub(crate) fn num_choices(&self) -> usize {
+        if let Some(count) = self.state().num_choices {
+            return count;
+        }
+        self.with_all(|choices| choices.len())
+    }
+}
+
+impl fmt::Debug for LazyChoices {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = self.state();
+        f.debug_struct("LazyChoices")
+            .field("num_read", &state.cache.len())
+            .field("num_choices", &state.num_choices)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Two LazyChoices are equal if they share the same source.
+impl PartialEq for LazyChoices {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_reads_lazily_and_caches() {
+        let num_calls = Arc::new(AtomicUsize::new(0));
+        let num_calls_2 = num_calls.clone();
+        let mut next = 0;
+        let choices = LazyChoices::new(move || {
+            num_calls_2.fetch_add(1, Ordering::Relaxed);
+            next += 1;
+            (next <= 3).then(|| next.to_string())
+        });
+
+        assert_eq!(choices.with_choice(1, str::to_owned), Some("2".to_owned()));
+        assert_eq!(num_calls.load(Ordering::Relaxed), 2);
+        assert_eq!(choices.clone().with_choice(0, str::to_owned), Some("1".to_owned()));
+        assert_eq!(num_calls.load(Ordering::Relaxed), 2);
+
+        assert_eq!(choices.num_choices(), 3);
+        assert_eq!(choices.with_choice(3, str::to_owned), None);
+        // 3 alternatives, and then None once
+        assert_eq!(num_calls.load(Ordering::Relaxed), 4);
+    }
+
+    #[test]
+    fn test_with_count() {
+        let mut v = vec!["b".to_owned(), "a".to_owned()];
+        let choices = LazyChoices::with_count(move || v.pop(), 2);
+        assert_eq!(choices.num_choices(), 2);
+        assert_eq!(format!("{choices:?}"), "LazyChoices { num_read: 0, num_choices: Some(2), .. }");
+        assert_eq!(choices.with_all(|v| v.to_vec()), vec!["a", "b"]);
+        assert_eq!(choices, choices.clone());
+    }
+}
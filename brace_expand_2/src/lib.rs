@@ -1,44 +1,355 @@
+mod analysis;
 mod ast;
+mod checksum;
+mod error;
+mod inverse;
+mod lazy;
+mod range;
 mod state_machines;
 mod tokenizer;
 
-use std::error::Error;
+use std::cell::OnceCell;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::io::{self, Write};
+use std::ops::Range;
 
-use ast::{ast_from_tokens, ast_max_expansion_length, ast_num_expansions};
+use djb2_utils::hash_djb2;
+
+use ast::{Ast, AstItem, ast_alphabet, ast_common_prefix, ast_common_suffix, ast_from_pattern,
+    ast_from_tokens, ast_from_tokens_with_offsets, ast_is_empty, ast_length_distribution, ast_max_expansion_length, ast_nth_expansion, ast_num_expansions_checked,
+    ast_to_dot, ast_to_pattern};
+use checksum::parse_checksum_marker;
+use inverse::find_expansions_with_hashes;
+use tokenizer::tokenize_with_offsets_and_config;
 use state_machines::{AstStateMachine, StateMachine};
-use tokenizer::tokenize;
+
+pub use analysis::patterns_overlap;
+pub use checksum::Checksum;
+pub use error::BraceExpandError;
+pub use inverse::InverseSearchResult;
+pub use lazy::{ChoiceSource, LazyChoices};
+pub use tokenizer::{escape_for_pattern, tokenize, tokenize_reader, tokenize_with, Token, TokenizerConfig};
 
 // ---------------------------------------------------------------------
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BraceExpandIterator {
+    ast: Ast,
     state_machine: AstStateMachine,
+    /// For iterating from the end (created on the first next_back())
+    back_state_machine: Option<AstStateMachine>,
+    checksum: Option<Checksum>,
     is_done: bool,
-    length_hint: usize,
-    num_expansions_hint: usize
+    // These are calculated when first needed, since with LazyChoices,
+    // that can mean reading a whole source
+    length_hint: OnceCell<usize>,
+    num_expansions_hint: OnceCell<Option<usize>>,
+    is_empty_hint: OnceCell<bool>,
+    /// How many expansions have been produced so far, from the front
+    /// and from the back
+    num_produced: usize,
+    num_produced_back: usize,
 }
 
 impl BraceExpandIterator {
-    fn new(state_machine: AstStateMachine, length_hint: usize, num_expansions_hint: usize) -> Self {
-        Self{state_machine, is_done: false, length_hint, num_expansions_hint}
+    fn new(ast: Ast, checksum: Option<Checksum>) -> Self {
+        let state_machine = AstStateMachine::new(&ast).with_checksum(checksum);
+        Self{
+            ast,
+            state_machine,
+            back_state_machine: None,
+            checksum,
+            is_done: false,
+            length_hint: OnceCell::new(),
+            num_expansions_hint: OnceCell::new(),
+            is_empty_hint: OnceCell::new(),
+            num_produced: 0,
+            num_produced_back: 0,
+        }
+    }
+
+    /// Appends a checksum to every expansion (replacing any from a "#"
+    /// in the pattern), or removes it if None. This restarts iteration
+    /// from the beginning.
+    pub fn with_checksum(self, checksum: Option<Checksum>) -> Self {
+        Self::new(self.ast, checksum)
+    }
+
+    /// Restarts iteration from the first expansion, without re-parsing
+    /// the pattern.
+    pub fn reset(&mut self) {
+        self.state_machine.reset();
+        self.back_state_machine = None;
+        self.is_done = false;
+        self.num_produced = 0;
+        self.num_produced_back = 0;
+    }
+
+    /// Jumps to the n-th expansion (counting from 0, from the beginning),
+    /// so that it's the next one produced, without generating the ones
+    /// before it. Like reset(), this forgets about any iteration from the
+    /// back.
+    pub fn skip_to(&mut self, n: usize) {
+        self.reset();
+        if n >= self.num_expansions() {
+            self.is_done = true;
+        } else {
+            self.state_machine.seek(n);
+        }
+        self.num_produced = n;
+    }
+
+    /// Switches to iterating over a different pattern (from the
+    /// beginning), reusing this iterator's allocations where possible.
+    /// On error, the iterator is left unchanged.
+    pub fn set_pattern(&mut self, input: &str, escape: bool) -> Result<(), BraceExpandError> {
+        let (ast, checksum) = parse_pattern(input, escape)?;
+        self.state_machine.rebuild(&ast);
+        self.state_machine.set_checksum(checksum);
+        self.ast = ast;
+        self.checksum = checksum;
+        self.length_hint = OnceCell::new();
+        self.num_expansions_hint = OnceCell::new();
+        self.is_empty_hint = OnceCell::new();
+        self.reset();
+        Ok(())
+    }
+
+    /// Moves on to the next expansion, after the current one has been
+    /// produced.
+    fn advance(&mut self) {
+        self.is_done = !self.state_machine.advance();
+        self.num_produced += 1;
+    }
+
+    /// Whether every expansion has been produced (from either end).
+    fn is_exhausted(&self) -> bool {
+        // (Only check the count if we have to, since it may mean reading
+        // LazyChoices sources. A pattern with an empty source has no
+        // expansions, but the state machine would still produce one.)
+        self.is_done
+            || *self.is_empty_hint.get_or_init(|| ast_is_empty(&self.ast))
+            || (self.num_produced_back > 0 && self.remaining() == 0)
     }
 
     pub fn next_into(&mut self, output: &mut String) -> bool {
-        if self.is_done {
+        if self.is_exhausted() {
+            return false;
+        }
+        output.clear();
+        self.state_machine.fill(output);
+        self.advance();
+        true
+    }
+
+    /// Like next_into(), but doesn't advance, so the next call to this
+    /// or next_into() produces the same expansion again.
+    pub fn peek_into(&self, output: &mut String) -> bool {
+        if self.is_exhausted() {
             return false;
         }
         output.clear();
         self.state_machine.fill(output);
-        self.is_done = !self.state_machine.advance();
         true
     }
 
+    /// Like next_into(), but writes the expansion as bytes, for callers
+    /// that don't need it to be a String.
+    pub fn next_into_bytes(&mut self, output: &mut Vec<u8>) -> bool {
+        if self.is_exhausted() {
+            return false;
+        }
+        output.clear();
+        self.state_machine.fill_bytes(output);
+        self.advance();
+        true
+    }
+
+    /// Writes each remaining expansion to `w`, followed by `sep`, and
+    /// returns how many were written. Expansions are built in one reused
+    /// buffer, so nothing is allocated per expansion. (`w` isn't
+    /// flushed, and may want to be buffered.)
+    pub fn write_all_to<W: Write>(mut self, sep: u8, w: &mut W) -> io::Result<usize> {
+        let mut scratch = Vec::with_capacity(self.max_expansion_length() + 1);
+        let mut count = 0;
+        while self.next_into_bytes(&mut scratch) {
+            scratch.push(sep);
+            w.write_all(&scratch)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Like next_into(), but produces the djb2 hash of the expansion
+    /// (continuing from `seed`) instead of the expansion itself. Only
+    /// the part of the expansion that changed since the previous call is
+    /// re-hashed, so this is much faster than hashing each expansion
+    /// from scratch.
+    pub fn next_hash(&mut self, seed: u32) -> Option<u32> {
+        if self.is_exhausted() {
+            return None;
+        }
+        let hash = self.state_machine.fill_hashed_incremental(seed);
+        self.advance();
+        Some(hash)
+    }
+
     pub fn max_expansion_length(&self) -> usize {
-        self.length_hint
+        *self.length_hint.get_or_init(|| {
+            ast_max_expansion_length(&self.ast) + self.checksum.map_or(0, |checksum| checksum.max_len())
+        })
     }
 
+    /// The total number of expansions (saturating at usize::MAX).
     pub fn num_expansions(&self) -> usize {
-        self.num_expansions_hint
+        self.num_expansions_checked().unwrap_or(usize::MAX)
+    }
+
+    /// The total number of expansions, or None if that doesn't fit in a
+    /// usize.
+    pub fn num_expansions_checked(&self) -> Option<usize> {
+        *self.num_expansions_hint.get_or_init(|| ast_num_expansions_checked(&self.ast))
+    }
+
+    /// The number of expansions that haven't been produced yet.
+    pub fn remaining(&self) -> usize {
+        if self.is_done {
+            0
+        } else {
+            self.num_expansions().saturating_sub(self.num_produced + self.num_produced_back)
+        }
+    }
+
+    /// The set of bytes that can appear in the expansions.
+    pub fn alphabet(&self) -> BTreeSet<u8> {
+        let mut alphabet = ast_alphabet(&self.ast);
+        if self.checksum.is_some() {
+            alphabet.extend(b'0'..=b'9');
+        }
+        alphabet
+    }
+
+    /// The longest string that every expansion begins with.
+    pub fn common_prefix(&self) -> String {
+        ast_common_prefix(&self.ast)
+    }
+
+    /// The longest string that every expansion ends with.
+    pub fn common_suffix(&self) -> String {
+        if self.checksum.is_some() {
+            // (It could be worked out, but it's not worth the trouble)
+            return String::new();
+        }
+        ast_common_suffix(&self.ast)
+    }
+
+    /// Finds every expansion (from the beginning, regardless of how far
+    /// this iterator has gotten) whose djb2 hash from `seed` is in
+    /// `targets`, by working backwards from the targets to prune the
+    /// search instead of hashing every expansion. Returns None if there's
+    /// a checksum, since that depends on the whole string and so can't be
+    /// un-hashed.
+    pub fn find_by_hash(&self, seed: u32, targets: &HashSet<u32>) -> Option<InverseSearchResult> {
+        if self.checksum.is_some() {
+            return None;
+        }
+        Some(find_expansions_with_hashes(&self.ast, seed, targets))
+    }
+
+    /// The n-th expansion (counting from 0, and from the beginning
+    /// regardless of how far this iterator has gotten), found directly
+    /// without generating the ones before it.
+    pub fn nth_expansion(&self, n: usize) -> Option<String> {
+        let mut s = String::new();
+        if self.nth_expansion_into(n, &mut s) {
+            Some(s)
+        } else {
+            None
+        }
+    }
+
+    /// Like nth_expansion(), but writes the expansion into `output`
+    /// (which is cleared first), so that a buffer can be reused. Returns
+    /// false if there are only n or fewer expansions.
+    pub fn nth_expansion_into(&self, n: usize, output: &mut String) -> bool {
+        output.clear();
+        if !ast_nth_expansion(&self.ast, n, output) {
+            return false;
+        }
+        if let Some(checksum) = &self.checksum {
+            checksum.append(output, 0);
+        }
+        true
+    }
+
+    /// Splits the expansions into `n` contiguous ranges of indices (for
+    /// nth_expansion() or range_iter()), as evenly as possible, in order.
+    /// Some may be empty if there are fewer than `n` expansions.
+    pub fn split_ranges(&self, n: usize) -> Vec<Range<usize>> {
+        if n == 0 {
+            return Vec::new();
+        }
+        let total = self.num_expansions();
+        let (size, extra) = (total / n, total % n);
+        let mut start = 0;
+        (0..n).map(|i| {
+            // The first `extra` ranges get one more each
+            let end = start + size + usize::from(i < extra);
+            let range = start..end;
+            start = end;
+            range
+        }).collect()
+    }
+
+    /// An iterator over just the expansions with indices in `range`
+    /// (counting from the beginning, regardless of how far this iterator
+    /// has gotten). It starts there directly, without generating the
+    /// expansions before it.
+    pub fn range_iter(&self, range: Range<usize>) -> BraceExpandRangeIterator {
+        BraceExpandRangeIterator::new(&self.ast, self.checksum, range, self.num_expansions())
+    }
+
+    /// The structure of the pattern, as a Graphviz DOT graph.
+    pub fn to_dot(&self) -> String {
+        ast_to_dot(&self.ast)
+    }
+
+    /// A normalized pattern that parses to the same thing as this one
+    /// (with the same `escape` setting). Literal braces, commas and so on
+    /// are re-escaped if `escape` is true, and the checksum (if any) is
+    /// written as its marker.
+    pub fn to_pattern(&self, escape: bool) -> String {
+        let mut pattern = ast_to_pattern(&self.ast, escape);
+        if let Some(checksum) = &self.checksum {
+            pattern.push_str(&checksum.marker());
+        }
+        pattern
+    }
+
+    /// Consumes the remaining expansions and groups them by length (in
+    /// bytes). Buckets are yielded in ascending length order, and each
+    /// one keeps its expansions in their original order.
+    pub fn by_length(self) -> impl Iterator<Item = (usize, Vec<String>)> {
+        let mut buckets: BTreeMap<usize, Vec<String>> = ast_length_distribution(&self.ast)
+            .into_iter()
+            .map(|(length, count)| (length, Vec::with_capacity(count)))
+            .collect();
+        for s in self {
+            buckets.entry(s.len()).or_default().push(s);
+        }
+        buckets.into_iter().filter(|(_, v)| !v.is_empty())
+    }
+
+    /// Skips any expansions that have already been produced (like the
+    /// second "a" in "{a,a,b}").
+    pub fn dedup(self) -> BraceExpandDedupIterator {
+        BraceExpandDedupIterator::new(self)
+    }
+
+    /// Skips any expansions whose length (in bytes) isn't between `min`
+    /// and `max`, inclusive.
+    pub fn with_length_filter(self, min: usize, max: usize) -> BraceExpandLengthFilterIterator {
+        BraceExpandLengthFilterIterator{inner: self, min, max}
     }
 }
 
@@ -46,24 +357,365 @@ impl Iterator for BraceExpandIterator {
     type Item = String;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.is_done {
+        if self.is_exhausted() {
             return None;
         }
         let mut output = String::new();
         self.state_machine.fill(&mut output);
-        self.is_done = !self.state_machine.advance();
+        self.advance();
         Some(output)
     }
+
+    /// Exact, unless the pattern has more than usize::MAX expansions.
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.remaining();
+        (remaining, Some(remaining))
+    }
+}
+
+impl DoubleEndedIterator for BraceExpandIterator {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.is_exhausted() {
+            return None;
+        }
+        let back_state_machine = self.back_state_machine.get_or_insert_with(|| {
+            let mut sm = AstStateMachine::new(&self.ast).with_checksum(self.checksum);
+            sm.reset_to_end();
+            sm
+        });
+        let mut output = String::new();
+        back_state_machine.fill(&mut output);
+        // Running out at the back means the front has nothing left either
+        self.is_done = !back_state_machine.retreat();
+        self.num_produced_back += 1;
+        Some(output)
+    }
+}
+
+impl ExactSizeIterator for BraceExpandIterator {}
+
+/// Parses a pattern, along with the checksum marked at the end (if any).
+pub(crate) fn parse_pattern(input: &str, escape: bool) -> Result<(Ast, Option<Checksum>), BraceExpandError> {
+    match parse_checksum_marker(input, escape) {
+        Some((input, checksum)) => Ok((ast_from_pattern(input, escape)?, Some(checksum))),
+        None => Ok((ast_from_pattern(input, escape)?, None)),
+    }
+}
+
+/// Parses a pattern and iterates over its expansions.
+///
+/// A pattern ending with "#" appends a checksum to every expansion: the
+/// sum of its bytes, mod 10. "#%N" at the end sums mod N instead, and
+/// "#d" sums just the decimal digits, mod 10. (So, for example, a
+/// pattern ending with "#d" doesn't produce a literal "#d". With
+/// `escape`, "\#" is always literal; without it, a pattern can't end
+/// with a literal marker.)
+pub fn brace_expand_iter(input: &str, escape: bool) -> Result<BraceExpandIterator, BraceExpandError> {
+    let (ast, checksum) = parse_pattern(input, escape)?;
+    Ok(BraceExpandIterator::new(ast, checksum))
+}
+
+/// Same as brace_expand_iter(), but with configurable metacharacters
+/// (see tokenize_with()). There's no checksum syntax, so a "#" at the end
+/// is literal.
+pub fn brace_expand_iter_with(input: &str, config: &TokenizerConfig) -> Result<BraceExpandIterator, BraceExpandError> {
+    let (tokens, offsets) = tokenize_with_offsets_and_config(input, config);
+    Ok(BraceExpandIterator::new(ast_from_tokens_with_offsets(&tokens, &offsets)?, None))
+}
+
+/// Expands a pattern that's already been tokenized (by tokenize_with(),
+/// for example). As with brace_expand_iter_with(), there's no checksum
+/// syntax. Byte offsets in errors assume that the tokens were written
+/// without any escapes.
+pub fn brace_expand_tokens(tokens: &[Token]) -> Result<BraceExpandIterator, BraceExpandError> {
+    Ok(BraceExpandIterator::new(ast_from_tokens(tokens)?, None))
+}
+
+/// Splits a pattern into up to `max_parts` patterns that together produce
+/// exactly the same expansions, with no overlap between them, by dividing
+/// up the alternatives of the first top-level brace group. (So patterns
+/// with no such group, or with fewer alternatives than `max_parts`,
+/// produce fewer parts.) The parts use the same `escape` setting.
+pub fn split_pattern(input: &str, escape: bool, max_parts: usize) -> Result<Vec<String>, BraceExpandError> {
+    let (ast, checksum) = parse_pattern(input, escape)?;
+
+    let Some(i) = ast.iter().position(|item| matches!(item, AstItem::Choices(_))) else {
+        return Ok(vec![input.to_owned()]);
+    };
+    let AstItem::Choices(alternatives) = &ast[i] else {
+        unreachable!();
+    };
+
+    let num_parts = max_parts.clamp(1, alternatives.len().max(1));
+    let (size, extra) = (alternatives.len() / num_parts, alternatives.len() % num_parts);
+    let mut start = 0;
+    Ok((0..num_parts).map(|part| {
+        let end = start + size + usize::from(part < extra);
+        let mut part_ast = ast.clone();
+        part_ast[i] = AstItem::Choices(alternatives[start..end].to_vec());
+        start = end;
+        let mut part = ast_to_pattern(&part_ast, escape);
+        if let Some(checksum) = &checksum {
+            part.push_str(&checksum.marker());
+        }
+        part
+    }).collect())
+}
+
+// ---------------------------------------------------------------------
+
+/// Yields the expansions of a pattern with indices in a certain range,
+/// in order. Create these with BraceExpandIterator::range_iter().
+#[derive(Debug)]
+pub struct BraceExpandRangeIterator {
+    state_machine: AstStateMachine,
+    remaining: usize,
+}
+
+impl BraceExpandRangeIterator {
+    fn new(ast: &Ast, checksum: Option<Checksum>, range: Range<usize>, num_expansions: usize) -> Self {
+        let mut state_machine = AstStateMachine::new(ast).with_checksum(checksum);
+        let remaining = range.end.min(num_expansions).saturating_sub(range.start);
+        if remaining > 0 {
+            state_machine.seek(range.start);
+        }
+        Self{state_machine, remaining}
+    }
+
+    /// Like BraceExpandIterator::next_into().
+    pub fn next_into(&mut self, output: &mut String) -> bool {
+        if self.remaining == 0 {
+            return false;
+        }
+        output.clear();
+        self.state_machine.fill(output);
+        self.state_machine.advance();
+        self.remaining -= 1;
+        true
+    }
+
+    /// Like BraceExpandIterator::next_hash().
+    pub fn next_hash(&mut self, seed: u32) -> Option<u32> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let hash = self.state_machine.fill_hashed_incremental(seed);
+        self.state_machine.advance();
+        self.remaining -= 1;
+        Some(hash)
+    }
+}
+
+impl Iterator for BraceExpandRangeIterator {
+    type Item = String;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut output = String::new();
+        if self.next_into(&mut output) {
+            Some(output)
+        } else {
+            None
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl ExactSizeIterator for BraceExpandRangeIterator {}
+
+// ---------------------------------------------------------------------
+
+/// Builds a pattern piece by piece. This is the only way to use
+/// LazyChoices, for alternatives that aren't known up front.
+#[derive(Debug, Default)]
+pub struct PatternBuilder {
+    ast: Ast,
+}
+
+impl PatternBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn push(&mut self, item: AstItem) {
+        // Keep Leafs merged, like the parser does
+        if let (Some(AstItem::Leaf(last)), AstItem::Leaf(s)) = (self.ast.last_mut(), &item) {
+            last.push_str(s);
+        } else {
+            self.ast.push(item);
+        }
+    }
+
+    /// Appends a literal string.
+    pub fn literal(mut self, s: &str) -> Self {
+        self.push(AstItem::Leaf(s.to_owned()));
+        self
+    }
+
+    /// Appends a brace expansion pattern (as for brace_expand_iter()).
+    pub fn pattern(mut self, pattern: &str, escape: bool) -> Result<Self, BraceExpandError> {
+        for item in ast_from_pattern(pattern, escape)? {
+            self.push(item);
+        }
+        Ok(self)
+    }
+
+    /// Appends a set of alternatives read lazily from a ChoiceSource.
+    pub fn lazy_choices(mut self, choices: LazyChoices) -> Self {
+        self.push(AstItem::Lazy(choices));
+        self
+    }
+
+    pub fn build(self) -> BraceExpandIterator {
+        BraceExpandIterator::new(self.ast, None)
+    }
+}
+
+// ---------------------------------------------------------------------
+
+/// Like BraceExpandIterator, but yields the djb2 hash of each expansion
+/// instead of the expansion itself. Expansions are built in a reused
+/// scratch buffer, so there's no allocation per item.
+#[derive(Debug)]
+pub struct BraceExpandHashIterator {
+    inner: BraceExpandIterator,
+    scratch: String,
+    seed: u32,
+}
+
+impl BraceExpandHashIterator {
+    fn new(inner: BraceExpandIterator, seed: u32) -> Self {
+        let scratch = String::with_capacity(inner.max_expansion_length());
+        Self{inner, scratch, seed}
+    }
+
+    pub fn max_expansion_length(&self) -> usize {
+        self.inner.max_expansion_length()
+    }
+
+    pub fn num_expansions(&self) -> usize {
+        self.inner.num_expansions()
+    }
+}
+
+impl Iterator for BraceExpandHashIterator {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.inner.next_into(&mut self.scratch) {
+            Some(hash_djb2(self.scratch.as_bytes(), self.seed))
+        } else {
+            None
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl ExactSizeIterator for BraceExpandHashIterator {}
+
+// ---------------------------------------------------------------------
+
+/// Like BraceExpandIterator, but skips expansions that have already been
+/// produced. Every distinct expansion is kept in memory, so this is best
+/// for patterns that aren't too huge.
+#[derive(Debug)]
+pub struct BraceExpandDedupIterator {
+    inner: BraceExpandIterator,
+    seen: HashSet<String>,
+}
+
+impl BraceExpandDedupIterator {
+    fn new(inner: BraceExpandIterator) -> Self {
+        Self{inner, seen: HashSet::new()}
+    }
+
+    /// Like BraceExpandIterator::next_into().
+    pub fn next_into(&mut self, s: &mut String) -> bool {
+        while self.inner.next_into(s) {
+            if !self.seen.contains(s.as_str()) {
+                self.seen.insert(s.clone());
+                return true;
+            }
+        }
+        false
+    }
+
+    pub fn max_expansion_length(&self) -> usize {
+        self.inner.max_expansion_length()
+    }
+
+    /// An upper bound on the number of (distinct) expansions.
+    pub fn num_expansions(&self) -> usize {
+        self.inner.num_expansions()
+    }
+}
+
+impl Iterator for BraceExpandDedupIterator {
+    type Item = String;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut output = String::new();
+        self.next_into(&mut output).then_some(output)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.inner.size_hint().1)
+    }
+}
+
+// ---------------------------------------------------------------------
+
+/// Like BraceExpandIterator, but only yields expansions within a range of
+/// lengths. Lengths are checked in the reused buffer, so skipped
+/// expansions are never allocated.
+#[derive(Debug)]
+pub struct BraceExpandLengthFilterIterator {
+    inner: BraceExpandIterator,
+    min: usize,
+    max: usize,
+}
+
+impl BraceExpandLengthFilterIterator {
+    /// Like BraceExpandIterator::next_into().
+    pub fn next_into(&mut self, s: &mut String) -> bool {
+        while self.inner.next_into(s) {
+            if (self.min..=self.max).contains(&s.len()) {
+                return true;
+            }
+        }
+        false
+    }
+
+    pub fn max_expansion_length(&self) -> usize {
+        self.inner.max_expansion_length().min(self.max)
+    }
+
+    /// An upper bound on the number of expansions that pass the filter.
+    pub fn num_expansions(&self) -> usize {
+        self.inner.num_expansions()
+    }
 }
 
-// TODO: proper error return type
-pub fn brace_expand_iter(input: &str, escape: bool) -> Result<BraceExpandIterator, Box<dyn Error>> {
-    let tokens = tokenize(input, escape);
-    let ast = ast_from_tokens(&tokens)?;
-    let size_hint = ast_max_expansion_length(&ast);
-    let num_expansions_hint = ast_num_expansions(&ast);
-    let sm = AstStateMachine::new(&ast);
-    Ok(BraceExpandIterator::new(sm, size_hint, num_expansions_hint))
+impl Iterator for BraceExpandLengthFilterIterator {
+    type Item = String;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut output = String::with_capacity(self.inner.max_expansion_length());
+        self.next_into(&mut output).then_some(output)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.inner.size_hint().1)
+    }
+}
+
+pub fn brace_expand_hash_iter(input: &str, escape: bool, seed: u32) -> Result<BraceExpandHashIterator, BraceExpandError> {
+    Ok(BraceExpandHashIterator::new(brace_expand_iter(input, escape)?, seed))
 }
 
 
@@ -141,4 +793,581 @@ mod tests {
         assert_eq!(&output, "ad");
         assert!(!iter.next_into(&mut output));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_by_length() {
+        let output: Vec<(usize, Vec<String>)> = brace_expand_iter("{a,bb}{c,dd}", true).unwrap().by_length().collect();
+
+        assert_eq!(output, vec![
+            (2, vec!["ac".to_owned()]),
+            (3, vec!["add".to_owned(), "bbc".to_owned()]),
+            (4, vec!["bbdd".to_owned()]),
+        ]);
+    }
+
+    #[test]
+    fn test_next_hash() {
+        let expected: Vec<u32> = brace_expand_iter("{a,b}c{e,f{g,h}}", true).unwrap()
+            .map(|s| hash_djb2(s.as_bytes(), 0x1505))
+            .collect();
+
+        let mut iter = brace_expand_iter("{a,b}c{e,f{g,h}}", true).unwrap();
+        let mut hashes = Vec::new();
+        while let Some(hash) = iter.next_hash(0x1505) {
+            hashes.push(hash);
+        }
+
+        assert_eq!(hashes, expected);
+    }
+
+    #[test]
+    fn test_hash_iter() {
+        let pattern = "{a,b}c{e,f{g,h}}";
+        let expected: Vec<u32> = brace_expand_iter(pattern, true).unwrap()
+            .map(|s| hash_djb2(s.as_bytes(), 0x12345678))
+            .collect();
+
+        let iter = brace_expand_hash_iter(pattern, true, 0x12345678).unwrap();
+        assert_eq!(iter.num_expansions(), 6);
+        assert_eq!(iter.max_expansion_length(), 4);
+        assert_eq!(iter.collect::<Vec<u32>>(), expected);
+    }
+
+    #[test]
+    fn test_ranges() {
+        let output: Vec<String> = brace_expand_iter("{1..3}", true).unwrap().collect();
+        assert_eq!(output, vec!["1", "2", "3"]);
+
+        let output: Vec<String> = brace_expand_iter("{3..1}", true).unwrap().collect();
+        assert_eq!(output, vec!["3", "2", "1"]);
+
+        let iter = brace_expand_iter("a{1..3}b", true).unwrap();
+        assert_eq!(iter.num_expansions(), 3);
+        assert_eq!(iter.max_expansion_length(), 3);
+        assert_eq!(iter.collect::<Vec<String>>(), vec!["a1b", "a2b", "a3b"]);
+
+        let iter = brace_expand_iter("{9..10}{a,b}", true).unwrap();
+        assert_eq!(iter.max_expansion_length(), 3);
+        assert_eq!(iter.collect::<Vec<String>>(), vec!["9a", "9b", "10a", "10b"]);
+    }
+
+    #[test]
+    fn test_custom_metacharacters() {
+        let config = TokenizerConfig{open: '<', close: '>', sep: ';', escape: Some('%')};
+        let output: Vec<String> = brace_expand_iter_with("a<b;c%;>{d,e}#", &config).unwrap().collect();
+        assert_eq!(output, vec!["ab{d,e}#", "ac;{d,e}#"]);
+        assert_eq!(brace_expand_iter_with("a<b;c", &config).err(), Some(BraceExpandError::UnbalancedBrace(1)));
+
+        let output: Vec<String> = brace_expand_tokens(&tokenize_with("<x;y><1..2>", &config)).unwrap().collect();
+        assert_eq!(output, vec!["x1", "x2", "y1", "y2"]);
+        assert_eq!(brace_expand_tokens(&[Token::CloseBrace]).err(), Some(BraceExpandError::UnbalancedBrace(0)));
+    }
+
+    #[test]
+    fn test_expand_streamed_tokens() {
+        let tokens: Vec<Token> = tokenize_reader("a{b,c\\,}{1..2}".as_bytes(), true).collect::<std::io::Result<_>>().unwrap();
+        let output: Vec<String> = brace_expand_tokens(&tokens).unwrap().collect();
+        assert_eq!(output, vec!["ab1", "ab2", "ac,1", "ac,2"]);
+    }
+
+    #[test]
+    fn test_radix_ranges() {
+        let output: Vec<String> = brace_expand_iter("{0x0e..0x11}", true).unwrap().collect();
+        assert_eq!(output, vec!["0e", "0f", "10", "11"]);
+
+        let output: Vec<String> = brace_expand_iter("a{0b00..0b11}b", true).unwrap().collect();
+        assert_eq!(output, vec!["a00b", "a01b", "a10b", "a11b"]);
+
+        assert_eq!(brace_expand_iter("{0x0e..0b11}", true).err(),
+            Some(BraceExpandError::MismatchedRangeBases("0x0e..0b11".to_owned())));
+    }
+
+    #[test]
+    fn test_lazy_choices() {
+        let mut words = vec!["c".to_owned(), "b".to_owned(), "a".to_owned()];
+        let choices = LazyChoices::with_count(move || words.pop(), 3);
+        let iter = PatternBuilder::new()
+            .pattern("{x,y}", true).unwrap()
+            .literal("_")
+            .lazy_choices(choices.clone())
+            .literal("!")
+            .build();
+
+        assert_eq!(iter.num_expansions(), 6);
+        // Nothing needed to be read for that
+        assert_eq!(format!("{choices:?}"), "LazyChoices { num_read: 0, num_choices: Some(3), .. }");
+        assert!(iter.to_dot().contains("[label=\"{...}\", shape=diamond, style=dashed]"));
+
+        let output: Vec<String> = iter.collect();
+        assert_eq!(output, vec!["x_a!", "x_b!", "x_c!", "y_a!", "y_b!", "y_c!"]);
+    }
+
+    #[test]
+    fn test_lazy_choices_without_count() {
+        let mut n = 0;
+        let builder = || PatternBuilder::new().literal("v").lazy_choices(LazyChoices::new(move || {
+            n += 1;
+            (n <= 3).then(|| n.to_string())
+        }));
+
+        let iter = builder().build();
+        assert_eq!(iter.num_expansions(), 3);
+        assert_eq!(iter.max_expansion_length(), 2);
+        assert_eq!(iter.common_prefix(), "v");
+        assert_eq!(iter.nth_expansion(2), Some("v3".to_owned()));
+
+        let mut iter = builder().build();
+        let expected: Vec<u32> = ["v1", "v2", "v3"].iter().map(|s| hash_djb2(s.as_bytes(), 0x12345678)).collect();
+        let hashes: Vec<u32> = std::iter::from_fn(|| iter.next_hash(0x12345678)).collect();
+        assert_eq!(hashes, expected);
+    }
+
+    #[test]
+    fn test_empty_lazy_choices() {
+        let builders: [fn() -> PatternBuilder; 2] = [
+            || PatternBuilder::new().literal("v").lazy_choices(LazyChoices::new(|| None)),
+            || PatternBuilder::new().pattern("{a,b}", true).unwrap().lazy_choices(LazyChoices::with_count(|| None, 0)).literal("x"),
+        ];
+        for builder in builders {
+            let iter = builder().build();
+            assert_eq!(iter.num_expansions(), 0);
+            assert_eq!(iter.remaining(), 0);
+            assert_eq!(iter.nth_expansion(0), None);
+            assert_eq!(iter.collect::<Vec<String>>(), Vec::<String>::new());
+
+            assert_eq!(builder().build().next_back(), None);
+            assert_eq!(builder().build().next_hash(0), None);
+            assert_eq!(builder().build().range_iter(0..5).count(), 0);
+
+            let mut iter = builder().build();
+            iter.skip_to(0);
+            assert_eq!(iter.next(), None);
+            iter.reset();
+            assert_eq!(iter.next(), None);
+        }
+    }
+
+    #[test]
+    fn test_double_ended() {
+        let pattern = "{a,b}c{d,e}";
+        let forward: Vec<String> = brace_expand_iter(pattern, true).unwrap().collect();
+        assert_eq!(forward, vec!["acd", "ace", "bcd", "bce"]);
+        let backward: Vec<String> = brace_expand_iter(pattern, true).unwrap().rev().collect();
+        assert_eq!(backward, vec!["bce", "bcd", "ace", "acd"]);
+
+        let mut iter = brace_expand_iter(pattern, true).unwrap();
+        assert_eq!(iter.next().as_deref(), Some("acd"));
+        assert_eq!(iter.next_back().as_deref(), Some("bce"));
+        assert_eq!(iter.len(), 2);
+        assert_eq!(iter.next_back().as_deref(), Some("bcd"));
+        assert_eq!(iter.next().as_deref(), Some("ace"));
+        assert_eq!(iter.next(), None);
+        assert_eq!(iter.next_back(), None);
+
+        // Meeting in the middle of a bigger pattern, with an odd count
+        let pattern = "{x,y{1..3}}{,z{a..c}}";
+        let forward: Vec<String> = brace_expand_iter(pattern, true).unwrap().collect();
+        let mut iter = brace_expand_iter(pattern, true).unwrap();
+        let (mut front, mut back) = (Vec::new(), Vec::new());
+        while let Some(s) = iter.next() {
+            front.push(s);
+            let Some(s) = iter.next_back() else { break };
+            back.push(s);
+        }
+        assert_eq!(forward.len(), 16);
+        back.reverse();
+        front.extend(back);
+        assert_eq!(front, forward);
+    }
+
+    #[test]
+    fn test_nth_expansion() {
+        let pattern = "{a,b{c,d{e,}}}x{,y{1..3..2}}{p..q}{0x0e..0x10}";
+        let iter = brace_expand_iter(pattern, true).unwrap();
+        let expected: Vec<String> = brace_expand_iter(pattern, true).unwrap().collect();
+        assert_eq!(expected.len(), 4 * 3 * 2 * 3);
+
+        let mut s = "leftover".to_owned();
+        for (k, expansion) in expected.iter().enumerate() {
+            assert!(iter.nth_expansion_into(k, &mut s));
+            assert_eq!(&s, expansion, "{k}");
+            assert_eq!(iter.nth_expansion(k).as_ref(), Some(expansion));
+        }
+        assert!(!iter.nth_expansion_into(expected.len(), &mut s));
+        assert_eq!(iter.nth_expansion(usize::MAX), None);
+    }
+
+    #[test]
+    fn test_split_ranges() {
+        let pattern = "{a,b{c,d{e,}}}x{,y{1..3..2}}{p..q}";
+        let iter = brace_expand_iter(pattern, true).unwrap();
+        let expected: Vec<String> = brace_expand_iter(pattern, true).unwrap().collect();
+        assert_eq!(expected.len(), 24);
+
+        for n in [1, 5, 7, 24, 30] {
+            let ranges = iter.split_ranges(n);
+            assert_eq!(ranges.len(), n);
+            assert_eq!(ranges.first().unwrap().start, 0);
+            assert_eq!(ranges.last().unwrap().end, 24);
+            assert!(ranges.iter().all(|r| r.len() == 24 / n || r.len() == 24 / n + 1));
+
+            let mut output = Vec::new();
+            for range in ranges {
+                let sub_iter = iter.range_iter(range.clone());
+                assert_eq!(sub_iter.len(), range.len());
+                output.extend(sub_iter);
+            }
+            assert_eq!(output, expected, "{n}");
+        }
+        assert!(iter.split_ranges(0).is_empty());
+
+        // Ranges past the end are cut short
+        assert_eq!(iter.range_iter(22..100).collect::<Vec<String>>(), expected[22..]);
+        assert_eq!(iter.range_iter(30..40).next(), None);
+
+        let hashes: Vec<u32> = {
+            let mut sub_iter = iter.range_iter(5..9);
+            std::iter::from_fn(|| sub_iter.next_hash(0x12345678)).collect()
+        };
+        assert_eq!(hashes, expected[5..9].iter().map(|s| hash_djb2(s.as_bytes(), 0x12345678)).collect::<Vec<u32>>());
+    }
+
+    #[test]
+    fn test_range_iters_on_threads() {
+        let iter = brace_expand_iter("{a..z}{0..99}", true).unwrap();
+        let expected: Vec<String> = brace_expand_iter("{a..z}{0..99}", true).unwrap().collect();
+
+        let outputs: Vec<Vec<String>> = std::thread::scope(|scope| {
+            let handles: Vec<_> = iter.split_ranges(4).into_iter()
+                .map(|range| {
+                    let sub_iter = iter.range_iter(range);
+                    scope.spawn(move || sub_iter.collect())
+                })
+                .collect();
+            handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+        });
+        assert_eq!(outputs.concat(), expected);
+    }
+
+    #[test]
+    fn test_to_pattern() {
+        for pattern in ["a{b,{c,d}e}f", "x\\,{y\\{,}z#", "{1..3}{,a,,b,}"] {
+            let normalized = brace_expand_iter(pattern, true).unwrap().to_pattern(true);
+            assert_eq!(normalized, pattern);
+        }
+        assert_eq!(brace_expand_iter("a{b,c}", true).unwrap().with_checksum(Some(Checksum::DigitSum)).to_pattern(true), "a{b,c}#d");
+        assert_eq!(brace_expand_iter("a{b,c}#%256", true).unwrap().to_pattern(true), "a{b,c}#%256");
+    }
+
+    #[test]
+    fn test_split_pattern() {
+        let expand_all = |patterns: &[String]| -> Vec<String> {
+            let mut v: Vec<String> = patterns.iter().flat_map(|p| brace_expand_iter(p, true).unwrap()).collect();
+            v.sort();
+            v
+        };
+
+        let pattern = "{a,b,c}x{y,z}";
+        let parts = split_pattern(pattern, true, 3).unwrap();
+        assert_eq!(parts, vec!["{a}x{y,z}", "{b}x{y,z}", "{c}x{y,z}"]);
+        assert_eq!(expand_all(&parts), expand_all(&[pattern.to_owned()]));
+
+        let pattern = "p{1..3}{a,b{c,d},e,\\,,,f}q";
+        for max_parts in [0, 1, 2, 4, 6, 100] {
+            let parts = split_pattern(pattern, true, max_parts).unwrap();
+            assert_eq!(parts.len(), max_parts.clamp(1, 6));
+            assert_eq!(expand_all(&parts), expand_all(&[pattern.to_owned()]), "{max_parts}");
+        }
+
+        // Single alternatives that look like ranges mustn't become ranges
+        for pattern in ["v{1..3,x}", "{a..c,x}", "{x,1..10..2}{y,z}"] {
+            for max_parts in [2, 3] {
+                let parts = split_pattern(pattern, true, max_parts).unwrap();
+                assert_eq!(expand_all(&parts), expand_all(&[pattern.to_owned()]), "{pattern} {max_parts}");
+            }
+        }
+        assert_eq!(split_pattern("v{1..3,x}", true, 2).unwrap(), vec!["v1..3", "v{x}"]);
+
+        assert_eq!(split_pattern("abc{1..3}", true, 5).unwrap(), vec!["abc{1..3}"]);
+        assert!(split_pattern("a}b", true, 2).is_err());
+    }
+
+    #[test]
+    fn test_num_expansions_checked() {
+        let iter = brace_expand_iter(&"{a,b}".repeat(64), true).unwrap();
+        assert_eq!(iter.num_expansions_checked(), None);
+        assert_eq!(iter.num_expansions(), usize::MAX);
+
+        let iter = brace_expand_iter(&"{a,b}".repeat(10), true).unwrap();
+        assert_eq!(iter.num_expansions_checked(), Some(1024));
+    }
+
+    #[test]
+    fn test_dedup() {
+        let output: Vec<String> = brace_expand_iter("{a,a,b}", true).unwrap().dedup().collect();
+        assert_eq!(output, vec!["a", "b"]);
+
+        let iter = brace_expand_iter("a{,b,}", true).unwrap().dedup();
+        assert_eq!(iter.num_expansions(), 3);
+        assert_eq!(iter.size_hint(), (0, Some(3)));
+        let output: Vec<String> = iter.collect();
+        assert_eq!(output, vec!["a", "ab"]);
+
+        let mut iter = brace_expand_iter("a{,b}{,b}", true).unwrap().dedup();
+        let mut output = String::new();
+        assert!(iter.next_into(&mut output));
+        assert_eq!(output, "a");
+        assert!(iter.next_into(&mut output));
+        assert_eq!(output, "ab");
+        assert!(iter.next_into(&mut output));
+        assert_eq!(output, "abb");
+        assert!(!iter.next_into(&mut output));
+    }
+
+    #[test]
+    fn test_length_filter() {
+        let output: Vec<String> = brace_expand_iter("{a,bb,ccc}", true).unwrap().with_length_filter(2, 2).collect();
+        assert_eq!(output, vec!["bb"]);
+
+        let output: Vec<String> = brace_expand_iter("x{,a,bb}{,c}", true).unwrap().with_length_filter(2, 3).collect();
+        assert_eq!(output, vec!["xc", "xa", "xac", "xbb"]);
+
+        let mut iter = brace_expand_iter("{a,bb,ccc}", true).unwrap().with_length_filter(4, 10);
+        assert_eq!(iter.next(), None);
+        let iter = brace_expand_iter("{a,bb,ccc}", true).unwrap().with_length_filter(0, 2);
+        assert_eq!(iter.max_expansion_length(), 2);
+        assert_eq!(iter.size_hint(), (0, Some(3)));
+    }
+
+    #[test]
+    fn test_next_into_bytes() {
+        let mut iter = brace_expand_iter("a{b,c}d", true).unwrap();
+        let mut output = Vec::new();
+        assert!(iter.next_into_bytes(&mut output));
+        assert_eq!(output, b"abd");
+        assert!(iter.next_into_bytes(&mut output));
+        assert_eq!(output, b"acd");
+        assert!(!iter.next_into_bytes(&mut output));
+
+        let expected: Vec<String> = brace_expand_iter("x{8..11}{a..c}#", true).unwrap().collect();
+        let mut iter = brace_expand_iter("x{8..11}{a..c}#", true).unwrap();
+        for s in expected {
+            assert!(iter.next_into_bytes(&mut output));
+            assert_eq!(output, s.as_bytes());
+        }
+        assert!(!iter.next_into_bytes(&mut output));
+    }
+
+    #[test]
+    fn test_write_all_to() {
+        let mut output = Vec::new();
+        assert_eq!(brace_expand_iter("{a,b}", true).unwrap().write_all_to(b'\n', &mut output).unwrap(), 2);
+        assert_eq!(output, b"a\nb\n");
+
+        let mut iter = brace_expand_iter("x{1..3}", true).unwrap();
+        iter.next();
+        let mut output = Vec::new();
+        assert_eq!(iter.write_all_to(0, &mut output).unwrap(), 2);
+        assert_eq!(output, b"x2\0x3\0");
+    }
+
+    #[test]
+    fn test_checksum() {
+        // 97 + 98 + 99 = 294
+        let output: Vec<String> = brace_expand_iter("abc#", true).unwrap().collect();
+        assert_eq!(output, vec!["abc4"]);
+
+        let mut iter = brace_expand_iter("{a,b,c}x#", true).unwrap();
+        assert_eq!(iter.max_expansion_length(), 3);
+        assert_eq!(iter.nth_expansion(1), Some("bx8".to_owned()));
+        assert_eq!(iter.next_back(), Some("cx9".to_owned()));
+        assert_eq!(iter.next_hash(0), Some(hash_djb2(b"ax7", 0)));
+        let ranged: Vec<String> = iter.range_iter(1..3).collect();
+        assert_eq!(ranged, vec!["bx8", "cx9"]);
+        assert_eq!(split_pattern("{a,b,c}x#", true, 2).unwrap(), vec!["{a,b}x#", "{c}x#"]);
+
+        let output: Vec<String> = brace_expand_iter("a{1,2}b#", true).unwrap()
+            .with_checksum(Some(Checksum::DigitSum))
+            .collect();
+        assert_eq!(output, vec!["a1b1", "a2b2"]);
+        let output: Vec<String> = brace_expand_iter("a{1,2}b#d", true).unwrap().collect();
+        assert_eq!(output, vec!["a1b1", "a2b2"]);
+        // 294 % 256 = 38
+        let output: Vec<String> = brace_expand_iter("abc#%256", true).unwrap().collect();
+        assert_eq!(output, vec!["abc38"]);
+        assert_eq!(split_pattern("{a,b}#d", true, 2).unwrap(), vec!["{a}#d", "{b}#d"]);
+        let output: Vec<String> = brace_expand_iter("ab#", true).unwrap().with_checksum(None).collect();
+        assert_eq!(output, vec!["ab"]);
+
+        // Escaped, it's just a "#"
+        let output: Vec<String> = brace_expand_iter("abc\\#", true).unwrap().collect();
+        assert_eq!(output, vec!["abc#"]);
+        let output: Vec<String> = brace_expand_iter("abc\\#d", true).unwrap().collect();
+        assert_eq!(output, vec!["abc#d"]);
+        // (Only these exact markers count)
+        let output: Vec<String> = brace_expand_iter("abc#x", true).unwrap().collect();
+        assert_eq!(output, vec!["abc#x"]);
+    }
+
+    #[test]
+    fn test_size_hint() {
+        let mut iter = brace_expand_iter("{a,b}c{d,e}", true).unwrap();
+        assert_eq!(iter.size_hint(), (4, Some(4)));
+        iter.next();
+        assert_eq!(iter.size_hint(), (3, Some(3)));
+        let mut s = String::new();
+        iter.next_into(&mut s);
+        assert_eq!(iter.len(), 2);
+        iter.next_hash(0);
+        iter.next();
+        assert_eq!(iter.size_hint(), (0, Some(0)));
+        assert_eq!(iter.next(), None);
+        assert_eq!(iter.len(), 0);
+
+        let mut iter = brace_expand_hash_iter("x{1..10..2}", true, 0).unwrap();
+        assert_eq!(iter.len(), 5);
+        iter.next();
+        assert_eq!(iter.len(), 4);
+        assert_eq!(iter.count(), 4);
+    }
+
+    #[test]
+    fn test_stepped_ranges() {
+        let output: Vec<String> = brace_expand_iter("{1..10..2}", true).unwrap().collect();
+        assert_eq!(output, vec!["1", "3", "5", "7", "9"]);
+
+        let iter = brace_expand_iter("x{10..1..3}y", true).unwrap();
+        assert_eq!(iter.num_expansions(), 4);
+        let output: Vec<String> = iter.collect();
+        assert_eq!(output, vec!["x10y", "x7y", "x4y", "x1y"]);
+
+        let hashes: Vec<u32> = brace_expand_hash_iter("x{10..1..3}y", true, 0x12345678).unwrap().collect();
+        assert_eq!(hashes, ["x10y", "x7y", "x4y", "x1y"].map(|s| hash_djb2(s.as_bytes(), 0x12345678)));
+
+        assert_eq!(brace_expand_iter("{1..10..0}", true).err(),
+            Some(BraceExpandError::ZeroRangeStep("1..10..0".to_owned())));
+    }
+
+    #[test]
+    fn test_unbalanced_braces() {
+        assert_eq!(brace_expand_iter("x{a,{b,c}", true).err(), Some(BraceExpandError::UnbalancedBrace(1)));
+        assert_eq!(brace_expand_iter("x{a,b}}", false).err(), Some(BraceExpandError::UnbalancedBrace(6)));
+        assert_eq!(BraceExpandError::UnbalancedBrace(1).to_string(), "unbalanced brace at byte 1");
+
+        let output: Vec<String> = brace_expand_iter("{a,{b,{c,d}e}f}", true).unwrap().collect();
+        assert_eq!(output, vec!["a", "bf", "cef", "def"]);
+    }
+
+    #[test]
+    fn test_char_ranges() {
+        let output: Vec<String> = brace_expand_iter("x{a..c}", true).unwrap().collect();
+        assert_eq!(output, vec!["xa", "xb", "xc"]);
+
+        let output: Vec<String> = brace_expand_iter("{c..a}{1..2}", true).unwrap().collect();
+        assert_eq!(output, vec!["c1", "c2", "b1", "b2", "a1", "a2"]);
+
+        // Multi-character bounds aren't ranges
+        let output: Vec<String> = brace_expand_iter("{aa..bb}", true).unwrap().collect();
+        assert_eq!(output, vec!["aa..bb"]);
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut iter = brace_expand_iter("{a,b}", true).unwrap();
+        let output: Vec<String> = iter.by_ref().collect();
+        assert_eq!(output, vec!["a", "b"]);
+        assert_eq!(iter.next(), None);
+
+        iter.reset();
+        assert_eq!(iter.len(), 2);
+        let output: Vec<String> = iter.by_ref().collect();
+        assert_eq!(output, vec!["a", "b"]);
+
+        // Also after iterating from the back
+        iter.reset();
+        assert_eq!(iter.next_back().as_deref(), Some("b"));
+        iter.reset();
+        let output: Vec<String> = iter.collect();
+        assert_eq!(output, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn test_skip_to() {
+        let expected: Vec<String> = brace_expand_iter("{a..c}{1..4}#", true).unwrap().collect();
+        let mut iter = brace_expand_iter("{a..c}{1..4}#", true).unwrap();
+        for n in [5, 0, 11, 7] {
+            iter.skip_to(n);
+            assert_eq!(iter.len(), 12 - n);
+            let output: Vec<String> = iter.by_ref().collect();
+            assert_eq!(output, expected[n..]);
+        }
+
+        iter.skip_to(12);
+        assert_eq!(iter.len(), 0);
+        assert_eq!(iter.next(), None);
+        iter.skip_to(100);
+        assert_eq!(iter.next(), None);
+    }
+
+    #[test]
+    fn test_set_pattern() {
+        let mut iter = brace_expand_iter("{a,b}c{d,e}", true).unwrap();
+        iter.next();
+        iter.set_pattern("x{1..3}#", true).unwrap();
+        assert_eq!(iter.num_expansions(), 3);
+        assert_eq!(iter.max_expansion_length(), 3);
+        let output: Vec<String> = iter.by_ref().collect();
+        assert_eq!(output, vec!["x19", "x20", "x31"]);
+
+        assert!(iter.set_pattern("x{", true).is_err());
+        iter.reset();
+        assert_eq!(iter.count(), 3);
+    }
+
+    #[test]
+    fn test_find_by_hash() {
+        let pattern = "{a,b,c,d,e,f,g,h}{1..100}{x,y,z}__10dWmActor_cFv";
+        let target = hash_djb2(b"f42y__10dWmActor_cFv", 0x1505);
+        let iter = brace_expand_iter(pattern, true).unwrap();
+        let result = iter.find_by_hash(0x1505, &HashSet::from([target])).unwrap();
+        assert_eq!(result.matches, vec!["f42y__10dWmActor_cFv"]);
+        // (8 forwards to get to {1..100}, 1 + 3 + 300 backwards from the
+        // target to there, and 100 + 3 + 1 to follow the match, vs. 2400)
+        assert_eq!(result.num_hashed, 416);
+
+        let iter = brace_expand_iter("{a,b}#", true).unwrap();
+        assert_eq!(iter.find_by_hash(0x1505, &HashSet::from([target])), None);
+    }
+
+    #[test]
+    fn test_peek_into() {
+        let mut iter = brace_expand_iter("{a,b}", true).unwrap();
+        let mut s = String::new();
+        assert!(iter.peek_into(&mut s));
+        assert_eq!(s, "a");
+        assert!(iter.peek_into(&mut s));
+        assert_eq!(s, "a");
+        assert_eq!(iter.len(), 2);
+
+        assert!(iter.next_into(&mut s));
+        assert_eq!(s, "a");
+        assert!(iter.peek_into(&mut s));
+        assert_eq!(s, "b");
+        assert!(iter.next_into(&mut s));
+        assert_eq!(s, "b");
+        assert!(!iter.peek_into(&mut s));
+        assert!(!iter.next_into(&mut s));
+    }
+
+    #[test]
+    fn test_clone() {
+        let mut iter = brace_expand_iter("{a,b}{c,{d,e}f}{1..2}", true).unwrap();
+        iter.next();
+        iter.next();
+        let fork = iter.clone();
+        let remaining: Vec<String> = iter.collect();
+        assert_eq!(remaining, vec!["adf1", "adf2", "aef1", "aef2", "bc1", "bc2", "bdf1", "bdf2", "bef1", "bef2"]);
+        let fork_remaining: Vec<String> = fork.collect();
+        assert_eq!(fork_remaining, remaining);
+    }
+}
@@ -0,0 +1,486 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use crate::error::BraceExpandError;
+
+
+/// Ranges with more values than this (and a step other than 1) have their
+/// alphabet estimated instead of checked value-by-value.
+const MAX_ALPHABET_SCAN_LEN: u64 = 0x10000;
+
+
+/// How the values in a BraceRange are written.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RangeKind {
+    /// Numbers in the given radix (2, 10 or 16), zero-padded to at least
+    /// `width` digits
+    Numeric{radix: u32, width: usize},
+    /// Single ASCII characters (the values are their byte values)
+    Char,
+}
+
+
+/// A range like "1..5", "0x0e..0x11", "0b000..0b111" or "a..e", with an
+/// optional step ("1..10..2"). `end` is only reached if the step divides
+/// the span evenly.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BraceRange {
+    pub start: u64,
+    pub end: u64,
+    /// Distance between consecutive values (never zero). This is always
+    /// positive: descending ranges step downwards automatically.
+    pub step: u64,
+    pub kind: RangeKind,
+}
+
+
+/// Splits a range bound into its radix and digits, if it looks like a
+/// number ("0x" + hex digits, "0b" + binary digits, or decimal digits).
+fn parse_bound(s: &str) -> Option<(u32, &str)> {
+    let (radix, digits) = if let Some(digits) = s.strip_prefix("0x") {
+        (16, digits)
+    } else if let Some(digits) = s.strip_prefix("0b") {
+        (2, digits)
+    } else {
+        (10, s)
+    };
+
+    if !digits.is_empty() && digits.chars().all(|c| c.is_digit(radix)) {
+        Some((radix, digits))
+    } else {
+        None
+    }
+}
+
+
+/// Parses a character range bound (a single ASCII letter).
+fn parse_char_bound(s: &str) -> Option<u8> {
+    match s.as_bytes() {
+        [c] if c.is_ascii_alphabetic() => Some(*c),
+        _ => None,
+    }
+}
+
+
+impl BraceRange {
+    /// Parses the contents of a brace pair as a range. Returns None if
+    /// it isn't range syntax at all (so it should be treated as a
+    /// literal), or an error if it is, but the range is invalid.
+    pub fn parse(s: &str) -> Option<Result<Self, BraceExpandError>> {
+        let (start, end) = s.split_once("..")?;
+
+        // The step is always decimal, regardless of the bounds' radix
+        let (end, step) = match end.split_once("..") {
+            Some((end, step)) => {
+                if step.is_empty() || !step.bytes().all(|c| c.is_ascii_digit()) {
+                    return None;
+                }
+                match step.parse() {
+                    Ok(0) => return Some(Err(BraceExpandError::ZeroRangeStep(s.to_owned()))),
+                    Ok(step) => (end, step),
+                    Err(_) => return None,
+                }
+            }
+            None => (end, 1),
+        };
+
+        if let (Some(start), Some(end)) = (parse_char_bound(start), parse_char_bound(end)) {
+            // Ranges spanning both cases (like "Z..a") include whatever
+            // punctuation is in between, like in bash
+            return Some(Ok(Self{start: start as u64, end: end as u64, step, kind: RangeKind::Char}));
+        }
+
+        let (start_radix, start_digits) = parse_bound(start)?;
+        let (end_radix, end_digits) = parse_bound(end)?;
+
+        if start_radix != end_radix {
+            return Some(Err(BraceExpandError::MismatchedRangeBases(s.to_owned())));
+        }
+        let radix = start_radix;
+
+        let (Ok(start), Ok(end)) = (u64::from_str_radix(start_digits, radix), u64::from_str_radix(end_digits, radix)) else {
+            return None;
+        };
+
+        // Hex and binary values are always padded to the width of the
+        // literals. Decimal ones are only padded if a literal has a
+        // leading zero (like in bash).
+        let has_leading_zero = |digits: &str| digits.len() > 1 && digits.starts_with('0');
+        let width = if radix != 10 || has_leading_zero(start_digits) || has_leading_zero(end_digits) {
+            start_digits.len().max(end_digits.len())
+        } else {
+            0
+        };
+
+        Some(Ok(Self{start, end, step, kind: RangeKind::Numeric{radix, width}}))
+    }
+
+    /// The number of values in the range (both ends are inclusive).
+    /// This saturates, so the full 0..u64::MAX range is one short.
+    pub fn len(&self) -> u64 {
+        (self.start.abs_diff(self.end) / self.step).saturating_add(1)
+    }
+
+    /// The i-th value in the range (counting from `start`).
+    pub fn value(&self, i: u64) -> u64 {
+        if self.start <= self.end {
+            self.start + i * self.step
+        } else {
+            self.start - i * self.step
+        }
+    }
+
+    /// The last value in the range (which may fall short of `end`).
+    fn last(&self) -> u64 {
+        let span = self.start.abs_diff(self.end) / self.step * self.step;
+        if self.start <= self.end {
+            self.start + span
+        } else {
+            self.start - span
+        }
+    }
+
+    fn lowest(&self) -> u64 {
+        self.start.min(self.last())
+    }
+
+    fn highest(&self) -> u64 {
+        self.start.max(self.last())
+    }
+
+    /// How many values in the range are between `first` and `last`
+    /// (inclusive).
+    fn count_between(&self, first: u64, last: u64) -> u64 {
+        let (lowest, highest) = (self.lowest(), self.highest());
+        let (first, last) = (first.max(lowest), last.min(highest));
+        if first > last {
+            return 0;
+        }
+        // Values are lowest + j * step, so count the j's that fit
+        let first_j = (first - lowest).div_ceil(self.step);
+        let last_j = (last - lowest) / self.step;
+        if first_j > last_j {
+            0
+        } else {
+            (last_j - first_j).saturating_add(1)
+        }
+    }
+
+    /// The radix, and the number of symbols it uses. (Char ranges are
+    /// treated as one "digit" in radix 256.)
+    fn radix(&self) -> u64 {
+        match self.kind {
+            RangeKind::Numeric{radix, ..} => radix as u64,
+            RangeKind::Char => 256,
+        }
+    }
+
+    fn width(&self) -> usize {
+        match self.kind {
+            RangeKind::Numeric{width, ..} => width,
+            RangeKind::Char => 1,
+        }
+    }
+
+    /// Number of digits in a value, without padding.
+    fn num_digits(&self, mut value: u64) -> usize {
+        let mut digits = 1;
+        while value >= self.radix() {
+            value /= self.radix();
+            digits += 1;
+        }
+        digits
+    }
+
+    /// Length of a value once formatted (including padding).
+    pub fn formatted_len(&self, value: u64) -> usize {
+        self.num_digits(value).max(self.width())
+    }
+
+    /// Calls `f` with each byte of a formatted value, in order, without
+    /// allocating.
+    pub fn for_each_byte(&self, value: u64, mut f: impl FnMut(u8)) {
+        if self.kind == RangeKind::Char {
+            f(value as u8);
+            return;
+        }
+
+        let num_digits = self.num_digits(value);
+        for _ in num_digits..self.width() {
+            f(b'0');
+        }
+        let radix = self.radix();
+        let mut place = radix.pow(num_digits as u32 - 1);
+        while place > 0 {
+            f(b"0123456789abcdef"[(value / place % radix) as usize]);
+            place /= radix;
+        }
+    }
+
+    /// Formats a value in the range's radix and width.
+    pub fn format(&self, value: u64) -> String {
+        match self.kind {
+            RangeKind::Numeric{radix: 16, width} => format!("{value:0width$x}"),
+            RangeKind::Numeric{radix: 2, width} => format!("{value:0width$b}"),
+            RangeKind::Numeric{width, ..} => format!("{value:0width$}"),
+            RangeKind::Char => (value as u8 as char).to_string(),
+        }
+    }
+
+    pub fn min_len(&self) -> usize {
+        self.formatted_len(self.lowest())
+    }
+
+    pub fn max_len(&self) -> usize {
+        self.formatted_len(self.highest())
+    }
+
+    /// How many values of each formatted length there are, as a map
+    /// {length: count}. (Counts saturate at usize::MAX.)
+    pub fn length_distribution(&self) -> BTreeMap<usize, usize> {
+        let (lowest, highest) = (self.lowest(), self.highest());
+        let mut dist = BTreeMap::new();
+        for digits in self.num_digits(lowest)..=self.num_digits(highest) {
+            // Values with exactly this many (unpadded) digits
+            let first = if digits == 1 { 0 } else { self.radix().pow(digits as u32 - 1) };
+            let last = self.radix().checked_pow(digits as u32).map_or(u64::MAX, |p| p - 1);
+            let count = self.count_between(first, last);
+            if count == 0 {
+                continue;
+            }
+            let entry = dist.entry(digits.max(self.width())).or_insert(0usize);
+            *entry = entry.saturating_add(usize::try_from(count).unwrap_or(usize::MAX));
+        }
+        dist
+    }
+
+    /// The set of bytes that can appear in formatted values.
+    pub fn alphabet(&self) -> BTreeSet<u8> {
+        let mut alphabet = BTreeSet::new();
+        if self.step == 1 && self.len() >= self.radix() {
+            // Every digit shows up in the last place
+            alphabet.extend(&b"0123456789abcdef"[..self.radix() as usize]);
+        } else if self.len() > MAX_ALPHABET_SCAN_LEN {
+            // Too many values to check individually, so just assume
+            // every digit is used
+            alphabet.extend(&b"0123456789abcdef"[..self.radix() as usize]);
+        } else {
+            for i in 0..self.len() {
+                self.for_each_byte(self.value(i), |c| { alphabet.insert(c); });
+            }
+        }
+        alphabet
+    }
+
+    /// The longest prefix shared by every formatted value.
+    pub fn common_prefix(&self) -> String {
+        let (lowest, highest) = (self.format(self.lowest()), self.format(self.highest()));
+        if lowest.len() != highest.len() {
+            // e.g. 1..10 -- "1" and "10" share a prefix, but "2" doesn't
+            return String::new();
+        }
+        // Same-length values sort lexicographically in numeric order, so
+        // everything in between shares the endpoints' common prefix
+        lowest.bytes().zip(highest.bytes())
+            .take_while(|(a, b)| a == b)
+            .map(|(a, _)| a as char)
+            .collect()
+    }
+
+    /// The longest suffix shared by every formatted value.
+    pub fn common_suffix(&self) -> String {
+        let first = self.format(self.start);
+        if self.len() == 1 {
+            return first;
+        }
+        // The last k digits are the same in every value exactly when the
+        // step is a multiple of radix^k (as long as every value has at
+        // least k digits)
+        let num_digits = (0..=self.min_len())
+            .take_while(|&k| self.radix().checked_pow(k as u32).is_some_and(|p| self.step.is_multiple_of(p)))
+            .last()
+            .unwrap_or(0);
+        first[first.len() - num_digits..].to_owned()
+    }
+}
+
+impl fmt::Display for BraceRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let prefix = match self.kind {
+            RangeKind::Numeric{radix: 16, ..} => "0x",
+            RangeKind::Numeric{radix: 2, ..} => "0b",
+            _ => "",
+        };
+        write!(f, "{prefix}{}..{prefix}{}", self.format(self.start), self.format(self.end))?;
+        if self.step != 1 {
+            write!(f, "..{}", self.step)?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(s: &str) -> Vec<String> {
+        let range = BraceRange::parse(s).unwrap().unwrap();
+        (0..range.len()).map(|i| range.format(range.value(i))).collect()
+    }
+
+    #[test]
+    fn test_not_a_range() {
+        assert_eq!(BraceRange::parse("abc"), None);
+        assert_eq!(BraceRange::parse("1..x"), None);
+        assert_eq!(BraceRange::parse("1.5"), None);
+        assert_eq!(BraceRange::parse("0x..0x1"), None);
+        assert_eq!(BraceRange::parse("0b2..0b3"), None);
+    }
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(expand("0x0e..0x11"), vec!["0e", "0f", "10", "11"]);
+        assert_eq!(expand("0x0..0xA").len(), 11);
+        assert_eq!(expand("0x00..0xff").last().unwrap(), "ff");
+    }
+
+    #[test]
+    fn test_binary() {
+        assert_eq!(expand("0b000..0b011"), vec!["000", "001", "010", "011"]);
+        assert_eq!(expand("0b1..0b11"), vec!["01", "10", "11"]);
+    }
+
+    #[test]
+    fn test_decimal() {
+        assert_eq!(expand("8..11"), vec!["8", "9", "10", "11"]);
+        assert_eq!(expand("08..11"), vec!["08", "09", "10", "11"]);
+        assert_eq!(expand("3..1"), vec!["3", "2", "1"]);
+    }
+
+    #[test]
+    fn test_zero_padded() {
+        assert_eq!(expand("01..10"), vec!["01", "02", "03", "04", "05", "06", "07", "08", "09", "10"]);
+        assert_eq!(expand("001..10")[..3], ["001", "002", "003"]);
+        assert_eq!(expand("001..10").last().unwrap(), "010");
+        assert_eq!(expand("1..10"), vec!["1", "2", "3", "4", "5", "6", "7", "8", "9", "10"]);
+        // Padding comes from either end
+        assert_eq!(expand("10..08"), vec!["10", "09", "08"]);
+    }
+
+    #[test]
+    fn test_for_each_byte() {
+        for s in ["0x0e..0x11", "0b1..0b11", "08..11", "3..1", "0..0", "0x00..0xffffffffffffffff"] {
+            let range = BraceRange::parse(s).unwrap().unwrap();
+            for i in [0, 1, range.len() - 1] {
+                let mut bytes = Vec::new();
+                range.for_each_byte(range.value(i), |c| bytes.push(c));
+                assert_eq!(String::from_utf8(bytes).unwrap(), range.format(range.value(i)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_lengths() {
+        let range = BraceRange::parse("95..1005").unwrap().unwrap();
+        assert_eq!(range.min_len(), 2);
+        assert_eq!(range.max_len(), 4);
+        assert_eq!(range.length_distribution(), BTreeMap::from([(2, 5), (3, 900), (4, 6)]));
+
+        let range = BraceRange::parse("0x0e..0x11").unwrap().unwrap();
+        assert_eq!(range.length_distribution(), BTreeMap::from([(2, 4)]));
+    }
+
+    #[test]
+    fn test_alphabet() {
+        let range = BraceRange::parse("0x0e..0x11").unwrap().unwrap();
+        assert_eq!(range.alphabet(), BTreeSet::from(*b"01ef"));
+        let range = BraceRange::parse("0..20").unwrap().unwrap();
+        assert_eq!(range.alphabet(), BTreeSet::from(*b"0123456789"));
+    }
+
+    #[test]
+    fn test_affixes() {
+        let range = BraceRange::parse("120..129").unwrap().unwrap();
+        assert_eq!(range.common_prefix(), "12");
+        assert_eq!(range.common_suffix(), "");
+        let range = BraceRange::parse("1..10").unwrap().unwrap();
+        assert_eq!(range.common_prefix(), "");
+        let range = BraceRange::parse("0x10..0x30").unwrap().unwrap();
+        assert_eq!(range.common_prefix(), "");
+        let range = BraceRange::parse("0b101..0b111").unwrap().unwrap();
+        assert_eq!(range.common_prefix(), "1");
+        assert_eq!(range.common_suffix(), "");
+        let range = BraceRange::parse("5..5").unwrap().unwrap();
+        assert_eq!((range.common_prefix(), range.common_suffix()), ("5".to_owned(), "5".to_owned()));
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(BraceRange::parse("0x0e..0x11").unwrap().unwrap().to_string(), "0x0e..0x11");
+        assert_eq!(BraceRange::parse("3..1").unwrap().unwrap().to_string(), "3..1");
+    }
+
+    #[test]
+    fn test_chars() {
+        assert_eq!(expand("a..e"), vec!["a", "b", "c", "d", "e"]);
+        assert_eq!(expand("e..a"), vec!["e", "d", "c", "b", "a"]);
+        assert_eq!(expand("Y..b"), vec!["Y", "Z", "[", "\\", "]", "^", "_", "`", "a", "b"]);
+        assert_eq!(BraceRange::parse("aa..bb"), None);
+        assert_eq!(BraceRange::parse("a..5"), None);
+
+        let range = BraceRange::parse("x..v").unwrap().unwrap();
+        assert_eq!(range.length_distribution(), BTreeMap::from([(1, 3)]));
+        assert_eq!(range.alphabet(), BTreeSet::from(*b"vwx"));
+        assert_eq!((range.common_prefix(), range.common_suffix()), (String::new(), String::new()));
+        assert_eq!(range.to_string(), "x..v");
+        let mut bytes = Vec::new();
+        range.for_each_byte(range.value(1), |c| bytes.push(c));
+        assert_eq!(bytes, b"w");
+    }
+
+    #[test]
+    fn test_steps() {
+        assert_eq!(expand("1..10..2"), vec!["1", "3", "5", "7", "9"]);
+        assert_eq!(expand("10..1..3"), vec!["10", "7", "4", "1"]);
+        // Steps are always decimal
+        assert_eq!(expand("0x00..0x20..16"), vec!["00", "10", "20"]);
+        assert_eq!(expand("a..g..3"), vec!["a", "d", "g"]);
+        assert_eq!(expand("3..3..5"), vec!["3"]);
+
+        let range = BraceRange::parse("1..10..4").unwrap().unwrap();
+        assert_eq!(range.len(), 3);
+        assert_eq!(range.to_string(), "1..10..4");
+        assert_eq!(BraceRange::parse("1..10..x"), None);
+        assert_eq!(BraceRange::parse("1..10.."), None);
+        assert_eq!(BraceRange::parse("0x00..0x20..0x10"), None);
+        assert_eq!(BraceRange::parse("1..10..0"), Some(Err(BraceExpandError::ZeroRangeStep("1..10..0".to_owned()))));
+    }
+
+    #[test]
+    fn test_step_lengths_and_affixes() {
+        let range = BraceRange::parse("5..1000..10").unwrap().unwrap();
+        assert_eq!(range.len(), 100);
+        assert_eq!(range.max_len(), 3);
+        assert_eq!(range.length_distribution(), BTreeMap::from([(1, 1), (2, 9), (3, 90)]));
+        assert_eq!(range.common_suffix(), "5");
+        assert_eq!(range.alphabet(), BTreeSet::from(*b"0123456789"));
+
+        let range = BraceRange::parse("100..0..50").unwrap().unwrap();
+        assert_eq!(range.length_distribution(), BTreeMap::from([(1, 1), (2, 1), (3, 1)]));
+        assert_eq!(range.common_suffix(), "0");
+        assert_eq!(range.alphabet(), BTreeSet::from(*b"0150"));
+
+        let range = BraceRange::parse("0200..1900..100").unwrap().unwrap();
+        assert_eq!(range.common_suffix(), "00");
+        assert_eq!(range.common_prefix(), "");
+        assert_eq!(BraceRange::parse("1..9..2").unwrap().unwrap().common_suffix(), "");
+    }
+
+    #[test]
+    fn test_mismatched_bases() {
+        assert_eq!(BraceRange::parse("0x0e..0b11"),
+            Some(Err(BraceExpandError::MismatchedRangeBases("0x0e..0b11".to_owned()))));
+        assert!(matches!(BraceRange::parse("0x0e..17"), Some(Err(_))));
+    }
+}
@@ -1,4 +1,9 @@
-use crate::ast::{Ast, AstItem};
+use djb2_utils::hash_djb2;
+
+use crate::ast::{locate_choice, mixed_radix_digits, Ast, AstItem};
+use crate::checksum::Checksum;
+use crate::lazy::LazyChoices;
+use crate::range::BraceRange;
 
 
 pub trait StateMachine {
@@ -10,13 +15,36 @@ pub trait StateMachine {
     /// provided String.
     fn fill(&self, target: &mut String);
 
+    /// Same as fill(), but appends the bytes to a Vec<u8> instead.
+    fn fill_bytes(&self, target: &mut Vec<u8>);
+
+    /// Calculates the djb2 hash of the string representation of the
+    /// current state, continuing from the provided hash value.
+    fn fill_hashed(&self, seed: u32) -> u32;
+
     /// Advances to the next state. Returns true if the state we
     /// advanced to is valid, false otherwise. When the state becomes
     /// invalid, you'll need to reset() in order to iterate again.
     fn advance(&mut self) -> bool;
+
+    /// Moves the state machine and all of its children to their final
+    /// states, for iterating backwards with retreat().
+    fn reset_to_end(&mut self);
+
+    /// Goes back to the previous state. Returns true if there was one,
+    /// false otherwise. After returning false, the state is unspecified
+    /// until reset() or reset_to_end() is called.
+    fn retreat(&mut self) -> bool;
+
+    /// The total number of states (saturating at usize::MAX).
+    fn num_states(&self) -> usize;
+
+    /// Jumps directly to the n-th state (counting from 0, in the order
+    /// advance() visits them), which must exist.
+    fn seek(&mut self, n: usize);
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct AstLeafItemStateMachine {
     contents: String,
     valid: bool,
@@ -39,13 +67,44 @@ impl StateMachine for AstLeafItemStateMachine {
         }
     }
 
+    fn fill_bytes(&self, target: &mut Vec<u8>) {
+        if self.valid {
+            target.extend_from_slice(self.contents.as_bytes())
+        }
+    }
+
+    fn fill_hashed(&self, seed: u32) -> u32 {
+        if self.valid {
+            hash_djb2(self.contents.as_bytes(), seed)
+        } else {
+            seed
+        }
+    }
+
     fn advance(&mut self) -> bool {
         self.valid = false;
         false
     }
+
+    fn reset_to_end(&mut self) {
+        self.valid = true;
+    }
+
+    fn retreat(&mut self) -> bool {
+        self.valid = false;
+        false
+    }
+
+    fn num_states(&self) -> usize {
+        1
+    }
+
+    fn seek(&mut self, _n: usize) {
+        self.valid = true;
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct AstChoicesItemStateMachine {
     children: Vec<AstStateMachine>,
     current_index: usize,
@@ -55,6 +114,14 @@ impl AstChoicesItemStateMachine {
     fn new(choices: &[Ast]) -> Self {
         Self{children: choices.iter().map(AstStateMachine::new).collect(), current_index: 0}
     }
+
+    fn fill_hashed_incremental(&mut self, seed: u32) -> u32 {
+        if self.current_index < self.children.len() {
+            self.children[self.current_index].fill_hashed_incremental(seed)
+        } else {
+            seed
+        }
+    }
 }
 
 impl StateMachine for AstChoicesItemStateMachine {
@@ -71,6 +138,20 @@ impl StateMachine for AstChoicesItemStateMachine {
         }
     }
 
+    fn fill_bytes(&self, target: &mut Vec<u8>) {
+        if self.current_index < self.children.len() {
+            self.children[self.current_index].fill_bytes(target);
+        }
+    }
+
+    fn fill_hashed(&self, seed: u32) -> u32 {
+        if self.current_index < self.children.len() {
+            self.children[self.current_index].fill_hashed(seed)
+        } else {
+            seed
+        }
+    }
+
     fn advance(&mut self) -> bool {
         if self.current_index >= self.children.len() {
             return false;
@@ -81,19 +162,212 @@ impl StateMachine for AstChoicesItemStateMachine {
         self.current_index += 1;
         self.current_index < self.children.len()
     }
+
+    fn reset_to_end(&mut self) {
+        for it in &mut self.children {
+            it.reset_to_end();
+        }
+        self.current_index = self.children.len().saturating_sub(1);
+    }
+
+    fn retreat(&mut self) -> bool {
+        if self.current_index >= self.children.len() {
+            return false;
+        }
+        if self.children[self.current_index].retreat() {
+            return true;
+        }
+        if self.current_index == 0 {
+            return false;
+        }
+        self.current_index -= 1;
+        true
+    }
+
+    fn num_states(&self) -> usize {
+        self.children.iter().fold(0usize, |acc, child| acc.saturating_add(child.num_states()))
+    }
+
+    fn seek(&mut self, n: usize) {
+        self.reset();
+        let Some((i, n)) = locate_choice(n, self.children.iter().map(StateMachine::num_states)) else {
+            debug_assert!(false, "seek() past the last state");
+            return;
+        };
+        self.current_index = i;
+        self.children[i].seek(n);
+    }
+}
+
+#[derive(Debug, Clone)]
+struct AstRangeItemStateMachine {
+    range: BraceRange,
+    current_index: u64,
+}
+
+impl AstRangeItemStateMachine {
+    fn new(range: &BraceRange) -> Self {
+        Self{range: range.clone(), current_index: 0}
+    }
+}
+
+impl StateMachine for AstRangeItemStateMachine {
+    fn reset(&mut self) {
+        self.current_index = 0;
+    }
+
+    fn fill(&self, target: &mut String) {
+        if self.current_index < self.range.len() {
+            self.range.for_each_byte(self.range.value(self.current_index), |c| target.push(c as char));
+        }
+    }
+
+    fn fill_bytes(&self, target: &mut Vec<u8>) {
+        if self.current_index < self.range.len() {
+            self.range.for_each_byte(self.range.value(self.current_index), |c| target.push(c));
+        }
+    }
+
+    fn fill_hashed(&self, seed: u32) -> u32 {
+        let mut hash = seed;
+        if self.current_index < self.range.len() {
+            self.range.for_each_byte(self.range.value(self.current_index), |c| {
+                hash = hash.overflowing_mul(33).0 ^ (c as u32);
+            });
+        }
+        hash
+    }
+
+    fn advance(&mut self) -> bool {
+        if self.current_index >= self.range.len() {
+            return false;
+        }
+        self.current_index += 1;
+        self.current_index < self.range.len()
+    }
+
+    fn reset_to_end(&mut self) {
+        self.current_index = self.range.len() - 1;
+    }
+
+    fn retreat(&mut self) -> bool {
+        if self.current_index == 0 || self.current_index >= self.range.len() {
+            return false;
+        }
+        self.current_index -= 1;
+        true
+    }
+
+    fn num_states(&self) -> usize {
+        usize::try_from(self.range.len()).unwrap_or(usize::MAX)
+    }
+
+    fn seek(&mut self, n: usize) {
+        self.current_index = n as u64;
+    }
+}
+
+#[derive(Debug, Clone)]
+struct AstLazyItemStateMachine {
+    choices: LazyChoices,
+    current_index: usize,
+}
+
+impl AstLazyItemStateMachine {
+    fn new(choices: &LazyChoices) -> Self {
+        Self{choices: choices.clone(), current_index: 0}
+    }
+}
+
+impl StateMachine for AstLazyItemStateMachine {
+    fn reset(&mut self) {
+        self.current_index = 0;
+    }
+
+    fn fill(&self, target: &mut String) {
+        self.choices.with_choice(self.current_index, |s| target.push_str(s));
+    }
+
+    fn fill_bytes(&self, target: &mut Vec<u8>) {
+        self.choices.with_choice(self.current_index, |s| target.extend_from_slice(s.as_bytes()));
+    }
+
+    fn fill_hashed(&self, seed: u32) -> u32 {
+        self.choices.with_choice(self.current_index, |s| hash_djb2(s.as_bytes(), seed)).unwrap_or(seed)
+    }
+
+    fn advance(&mut self) -> bool {
+        if self.choices.with_choice(self.current_index, |_| ()).is_none() {
+            return false;
+        }
+        self.current_index += 1;
+        self.choices.with_choice(self.current_index, |_| ()).is_some()
+    }
+
+    fn reset_to_end(&mut self) {
+        self.current_index = self.choices.num_choices().saturating_sub(1);
+    }
+
+    fn retreat(&mut self) -> bool {
+        if self.current_index == 0 {
+            return false;
+        }
+        self.current_index -= 1;
+        true
+    }
+
+    fn num_states(&self) -> usize {
+        self.choices.num_choices()
+    }
+
+    fn seek(&mut self, n: usize) {
+        self.current_index = n;
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 enum AstItemStateMachine {
     Leaf(AstLeafItemStateMachine),
     Choices(AstChoicesItemStateMachine),
+    Range(AstRangeItemStateMachine),
+    Lazy(AstLazyItemStateMachine),
 }
 
 impl AstItemStateMachine {
     fn new(item: &AstItem) -> Self {
         match item {
             AstItem::Leaf(s) => Self::Leaf(AstLeafItemStateMachine::new(s)),
-            AstItem::Choices(v) => Self::Choices(AstChoicesItemStateMachine::new(v))
+            AstItem::Choices(v) => Self::Choices(AstChoicesItemStateMachine::new(v)),
+            AstItem::Range(range) => Self::Range(AstRangeItemStateMachine::new(range)),
+            AstItem::Lazy(choices) => Self::Lazy(AstLazyItemStateMachine::new(choices)),
+        }
+    }
+
+    /// Calls `f` with each byte of the current state's string
+    /// representation, without building it.
+    fn for_each_byte(&self, f: &mut impl FnMut(u8)) {
+        match self {
+            Self::Leaf(sm) => if sm.valid {
+                sm.contents.bytes().for_each(f);
+            },
+            Self::Choices(sm) => if let Some(child) = sm.children.get(sm.current_index) {
+                child.children.iter().for_each(|item| item.for_each_byte(f));
+            },
+            Self::Range(sm) => if sm.current_index < sm.range.len() {
+                sm.range.for_each_byte(sm.range.value(sm.current_index), f);
+            },
+            Self::Lazy(sm) => {
+                sm.choices.with_choice(sm.current_index, |s| s.bytes().for_each(f));
+            },
+        }
+    }
+
+    fn fill_hashed_incremental(&mut self, seed: u32) -> u32 {
+        match self {
+            Self::Leaf(sm) => sm.fill_hashed(seed),
+            Self::Choices(sm) => sm.fill_hashed_incremental(seed),
+            Self::Range(sm) => sm.fill_hashed(seed),
+            Self::Lazy(sm) => sm.fill_hashed(seed),
         }
     }
 }
@@ -103,6 +377,8 @@ impl StateMachine for AstItemStateMachine {
         match self {
             Self::Leaf(sm) => sm.reset(),
             Self::Choices(sm) => sm.reset(),
+            Self::Range(sm) => sm.reset(),
+            Self::Lazy(sm) => sm.reset(),
         }
     }
 
@@ -110,6 +386,26 @@ impl StateMachine for AstItemStateMachine {
         match self {
             Self::Leaf(sm) => sm.fill(target),
             Self::Choices(sm) => sm.fill(target),
+            Self::Range(sm) => sm.fill(target),
+            Self::Lazy(sm) => sm.fill(target),
+        }
+    }
+
+    fn fill_bytes(&self, target: &mut Vec<u8>) {
+        match self {
+            Self::Leaf(sm) => sm.fill_bytes(target),
+            Self::Choices(sm) => sm.fill_bytes(target),
+            Self::Range(sm) => sm.fill_bytes(target),
+            Self::Lazy(sm) => sm.fill_bytes(target),
+        }
+    }
+
+    fn fill_hashed(&self, seed: u32) -> u32 {
+        match self {
+            Self::Leaf(sm) => sm.fill_hashed(seed),
+            Self::Choices(sm) => sm.fill_hashed(seed),
+            Self::Range(sm) => sm.fill_hashed(seed),
+            Self::Lazy(sm) => sm.fill_hashed(seed),
         }
     }
 
@@ -117,18 +413,134 @@ impl StateMachine for AstItemStateMachine {
         match self {
             Self::Leaf(sm) => sm.advance(),
             Self::Choices(sm) => sm.advance(),
+            Self::Range(sm) => sm.advance(),
+            Self::Lazy(sm) => sm.advance(),
+        }
+    }
+
+    fn reset_to_end(&mut self) {
+        match self {
+            Self::Leaf(sm) => sm.reset_to_end(),
+            Self::Choices(sm) => sm.reset_to_end(),
+            Self::Range(sm) => sm.reset_to_end(),
+            Self::Lazy(sm) => sm.reset_to_end(),
+        }
+    }
+
+    fn retreat(&mut self) -> bool {
+        match self {
+            Self::Leaf(sm) => sm.retreat(),
+            Self::Choices(sm) => sm.retreat(),
+            Self::Range(sm) => sm.retreat(),
+            Self::Lazy(sm) => sm.retreat(),
+        }
+    }
+
+    fn num_states(&self) -> usize {
+        match self {
+            Self::Leaf(sm) => sm.num_states(),
+            Self::Choices(sm) => sm.num_states(),
+            Self::Range(sm) => sm.num_states(),
+            Self::Lazy(sm) => sm.num_states(),
+        }
+    }
+
+    fn seek(&mut self, n: usize) {
+        match self {
+            Self::Leaf(sm) => sm.seek(n),
+            Self::Choices(sm) => sm.seek(n),
+            Self::Range(sm) => sm.seek(n),
+            Self::Lazy(sm) => sm.seek(n),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct AstStateMachine {
     children: Vec<AstItemStateMachine>,
+    /// The hash state after each child, as of the last
+    /// fill_hashed_incremental() call
+    hash_cache: Vec<u32>,
+    /// The seed that hash_cache was calculated from
+    hash_cache_seed: u32,
+    /// The running checksum sum after each child, alongside hash_cache
+    /// (only kept up to date if there's a checksum)
+    checksum_sum_cache: Vec<u64>,
+    /// Index of the first child that may have changed since hash_cache
+    /// was calculated (everything before it is still valid)
+    hash_cache_dirty_from: usize,
+    /// Appended after all of the children, if set
+    checksum: Option<Checksum>,
 }
 
 impl AstStateMachine {
     pub fn new(ast: &Ast) -> Self {
-        Self{children: ast.iter().map(AstItemStateMachine::new).collect()}
+        Self{
+            children: ast.iter().map(AstItemStateMachine::new).collect(),
+            hash_cache: vec![0; ast.len()],
+            checksum_sum_cache: vec![0; ast.len()],
+            hash_cache_seed: 0,
+            hash_cache_dirty_from: 0,
+            checksum: None,
+        }
+    }
+
+    /// Replaces this with a state machine for a different AST (keeping
+    /// the checksum setting), reusing the existing allocations where
+    /// possible.
+    pub fn rebuild(&mut self, ast: &Ast) {
+        self.children.clear();
+        self.children.extend(ast.iter().map(AstItemStateMachine::new));
+        self.hash_cache.clear();
+        self.hash_cache.resize(ast.len(), 0);
+        self.checksum_sum_cache.clear();
+        self.checksum_sum_cache.resize(ast.len(), 0);
+        self.hash_cache_seed = 0;
+        self.hash_cache_dirty_from = 0;
+    }
+
+    /// Sets a checksum to append to every state's string representation.
+    pub fn with_checksum(mut self, checksum: Option<Checksum>) -> Self {
+        self.set_checksum(checksum);
+        self
+    }
+
+    /// Same as with_checksum(), but in-place.
+    pub fn set_checksum(&mut self, checksum: Option<Checksum>) {
+        self.checksum = checksum;
+        // (The cached checksum sums may be for a different one)
+        self.hash_cache_dirty_from = 0;
+    }
+
+    /// Same as fill_hashed(), but only re-hashes the children that have
+    /// changed since the last time this was called (with the same seed).
+    /// Since consecutive states usually only differ near the end, this
+    /// is much faster when iterating over all of them.
+    pub fn fill_hashed_incremental(&mut self, seed: u32) -> u32 {
+        if seed != self.hash_cache_seed {
+            self.hash_cache_seed = seed;
+            self.hash_cache_dirty_from = 0;
+        }
+
+        let dirty_from = self.hash_cache_dirty_from;
+        let mut state = if dirty_from == 0 { seed } else { self.hash_cache[dirty_from - 1] };
+        let mut sum = if dirty_from == 0 { 0 } else { self.checksum_sum_cache[dirty_from - 1] };
+        for ((child, cached_state), cached_sum) in self.children[dirty_from..].iter_mut()
+                .zip(&mut self.hash_cache[dirty_from..])
+                .zip(&mut self.checksum_sum_cache[dirty_from..]) {
+            state = child.fill_hashed_incremental(state);
+            *cached_state = state;
+            if let Some(checksum) = &self.checksum {
+                child.for_each_byte(&mut |c| sum = checksum.add_byte(sum, c));
+                *cached_sum = sum;
+            }
+        }
+
+        self.hash_cache_dirty_from = self.children.len();
+        match &self.checksum {
+            Some(checksum) => checksum.hash_appended(sum, state),
+            None => state,
+        }
     }
 }
 
@@ -137,24 +549,93 @@ impl StateMachine for AstStateMachine {
         for it in &mut self.children {
             it.reset();
         }
+        self.hash_cache_dirty_from = 0;
     }
 
     fn fill(&self, target: &mut String) {
+        let start = target.len();
         for child in &self.children {
             child.fill(target);
         }
+        if let Some(checksum) = &self.checksum {
+            checksum.append(target, start);
+        }
+    }
+
+    fn fill_bytes(&self, target: &mut Vec<u8>) {
+        let start = target.len();
+        for child in &self.children {
+            child.fill_bytes(target);
+        }
+        if let Some(checksum) = &self.checksum {
+            checksum.append_bytes(target, start);
+        }
+    }
+
+    fn fill_hashed(&self, seed: u32) -> u32 {
+        let Some(checksum) = &self.checksum else {
+            return self.children.iter().fold(seed, |state, child| child.fill_hashed(state));
+        };
+        // Hash and sum in the same pass
+        let (mut state, mut sum) = (seed, 0);
+        for child in &self.children {
+            child.for_each_byte(&mut |c| {
+                state = state.overflowing_mul(33).0 ^ (c as u32);
+                sum = checksum.add_byte(sum, c);
+            });
+        }
+        checksum.hash_appended(sum, state)
     }
 
     fn advance(&mut self) -> bool {
-        for child in &mut self.children.iter_mut().rev() {
+        for (i, child) in self.children.iter_mut().enumerate().rev() {
             if child.advance() {
+                self.hash_cache_dirty_from = self.hash_cache_dirty_from.min(i);
                 return true;
             } else {
                 child.reset();
             }
         }
+        self.hash_cache_dirty_from = 0;
+        false
+    }
+
+    fn reset_to_end(&mut self) {
+        for it in &mut self.children {
+            it.reset_to_end();
+        }
+        self.hash_cache_dirty_from = 0;
+    }
+
+    fn retreat(&mut self) -> bool {
+        for (i, child) in self.children.iter_mut().enumerate().rev() {
+            if child.retreat() {
+                self.hash_cache_dirty_from = self.hash_cache_dirty_from.min(i);
+                return true;
+            } else {
+                child.reset_to_end();
+            }
+        }
+        self.hash_cache_dirty_from = 0;
         false
     }
+
+    fn num_states(&self) -> usize {
+        self.children.iter().fold(1usize, |acc, child| acc.saturating_mul(child.num_states()))
+    }
+
+    fn seek(&mut self, n: usize) {
+        let counts: Vec<usize> = self.children.iter().map(StateMachine::num_states).collect();
+        match mixed_radix_digits(n, &counts) {
+            Some(digits) => {
+                for (child, digit) in self.children.iter_mut().zip(digits) {
+                    child.seek(digit);
+                }
+            },
+            None => debug_assert!(false, "seek() with no states"),
+        }
+        self.hash_cache_dirty_from = 0;
+    }
 }
 
 
@@ -165,6 +646,66 @@ mod tests {
     use crate::tokenizer::tokenize;
     use crate::ast::ast_from_tokens;
 
+    /// Checks that fill_hashed() and fill_hashed_incremental() agree
+    /// with hash_djb2() on every state, and returns the strings.
+    fn check_hashes(pattern: &str, seed: u32) -> Vec<String> {
+        let tokens = tokenize(pattern, true);
+        let ast = ast_from_tokens(&tokens).unwrap();
+        let mut sm = AstStateMachine::new(&ast);
+
+        let mut strings = Vec::new();
+        loop {
+            let mut s = String::new();
+            sm.fill(&mut s);
+            assert_eq!(sm.fill_hashed(seed), hash_djb2(s.as_bytes(), seed), "{s}");
+            assert_eq!(sm.fill_hashed_incremental(seed), hash_djb2(s.as_bytes(), seed), "{s}");
+            strings.push(s);
+            if !sm.advance() {
+                break;
+            }
+        }
+        strings
+    }
+
+    #[test]
+    fn test_fill_bytes() {
+        for pattern in ["a{b,c}d", "{a,b}c{e,f{g,h}}", "x{8..11}{a..c}{,y}"] {
+            let ast = ast_from_tokens(&tokenize(pattern, true)).unwrap();
+            let mut sm = AstStateMachine::new(&ast).with_checksum(Some(Checksum::SumMod(100)));
+            loop {
+                let mut s = String::new();
+                sm.fill(&mut s);
+                let mut bytes = Vec::new();
+                sm.fill_bytes(&mut bytes);
+                assert_eq!(bytes, s.as_bytes());
+                if !sm.advance() {
+                    break;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_checksum() {
+        let ast = ast_from_tokens(&tokenize("{a,b}c{1..3}", true)).unwrap();
+        let mut sm = AstStateMachine::new(&ast).with_checksum(Some(Checksum::DigitSum));
+        let seed = 0x1234;
+
+        let mut strings = Vec::new();
+        loop {
+            let mut s = "prefix".to_owned();
+            sm.fill(&mut s);
+            let s = s["prefix".len()..].to_owned();
+            assert_eq!(sm.fill_hashed(seed), hash_djb2(s.as_bytes(), seed), "{s}");
+            assert_eq!(sm.fill_hashed_incremental(seed), hash_djb2(s.as_bytes(), seed), "{s}");
+            strings.push(s);
+            if !sm.advance() {
+                break;
+            }
+        }
+        assert_eq!(strings, vec!["ac11", "ac22", "ac33", "bc11", "bc22", "bc33"]);
+    }
+
     #[test]
     fn test_simple_expansion_in_middle_of_string() {
         let tokens = tokenize("a{b,c}d", true);
@@ -253,4 +794,110 @@ mod tests {
         assert_eq!(&s, "ad");
         assert!(!sm.advance());
     }
+
+    #[test]
+    fn test_fill_hashed() {
+        let strings = check_hashes("{a,b}c{d,e}", 0x1505);
+        assert_eq!(strings, vec!["acd", "ace", "bcd", "bce"]);
+    }
+
+    #[test]
+    fn test_fill_hashed_nested() {
+        check_hashes("{a,b}c{e,f{g,h}}{,i{j,k}l}m", 0x12345678);
+        check_hashes("a{,b,,c,}d{}", 0x1505);
+    }
+
+    #[test]
+    fn test_fill_hashed_incremental_seed_change() {
+        let tokens = tokenize("{a,b}c{d,e}", true);
+        let ast = ast_from_tokens(&tokens).unwrap();
+        let mut sm = AstStateMachine::new(&ast);
+
+        assert_eq!(sm.fill_hashed_incremental(0x1505), hash_djb2(b"acd", 0x1505));
+        assert!(sm.advance());
+        assert_eq!(sm.fill_hashed_incremental(0x1505), hash_djb2(b"ace", 0x1505));
+        assert_eq!(sm.fill_hashed_incremental(0x1234), hash_djb2(b"ace", 0x1234));
+        assert!(sm.advance());
+        assert_eq!(sm.fill_hashed_incremental(0x1234), hash_djb2(b"bcd", 0x1234));
+    }
+
+    #[test]
+    fn test_fill_hashed_ranges() {
+        assert_eq!(check_hashes("a{1..3}b", 0x1505), vec!["a1b", "a2b", "a3b"]);
+        assert_eq!(check_hashes("{3..1}{0x0e..0x10}", 0x1505), vec![
+            "30e", "30f", "310", "20e", "20f", "210", "10e", "10f", "110",
+        ]);
+        check_hashes("{x,y{8..11}}z{0b0..0b1}", 0x12345678);
+    }
+
+    #[test]
+    fn test_seek() {
+        for pattern in ["{a,b}c{e,f{g,h}}{,i{j,k}l}m", "x{3..1}{0x0e..0x10..2}y", "plain"] {
+            let all = check_hashes(pattern, 0x1505);
+            let ast = ast_from_tokens(&tokenize(pattern, true)).unwrap();
+            let mut sm = AstStateMachine::new(&ast);
+            assert_eq!(sm.num_states(), all.len());
+
+            // Seek to each state (in a scrambled order), then check that
+            // iteration continues correctly from there
+            for n in (0..all.len()).map(|i| i * 7 % all.len()) {
+                sm.seek(n);
+                let mut rest = Vec::new();
+                loop {
+                    let mut s = String::new();
+                    sm.fill(&mut s);
+                    assert_eq!(sm.fill_hashed_incremental(0x1505), hash_djb2(s.as_bytes(), 0x1505), "{s}");
+                    rest.push(s);
+                    if !sm.advance() {
+                        break;
+                    }
+                }
+                assert_eq!(rest, all[n..], "{pattern} {n}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_retreat() {
+        for pattern in ["{a,b}c{d,e}", "{a,b}c{e,f{g,h}}{,i{j,k}l}m", "a{,b,,c,}d{}", "x{3..1}{0x0e..0x10..2}y", "plain"] {
+            let mut forward = check_hashes(pattern, 0x1505);
+            forward.reverse();
+
+            let ast = ast_from_tokens(&tokenize(pattern, true)).unwrap();
+            let mut sm = AstStateMachine::new(&ast);
+            sm.reset_to_end();
+            let mut backward = Vec::new();
+            loop {
+                let mut s = String::new();
+                sm.fill(&mut s);
+                assert_eq!(sm.fill_hashed_incremental(0x1505), hash_djb2(s.as_bytes(), 0x1505), "{s}");
+                backward.push(s);
+                if !sm.retreat() {
+                    break;
+                }
+            }
+            assert_eq!(backward, forward, "{pattern}");
+        }
+    }
+
+    #[test]
+    fn test_rebuild() {
+        let mut sm = AstStateMachine::new(&ast_from_tokens(&tokenize("{a,b}c{d,e}", true)).unwrap());
+        sm.advance();
+        sm.fill_hashed_incremental(0x1505);
+
+        let ast = ast_from_tokens(&tokenize("x{1..3}", true)).unwrap();
+        sm.rebuild(&ast);
+        let mut strings = Vec::new();
+        loop {
+            let mut s = String::new();
+            sm.fill(&mut s);
+            assert_eq!(sm.fill_hashed_incremental(0x1505), hash_djb2(s.as_bytes(), 0x1505), "{s}");
+            strings.push(s);
+            if !sm.advance() {
+                break;
+            }
+        }
+        assert_eq!(strings, vec!["x1", "x2", "x3"]);
+    }
 }
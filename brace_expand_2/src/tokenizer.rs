@@ -1,4 +1,11 @@
 
+use std::io::{self, Read};
+
+
+/// How many bytes tokenize_reader() reads at a time.
+const READ_BUF_SIZE: usize = 4096;
+
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     OpenBrace,
@@ -8,32 +15,74 @@ pub enum Token {
 }
 
 
+/// The characters that the tokenizer treats specially.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenizerConfig {
+    /// Starts a group of alternatives (OpenBrace)
+    pub open: char,
+    /// Ends a group of alternatives (CloseBrace)
+    pub close: char,
+    /// Separates alternatives (Comma)
+    pub sep: char,
+    /// Makes the next character literal, if set
+    pub escape: Option<char>,
+}
+
+impl Default for TokenizerConfig {
+    /// The usual "{", "}", "," and "\\".
+    fn default() -> Self {
+        Self{open: '{', close: '}', sep: ',', escape: Some('\\')}
+    }
+}
+
+
 /// Converts a string slice to a Vec of Tokens.
 ///
 /// If escape is true, you can use backslashes to escape any character,
 /// such as braces and commas. If it's false, backslashes will just be
 /// treated like any other character.
 pub fn tokenize(pattern: &str, escape: bool) -> Vec<Token> {
+    tokenize_with_offsets(pattern, escape).0
+}
+
+
+/// Same as tokenize(), but with configurable metacharacters.
+pub fn tokenize_with(pattern: &str, config: &TokenizerConfig) -> Vec<Token> {
+    tokenize_with_offsets_and_config(pattern, config).0
+}
+
+
+/// Same as tokenize(), but also returns the byte offset in the pattern
+/// where each token starts (including any backslash before it).
+pub fn tokenize_with_offsets(pattern: &str, escape: bool) -> (Vec<Token>, Vec<usize>) {
+    let config = TokenizerConfig{escape: escape.then_some('\\'), ..TokenizerConfig::default()};
+    tokenize_with_offsets_and_config(pattern, &config)
+}
+
+
+pub(crate) fn tokenize_with_offsets_and_config(pattern: &str, config: &TokenizerConfig) -> (Vec<Token>, Vec<usize>) {
     let mut tokens = Vec::new();
+    let mut offsets = Vec::new();
 
-    let mut is_escape_seq = false;
+    let mut escape_seq_start = None;
 
-    for c in pattern.chars() {
-        if is_escape_seq {
+    for (i, c) in pattern.char_indices() {
+        if let Some(start) = escape_seq_start {
             if let Some(Token::Term(s)) = tokens.last_mut() {
                 s.push(c);
             } else {
                 tokens.push(Token::Term(c.to_string()));
+                offsets.push(start);
             }
-            is_escape_seq = false;
+            escape_seq_start = None;
         } else {
             match c {
-                '{' => tokens.push(Token::OpenBrace),
-                '}' => tokens.push(Token::CloseBrace),
-                ',' => tokens.push(Token::Comma),
+                _ if c == config.open => tokens.push(Token::OpenBrace),
+                _ if c == config.close => tokens.push(Token::CloseBrace),
+                _ if c == config.sep => tokens.push(Token::Comma),
                 _ => {
-                    if escape && c == '\\' {
-                        is_escape_seq = true;
+                    if config.escape == Some(c) {
+                        escape_seq_start = Some(i);
                     } else if let Some(Token::Term(s)) = tokens.last_mut() {
                         s.push(c);
                     } else {
@@ -41,10 +90,139 @@ pub fn tokenize(pattern: &str, escape: bool) -> Vec<Token> {
                     }
                 }
             }
+            if offsets.len() < tokens.len() {
+                offsets.push(i);
+            }
         }
     }
 
-    tokens
+    (tokens, offsets)
+}
+
+
+/// Iterator for tokenize_reader().
+struct ReaderTokenizer<R: Read> {
+    reader: R,
+    buf: Vec<u8>,
+    pos: usize,
+    len: usize,
+    escape: bool,
+    /// Bytes of the Term currently being built
+    term: Vec<u8>,
+    /// Whether the last byte was an (unescaped) backslash
+    in_escape_seq: bool,
+    /// A token that ended the previous Term, to produce next
+    pending: Option<Token>,
+    done: bool,
+}
+
+impl<R: Read> ReaderTokenizer<R> {
+    fn next_byte(&mut self) -> io::Result<Option<u8>> {
+        while self.pos == self.len {
+            match self.reader.read(&mut self.buf) {
+                Ok(0) => return Ok(None),
+                Ok(n) => (self.pos, self.len) = (0, n),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => return Err(e),
+            }
+        }
+        self.pos += 1;
+        Ok(Some(self.buf[self.pos - 1]))
+    }
+
+    fn take_term(&mut self) -> io::Result<Token> {
+        String::from_utf8(std::mem::take(&mut self.term))
+            .map(Token::Term)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+impl<R: Read> Iterator for ReaderTokenizer<R> {
+    type Item = io::Result<Token>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(token) = self.pending.take() {
+            return Some(Ok(token));
+        }
+
+        while !self.done {
+            let c = match self.next_byte() {
+                Ok(Some(c)) => c,
+                Ok(None) => {
+                    // (A trailing backslash is dropped, like in tokenize())
+                    self.done = true;
+                    break;
+                },
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                },
+            };
+
+            if self.in_escape_seq {
+                self.term.push(c);
+                self.in_escape_seq = false;
+                continue;
+            }
+
+            // (Multi-byte UTF-8 characters never contain these bytes, so
+            // it's fine to look at one byte at a time)
+            let token = match c {
+                b'{' => Token::OpenBrace,
+                b'}' => Token::CloseBrace,
+                b',' => Token::Comma,
+                b'\\' if self.escape => {
+                    self.in_escape_seq = true;
+                    continue;
+                },
+                _ => {
+                    self.term.push(c);
+                    continue;
+                },
+            };
+
+            if self.term.is_empty() {
+                return Some(Ok(token));
+            }
+            self.pending = Some(token);
+            return Some(self.take_term());
+        }
+
+        (!self.term.is_empty()).then(|| self.take_term())
+    }
+}
+
+
+/// Same as tokenize(), but reads the pattern incrementally from a
+/// reader instead of needing it all in memory at once.
+pub fn tokenize_reader<R: Read>(reader: R, escape: bool) -> impl Iterator<Item = io::Result<Token>> {
+    ReaderTokenizer{
+        reader,
+        buf: vec![0; READ_BUF_SIZE],
+        pos: 0,
+        len: 0,
+        escape,
+        term: Vec::new(),
+        in_escape_seq: false,
+        pending: None,
+        done: false,
+    }
+}
+
+
+/// Escapes all braces, commas, backslashes and "#"s in a string, so
+/// that tokenize() (with escaping enabled) will turn it into a single
+/// literal Term, and brace_expand_iter() won't mistake a "#" at the end
+/// for a checksum.
+pub fn escape_for_pattern(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '{' | '}' | ',' | '\\' | '#') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
 }
 
 
@@ -226,4 +404,80 @@ mod tests {
             Token::Term("d".to_owned()),
         ]);
     }
+
+    #[test]
+    fn test_escape_for_pattern() {
+        let s = "a{b,c}\\d";
+        let escaped = escape_for_pattern(s);
+
+        assert_eq!(escaped, "a\\{b\\,c\\}\\\\d");
+        assert_eq!(tokenize(&escaped, true), vec![Token::Term(s.to_owned())]);
+    }
+
+    #[test]
+    fn test_offsets() {
+        let (tokens, offsets) = tokenize_with_offsets("ab{\\,c,é}\\}", true);
+        assert_eq!(tokens, vec![
+            Token::Term("ab".to_owned()),
+            Token::OpenBrace,
+            Token::Term(",c".to_owned()),
+            Token::Comma,
+            Token::Term("é".to_owned()),
+            Token::CloseBrace,
+            Token::Term("}".to_owned()),
+        ]);
+        assert_eq!(offsets, vec![0, 2, 3, 6, 7, 9, 10]);
+    }
+
+    /// A reader that only ever returns one byte at a time.
+    struct OneByteReader<R: Read>(R);
+
+    impl<R: Read> Read for OneByteReader<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(1);
+            self.0.read(&mut buf[..len])
+        }
+    }
+
+    #[test]
+    fn test_tokenize_reader() {
+        let tokens: Vec<Token> = tokenize_reader(io::BufReader::new(OneByteReader("a{b,c}d".as_bytes())), true)
+            .collect::<io::Result<_>>().unwrap();
+        assert_eq!(tokens, tokenize("a{b,c}d", true));
+
+        for pattern in ["abc{def,ghi}jkl", "a{,b,,c,}d", "{\\{a,b\\},c}d", "{a\\,,b\\,}c", "ab{\\,c,é}\\}", "trailing\\", ""] {
+            for escape in [true, false] {
+                let tokens: Vec<Token> = tokenize_reader(OneByteReader(pattern.as_bytes()), escape)
+                    .collect::<io::Result<_>>().unwrap();
+                assert_eq!(tokens, tokenize(pattern, escape), "{pattern}");
+                let tokens: Vec<Token> = tokenize_reader(pattern.as_bytes(), escape)
+                    .collect::<io::Result<_>>().unwrap();
+                assert_eq!(tokens, tokenize(pattern, escape), "{pattern}");
+            }
+        }
+
+        let mut tokens = tokenize_reader(&b"a{\xff}"[..], true);
+        assert_eq!(tokens.next().unwrap().unwrap(), Token::Term("a".to_owned()));
+        assert_eq!(tokens.next().unwrap().unwrap(), Token::OpenBrace);
+        assert_eq!(tokens.next().unwrap().unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_custom_metacharacters() {
+        let config = TokenizerConfig{open: '<', close: '>', sep: ';', escape: Some('\\')};
+        assert_eq!(tokenize_with("a<b;c>d", &config), tokenize("a{b,c}d", true));
+        assert_eq!(tokenize_with("{a,b}<c\\;;d>", &config), vec![
+            Token::Term("{a,b}".to_owned()),
+            Token::OpenBrace,
+            Token::Term("c;".to_owned()),
+            Token::Comma,
+            Token::Term("d".to_owned()),
+            Token::CloseBrace,
+        ]);
+
+        let config = TokenizerConfig{escape: Some('%'), ..TokenizerConfig::default()};
+        assert_eq!(tokenize_with("a%{b\\", &config), vec![Token::Term("a{b\\".to_owned())]);
+        let config = TokenizerConfig{escape: None, ..TokenizerConfig::default()};
+        assert_eq!(tokenize_with("a\\{b,c}", &config), tokenize("a\\{b,c}", false));
+    }
 }
@@ -0,0 +1,398 @@
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+use nvidia_demangle::DEFAULT_BUF_SIZE;
+
+use crate::checkpoint::DEFAULT_CHECKPOINT_INTERVAL;
+use crate::sinks::LogFormat;
+
+
+/// Environment variable listing word list directories (separated like
+/// PATH), used if there are no --wordlist-dir arguments.
+pub const WORD_LIST_DIRS_ENV_VAR: &str = "NSMBW_WORDLISTS";
+
+
+/// The word list directories to use if none were given on the command
+/// line: the ones in the environment variable (its value is passed in),
+/// or else just the current working directory.
+pub fn default_word_list_dirs(env_value: Option<OsString>) -> Vec<PathBuf> {
+    let dirs: Vec<PathBuf> = env_value
+        .map(|value| std::env::split_paths(&value).filter(|dir| !dir.as_os_str().is_empty()).collect())
+        .unwrap_or_default();
+    if dirs.is_empty() {
+        vec![PathBuf::from(".")]
+    } else {
+        dirs
+    }
+}
+
+
+/// Parsed command-line arguments.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Args {
+    /// Symbol maps to load (and merge, if there's more than one)
+    pub symbol_map_paths: Vec<PathBuf>,
+    /// Directories to search for word list files in, highest precedence
+    /// first. Defaults to the ones in NSMBW_WORDLISTS, or just the
+    /// current working directory.
+    pub word_list_dirs: Vec<PathBuf>,
+    /// If set, run the commands in this file instead of starting the REPL
+    pub batch_path: Option<PathBuf>,
+    /// If set, run each line of this file as a pattern instead of
+    /// starting the REPL
+    pub patterns_path: Option<PathBuf>,
+    /// Start with backslash-escapes enabled
+    pub escapes: bool,
+    /// Line of the batch or patterns file to start from (1-based), for
+    /// resuming an interrupted run
+    pub start_line: usize,
+    /// If nonempty, run these commands instead of starting the REPL
+    pub patterns: Vec<String>,
+    /// If set, found symbols are also logged here as re-runnable patterns
+    pub pattern_log_path: Option<PathBuf>,
+    /// If set, found symbols are also logged here as JSON lines
+    pub json_matches_path: Option<PathBuf>,
+    /// Format of the main match log
+    pub log_format: LogFormat,
+    /// Stream the symbol map instead of loading it all at once
+    pub stream_map: bool,
+    /// Candidates shorter than this aren't demangled (and so can't match)
+    pub min_demangle_length: usize,
+    /// Size of the buffer demangled names are written into
+    pub demangle_buf_size: usize,
+    /// Candidates containing any of these are skipped
+    pub excluded_substrings: Vec<String>,
+    /// Report the closest any candidate's hash came to an unknown symbol's
+    pub report_nearest_hash: bool,
+    /// Skip candidates that are the same as one of the last this-many
+    /// (0 means no deduplication)
+    pub dedup_window: usize,
+    /// If set, this namespace is removed from the start of candidates
+    /// before they're hashed
+    pub strip_namespace: Option<String>,
+    /// Prune candidates by working backwards from the target hashes
+    pub inverse_prune: bool,
+    /// Number of threads to search with
+    pub threads: usize,
+    /// If set, patterns with more expansions than this are skipped in
+    /// batch mode, or ask for confirmation in the REPL (0 means no
+    /// limit). The REPL has a default limit; batch mode doesn't.
+    pub max_expansions: Option<usize>,
+    /// If set, long searches save their progress here
+    pub checkpoint_path: Option<PathBuf>,
+    /// How many candidates to check between checkpoints
+    pub checkpoint_interval: usize,
+    pub verbose: bool,
+}
+
+
+impl Args {
+    /// Parses the arguments (not including the program name).
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self, String> {
+        let mut symbol_map_paths = Vec::new();
+        let mut word_list_dirs = Vec::new();
+        let mut batch_path = None;
+        let mut patterns_path = None;
+        let mut escapes = false;
+        let mut start_line = None;
+        let mut patterns = Vec::new();
+        let mut pattern_log_path = None;
+        let mut json_matches_path = None;
+        let mut log_format = LogFormat::default();
+        let mut stream_map = false;
+        let mut min_demangle_length = 0;
+        let mut demangle_buf_size = DEFAULT_BUF_SIZE;
+        let mut excluded_substrings = Vec::new();
+        let mut report_nearest_hash = false;
+        let mut dedup_window = 0;
+        let mut strip_namespace = None;
+        let mut inverse_prune = false;
+        let mut threads = 1;
+        let mut max_expansions = None;
+        let mut checkpoint_path = None;
+        let mut checkpoint_interval = DEFAULT_CHECKPOINT_INTERVAL;
+        let mut verbose = false;
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--wordlist-dir" => {
+                    let dir = args.next().ok_or("--wordlist-dir requires a directory")?;
+                    word_list_dirs.push(PathBuf::from(dir));
+                },
+                "--batch" => {
+                    batch_path = Some(PathBuf::from(args.next().ok_or("--batch requires a file")?));
+                },
+                "--patterns" => {
+                    patterns_path = Some(PathBuf::from(args.next().ok_or("--patterns requires a file")?));
+                },
+                "--escapes" => escapes = true,
+                "--start-line" => {
+                    let line = args.next().ok_or("--start-line requires a line number")?;
+                    start_line = Some(line.parse().ok().filter(|line| *line > 0).ok_or(format!("invalid line number \"{line}\""))?);
+                },
+                "--pattern" => {
+                    patterns.push(args.next().ok_or("--pattern requires a pattern")?);
+                },
+                "--pattern-log" => {
+                    pattern_log_path = Some(PathBuf::from(args.next().ok_or("--pattern-log requires a file")?));
+                },
+                "--json-matches" => {
+                    json_matches_path = Some(PathBuf::from(args.next().ok_or("--json-matches requires a file")?));
+                },
+                "--log-format" => {
+                    let name = args.next().ok_or("--log-format requires a format")?;
+                    log_format = LogFormat::from_name(&name).ok_or(format!("unknown log format \"{name}\" (expected text, csv or json)"))?;
+                },
+                "--exclude" => {
+                    excluded_substrings.push(args.next().ok_or("--exclude requires a substring")?);
+                },
+                "--stream-map" => stream_map = true,
+                "--nearest-hash" => report_nearest_hash = true,
+                "--inverse-prune" => inverse_prune = true,
+                "--min-demangle-length" => {
+                    let length = args.next().ok_or("--min-demangle-length requires a number")?;
+                    min_demangle_length = length.parse().map_err(|_| format!("invalid length \"{length}\""))?;
+                },
+                "--demangle-buf" => {
+                    let size = args.next().ok_or("--demangle-buf requires a size")?;
+                    demangle_buf_size = size.parse().ok().filter(|size| *size > 0).ok_or(format!("invalid buffer size \"{size}\""))?;
+                },
+                "--strip-namespace" => {
+                    strip_namespace = Some(args.next().ok_or("--strip-namespace requires a namespace")?);
+                },
+                "--dedup-window" => {
+                    let size = args.next().ok_or("--dedup-window requires a number")?;
+                    dedup_window = size.parse().map_err(|_| format!("invalid window size \"{size}\""))?;
+                },
+                "--threads" => {
+                    let count = args.next().ok_or("--threads requires a number")?;
+                    threads = count.parse().ok().filter(|count| *count > 0).ok_or(format!("invalid thread count \"{count}\""))?;
+                },
+                "--max-expansions" => {
+                    let count = args.next().ok_or("--max-expansions requires a number")?;
+                    max_expansions = Some(count.parse().map_err(|_| format!("invalid expansion count \"{count}\""))?);
+                },
+                "--checkpoint" => {
+                    checkpoint_path = Some(PathBuf::from(args.next().ok_or("--checkpoint requires a file")?));
+                },
+                "--checkpoint-interval" => {
+                    let interval = args.next().ok_or("--checkpoint-interval requires a number")?;
+                    checkpoint_interval = interval.parse().ok().filter(|interval| *interval > 0).ok_or(format!("invalid interval \"{interval}\""))?;
+                },
+                "-v" | "--verbose" => verbose = true,
+                _ if arg.starts_with("--") => return Err(format!("unknown option \"{arg}\"")),
+                _ => symbol_map_paths.push(PathBuf::from(arg)),
+            }
+        }
+
+        if symbol_map_paths.is_empty() {
+            return Err("Specify the path to the symbol map (or several) as the first argument".to_owned());
+        }
+        if start_line.is_some() && batch_path.is_none() && patterns_path.is_none() {
+            return Err("--start-line only works with --batch or --patterns".to_owned());
+        }
+        let start_line = start_line.unwrap_or(1);
+        if word_list_dirs.is_empty() {
+            word_list_dirs = default_word_list_dirs(std::env::var_os(WORD_LIST_DIRS_ENV_VAR));
+        }
+
+        Ok(Self{
+            symbol_map_paths,
+            word_list_dirs,
+            batch_path,
+            patterns_path,
+            escapes,
+            start_line,
+            patterns,
+            pattern_log_path,
+            json_matches_path,
+            log_format,
+            stream_map,
+            min_demangle_length,
+            demangle_buf_size,
+            excluded_substrings,
+            report_nearest_hash,
+            dedup_window,
+            strip_namespace,
+            inverse_prune,
+            threads,
+            max_expansions,
+            checkpoint_path,
+            checkpoint_interval,
+            verbose,
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        Args::parse(args.iter().map(|s| s.to_string()))
+    }
+
+    #[test]
+    fn test_defaults() {
+        let args = parse(&["map.map"]).unwrap();
+        assert_eq!(args.symbol_map_paths, vec![PathBuf::from("map.map")]);
+        assert_eq!(args.word_list_dirs, default_word_list_dirs(std::env::var_os(WORD_LIST_DIRS_ENV_VAR)));
+        assert_eq!(args.batch_path, None);
+        assert_eq!(args.start_line, 1);
+        assert!(args.patterns.is_empty());
+        assert_eq!(args.pattern_log_path, None);
+        assert_eq!(args.json_matches_path, None);
+        assert!(!args.stream_map);
+        assert_eq!(args.min_demangle_length, 0);
+        assert!(args.excluded_substrings.is_empty());
+        assert!(!args.report_nearest_hash);
+        assert_eq!(args.dedup_window, 0);
+        assert_eq!(args.strip_namespace, None);
+        assert!(!args.inverse_prune);
+        assert!(!args.verbose);
+    }
+
+    #[test]
+    fn test_multiple_maps() {
+        let args = parse(&["a.map", "--threads", "2", "b.map"]).unwrap();
+        assert_eq!(args.symbol_map_paths, vec![PathBuf::from("a.map"), PathBuf::from("b.map")]);
+        assert!(parse(&["--threads", "2"]).is_err());
+    }
+
+    #[test]
+    fn test_batch_and_pattern_log() {
+        let args = parse(&["map.map", "--batch", "in.txt", "--pattern-log", "out.txt"]).unwrap();
+        assert_eq!(args.batch_path, Some(PathBuf::from("in.txt")));
+        assert_eq!(args.pattern_log_path, Some(PathBuf::from("out.txt")));
+    }
+
+    #[test]
+    fn test_patterns_file() {
+        let args = parse(&["map.map"]).unwrap();
+        assert_eq!((args.patterns_path, args.escapes), (None, false));
+        let args = parse(&["map.map", "--patterns", "overnight.txt", "--escapes"]).unwrap();
+        assert_eq!((args.patterns_path, args.escapes), (Some(PathBuf::from("overnight.txt")), true));
+        assert!(parse(&["map.map", "--patterns"]).is_err());
+    }
+
+    #[test]
+    fn test_patterns() {
+        let args = parse(&["map.map", "--pattern", "a{b,c}", "--pattern", "x{y,z}"]).unwrap();
+        assert_eq!(args.patterns, vec!["a{b,c}", "x{y,z}"]);
+        assert!(parse(&["map.map", "--pattern"]).is_err());
+    }
+
+    #[test]
+    fn test_json_matches() {
+        let args = parse(&["map.map", "--json-matches", "matches.jsonl"]).unwrap();
+        assert_eq!(args.json_matches_path, Some(PathBuf::from("matches.jsonl")));
+        assert!(parse(&["map.map", "--json-matches"]).is_err());
+    }
+
+    #[test]
+    fn test_min_demangle_length() {
+        let args = parse(&["map.map", "--min-demangle-length", "6"]).unwrap();
+        assert_eq!(args.min_demangle_length, 6);
+    }
+
+    #[test]
+    fn test_threads() {
+        assert_eq!(parse(&["map.map"]).unwrap().threads, 1);
+        assert_eq!(parse(&["map.map", "--threads", "8"]).unwrap().threads, 8);
+        assert!(parse(&["map.map", "--threads", "0"]).is_err());
+        assert!(parse(&["map.map", "--threads"]).is_err());
+    }
+
+    #[test]
+    fn test_log_format() {
+        assert_eq!(parse(&["map.map"]).unwrap().log_format, LogFormat::Text);
+        assert_eq!(parse(&["map.map", "--log-format", "csv"]).unwrap().log_format, LogFormat::Csv);
+        assert!(parse(&["map.map", "--log-format", "xml"]).is_err());
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        let args = parse(&["map.map"]).unwrap();
+        assert_eq!((args.checkpoint_path, args.checkpoint_interval), (None, DEFAULT_CHECKPOINT_INTERVAL));
+        let args = parse(&["map.map", "--checkpoint", "cp.txt", "--checkpoint-interval", "1000"]).unwrap();
+        assert_eq!((args.checkpoint_path, args.checkpoint_interval), (Some(PathBuf::from("cp.txt")), 1000));
+        assert!(parse(&["map.map", "--checkpoint-interval", "0"]).is_err());
+    }
+
+    #[test]
+    fn test_demangle_buf() {
+        assert_eq!(parse(&["map.map"]).unwrap().demangle_buf_size, DEFAULT_BUF_SIZE);
+        assert_eq!(parse(&["map.map", "--demangle-buf", "4096"]).unwrap().demangle_buf_size, 4096);
+        assert!(parse(&["map.map", "--demangle-buf", "0"]).is_err());
+    }
+
+    #[test]
+    fn test_exclude() {
+        let args = parse(&["map.map", "--exclude", "Fv", "--exclude", "PC"]).unwrap();
+        assert_eq!(args.excluded_substrings, vec!["Fv", "PC"]);
+        assert!(parse(&["map.map", "--exclude"]).is_err());
+    }
+
+    #[test]
+    fn test_strip_namespace() {
+        let args = parse(&["map.map", "--strip-namespace", "nw4r"]).unwrap();
+        assert_eq!(args.strip_namespace.as_deref(), Some("nw4r"));
+        assert!(parse(&["map.map", "--strip-namespace"]).is_err());
+    }
+
+    #[test]
+    fn test_start_line() {
+        let args = parse(&["map.map", "--batch", "patterns.txt", "--start-line", "3"]).unwrap();
+        assert_eq!(args.start_line, 3);
+        let args = parse(&["map.map", "--start-line", "2", "--patterns", "patterns.txt"]).unwrap();
+        assert_eq!(args.start_line, 2);
+        assert_eq!(parse(&["map.map"]).unwrap().start_line, 1);
+        assert!(parse(&["map.map", "--batch", "patterns.txt", "--start-line", "0"]).is_err());
+        assert!(parse(&["map.map", "--batch", "patterns.txt", "--start-line"]).is_err());
+        // (There's nothing for it to skip lines of)
+        assert!(parse(&["map.map", "--start-line", "3"]).is_err());
+        assert!(parse(&["map.map", "--pattern", "a{b,c}", "--start-line", "3"]).is_err());
+    }
+
+    #[test]
+    fn test_dedup_window() {
+        let args = parse(&["map.map", "--dedup-window", "16"]).unwrap();
+        assert_eq!(args.dedup_window, 16);
+        assert!(parse(&["map.map", "--dedup-window"]).is_err());
+        assert!(parse(&["map.map", "--dedup-window", "-1"]).is_err());
+    }
+
+    #[test]
+    fn test_word_list_dirs_keep_order() {
+        let args = parse(&["--wordlist-dir", "local", "map.map", "--wordlist-dir", "shared", "-v"]).unwrap();
+        assert_eq!(args.word_list_dirs, vec![PathBuf::from("local"), PathBuf::from("shared")]);
+        assert!(args.verbose);
+    }
+
+    #[test]
+    fn test_default_word_list_dirs() {
+        assert_eq!(default_word_list_dirs(None), vec![PathBuf::from(".")]);
+        assert_eq!(default_word_list_dirs(Some(OsString::new())), vec![PathBuf::from(".")]);
+        let value = std::env::join_paths(["/a/lists", "/b/lists"]).unwrap();
+        assert_eq!(default_word_list_dirs(Some(value)), vec![PathBuf::from("/a/lists"), PathBuf::from("/b/lists")]);
+    }
+
+    #[test]
+    fn test_max_expansions() {
+        assert_eq!(parse(&["map.map"]).unwrap().max_expansions, None);
+        assert_eq!(parse(&["map.map", "--max-expansions", "1000"]).unwrap().max_expansions, Some(1000));
+        assert_eq!(parse(&["map.map", "--max-expansions", "0"]).unwrap().max_expansions, Some(0));
+        assert!(parse(&["map.map", "--max-expansions", "x"]).is_err());
+        assert!(parse(&["map.map", "--max-expansions"]).is_err());
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(parse(&[]).is_err());
+        assert!(parse(&["map.map", "--wordlist-dir"]).is_err());
+        assert!(parse(&["map.map", "--bogus"]).is_err());
+        assert!(parse(&["map.map", "--min-demangle-length", "x"]).is_err());
+    }
+}
@@ -0,0 +1,94 @@
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+
+
+/// How many candidates are checked between checkpoints, by default.
+pub const DEFAULT_CHECKPOINT_INTERVAL: usize = 100_000_000;
+
+
+/// How far a search had gotten, so that it can be resumed later. Saved
+/// as three "key: value" lines.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Checkpoint {
+    /// The pattern as it was entered
+    pub pattern: String,
+    /// The pattern after all substitutions, for checking that it still
+    /// means the same thing when resuming (word lists may have changed)
+    pub expanded_pattern: String,
+    /// Index of the next expansion to check
+    pub index: usize,
+}
+
+
+impl Checkpoint {
+    pub fn write_to<W: Write>(&self, mut w: W) -> Result<(), Box<dyn Error>> {
+        writeln!(w, "pattern: {}", self.pattern)?;
+        writeln!(w, "expanded: {}", self.expanded_pattern)?;
+        writeln!(w, "index: {}", self.index)?;
+        Ok(())
+    }
+
+    pub fn read_from<R: BufRead>(reader: R) -> Result<Self, Box<dyn Error>> {
+        let (mut pattern, mut expanded_pattern, mut index) = (None, None, None);
+        for line in reader.lines() {
+            let line = line?;
+            if let Some(value) = line.strip_prefix("pattern: ") {
+                pattern = Some(value.to_owned());
+            } else if let Some(value) = line.strip_prefix("expanded: ") {
+                expanded_pattern = Some(value.to_owned());
+            } else if let Some(value) = line.strip_prefix("index: ") {
+                index = Some(value.parse().map_err(|_| format!("invalid index \"{value}\""))?);
+            } else if !line.trim().is_empty() {
+                return Err(format!("unexpected line in checkpoint: \"{line}\"").into());
+            }
+        }
+
+        Ok(Self{
+            pattern: pattern.ok_or("checkpoint has no pattern")?,
+            expanded_pattern: expanded_pattern.ok_or("checkpoint has no expanded pattern")?,
+            index: index.ok_or("checkpoint has no index")?,
+        })
+    }
+
+    /// Writes the checkpoint to a file. It's written to a temporary file
+    /// first and then moved into place, so that being interrupted part
+    /// of the way through doesn't destroy the previous checkpoint.
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let temp_path = path.with_extension("tmp");
+        self.write_to(File::create(&temp_path)?)?;
+        fs::rename(&temp_path, path)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Self::read_from(BufReader::new(File::open(path)?))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    #[test]
+    fn test_roundtrip() {
+        let checkpoint = Checkpoint{
+            pattern: "{a,b}W[words]__Fv".to_owned(),
+            expanded_pattern: "{a,b}{x,y}__Fv".to_owned(),
+            index: 12345,
+        };
+        let mut written = Vec::new();
+        checkpoint.write_to(&mut written).unwrap();
+        assert_eq!(String::from_utf8(written.clone()).unwrap(),
+            "pattern: {a,b}W[words]__Fv\nexpanded: {a,b}{x,y}__Fv\nindex: 12345\n");
+        assert_eq!(Checkpoint::read_from(Cursor::new(written)).unwrap(), checkpoint);
+
+        assert!(Checkpoint::read_from(Cursor::new("pattern: a\nexpanded: a\n")).is_err());
+        assert!(Checkpoint::read_from(Cursor::new("pattern: a\nexpanded: a\nindex: -1\n")).is_err());
+        assert!(Checkpoint::read_from(Cursor::new("pattern: a\nexpanded: a\nindex: 1\nwhat\n")).is_err());
+    }
+}
@@ -0,0 +1,614 @@
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use std::io::BufRead;
+
+use djb2_utils::{hash_djb2, DJB2_HASH_SEED};
+use lazy_static::lazy_static;
+use nvidia_demangle::demangle;
+use regex::Regex;
+use symbol_map_formats::{BasicSymbolMap, SymbolMapFormat};
+
+
+#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub struct SymbolDatabaseEntry {
+    pub address: u32,
+    pub mangled_hash: u32,
+    pub demangled_hash: u32,
+    pub mangled_name: Option<String>,
+    /// Which of the database's sources (symbol maps) this came from
+    pub source: usize,
+}
+
+
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct SymbolDatabase {
+    /// mangled hash -> demangled hash -> symbols with those hashes.
+    /// Organized this way so we can efficiently check if there are any
+    /// symbols matching a mangled hash, so we can skip demangling
+    /// altogether if not.
+    pub contents: HashMap<u32, HashMap<u32, Vec<SymbolDatabaseEntry>>>,
+    /// Addresses of unknown symbols whose names have been confirmed by
+    /// hand (with the "confirm" command). These are treated as known.
+    pub confirmed_addresses: BTreeSet<u32>,
+    /// demangled hash -> symbols with that hash, for matching on just
+    /// the demangled hash
+    pub demangled_index: HashMap<u32, Vec<SymbolDatabaseEntry>>,
+    /// address -> name, as it was in the symbol map (including
+    /// "hashname_" placeholders). If several symbols share an address,
+    /// this has the last one.
+    pub names: HashMap<u32, String>,
+    /// Known names paired with the demangled hash from a "hashname_"
+    /// placeholder for the same symbol in another map. That hash was
+    /// made by whatever demangler the map was made with, so these can
+    /// be used to check ours (see find_demangler_mismatches()).
+    pub demangled_hash_references: Vec<(String, u32)>,
+    /// address -> (mangled hash, demangled hash) of each symbol at that
+    /// address, for finding them in `contents`
+    addresses: HashMap<u32, Vec<(u32, u32)>>,
+    /// Names of the symbol maps merged into the database with
+    /// merge_map() or merge_from_reader(), indexed by
+    /// SymbolDatabaseEntry::source
+    pub sources: Vec<String>,
+}
+
+
+/// If a name is a "hashname_" placeholder, returns the mangled and
+/// demangled hashes from it.
+pub fn parse_hashname(name: &str) -> Option<(u32, u32)> {
+    lazy_static! {
+        static ref HASHNAME_REGEX: Regex = Regex::new(concat!(
+            r"^",                 // (start of string)
+            r"hashname_",         // "hashname_"
+            r"([a-fA-F0-9]{8})",  // hex number
+            r"_",                 // underscore
+            r"([a-fA-F0-9]{8})",  // hex number
+        )).unwrap();
+    }
+
+    let caps = HASHNAME_REGEX.captures(name)?;
+    // These are guaranteed to succeed because the regex only allows hex
+    // digits for them
+    Some((u32::from_str_radix(caps.get(1).unwrap().as_str(), 16).unwrap(),
+          u32::from_str_radix(caps.get(2).unwrap().as_str(), 16).unwrap()))
+}
+
+
+impl SymbolDatabase {
+    pub fn new(basic_map: &BasicSymbolMap) -> Self {
+        let mut db = Self::default();
+        for (address, name) in basic_map.iter() {
+            db.insert(*address, name);
+        }
+        db
+    }
+
+    /// Builds the database directly from a symbol map file, parsing and
+    /// inserting one symbol at a time. This avoids ever having the whole
+    /// map in memory as a BasicSymbolMap, which matters for very large
+    /// maps. (Unlike with new(), symbols that share an address are all
+    /// kept.)
+    pub fn from_reader<R: BufRead>(reader: R, format: SymbolMapFormat) -> Result<Self, Box<dyn Error>> {
+        let mut db = Self::default();
+        for symbol in format.stream_symbols(reader) {
+            let (address, name) = symbol?;
+            db.insert(address, &name);
+        }
+        Ok(db)
+    }
+
+    /// Adds all of a symbol map's symbols to the database, tagged with
+    /// `source_name`.
+    pub fn merge_map(&mut self, basic_map: &BasicSymbolMap, source_name: &str) {
+        let source = self.add_source(source_name);
+        for (address, name) in basic_map.iter() {
+            self.insert_from_source(*address, name, source);
+        }
+    }
+
+    /// Same as merge_map(), but parses the map incrementally, like
+    /// from_reader().
+    pub fn merge_from_reader<R: BufRead>(&mut self, reader: R, format: SymbolMapFormat, source_name: &str) -> Result<(), Box<dyn Error>> {
+        let source = self.add_source(source_name);
+        for symbol in format.stream_symbols(reader) {
+            let (address, name) = symbol?;
+            self.insert_from_source(address, &name, source);
+        }
+        Ok(())
+    }
+
+    fn add_source(&mut self, source_name: &str) -> usize {
+        self.sources.push(source_name.to_owned());
+        self.sources.len() - 1
+    }
+
+    /// The name of the symbol map an entry came from, if it's known.
+    pub fn source_name(&self, entry: &SymbolDatabaseEntry) -> Option<&str> {
+        self.sources.get(entry.source).map(String::as_str)
+    }
+
+    /// Adds a symbol to the database. Its hashes are taken from the name
+    /// if it's a "hashname_" placeholder, or calculated otherwise.
+    pub fn insert(&mut self, address: u32, name: &str) {
+        self.insert_from_source(address, name, 0);
+    }
+
+    /// Same as insert(), but tags the symbol with a source index. If a
+    /// different source already has symbols at this address, they're
+    /// merged into one: a name from any source beats a "hashname_"
+    /// placeholder, and otherwise the first source's symbol is kept.
+    pub fn insert_from_source(&mut self, address: u32, name: &str, source: usize) {
+        let (mangled_hash, demangled_hash, mangled_name) = if let Some((mangled_hash, demangled_hash)) = parse_hashname(name) {
+            (mangled_hash, demangled_hash, None)
+        } else {
+            (hash_djb2(name.as_bytes(), DJB2_HASH_SEED),
+             hash_djb2(demangle(name).unwrap_or_else(|_| "ERROR".to_owned()).as_bytes(), DJB2_HASH_SEED),
+             Some(name.to_owned()))
+        };
+
+        let others: Vec<SymbolDatabaseEntry> = self.entries_at(address)
+            .filter(|entry| entry.source != source)
+            .cloned()
+            .collect();
+        if !others.is_empty() {
+            if mangled_name.is_none() || others.iter().any(|entry| entry.mangled_name.is_some()) {
+                return;
+            }
+            for other in &others {
+                if other.mangled_hash == mangled_hash {
+                    self.demangled_hash_references.push((name.to_owned(), other.demangled_hash));
+                }
+                self.remove(other);
+            }
+        }
+
+        let entry = SymbolDatabaseEntry{
+            address,
+            mangled_hash,
+            demangled_hash,
+            mangled_name,
+            source,
+        };
+        self.demangled_index.entry(demangled_hash).or_default().push(entry.clone());
+        self.contents.entry(mangled_hash).or_default().entry(demangled_hash).or_default().push(entry);
+        self.addresses.entry(address).or_default().push((mangled_hash, demangled_hash));
+        self.names.insert(address, name.to_owned());
+    }
+
+    /// The entries for all of the symbols at this address.
+    fn entries_at(&self, address: u32) -> impl Iterator<Item = &SymbolDatabaseEntry> {
+        let mut keys = self.addresses.get(&address).cloned().unwrap_or_default();
+        keys.sort_unstable();
+        keys.dedup();
+        keys.into_iter()
+            .filter_map(|(mangled_hash, demangled_hash)| self.contents.get(&mangled_hash)?.get(&demangled_hash))
+            .flatten()
+            .filter(move |entry| entry.address == address)
+    }
+
+    /// Removes one entry from the database (not including its name).
+    fn remove(&mut self, entry: &SymbolDatabaseEntry) {
+        if let Some(sub_map) = self.contents.get_mut(&entry.mangled_hash) {
+            if let Some(entries) = sub_map.get_mut(&entry.demangled_hash) {
+                entries.retain(|other| other != entry);
+                if entries.is_empty() {
+                    sub_map.remove(&entry.demangled_hash);
+                }
+            }
+            if sub_map.is_empty() {
+                self.contents.remove(&entry.mangled_hash);
+            }
+        }
+        if let Some(entries) = self.demangled_index.get_mut(&entry.demangled_hash) {
+            entries.retain(|other| other != entry);
+            if entries.is_empty() {
+                self.demangled_index.remove(&entry.demangled_hash);
+            }
+        }
+        if let Some(keys) = self.addresses.get_mut(&entry.address) {
+            keys.retain(|key| *key != (entry.mangled_hash, entry.demangled_hash));
+        }
+    }
+
+    /// The name of the symbol at this address, as it was in the symbol
+    /// map.
+    pub fn name_at(&self, address: u32) -> Option<&str> {
+        self.names.get(&address).map(String::as_str)
+    }
+
+    /// The symbol with the highest address below this one, if any.
+    pub fn symbol_before(&self, address: u32) -> Option<(u32, &str)> {
+        self.names.iter()
+            .filter(|(other, _)| **other < address)
+            .max_by_key(|(other, _)| **other)
+            .map(|(other, name)| (*other, name.as_str()))
+    }
+
+    /// All of the entries in the database, in no particular order.
+    pub fn entries(&self) -> impl Iterator<Item = &SymbolDatabaseEntry> {
+        self.contents.values().flat_map(|sub_map| sub_map.values()).flatten()
+    }
+
+    /// Total number of symbols in the database.
+    pub fn num_symbols(&self) -> usize {
+        self.entries().count()
+    }
+
+    /// Number of distinct mangled hashes among all of the symbols.
+    pub fn num_distinct_mangled_hashes(&self) -> usize {
+        self.contents.len()
+    }
+
+    /// The most symbols that share a single mangled hash.
+    pub fn largest_bucket_size(&self) -> usize {
+        self.contents.values()
+            .map(|sub_map| sub_map.values().map(Vec::len).sum())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Whether a symbol's name is still unknown (not in the map, and not
+    /// confirmed).
+    pub fn is_unknown(&self, entry: &SymbolDatabaseEntry) -> bool {
+        entry.mangled_name.is_none() && !self.confirmed_addresses.contains(&entry.address)
+    }
+
+    /// Number of symbols in the database whose names are unknown.
+    pub fn num_unknown_symbols(&self) -> usize {
+        self.entries().filter(|entry| self.is_unknown(entry)).count()
+    }
+
+    /// Reads confirmed addresses (in hex, one per line) and adds them to
+    /// the set. Blank lines are ignored.
+    pub fn load_confirmed_addresses<R: BufRead>(&mut self, reader: R) -> Result<(), Box<dyn Error>> {
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let address = parse_address(line).ok_or_else(|| format!("invalid address \"{line}\""))?;
+            self.confirmed_addresses.insert(address);
+        }
+        Ok(())
+    }
+
+    pub fn unknown_contents(&self) -> HashMap<u32, HashMap<u32, Vec<SymbolDatabaseEntry>>> {
+        let mut new_map: HashMap<u32, HashMap<u32, Vec<SymbolDatabaseEntry>>> = HashMap::new();
+        for (mangled_hash, sub_map) in self.contents.iter() {
+            for (demangled_hash, entries) in sub_map.iter() {
+                for entry in entries {
+                    if self.is_unknown(entry) {
+                        new_map.entry(*mangled_hash).or_default().entry(*demangled_hash).or_default().push(entry.clone());
+                    }
+                }
+            }
+        }
+        new_map
+    }
+
+    /// All distinct mangled hashes of unknown symbols, in ascending
+    /// order.
+    pub fn sorted_unknown_mangled_hashes(&self) -> Vec<u32> {
+        let mut hashes: Vec<u32> = self.entries()
+            .filter(|entry| self.is_unknown(entry))
+            .map(|entry| entry.mangled_hash)
+            .collect();
+        hashes.sort_unstable();
+        hashes.dedup();
+        hashes
+    }
+
+    /// The set of bytes that appear in the names of known symbols.
+    pub fn known_name_alphabet(&self) -> BTreeSet<u8> {
+        self.entries()
+            .filter_map(|entry| entry.mangled_name.as_deref())
+            .flat_map(str::bytes)
+            .collect()
+    }
+
+    /// Demangles and hashes up to `max_samples` of the known names in
+    /// `demangled_hash_references` (spread evenly) with `demangler`, and
+    /// returns any whose demangled hash doesn't match the placeholder's,
+    /// or that can't be demangled at all. Either suggests that `demangler`
+    /// disagrees with the demangler the map was made with, in which case
+    /// demangled hashes of candidates won't match either. (This can only
+    /// find anything if some map names a symbol that another has a
+    /// placeholder for.)
+    pub fn find_demangler_mismatches<F>(&self, max_samples: usize, demangler: F) -> Vec<String>
+    where F: Fn(&str) -> Result<String, Box<dyn Error>> {
+        let mut references: Vec<&(String, u32)> = self.demangled_hash_references.iter().collect();
+        references.sort();
+        if references.is_empty() || max_samples == 0 {
+            return Vec::new();
+        }
+
+        let step = references.len().div_ceil(max_samples);
+        references.into_iter().step_by(step)
+            .filter_map(|(name, demangled_hash)| match demangler(name) {
+                Ok(demangled) if hash_djb2(demangled.as_bytes(), DJB2_HASH_SEED) == *demangled_hash => None,
+                _ => Some(name.clone()),
+            })
+            .collect()
+    }
+}
+
+
+/// Parses a symbol address in hex, with or without a "0x" prefix.
+pub fn parse_address(s: &str) -> Option<u32> {
+    let s = s.strip_prefix("0x").unwrap_or(s);
+    u32::from_str_radix(s, 16).ok()
+}
+
+
+/// The closest a candidate has come to matching a database hash.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct NearestHash {
+    pub candidate: String,
+    pub candidate_hash: u32,
+    pub db_hash: u32,
+    /// Numeric distance between the two hashes
+    pub distance: u32,
+}
+
+
+/// Keeps track of which candidate hash came numerically closest to any
+/// hash in a (sorted) set of database hashes, over the course of a
+/// query. This is purely diagnostic -- being numerically close to a
+/// djb2 hash doesn't mean anything about the strings themselves.
+pub struct NearestHashTracker {
+    sorted_hashes: Vec<u32>,
+    best: Option<NearestHash>,
+}
+
+
+impl NearestHashTracker {
+    /// `sorted_hashes` must be in ascending order.
+    pub fn new(sorted_hashes: Vec<u32>) -> Self {
+        debug_assert!(sorted_hashes.windows(2).all(|w| w[0] <= w[1]));
+        Self{sorted_hashes, best: None}
+    }
+
+    /// Finds the database hash closest to `hash`, and the distance to it.
+    /// (On a tie, the lower one is returned.)
+    pub fn nearest(&self, hash: u32) -> Option<(u32, u32)> {
+        let i = self.sorted_hashes.partition_point(|h| *h < hash);
+        let above = self.sorted_hashes.get(i).map(|h| (*h, h - hash));
+        let below = i.checked_sub(1).map(|i| (self.sorted_hashes[i], hash - self.sorted_hashes[i]));
+        match (below, above) {
+            (Some(below), Some(above)) => Some(if above.1 < below.1 {above} else {below}),
+            (below, above) => below.or(above),
+        }
+    }
+
+    /// Checks a candidate, and remembers it if it's the closest yet.
+    pub fn observe(&mut self, candidate: &str, candidate_hash: u32) {
+        if let Some((db_hash, distance)) = self.nearest(candidate_hash) {
+            if self.best.as_ref().is_none_or(|best| distance < best.distance) {
+                self.best = Some(NearestHash{
+                    candidate: candidate.to_owned(),
+                    candidate_hash,
+                    db_hash,
+                    distance,
+                });
+            }
+        }
+    }
+
+    /// The closest candidate seen so far, if any.
+    pub fn best(&self) -> Option<&NearestHash> {
+        self.best.as_ref()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    use symbol_map_formats::load_symbol_map_from_file;
+
+    const SAMPLE_MAP: &str = "\
+.text section layout
+  00000000 000024 80004000  4 construct__10dWmActor_cFv
+  00000024 000010 80004024  4 hashname_0a6729dd_1b07e645
+.data section layout
+  00000100 000008 80400000  8 hashname_12345678_9abcdef0
+";
+
+    #[test]
+    fn test_new() {
+        let db = SymbolDatabase::new(&load_symbol_map_from_file(Cursor::new(SAMPLE_MAP)).unwrap());
+        assert_eq!(db.contents[&0x0a6729dd][&0x1b07e645], vec![SymbolDatabaseEntry{
+            address: 0x24,
+            mangled_hash: 0x0a6729dd,
+            demangled_hash: 0x1b07e645,
+            mangled_name: None,
+            source: 0,
+        }]);
+        let known = &db.contents[&hash_djb2(b"construct__10dWmActor_cFv", DJB2_HASH_SEED)];
+        assert_eq!(known.values().next().unwrap()[0].mangled_name.as_deref(), Some("construct__10dWmActor_cFv"));
+    }
+
+    #[test]
+    fn test_merge_map() {
+        let mut db = SymbolDatabase::default();
+        db.merge_map(&BasicSymbolMap::from([
+            (0x200, "hashname_0a6729dd_1b07e645".to_owned()),
+        ]), "first.map");
+        db.merge_from_reader(Cursor::new(SAMPLE_MAP), SymbolMapFormat::Dolphin, "second.map").unwrap();
+
+        assert_eq!(db.sources, ["first.map", "second.map"]);
+        assert_eq!(db.num_symbols(), 4);
+        let entries = &db.contents[&0x0a6729dd][&0x1b07e645];
+        let mut tagged: Vec<(u32, Option<&str>)> = entries.iter().map(|entry| (entry.address, db.source_name(entry))).collect();
+        tagged.sort();
+        assert_eq!(tagged, [(0x24, Some("second.map")), (0x200, Some("first.map"))]);
+        let data = &db.contents[&0x12345678][&0x9abcdef0][0];
+        assert_eq!(db.source_name(data), Some("second.map"));
+    }
+
+    #[test]
+    fn test_merge_overlapping_maps() {
+        let mut db = SymbolDatabase::default();
+        db.merge_map(&BasicSymbolMap::from([
+            (0, "hashname_83f4cce8_c1f2528c".to_owned()),
+            (0x24, "hashname_0a6729dd_1b07e645".to_owned()),
+            (0x100, "first__Fv".to_owned()),
+        ]), "first.map");
+        db.merge_from_reader(Cursor::new(SAMPLE_MAP), SymbolMapFormat::Dolphin, "second.map").unwrap();
+        db.merge_map(&BasicSymbolMap::from([
+            (0, "hashname_83f4cce8_c1f2528c".to_owned()),
+        ]), "third.map");
+
+        // One symbol per address, named if any map names it
+        assert_eq!(db.num_symbols(), 3);
+        assert_eq!(db.num_unknown_symbols(), 1);
+        assert_eq!(db.name_at(0), Some("construct__10dWmActor_cFv"));
+        assert_eq!(db.name_at(0x100), Some("first__Fv"));
+        assert_eq!(db.name_at(0x24), Some("hashname_0a6729dd_1b07e645"));
+        let entries: Vec<&SymbolDatabaseEntry> = db.contents[&0x83f4cce8].values().flatten().collect();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(db.source_name(entries[0]), Some("second.map"));
+        assert_eq!(db.source_name(&db.contents[&0x0a6729dd][&0x1b07e645][0]), Some("first.map"));
+    }
+
+    #[test]
+    fn test_demangled_index() {
+        let db = SymbolDatabase::new(&BasicSymbolMap::from([
+            (0, "hashname_0a6729dd_1b07e645".to_owned()),
+            (4, "hashname_12345678_1b07e645".to_owned()),
+            (8, "hashname_12345678_9abcdef0".to_owned()),
+        ]));
+        let mut addresses: Vec<u32> = db.demangled_index[&0x1b07e645].iter().map(|entry| entry.address).collect();
+        addresses.sort();
+        assert_eq!(addresses, [0, 4]);
+        assert_eq!(db.demangled_index[&0x9abcdef0][0].mangled_hash, 0x12345678);
+    }
+
+    #[test]
+    fn test_aggregates() {
+        let db = SymbolDatabase::new(&load_symbol_map_from_file(Cursor::new(SAMPLE_MAP)).unwrap());
+        assert_eq!(db.num_distinct_mangled_hashes(), 3);
+        assert_eq!(db.largest_bucket_size(), 1);
+
+        // (Two symbols with the same mangled hash, but different
+        // demangled ones)
+        let db = SymbolDatabase::new(&BasicSymbolMap::from([
+            (0, "hashname_0a6729dd_1b07e645".to_owned()),
+            (4, "hashname_0a6729dd_00000000".to_owned()),
+            (8, "hashname_12345678_9abcdef0".to_owned()),
+        ]));
+        assert_eq!(db.num_distinct_mangled_hashes(), 2);
+        assert_eq!(db.largest_bucket_size(), 2);
+
+        assert_eq!(SymbolDatabase::default().largest_bucket_size(), 0);
+    }
+
+    #[test]
+    fn test_names() {
+        let db = SymbolDatabase::new(&load_symbol_map_from_file(Cursor::new(SAMPLE_MAP)).unwrap());
+        assert_eq!(db.name_at(0), Some("construct__10dWmActor_cFv"));
+        assert_eq!(db.name_at(0x24), Some("hashname_0a6729dd_1b07e645"));
+        assert_eq!(db.name_at(0x28), None);
+        assert_eq!(db.symbol_before(0x28), Some((0x24, "hashname_0a6729dd_1b07e645")));
+        assert_eq!(db.symbol_before(0x24), Some((0, "construct__10dWmActor_cFv")));
+        assert_eq!(db.symbol_before(0), None);
+
+        assert_eq!(parse_hashname("hashname_0a6729dd_1b07e645"), Some((0x0a6729dd, 0x1b07e645)));
+        assert_eq!(parse_hashname("hashname_0a6729dd"), None);
+        assert_eq!(parse_hashname("construct__10dWmActor_cFv"), None);
+    }
+
+    #[test]
+    fn test_from_reader_matches_two_step_loading() {
+        let two_step = SymbolDatabase::new(&load_symbol_map_from_file(Cursor::new(SAMPLE_MAP)).unwrap());
+        let streamed = SymbolDatabase::from_reader(Cursor::new(SAMPLE_MAP), SymbolMapFormat::Dolphin).unwrap();
+        assert_eq!(streamed, two_step);
+        assert_eq!(streamed.num_symbols(), 3);
+        assert_eq!(streamed.num_unknown_symbols(), 2);
+    }
+
+    #[test]
+    fn test_confirmed_addresses() {
+        let mut db = SymbolDatabase::new(&load_symbol_map_from_file(Cursor::new(SAMPLE_MAP)).unwrap());
+        db.load_confirmed_addresses(Cursor::new("0x24\n\n")).unwrap();
+        assert!(!db.is_unknown(&db.contents[&0x0a6729dd][&0x1b07e645][0]));
+        assert_eq!(db.num_unknown_symbols(), 1);
+        assert!(!db.unknown_contents().contains_key(&0x0a6729dd));
+        assert_eq!(db.sorted_unknown_mangled_hashes(), vec![0x12345678]);
+
+        assert!(db.load_confirmed_addresses(Cursor::new("nope\n")).is_err());
+        assert_eq!(parse_address("80004024"), Some(0x80004024));
+        assert_eq!(parse_address("0x80004024"), Some(0x80004024));
+    }
+
+    #[test]
+    fn test_known_name_alphabet() {
+        let mut db = SymbolDatabase::default();
+        db.insert(0, "ab__Fv");
+        db.insert(4, "b<c>__Fi");
+        db.insert(8, "hashname_0a6729dd_1b07e645");
+        assert_eq!(db.known_name_alphabet(), BTreeSet::from(*b"<>F_abciv"));
+    }
+
+    #[test]
+    fn test_demangler_mismatches() {
+        let map = load_symbol_map_from_file(Cursor::new(format!("{SAMPLE_MAP}  00000200 000008 80400100  8 destruct__10dWmActor_cFv\n"))).unwrap();
+        let mut db = SymbolDatabase::default();
+        db.merge_map(&map, "named.map");
+
+        // Nothing to check against yet
+        let failing_demangler = |_: &str| -> Result<String, Box<dyn Error>> { Err("unsupported".into()) };
+        assert!(db.find_demangler_mismatches(10, failing_demangler).is_empty());
+
+        // Placeholders (with hashes from the real demangler) for the same
+        // symbols, as another map might have
+        let placeholders: BasicSymbolMap = [(0, "construct__10dWmActor_cFv"), (0x200, "destruct__10dWmActor_cFv")].into_iter()
+            .map(|(address, name)| (address, format!("hashname_{:08x}_{:08x}",
+                hash_djb2(name.as_bytes(), DJB2_HASH_SEED), hash_djb2(demangle(name).unwrap().as_bytes(), DJB2_HASH_SEED))))
+            .collect();
+        let mut db = SymbolDatabase::default();
+        db.merge_map(&placeholders, "placeholders.map");
+        db.merge_map(&map, "named.map");
+        assert_eq!(db.demangled_hash_references.len(), 2);
+
+        assert!(db.find_demangler_mismatches(10, demangle).is_empty());
+
+        // A demangler that formats things slightly differently
+        let other_demangler = |name: &str| demangle(name).map(|s| s.replace("( void )", "()"));
+        assert_eq!(db.find_demangler_mismatches(10, other_demangler),
+            vec!["construct__10dWmActor_cFv".to_owned(), "destruct__10dWmActor_cFv".to_owned()]);
+        assert_eq!(db.find_demangler_mismatches(1, other_demangler).len(), 1);
+
+        assert_eq!(db.find_demangler_mismatches(10, failing_demangler).len(), 2);
+        assert!(db.find_demangler_mismatches(0, failing_demangler).is_empty());
+    }
+
+    #[test]
+    fn test_nearest_hash() {
+        let db = SymbolDatabase::new(&load_symbol_map_from_file(Cursor::new(SAMPLE_MAP)).unwrap());
+        assert_eq!(db.sorted_unknown_mangled_hashes(), vec![0x0a6729dd, 0x12345678]);
+
+        let mut tracker = NearestHashTracker::new(db.sorted_unknown_mangled_hashes());
+        assert_eq!(tracker.nearest(0), Some((0x0a6729dd, 0x0a6729dd)));
+        assert_eq!(tracker.nearest(0x0a6729e0), Some((0x0a6729dd, 3)));
+        assert_eq!(tracker.nearest(0x12345670), Some((0x12345678, 8)));
+        assert_eq!(tracker.nearest(0xffffffff), Some((0x12345678, 0xffffffff - 0x12345678)));
+        assert_eq!(tracker.nearest(0x12345678), Some((0x12345678, 0)));
+
+        assert_eq!(tracker.best(), None);
+        tracker.observe("far", 0x40000000);
+        tracker.observe("near", 0x12345670);
+        tracker.observe("nearish", 0x12345600);
+        assert_eq!(tracker.best(), Some(&NearestHash{
+            candidate: "near".to_owned(),
+            candidate_hash: 0x12345670,
+            db_hash: 0x12345678,
+            distance: 8,
+        }));
+
+        assert_eq!(NearestHashTracker::new(Vec::new()).nearest(5), None);
+    }
+}
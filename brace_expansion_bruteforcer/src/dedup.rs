@@ -0,0 +1,59 @@
+use std::collections::{HashSet, VecDeque};
+
+
+/// Remembers the last few distinct candidates, so that duplicates close
+/// to each other can be skipped without keeping every candidate ever
+/// seen in memory.
+pub struct DedupWindow {
+    size: usize,
+    /// Oldest first. Never contains duplicates, since those are
+    /// rejected.
+    recent: VecDeque<String>,
+    recent_set: HashSet<String>,
+}
+
+
+impl DedupWindow {
+    pub fn new(size: usize) -> Self {
+        Self{size, recent: VecDeque::with_capacity(size), recent_set: HashSet::with_capacity(size)}
+    }
+
+    /// Returns true if the candidate is one of the last `size` distinct
+    /// candidates. Otherwise, returns false and remembers it.
+    pub fn check_and_insert(&mut self, candidate: &str) -> bool {
+        if self.size == 0 {
+            return false;
+        }
+        if self.recent_set.contains(candidate) {
+            return true;
+        }
+
+        if self.recent.len() == self.size {
+            if let Some(oldest) = self.recent.pop_front() {
+                self.recent_set.remove(&oldest);
+            }
+        }
+        self.recent.push_back(candidate.to_owned());
+        self.recent_set.insert(candidate.to_owned());
+        false
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dedup_window() {
+        let mut window = DedupWindow::new(2);
+        let seen: Vec<bool> = ["a", "a", "b", "a", "c", "a", "c"].iter()
+            .map(|s| window.check_and_insert(s))
+            .collect();
+        assert_eq!(seen, vec![false, true, false, true, false, false, true]);
+
+        let mut window = DedupWindow::new(0);
+        assert!(!window.check_and_insert("a"));
+        assert!(!window.check_and_insert("a"));
+    }
+}
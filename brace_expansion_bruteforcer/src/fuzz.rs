@@ -0,0 +1,84 @@
+use std::iter::once;
+
+
+/// Bytes that can appear in mangled symbol names.
+pub const MANGLED_NAME_ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789_";
+
+/// Alphabets longer than this are truncated, to keep the number of
+/// variants manageable.
+pub const MAX_FUZZ_ALPHABET_SIZE: usize = 64;
+
+
+/// Yields every string within edit distance 1 of `base`: each single
+/// deletion, then each single substitution (with a different byte from
+/// `alphabet`), then each single insertion (of a byte from `alphabet`).
+///
+/// For a base string of n characters and an alphabet of a bytes (all of
+/// which appear in the base string), that's n + n*(a-1) + (n+1)*a
+/// variants. Duplicates aren't removed (e.g. inserting "a" just before
+/// or just after an existing "a" gives the same string).
+///
+/// `alphabet` should be ASCII, and is truncated to
+/// MAX_FUZZ_ALPHABET_SIZE bytes.
+pub fn edit_distance_1_variants<'a>(base: &'a str, alphabet: &'a [u8]) -> impl Iterator<Item = String> + 'a {
+    let alphabet = &alphabet[..alphabet.len().min(MAX_FUZZ_ALPHABET_SIZE)];
+
+    let deletions = base.char_indices().map(move |(i, c)| {
+        let mut s = base.to_owned();
+        s.replace_range(i..i+c.len_utf8(), "");
+        s
+    });
+
+    let substitutions = base.char_indices().flat_map(move |(i, c)| {
+        alphabet.iter()
+            .filter(move |new_c| **new_c as char != c)
+            .map(move |new_c| {
+                let mut s = base.to_owned();
+                s.replace_range(i..i+c.len_utf8(), (*new_c as char).encode_utf8(&mut [0; 4]));
+                s
+            })
+    });
+
+    let insertions = base.char_indices().map(|(i, _)| i).chain(once(base.len())).flat_map(move |i| {
+        alphabet.iter().map(move |new_c| {
+            let mut s = base.to_owned();
+            s.insert(i, *new_c as char);
+            s
+        })
+    });
+
+    deletions.chain(substitutions).chain(insertions)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_variant_count() {
+        let n = 5;
+        let a = 3;
+        let variants: Vec<String> = edit_distance_1_variants("abcab", b"abc").collect();
+        assert_eq!(variants.len(), n + n * (a - 1) + (n + 1) * a);
+    }
+
+    #[test]
+    fn test_variants() {
+        let variants: Vec<String> = edit_distance_1_variants("ab", b"ax").collect();
+        assert_eq!(variants, vec![
+            // deletions
+            "b", "a",
+            // substitutions
+            "xb", "aa", "ax",
+            // insertions
+            "aab", "xab", "aab", "axb", "aba", "abx",
+        ]);
+    }
+
+    #[test]
+    fn test_alphabet_is_capped() {
+        let alphabet = [b'a'; MAX_FUZZ_ALPHABET_SIZE * 2];
+        assert_eq!(edit_distance_1_variants("", &alphabet).count(), MAX_FUZZ_ALPHABET_SIZE);
+    }
+}
@@ -0,0 +1,2328 @@
+pub mod args;
+pub mod checkpoint;
+pub mod database;
+pub mod dedup;
+pub mod fuzz;
+pub mod lint;
+pub mod namespace;
+pub mod progress;
+pub mod sinks;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufRead, IsTerminal, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use brace_expand_2::{brace_expand_iter, escape_for_pattern, BraceExpandIterator, BraceExpandRangeIterator, InverseSearchResult};
+use djb2_utils::{hash_djb2, invhash_djb2, Djb2Hasher, DJB2_HASH_SEED};
+use nvidia_demangle::{demangle, demangle_into_with_buf_size, is_mangled};
+use symbol_map_formats::dolphin::DolphinSymbolMap;
+use symbol_map_formats::{load_symbol_map_from_path, SymbolMapFormat};
+
+use crate::args::Args;
+use crate::checkpoint::Checkpoint;
+use crate::database::{parse_address, parse_hashname, NearestHashTracker, SymbolDatabase, SymbolDatabaseEntry};
+use crate::dedup::DedupWindow;
+use crate::fuzz::{edit_distance_1_variants, MANGLED_NAME_ALPHABET};
+use crate::lint::lint_pattern;
+use crate::namespace::strip_namespace;
+use crate::progress::ProgressBar;
+use crate::sinks::{push_to_all, FoundMatch, MatchSink, TextSink};
+
+
+const ONLY_ECHO_FIRST: usize = 50;
+const ECHO_INTERVAL: usize = 2_000_000;
+const ECHO_INTERVAL_MAX_FUDGE: usize = 100;
+/// How many candidates from the middle and the end of a search are
+/// shown, with EchoSampling::Boundaries
+const ECHO_MIDDLE: usize = 10;
+const ECHO_LAST: usize = 50;
+
+const PREVIEW_FIRST: usize = 10;
+const PREVIEW_MIDDLE: usize = 5;
+const PREVIEW_LAST: usize = 10;
+
+/// How many known symbols to check the demangler against when loading a
+/// symbol map.
+const DEMANGLER_CHECK_SAMPLES: usize = 16;
+/// File that confirmed addresses are saved to, and loaded from at startup
+pub const CONFIRMED_ADDRESSES_PATH: &str = "confirmed.txt";
+/// How many times longer than a mangled candidate its demangled form is
+/// assumed to possibly be, when checking it against the demangle buffer
+const DEMANGLE_BUF_WARNING_RATIO: usize = 2;
+/// How many ranges of expansions to split a parallel search into per
+/// thread, so that threads that finish early can pick up more work
+const RANGES_PER_THREAD: usize = 4;
+/// Patterns with more expansions than this ask for confirmation before
+/// being searched, by default, in the REPL (see the "maxexpand" command)
+pub const DEFAULT_MAX_EXPANSIONS: usize = 1_000_000_000;
+
+
+pub fn make_pattern_shorthands() -> HashMap<String, String> {
+    let primitives = "{,P,R}{,C}{{,U,S}{c,s,i,l},f,b}";
+    HashMap::from([
+        ("END1".to_owned(), format!("{{,C}}F{{v,{primitives}}}", )),
+        ("END2".to_owned(), format!("{{,C}}F{{v,{primitives},{primitives}{primitives}}}")),
+        ("END3".to_owned(), format!("{{,C}}F{{v,{primitives},{primitives}{primitives},{primitives}{primitives}{primitives}}}")),
+    ])
+}
+
+
+/// Which candidates a search shows (besides matches), once it's past
+/// the first ONLY_ECHO_FIRST.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub enum EchoSampling {
+    /// One every ECHO_INTERVAL or so
+    #[default]
+    Periodic,
+    /// The last ECHO_LAST, and ECHO_MIDDLE spread evenly in between, to
+    /// show the boundaries of the pattern
+    Boundaries,
+}
+
+
+/// Settings that affect how patterns are processed.
+#[derive(Clone, Debug)]
+pub struct Settings {
+    pub escaping_enabled: bool,
+    /// Directories to search for word lists in, highest precedence first
+    pub word_list_dirs: Vec<PathBuf>,
+    /// Candidates shorter than this are only checked against mangled
+    /// hashes, and never demangled
+    pub min_demangle_length: usize,
+    /// Initial size of the buffer demangled names are written into (it
+    /// grows for longer ones, but that's slower)
+    pub demangle_buf_size: usize,
+    /// Candidates containing any of these are skipped without hashing
+    pub excluded_substrings: Vec<String>,
+    /// At the end of each query, report how close the nearest candidate
+    /// hash came to an unknown symbol's mangled hash
+    pub report_nearest_hash: bool,
+    /// Candidates that are the same as one of the last this-many
+    /// (distinct) candidates are skipped. 0 disables this.
+    pub dedup_window: usize,
+    /// If set, this namespace is removed from the start of each
+    /// candidate (where present) before it's hashed
+    pub strip_namespace: Option<String>,
+    /// Work backwards from the unknown symbols' hashes to skip
+    /// candidates that can't match, instead of hashing every one
+    pub inverse_prune: bool,
+    /// Number of threads to search with. With more than 1, symbols are
+    /// shown in whatever order the threads get to them.
+    pub threads: usize,
+    /// Patterns with more expansions than this ask for confirmation
+    /// first. 0 means never ask.
+    pub max_expansions: usize,
+    /// Asks the user whether to search a pattern with more than
+    /// `max_expansions` expansions. If None (when nobody's there to
+    /// answer, like in batch mode), such patterns are skipped with an
+    /// error instead.
+    pub confirm_large_patterns: Option<fn(&str) -> bool>,
+    /// Match candidates on just their demangled hashes, ignoring the
+    /// mangled ones (which means demangling every candidate)
+    pub demangled_only: bool,
+    /// Which candidates to show, other than matches
+    pub echo_sampling: EchoSampling,
+    /// If set, single-threaded searches save their progress here every
+    /// `checkpoint_interval` candidates (see the "resume" command)
+    pub checkpoint_path: Option<PathBuf>,
+    pub checkpoint_interval: usize,
+    pub verbose: bool,
+}
+
+
+/// Replaces any "P[" "]" pairs with length prefixes, in-place.
+///
+/// This runs on each expansion, after everything else (shorthands, word
+/// lists, brace expansion), so the lengths are of the final bytes.
+fn apply_square_bracket_length_prefix_substitution(s: &mut String) {
+    if !s.contains("P[") {
+        return;
+    }
+
+    // This is one pass over the string, keeping a stack of where each
+    // open group's contents start in the output. Inner groups are
+    // closed (and get their prefixes) before outer ones, so outer
+    // lengths include the inner prefixes.
+    let bytes = s.as_bytes();
+    let mut result = String::with_capacity(s.len() + 8);
+    let mut open_group_starts = Vec::new();
+    let mut copied_up_to = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'P' && bytes.get(i + 1) == Some(&b'[') {
+            result.push_str(&s[copied_up_to..i]);
+            open_group_starts.push(result.len());
+            i += 2;
+            copied_up_to = i;
+        } else if bytes[i] == b']' && !open_group_starts.is_empty() {
+            result.push_str(&s[copied_up_to..i]);
+            let start = open_group_starts.pop().unwrap();
+            result.insert_str(start, &(result.len() - start).to_string());
+            i += 1;
+            copied_up_to = i;
+        } else {
+            i += 1;
+        }
+    }
+    result.push_str(&s[copied_up_to..]);
+
+    // Groups that are never closed are left as they were
+    for start in open_group_starts.into_iter().rev() {
+        result.insert_str(start, "P[");
+    }
+
+    *s = result;
+}
+
+
+/// Replaces any "[?" "]" pairs (optional groups) with "{," "}", in-place.
+/// So "foo[?_bar]" becomes "foo{,_bar}", which expands to "foo" and
+/// "foo_bar". Optional groups can be nested, and can contain other
+/// square bracket pairs (like "P[" "]").
+fn apply_square_bracket_optional_substitution(s: &mut String) {
+    if !s.contains("[?") {
+        return;
+    }
+
+    let mut result = String::with_capacity(s.len());
+    // Whether each currently open square bracket is an optional group
+    let mut open_brackets = Vec::new();
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '[' if chars.peek() == Some(&'?') => {
+                chars.next();
+                open_brackets.push(true);
+                result.push_str("{,");
+            }
+            '[' => {
+                open_brackets.push(false);
+                result.push(c);
+            }
+            ']' if open_brackets.pop() == Some(true) => result.push('}'),
+            _ => result.push(c),
+        }
+    }
+
+    *s = result;
+}
+
+
+/// Finds the "]" that closes the "[" at the start of `s`, taking nested
+/// square brackets into account.
+fn find_matching_close_bracket(s: &str) -> Option<usize> {
+    let mut depth = 0usize;
+    for (i, c) in s.bytes().enumerate() {
+        match c {
+            b'[' => depth += 1,
+            b']' => {
+                depth = depth.checked_sub(1)?;
+                if depth == 0 {
+                    return Some(i);
+                }
+            },
+            _ => {},
+        }
+    }
+    None
+}
+
+
+/// Replaces any "C[" "]" pairs with every capitalization of their
+/// contents, in-place. So "C[ab]" becomes "{a,A}{b,B}". Anything other
+/// than ASCII letters is left as it is.
+///
+/// Character classes inside are expanded first, so "C[[a-b]x]" works.
+/// Any other square brackets inside ("C[P[ab]]") aren't supported, so
+/// those are left alone. This runs before apply_char_class_substitution(),
+/// so a "C" right before a class always makes it a case permutation
+/// ("C[a-c]" is "{a,A}-{c,C}"); a literal "C" has to be separated from
+/// the class, like "{C}[a-c]".
+fn apply_case_permutation_substitution(s: &mut String) {
+    let mut search_from = 0;
+    while let Some(found_byte_idx) = s[search_from..].find("C[") {
+        let open_bracket_byte_idx = search_from + found_byte_idx + 1;
+        let contents = find_matching_close_bracket(&s[open_bracket_byte_idx..]).and_then(|close_bracket_byte_idx| {
+            let close_bracket_byte_idx = open_bracket_byte_idx + close_bracket_byte_idx;
+            let mut contents = s[open_bracket_byte_idx+1..close_bracket_byte_idx].to_owned();
+            apply_char_class_substitution(&mut contents);
+            (!contents.contains(['[', ']'])).then_some((close_bracket_byte_idx, contents))
+        });
+        let Some((close_bracket_byte_idx, contents)) = contents else {
+            search_from = open_bracket_byte_idx + 1;
+            continue;
+        };
+
+        let mut permutations = String::new();
+        for c in contents.chars() {
+            if c.is_ascii_alphabetic() {
+                permutations.push('{');
+                permutations.push(c.to_ascii_lowercase());
+                permutations.push(',');
+                permutations.push(c.to_ascii_uppercase());
+                permutations.push('}');
+            } else {
+                permutations.push(c);
+            }
+        }
+
+        s.replace_range(
+            open_bracket_byte_idx-1..close_bracket_byte_idx+1,
+            &permutations);
+        search_from = open_bracket_byte_idx - 1 + permutations.len();
+    }
+}
+
+
+/// Lists the characters in the contents of a character class (like
+/// "a-z" or "xyz0-9"), in order and without duplicates. A "-" at either
+/// end is literal. Returns None if it isn't a valid class (if it's empty
+/// or has a backwards range).
+fn parse_char_class(contents: &str) -> Option<Vec<char>> {
+    let chars: Vec<char> = contents.chars().collect();
+    if chars.is_empty() {
+        return None;
+    }
+
+    let mut result = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if i + 2 < chars.len() && chars[i + 1] == '-' {
+            let (first, last) = (chars[i], chars[i + 2]);
+            if first > last {
+                return None;
+            }
+            result.extend(first..=last);
+            i += 3;
+        } else {
+            result.push(chars[i]);
+            i += 1;
+        }
+    }
+
+    let mut seen = HashSet::new();
+    result.retain(|c| seen.insert(*c));
+    Some(result)
+}
+
+
+/// Replaces character classes ("[a-z]", "[a-zA-Z0-9_]") with braces
+/// choosing one of their characters ("{a,b,...,z}"), in-place. Square
+/// brackets that belong to other substitutions ("P[", "W[", "C[", "[?")
+/// or that contain other square brackets are left alone. (Case
+/// permutations are substituted first, though, so by the time this runs,
+/// a "C" right before a class has already made it one: see
+/// apply_case_permutation_substitution().) Characters that are special
+/// in patterns are escaped, so those only work with backslash-escapes
+/// enabled.
+fn apply_char_class_substitution(s: &mut String) {
+    if !s.contains('[') {
+        return;
+    }
+
+    let mut result = String::with_capacity(s.len());
+    let mut rest = s.as_str();
+    while let Some(open_bracket_byte_idx) = rest.find('[') {
+        let (before, after) = (&rest[..open_bracket_byte_idx], &rest[open_bracket_byte_idx+1..]);
+        result.push_str(before);
+
+        let is_other_substitution = before.ends_with(['P', 'W', 'C']) || after.starts_with('?');
+        let class = after.find(']')
+            .filter(|close_bracket_byte_idx| !is_other_substitution && !after[..*close_bracket_byte_idx].contains('['))
+            .and_then(|close_bracket_byte_idx| parse_char_class(&after[..close_bracket_byte_idx]).map(|chars| (close_bracket_byte_idx, chars)));
+
+        if let Some((close_bracket_byte_idx, chars)) = class {
+            let choices: Vec<String> = chars.iter().map(|c| escape_for_pattern(&c.to_string())).collect();
+            result.push('{');
+            result.push_str(&choices.join(","));
+            result.push('}');
+            rest = &after[close_bracket_byte_idx+1..];
+        } else {
+            result.push('[');
+            rest = after;
+        }
+    }
+    result.push_str(rest);
+
+    *s = result;
+}
+
+
+/// Returns the path to the word list file in the first directory (in
+/// order of precedence) that has it.
+fn find_word_list_file(file_name: &str, dirs: &[PathBuf]) -> Option<PathBuf> {
+    dirs.iter().map(|dir| dir.join(file_name)).find(|path| path.is_file())
+}
+
+
+/// Word lists used by "W[...]" substitutions, kept in memory by file
+/// name so that each file is only read once (until the cache is
+/// cleared with the "reload wordlists" command).
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct WordListCache {
+    lists: HashMap<String, Vec<String>>,
+}
+
+
+impl WordListCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the words in a word list file, reading it (from the first
+    /// of `dirs` that has it) if it isn't cached yet. Files that can't be
+    /// opened aren't cached, so they're tried again next time. Invalid
+    /// UTF-8 is replaced with U+FFFD, rather than cutting the list short.
+    pub fn get(&mut self, file_name: &str, dirs: &[PathBuf], verbose: bool) -> Option<&[String]> {
+        if !self.lists.contains_key(file_name) {
+            let path = find_word_list_file(file_name, dirs)?;
+            let contents = fs::read(&path).ok()?;
+            if verbose {
+                println!("Loaded word list {file_name} from {}", path.display());
+            }
+            let words = String::from_utf8_lossy(&contents).lines().map(str::to_owned).collect();
+            self.lists.insert(file_name.to_owned(), words);
+        }
+        self.lists.get(file_name).map(Vec::as_slice)
+    }
+
+    /// Forgets all of the word lists, so they're re-read when next used.
+    pub fn clear(&mut self) {
+        self.lists.clear();
+    }
+}
+
+
+/// Replaces any "W[" "]" pairs with word lists, in-place.
+fn apply_square_bracket_word_list_substitution(s: &mut String, word_lists: &mut WordListCache, word_list_dirs: &[PathBuf], verbose: bool) {
+    while let Some(open_bracket_byte_idx) = s.find("W[") {
+        if let Some(close_bracket_byte_idx) = s[open_bracket_byte_idx+2..].find(']') {
+            let close_bracket_byte_idx = open_bracket_byte_idx + 2 + close_bracket_byte_idx;
+            let word_list_name = &s[open_bracket_byte_idx+2..close_bracket_byte_idx];
+            let mut word_list_name = word_list_name.to_owned();
+            word_list_name.push_str(".txt");
+
+            let mut word_list_pattern = "{".to_owned();
+            if let Some(words) = word_lists.get(&word_list_name, word_list_dirs, verbose) {
+                for word in words {
+                    word_list_pattern.push_str(&escape_for_pattern(word));
+                    word_list_pattern.push(',');
+                }
+                word_list_pattern.replace_range(word_list_pattern.len()-1..word_list_pattern.len(), "}");
+
+                s.replace_range(
+                    open_bracket_byte_idx..close_bracket_byte_idx+1,
+                    &word_list_pattern);
+            } else {
+                println!("WARNING: Couldn't open {word_list_name}");
+                break;
+            }
+        } else {
+            // TODO: um...?
+            break;
+        }
+    }
+}
+
+
+/// Applies the global pattern-shorthand replacements and returns a new
+/// String.
+fn apply_pattern_shorthands(s: &str) -> String {
+    let s = s.to_owned();
+    make_pattern_shorthands().iter().fold(s, |acc, kv| acc.replace(kv.0, kv.1))
+}
+
+
+/// Returns the running djb2 state after each byte of the string
+/// (starting from the default seed, which isn't itself included).
+fn hash_trace(s: &[u8]) -> Vec<u32> {
+    let mut hasher = Djb2Hasher::default();
+    s.iter().map(|c| {
+        hasher.push(*c);
+        hasher.state()
+    }).collect()
+}
+
+
+/// Prints the intermediate djb2 states for a string, one byte per line.
+fn print_hash_trace(s: &str) {
+    println!("     seed | {DJB2_HASH_SEED:08x}");
+    for (c, state) in s.bytes().zip(hash_trace(s.as_bytes())) {
+        println!("{:>4} ({c:02x}) | {state:08x}", format!("{:?}", c as char));
+    }
+}
+
+
+/// Describes the djb2 hash of a string, and of its demangled form if it
+/// looks like a mangled name. (The hash of an empty string is just the
+/// seed.)
+fn describe_hashes(s: &str) -> Vec<String> {
+    if s.is_empty() {
+        return vec![format!("{DJB2_HASH_SEED:08x} | (empty string, so this is just the seed)")];
+    }
+
+    let mut lines = vec![format!("{:08x} | {s}", hash_djb2(s.as_bytes(), DJB2_HASH_SEED))];
+    if is_mangled(s) {
+        match demangle(s) {
+            Ok(demangled) => lines.push(format!("{:08x} | {demangled} (demangled)", hash_djb2(demangled.as_bytes(), DJB2_HASH_SEED))),
+            Err(e) => lines.push(format!("(Couldn't demangle it: {e})")),
+        }
+    }
+    lines
+}
+
+
+/// Parses "<hex hash> <suffix>" and undoes the suffix from the hash,
+/// giving the hash that everything before the suffix must have.
+fn unhash(arg: &str) -> Result<u32, String> {
+    let (hash, suffix) = arg.split_once(' ').unwrap_or((arg, ""));
+    let hash = parse_address(hash).ok_or_else(|| format!("\"{hash}\" isn't a hex hash"))?;
+    Ok(invhash_djb2(suffix.as_bytes(), hash))
+}
+
+
+/// Describes the bytes used in the database's known symbol names, for
+/// checking them against what patterns produce. Bytes outside
+/// MANGLED_NAME_ALPHABET are listed separately, since they may point to
+/// an encoding problem (or just be unusual template arguments).
+fn describe_db_alphabet(alphabet: &BTreeSet<u8>) -> Vec<String> {
+    let (Some(min), Some(max)) = (alphabet.first(), alphabet.last()) else {
+        return vec!["No known symbol names in the database.".to_owned()];
+    };
+
+    let show = |c: &u8| if c.is_ascii_graphic() {(*c as char).to_string()} else {format!("\\x{c:02x}")};
+    let unexpected: Vec<String> = alphabet.iter()
+        .filter(|c| !MANGLED_NAME_ALPHABET.contains(c))
+        .map(show)
+        .collect();
+
+    let mut lines = vec![
+        format!("{} distinct bytes: {}", alphabet.len(), alphabet.iter().map(show).collect::<String>()),
+        format!("Lowest: {} ({min:02x}), highest: {} ({max:02x})", show(min), show(max)),
+    ];
+    if !unexpected.is_empty() {
+        lines.push(format!("Outside the usual mangled-name alphabet: {}", unexpected.join(" ")));
+    }
+    lines
+}
+
+
+/// Checks that a Dolphin map survives being written out and loaded back
+/// in, and prints any differences.
+fn process_verify_roundtrip_command(path: &Path) -> Result<(), Box<dyn Error>> {
+    let differences = DolphinSymbolMap::verify_roundtrip(File::open(path)?)?;
+    if differences.is_empty() {
+        println!("{} round-trips losslessly.", path.display());
+    } else {
+        println!("{} doesn't round-trip losslessly ({} difference{}):",
+            path.display(), differences.len(), if differences.len() == 1 {""} else {"s"});
+        for difference in differences {
+            println!("- {difference}");
+        }
+    }
+    Ok(())
+}
+
+
+/// Applies shorthands and word lists to a line, and strips whitespace.
+/// Length prefixes are left alone, since they're applied to each
+/// expansion afterwards (see apply_square_bracket_length_prefix_substitution()).
+fn preprocess_pattern(raw_line: &str, settings: &Settings, word_lists: &mut WordListCache) -> String {
+    let mut line = apply_pattern_shorthands(raw_line);
+    apply_square_bracket_optional_substitution(&mut line);
+    apply_case_permutation_substitution(&mut line);
+    apply_char_class_substitution(&mut line);
+    apply_square_bracket_word_list_substitution(&mut line, word_lists, &settings.word_list_dirs, settings.verbose);
+    line.retain(|c| !c.is_whitespace());
+    line
+}
+
+
+/// Runs a line as a pattern, and returns the number of new matches.
+/// `line_number` is where the line was in its batch (see FoundMatch).
+pub fn process_line_as_pattern(raw_line: &str, line_number: usize, db: &mut SymbolDatabase, settings: &Settings, word_lists: &mut WordListCache, sinks: &mut [Box<dyn MatchSink>]) -> usize {
+    process_line_as_pattern_from(raw_line, line_number, 0, db, settings, word_lists, sinks)
+}
+
+
+/// Same as process_line_as_pattern(), but starts from the expansion with
+/// index `start_index` instead of the first one.
+pub fn process_line_as_pattern_from(raw_line: &str, line_number: usize, start_index: usize, db: &mut SymbolDatabase, settings: &Settings, word_lists: &mut WordListCache, sinks: &mut [Box<dyn MatchSink>]) -> usize {
+    let line = preprocess_pattern(raw_line, settings, word_lists);
+
+    // (Parsing errors are reported by the search itself)
+    if let Ok(iter) = brace_expand_iter(&line, settings.escaping_enabled) {
+        let num_expansions = iter.num_expansions_checked();
+        if needs_confirmation(num_expansions, settings.max_expansions) {
+            let num_expansions_str = num_expansions.map_or_else(|| format!("more than {}", usize::MAX), |n| n.to_string());
+            match settings.confirm_large_patterns {
+                Some(confirm) => if !confirm(&format!("This pattern expands to {num_expansions_str} symbols, so it will take a long time. Continue?")) {
+                    println!("Skipped.");
+                    return 0;
+                },
+                None => {
+                    println!("Error: skipped pattern on line {line_number}, since it expands to {num_expansions_str} symbols (the limit is {}; see --max-expansions)", settings.max_expansions);
+                    return 0;
+                },
+            }
+        }
+    }
+
+    let origin = MatchOrigin{pattern: raw_line, line: line_number};
+    let num_new_matches = search_pattern_from(&line, &origin, start_index, db, settings, sinks).num_new_matches;
+    if num_new_matches == 0 {
+        print_pattern_suggestions(raw_line, &line, settings.escaping_enabled);
+    }
+    num_new_matches
+}
+
+
+/// Whether searching this many expansions (None if it's too many to
+/// count) needs confirmation first.
+fn needs_confirmation(num_expansions: Option<usize>, max_expansions: usize) -> bool {
+    max_expansions > 0 && num_expansions.is_none_or(|n| n > max_expansions)
+}
+
+
+/// Asks a yes-or-no question on stdin. Anything other than "y" or "yes"
+/// (including an error or end of input) means no.
+pub fn confirm_on_stdin(question: &str) -> bool {
+    print!("{question} [y/N] ");
+    io::stdout().flush().ok();
+    let mut answer = String::new();
+    if io::stdin().lock().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+
+/// Prints hints about likely mistakes in a pattern that didn't find
+/// anything.
+fn print_pattern_suggestions(raw_line: &str, line: &str, escape: bool) {
+    let shorthands = make_pattern_shorthands();
+    let mut shorthand_names: Vec<&str> = shorthands.keys().map(String::as_str).collect();
+    shorthand_names.sort();
+
+    for suggestion in lint_pattern(raw_line, line, escape, &shorthand_names) {
+        println!("Hint: {suggestion}");
+    }
+}
+
+
+/// Prints the structure of a pattern (after substitutions) as a
+/// Graphviz DOT graph.
+fn process_graph_command(raw_line: &str, settings: &Settings, word_lists: &mut WordListCache) {
+    let line = preprocess_pattern(raw_line, settings, word_lists);
+    match brace_expand_iter(&line, settings.escaping_enabled) {
+        Ok(iter) => print!("{}", iter.to_dot()),
+        Err(e) => println!("Parsing failure: {e}"),
+    }
+}
+
+
+/// Picks which expansions to show in a preview: the first and last few,
+/// plus some evenly spaced ones from the middle. Sorted, and without
+/// duplicates.
+fn preview_sample_indices(num_expansions: usize, first: usize, middle: usize, last: usize) -> Vec<usize> {
+    let mut indices = BTreeSet::new();
+    indices.extend(0..first.min(num_expansions));
+    indices.extend(num_expansions.saturating_sub(last)..num_expansions);
+    for k in 1..=middle {
+        let index = ((num_expansions as u128) * (k as u128) / (middle as u128 + 1)) as usize;
+        if index < num_expansions {
+            indices.insert(index);
+        }
+    }
+    indices.into_iter().collect()
+}
+
+
+/// Samples a pattern's expansions for a preview, jumping straight to
+/// each one instead of iterating through everything in between.
+fn preview_samples(iter: &BraceExpandIterator) -> Vec<(usize, String)> {
+    preview_sample_indices(iter.num_expansions(), PREVIEW_FIRST, PREVIEW_MIDDLE, PREVIEW_LAST)
+        .into_iter()
+        .filter_map(|i| iter.nth_expansion(i).map(|mut s| {
+            apply_square_bracket_length_prefix_substitution(&mut s);
+            (i, s)
+        }))
+        .collect()
+}
+
+
+/// Prints the first and last few expansions of a pattern, and a few from
+/// the middle, without searching for anything.
+fn process_preview_command(raw_line: &str, settings: &Settings, word_lists: &mut WordListCache) {
+    let line = preprocess_pattern(raw_line, settings, word_lists);
+    let iter = match brace_expand_iter(&line, settings.escaping_enabled) {
+        Ok(iter) => iter,
+        Err(e) => {
+            println!("Parsing failure: {e}");
+            return;
+        }
+    };
+
+    let num_expansions = iter.num_expansions();
+    println!("Previewing {num_expansions} symbol{}:", if num_expansions == 1 {""} else {"s"});
+    let mut prev_index = None;
+    for (i, sym_mangled) in preview_samples(&iter) {
+        if prev_index.is_some_and(|prev_index| i > prev_index + 1) {
+            println!("{:>12} |", "...");
+        }
+        prev_index = Some(i);
+        let sym_demangled = demangle(&sym_mangled).unwrap_or_else(|_| "ERROR".to_string());
+        println!("{i:>12} | {sym_mangled:<40} | {sym_demangled}");
+    }
+}
+
+
+/// Runs every edit-distance-1 variant of a string through the search,
+/// and returns the number of new matches.
+fn process_fuzz_command(base: &str, origin: &MatchOrigin, db: &mut SymbolDatabase, settings: &Settings, sinks: &mut [Box<dyn MatchSink>]) -> usize {
+    let variants: Vec<String> = edit_distance_1_variants(base, MANGLED_NAME_ALPHABET)
+        .map(|variant| escape_for_pattern(&variant))
+        .collect();
+    let pattern = format!("{{{}}}", variants.join(","));
+
+    search_pattern_with_stats(&pattern, origin, db, &Settings{escaping_enabled: true, ..settings.clone()}, sinks).num_new_matches
+}
+
+
+/// Demangles a candidate symbol name into `out` (with this thread's
+/// Demangler, so nothing is allocated per candidate), unless it's too
+/// short to be worth it (in which case this returns false).
+fn demangle_candidate(sym_mangled: &str, min_length: usize, buf_size: usize, out: &mut String) -> bool {
+    if sym_mangled.len() < min_length {
+        return false;
+    }
+    if demangle_into_with_buf_size(sym_mangled, buf_size, out).is_err() {
+        out.clear();
+        out.push_str("ERROR");
+    }
+    true
+}
+
+
+/// The longest a candidate from a pattern can be after length-prefix
+/// substitution, given the longest expansion of the pattern. Each "P[...]"
+/// replaces three bytes with the decimal length of its contents, which can
+/// be more than three digits for very long contents.
+fn max_candidate_length(pattern: &str, max_expansion_length: usize) -> usize {
+    let num_length_prefixes = pattern.matches("P[").count();
+    let growth_per_prefix = max_expansion_length.to_string().len().saturating_sub(3);
+    max_expansion_length.saturating_add(num_length_prefixes.saturating_mul(growth_per_prefix))
+}
+
+
+/// Returns a warning if candidates of the given maximum length might not
+/// fit in the initial demangle buffer once demangled (so it'd have to be
+/// grown, and the name demangled again). Demangled names are usually
+/// longer than mangled ones, so this warns once the mangled length is
+/// within a factor of DEMANGLE_BUF_WARNING_RATIO of the buffer size.
+fn demangle_buffer_warning(max_candidate_length: usize, buf_size: usize) -> Option<String> {
+    (max_candidate_length.saturating_mul(DEMANGLE_BUF_WARNING_RATIO) >= buf_size).then(|| format!(
+        "Warning: candidates can be up to {max_candidate_length} bytes long, which is close to or over the demangle buffer size ({buf_size} bytes). \
+        Long candidates will have to be demangled twice, which is slower -- consider a larger --demangle-buf."))
+}
+
+
+/// Checks whether a candidate contains any of the excluded substrings.
+fn is_excluded(sym_mangled: &str, excluded_substrings: &[String]) -> bool {
+    excluded_substrings.iter().any(|excluded| sym_mangled.contains(excluded.as_str()))
+}
+
+
+/// Where a search came from, for attributing matches to it.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct MatchOrigin<'a> {
+    /// The command as it was entered
+    pub pattern: &'a str,
+    /// Line number within the batch (1-based), or 0 if not in a batch
+    pub line: usize,
+}
+
+
+/// Statistics about one run of search_pattern_with_stats().
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct SearchStats {
+    pub num_new_matches: usize,
+    /// Candidates produced by the pattern (including skipped ones)
+    pub num_candidates: usize,
+    /// Hashes calculated (with inverse pruning, this includes partial
+    /// candidates)
+    pub num_hashed: usize,
+    /// Candidates that were actually demangled
+    pub num_demangled: usize,
+    /// Total time spent demangling
+    pub demangle_time: Duration,
+    /// Total time for the whole query
+    pub query_time: Duration,
+}
+
+
+impl SearchStats {
+    /// Candidates produced per second, over the whole query.
+    pub fn candidate_rate(&self) -> f64 {
+        self.num_candidates as f64 / self.query_time.as_secs_f64()
+    }
+
+    /// Demangles per second, counting only time spent demangling.
+    pub fn demangle_rate(&self) -> f64 {
+        self.num_demangled as f64 / self.demangle_time.as_secs_f64()
+    }
+}
+
+
+/// Where a search gets its candidates from.
+enum Candidates {
+    /// Every expansion of the pattern
+    All(Box<BraceExpandIterator>),
+    /// Only the expansions found by inverse pruning
+    Pruned(std::vec::IntoIter<String>),
+}
+
+
+impl Candidates {
+    fn next_into(&mut self, output: &mut String) -> bool {
+        match self {
+            Self::All(iter) => iter.next_into(output),
+            Self::Pruned(iter) => match iter.next() {
+                Some(candidate) => {
+                    *output = candidate;
+                    true
+                },
+                None => false,
+            },
+        }
+    }
+}
+
+
+/// Finds the expansions of a pattern whose mangled hashes match unknown
+/// symbols, by working backwards from those hashes (see
+/// BraceExpandIterator::find_by_hash()). Returns None (after saying so)
+/// if that isn't possible for this pattern. Length prefixes and
+/// namespace stripping change candidates after they're expanded, so
+/// they rule it out, as does a checksum. So does matching on demangled
+/// hashes only, since the pruning works on mangled ones.
+fn inverse_prune(line: &str, iter: &BraceExpandIterator, db: &SymbolDatabase, settings: &Settings) -> Option<InverseSearchResult> {
+    let pruned = if line.contains("P[") || settings.strip_namespace.is_some() || settings.demangled_only {
+        None
+    } else {
+        let targets: HashSet<u32> = db.sorted_unknown_mangled_hashes().into_iter().collect();
+        iter.find_by_hash(DJB2_HASH_SEED, &targets)
+    };
+    if pruned.is_none() {
+        println!("(Inverse pruning isn't possible for this pattern, so checking every symbol instead.)");
+    }
+    pruned
+}
+
+
+/// The database symbols matching a candidate. Normally that means both
+/// hashes (`matching_mangled_db` is what matched the mangled hash), but
+/// with `demangled_only`, only the demangled hash has to match.
+fn matching_entries<'a>(db: &'a SymbolDatabase, matching_mangled_db: Option<&'a HashMap<u32, Vec<SymbolDatabaseEntry>>>, hash_demangled: u32, demangled_only: bool) -> Option<&'a Vec<SymbolDatabaseEntry>> {
+    if demangled_only {
+        db.demangled_index.get(&hash_demangled)
+    } else {
+        matching_mangled_db.and_then(|matching_mangled_db| matching_mangled_db.get(&hash_demangled))
+    }
+}
+
+
+/// Whether the candidate at index `i` should be shown even if it doesn't
+/// match anything: the first ONLY_ECHO_FIRST, and a sample of the rest.
+/// (`echo_interval_fudge` is jitter for the sample; see
+/// search_pattern_from().)
+fn should_echo(i: usize, echo_interval_fudge: usize) -> bool {
+    i < ONLY_ECHO_FIRST || (i + echo_interval_fudge).is_multiple_of(ECHO_INTERVAL)
+}
+
+
+/// Decides which candidates a search shows, according to its
+/// EchoSampling.
+struct EchoSampler {
+    /// With EchoSampling::Boundaries, the indices of the candidates to
+    /// show
+    boundary_indices: Option<HashSet<usize>>,
+}
+
+
+impl EchoSampler {
+    fn new(sampling: EchoSampling, num_expansions: usize) -> Self {
+        let boundary_indices = (sampling == EchoSampling::Boundaries).then(|| {
+            preview_sample_indices(num_expansions, ONLY_ECHO_FIRST, ECHO_MIDDLE, ECHO_LAST).into_iter().collect()
+        });
+        Self{boundary_indices}
+    }
+
+    /// Whether the candidate at index `i` should be shown even if it
+    /// doesn't match anything. (`echo_interval_fudge` is only used for
+    /// periodic sampling; see should_echo().)
+    fn should_echo(&self, i: usize, echo_interval_fudge: usize) -> bool {
+        match &self.boundary_indices {
+            Some(indices) => indices.contains(&i),
+            None => should_echo(i, echo_interval_fudge),
+        }
+    }
+
+    /// Describes the sample, for once the first ONLY_ECHO_FIRST have
+    /// been shown.
+    fn describe(&self) -> String {
+        match self.boundary_indices {
+            Some(_) => format!("For performance, only the first {ONLY_ECHO_FIRST} symbols are displayed (above), plus {ECHO_MIDDLE} from the middle and the last {ECHO_LAST} (below):"),
+            None => format!("For performance, only the first {ONLY_ECHO_FIRST} symbols are displayed (above), plus a small sample of the rest (below):"),
+        }
+    }
+}
+
+
+/// Counts of what happened to the candidates in a search (or in one
+/// range of a parallel one).
+#[derive(Clone, Copy, Default)]
+struct SearchCounters {
+    num_excluded: usize,
+    num_duplicates: usize,
+    num_hashed: usize,
+    num_demangled: usize,
+    demangle_time: Duration,
+}
+
+
+impl SearchCounters {
+    fn add(&mut self, other: &Self) {
+        self.num_excluded += other.num_excluded;
+        self.num_duplicates += other.num_duplicates;
+        self.num_hashed += other.num_hashed;
+        self.num_demangled += other.num_demangled;
+        self.demangle_time += other.demangle_time;
+    }
+}
+
+
+/// A candidate worth showing: one that matched an unknown symbol, or
+/// that was going to be echoed anyway.
+struct CheckedCandidate<'a> {
+    hash_mangled: u32,
+    /// None if it was too short to demangle
+    hash_demangled: Option<u32>,
+    sym_demangled: String,
+    /// Whether it matched symbols, but only ones that are already known
+    known: bool,
+    /// The unknown symbols it matched
+    new_symbols: Vec<&'a SymbolDatabaseEntry>,
+}
+
+
+/// Checks candidates against the database one at a time. This is the
+/// part of a search that's the same for every candidate, whether it's
+/// serial or parallel (where each range gets its own checker).
+struct CandidateChecker<'a> {
+    db: &'a SymbolDatabase,
+    unknown_db_contents: &'a HashMap<u32, HashMap<u32, Vec<SymbolDatabaseEntry>>>,
+    settings: &'a Settings,
+    dedup_window: DedupWindow,
+    nearest_hash_tracker: Option<NearestHashTracker>,
+    counters: SearchCounters,
+    /// Reused for every candidate's demangled name
+    sym_demangled: String,
+}
+
+
+impl<'a> CandidateChecker<'a> {
+    fn new(db: &'a SymbolDatabase, unknown_db_contents: &'a HashMap<u32, HashMap<u32, Vec<SymbolDatabaseEntry>>>, settings: &'a Settings) -> Self {
+        let nearest_hash_tracker = if settings.report_nearest_hash {
+            Some(NearestHashTracker::new(db.sorted_unknown_mangled_hashes()))
+        } else {
+            None
+        };
+        Self{
+            db,
+            unknown_db_contents,
+            settings,
+            dedup_window: DedupWindow::new(settings.dedup_window),
+            nearest_hash_tracker,
+            counters: SearchCounters::default(),
+            sym_demangled: String::new(),
+        }
+    }
+
+    /// Checks a candidate, straight from the pattern (length prefixes and
+    /// namespace stripping are applied to it in-place). Returns None if
+    /// it was skipped, or if it didn't match an unknown symbol and `echo`
+    /// isn't set.
+    fn check(&mut self, sym_mangled: &mut String, echo: bool) -> Option<CheckedCandidate<'a>> {
+        let settings = self.settings;
+
+        apply_square_bracket_length_prefix_substitution(sym_mangled);
+
+        if let Some(stripped) = settings.strip_namespace.as_deref().and_then(|ns| strip_namespace(sym_mangled, ns)) {
+            *sym_mangled = stripped;
+        }
+
+        if is_excluded(sym_mangled, &settings.excluded_substrings) {
+            self.counters.num_excluded += 1;
+            return None;
+        }
+
+        if self.dedup_window.check_and_insert(sym_mangled) {
+            self.counters.num_duplicates += 1;
+            return None;
+        }
+
+        let hash_mangled = hash_djb2(sym_mangled.as_bytes(), DJB2_HASH_SEED);
+        self.counters.num_hashed += 1;
+
+        if let Some(tracker) = &mut self.nearest_hash_tracker {
+            tracker.observe(sym_mangled, hash_mangled);
+        }
+
+        // if echoing, use the full db so we can report "known" symbols --
+        // otherwise, we're not going to report those anyway, so use the
+        // smaller version to be more efficient
+        let matching_mangled_db = if echo {
+            self.db.contents.get(&hash_mangled)
+        } else {
+            self.unknown_db_contents.get(&hash_mangled)
+        };
+
+        // Important optimization
+        if matching_mangled_db.is_none() && !echo && !settings.demangled_only {
+            return None;
+        }
+
+        let demangle_start_time = Instant::now();
+        if !demangle_candidate(sym_mangled, settings.min_demangle_length, settings.demangle_buf_size, &mut self.sym_demangled) {
+            return echo.then(|| CheckedCandidate{
+                hash_mangled,
+                hash_demangled: None,
+                sym_demangled: "(too short to demangle)".to_owned(),
+                known: false,
+                new_symbols: Vec::new(),
+            });
+        }
+        self.counters.num_demangled += 1;
+        self.counters.demangle_time += demangle_start_time.elapsed();
+
+        let hash_demangled = hash_djb2(self.sym_demangled.as_bytes(), DJB2_HASH_SEED);
+        let (known, new_symbols) = match matching_entries(self.db, matching_mangled_db, hash_demangled, settings.demangled_only) {
+            Some(matching_both_db) => {
+                // There are symbols matching the hashes. Pick out the ones with unknown names
+                let new_symbols: Vec<&SymbolDatabaseEntry> = matching_both_db.iter().filter(|sym| self.db.is_unknown(sym)).collect();
+                (new_symbols.is_empty(), new_symbols)
+            },
+            None => (false, Vec::new()),
+        };
+
+        // (Only copying the demangled name out if it's going to be shown)
+        (echo || !new_symbols.is_empty()).then(|| CheckedCandidate{
+            hash_mangled,
+            hash_demangled: Some(hash_demangled),
+            sym_demangled: self.sym_demangled.clone(),
+            known,
+            new_symbols,
+        })
+    }
+}
+
+
+/// Prints a checked candidate, and sends any new matches it found to
+/// all of the sinks. Returns the new matches.
+fn report_candidate(sym_mangled: &str, checked: &CheckedCandidate, origin: &MatchOrigin, db: &SymbolDatabase, sinks: &mut [Box<dyn MatchSink>]) -> Vec<FoundMatch> {
+    let status = if !checked.new_symbols.is_empty() {
+        "!!!!!!!!!!!!!!!!! "  // (18 "!" + 1 " ")
+    } else if checked.known {
+        "(known)"
+    } else {
+        ""
+    };
+    let hash_demangled_str = checked.hash_demangled.map_or_else(|| "--------".to_string(), |h| format!("{h:08x}"));
+    println!("{:08x}_{hash_demangled_str} | {sym_mangled}", checked.hash_mangled);
+    println!("{status:^18}| {}", checked.sym_demangled);
+
+    if checked.new_symbols.is_empty() {
+        return Vec::new();
+    }
+    println!("{empty:^>width$}", empty = "", width = 70);  // ("^" * 70)
+
+    let mut newly_found_syms = Vec::new();
+    for matching_sym in &checked.new_symbols {
+        if let Some(note) = match_source_note(db, matching_sym) {
+            println!("{note}");
+        }
+        let found = FoundMatch{
+            address: matching_sym.address,
+            mangled_name: sym_mangled.to_owned(),
+            demangled_name: checked.sym_demangled.clone(),
+            source_pattern: origin.pattern.to_owned(),
+            line: origin.line,
+        };
+        push_to_all(sinks, &found);
+        newly_found_syms.push(found);
+    }
+    newly_found_syms
+}
+
+
+/// Saves a checkpoint for resuming a search from index `index`. Returns
+/// whether that worked (and prints why, if not).
+fn save_checkpoint(path: &Path, origin: &MatchOrigin, line: &str, index: usize) -> bool {
+    let checkpoint = Checkpoint{pattern: origin.pattern.to_owned(), expanded_pattern: line.to_owned(), index};
+    match checkpoint.save(path) {
+        Ok(()) => true,
+        Err(e) => {
+            println!("(Couldn't save checkpoint to {}: {e})", path.display());
+            false
+        },
+    }
+}
+
+
+/// Keeps track of which ranges of a parallel search have finished, to
+/// find how far it's gotten without any gaps (which is where it could
+/// be resumed from).
+struct CompletedRanges {
+    done_up_to: usize,
+    /// Finished ranges after done_up_to (start -> end)
+    pending: BTreeMap<usize, usize>,
+}
+
+
+impl CompletedRanges {
+    fn new(start: usize) -> Self {
+        Self{done_up_to: start, pending: BTreeMap::new()}
+    }
+
+    /// Marks a range as finished, and returns the new done_up_to.
+    fn complete(&mut self, range: Range<usize>) -> usize {
+        self.pending.insert(range.start, range.end);
+        while let Some(end) = self.pending.remove(&self.done_up_to) {
+            self.done_up_to = end;
+        }
+        self.done_up_to
+    }
+}
+
+
+/// Sent from search_in_parallel()'s threads as the search goes.
+enum ParallelMessage<'a> {
+    /// A candidate to show (see CandidateChecker::check())
+    Checked{sym_mangled: String, checked: CheckedCandidate<'a>},
+    /// A range of candidates has been searched
+    RangeDone{range: Range<usize>, counters: SearchCounters},
+}
+
+
+/// Checks one range of a pattern's expansions (starting from index
+/// `start`), sending what it finds to `sender` as it goes.
+fn search_range<'a>(start: usize, mut range_iter: BraceExpandRangeIterator, mut checker: CandidateChecker<'a>, echo_sampler: &EchoSampler, sender: &Sender<ParallelMessage<'a>>) {
+    let mut sym_mangled = String::new();
+    let mut index = start;
+    while range_iter.next_into(&mut sym_mangled) {
+        if let Some(checked) = checker.check(&mut sym_mangled, echo_sampler.should_echo(index, 0)) {
+            sender.send(ParallelMessage::Checked{sym_mangled: sym_mangled.clone(), checked}).ok();
+        }
+        index += 1;
+    }
+    sender.send(ParallelMessage::RangeDone{range: start..index, counters: checker.counters}).ok();
+}
+
+
+/// Searches a pattern's expansions (from index `start_index` onward) on
+/// `settings.threads` threads, each taking contiguous ranges of them.
+/// `handle` is called (on this thread) with each message from them, as
+/// they arrive.
+fn search_in_parallel<'a>(iter: &BraceExpandIterator, start_index: usize, db: &'a SymbolDatabase, unknown_db_contents: &'a HashMap<u32, HashMap<u32, Vec<SymbolDatabaseEntry>>>, settings: &'a Settings, echo_sampler: &EchoSampler, mut handle: impl FnMut(ParallelMessage<'a>)) -> Result<(), rayon::ThreadPoolBuildError> {
+    use rayon::prelude::*;
+
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(settings.threads).build()?;
+    // (The range iterators are made up front, since BraceExpandIterator
+    // itself can't be shared between threads)
+    let range_iters: Vec<(usize, BraceExpandRangeIterator)> = iter.split_ranges(settings.threads * RANGES_PER_THREAD)
+        .into_iter()
+        .map(|range| range.start.max(start_index)..range.end)
+        .filter(|range| !range.is_empty())
+        .map(|range| (range.start, iter.range_iter(range)))
+        .collect();
+
+    let (sender, receiver) = mpsc::channel();
+    thread::scope(|scope| {
+        scope.spawn(move || pool.install(|| range_iters.into_par_iter().for_each_with(sender, |sender, (start, range_iter)| {
+            search_range(start, range_iter, CandidateChecker::new(db, unknown_db_contents, settings), echo_sampler, sender);
+        })));
+        // (This ends once every range is done, and the senders are gone)
+        for message in receiver {
+            handle(message);
+        }
+    });
+    Ok(())
+}
+
+
+/// Brute-forces a pattern (which has already had all substitutions
+/// applied, other than length prefixes) against the database. New
+/// matches are sent to all of the sinks as they're found. Returns the
+/// number of new matches found.
+pub fn search_pattern(line: &str, db: &mut SymbolDatabase, settings: &Settings, sinks: &mut [Box<dyn MatchSink>]) -> usize {
+    search_pattern_with_stats(line, &MatchOrigin{pattern: line, line: 0}, db, settings, sinks).num_new_matches
+}
+
+
+/// Same as search_pattern(), but attributes matches to the given origin
+/// and returns statistics about the search.
+pub fn search_pattern_with_stats(line: &str, origin: &MatchOrigin, db: &mut SymbolDatabase, settings: &Settings, sinks: &mut [Box<dyn MatchSink>]) -> SearchStats {
+    search_pattern_from(line, origin, 0, db, settings, sinks)
+}
+
+
+/// Same as search_pattern_with_stats(), but starts from the expansion
+/// with index `start_index` (for resuming from a checkpoint). Inverse
+/// pruning always searches the whole pattern, so it isn't used if this
+/// is nonzero.
+pub fn search_pattern_from(line: &str, origin: &MatchOrigin, start_index: usize, db: &mut SymbolDatabase, settings: &Settings, sinks: &mut [Box<dyn MatchSink>]) -> SearchStats {
+    let unknown_db_contents = db.unknown_contents();
+
+    let iter = brace_expand_iter(line, settings.escaping_enabled);
+    if let Err(e) = iter {
+        println!("Parsing failure: {e}");
+        return SearchStats::default();
+    }
+    let mut iter = iter.unwrap();
+    let num_expansions = iter.num_expansions();
+    if start_index > 0 {
+        println!("(Starting from symbol {start_index} of {num_expansions}.)");
+        iter.skip_to(start_index);
+    }
+
+    if let Some(warning) = demangle_buffer_warning(max_candidate_length(line, iter.max_expansion_length()), settings.demangle_buf_size) {
+        println!("{warning}");
+    }
+
+    let query_start_time = Instant::now();
+
+    let mut sym_mangled = String::with_capacity(iter.max_expansion_length());
+    let mut num_hashed = 0;
+    let pruned = if settings.inverse_prune && start_index == 0 { inverse_prune(line, &iter, db, settings) } else { None };
+    let mut candidates = match pruned {
+        Some(pruned) => {
+            println!("Inverse pruning narrowed {num_expansions} symbols down to {} (with {} hashes).", pruned.matches.len(), pruned.num_hashed);
+            num_hashed = pruned.num_hashed;
+            Candidates::Pruned(pruned.matches.into_iter())
+        },
+        None => {
+            match iter.num_expansions_checked() {
+                Some(num_expansions) if num_expansions > ONLY_ECHO_FIRST => println!("Checking {num_expansions} symbols..."),
+                Some(_) => {},
+                None => println!("Checking more than {} symbols...", usize::MAX),
+            }
+            Candidates::All(Box::new(iter))
+        },
+    };
+    let mut next_i = start_index;
+    let echo_sampler = EchoSampler::new(settings.echo_sampling, num_expansions);
+    let mut echo_interval_fudge = 0;
+    let mut newly_found_syms = Vec::new();
+    let mut counters = SearchCounters::default();
+    let mut checker = CandidateChecker::new(db, &unknown_db_contents, settings);
+
+    // (Indices only mean something when going through every expansion)
+    let checkpoint_path = match &candidates {
+        Candidates::All(_) if settings.checkpoint_interval > 0 => settings.checkpoint_path.as_deref(),
+        _ => None,
+    };
+    let mut saved_checkpoint = false;
+
+    // The dedup window and nearest-hash tracking depend on seeing the
+    // candidates in order, so those need a serial search
+    let parallel_iter = match &candidates {
+        Candidates::All(iter) if settings.threads > 1 && settings.dedup_window == 0 && !settings.report_nearest_hash => Some(iter),
+        _ => None,
+    };
+    if let Some(iter) = parallel_iter {
+        println!("(Searching on {} threads, so symbols aren't shown in order.)", settings.threads);
+        let mut completed_ranges = CompletedRanges::new(start_index);
+        let mut last_checkpoint = start_index;
+        let result = search_in_parallel(iter, start_index, db, &unknown_db_contents, settings, &echo_sampler, |message| match message {
+            ParallelMessage::Checked{sym_mangled, checked} => {
+                newly_found_syms.extend(report_candidate(&sym_mangled, &checked, origin, db, sinks));
+            },
+            ParallelMessage::RangeDone{range, counters: range_counters} => {
+                next_i += range.len();
+                counters.add(&range_counters);
+                let done_up_to = completed_ranges.complete(range);
+                if let Some(path) = checkpoint_path.filter(|_| done_up_to - last_checkpoint >= settings.checkpoint_interval && done_up_to < num_expansions) {
+                    saved_checkpoint |= save_checkpoint(path, origin, line, done_up_to);
+                    last_checkpoint = done_up_to;
+                }
+            },
+        });
+        match result {
+            // (Everything's been searched, so there's nothing left for the
+            // serial loop below)
+            Ok(()) => candidates = Candidates::Pruned(Vec::new().into_iter()),
+            Err(e) => println!("(Couldn't start {} threads ({e}), so searching on one instead.)", settings.threads),
+        }
+    }
+
+    // (Only for searches long enough to not be echoed in full, and only
+    // on a terminal, so it doesn't clutter up logs)
+    let mut progress = match &candidates {
+        Candidates::All(_) if num_expansions > ONLY_ECHO_FIRST && io::stdout().is_terminal() => Some(ProgressBar::new(start_index, num_expansions)),
+        _ => None,
+    };
+
+    while candidates.next_into(&mut sym_mangled) {
+        // (doing it this way so we can safely `continue` in the middle
+        // of this loop if we want to)
+        let i = next_i;
+        next_i += 1;
+
+        if let Some(progress) = &mut progress {
+            progress.update(i);
+        }
+
+        if let Some(path) = checkpoint_path.filter(|_| i > start_index && i.is_multiple_of(settings.checkpoint_interval)) {
+            if let Some(progress) = &mut progress {
+                progress.clear();
+            }
+            saved_checkpoint |= save_checkpoint(path, origin, line, i);
+        }
+
+        let force_echo = echo_sampler.should_echo(i, echo_interval_fudge);
+        let Some(checked) = checker.check(&mut sym_mangled, force_echo) else {
+            continue;
+        };
+
+        if let Some(progress) = &mut progress {
+            progress.clear();
+        }
+
+        newly_found_syms.extend(report_candidate(&sym_mangled, &checked, origin, db, sinks));
+
+        if i == ONLY_ECHO_FIRST - 1 {
+            println!("{}", echo_sampler.describe());
+        }
+
+        // We add a bit of jitter to the echo interval because otherwise
+        // it can end up being a multiple of some sub-pattern in the
+        // brace-expansion output sequence, which causes us to only show
+        // some types of outputs and not a more representative sample.
+        echo_interval_fudge += 1;
+        if echo_interval_fudge > ECHO_INTERVAL_MAX_FUDGE {
+            echo_interval_fudge = 0;
+        }
+    }
+    counters.add(&checker.counters);
+
+    if let Some(progress) = &mut progress {
+        progress.clear();
+    }
+
+    // The search finished, so there's nothing left to resume
+    if let Some(path) = checkpoint_path.filter(|_| saved_checkpoint) {
+        fs::remove_file(path).ok();
+    }
+
+    let num_checked = num_expansions.saturating_sub(start_index);
+    let symbols_checked_str = format!("({} symbol{} checked)",
+        num_checked,
+        if num_checked == 1 {""} else {"s"});
+
+    let SearchCounters{num_excluded, num_duplicates, num_demangled, demangle_time, ..} = counters;
+    if num_excluded > 0 {
+        println!("({num_excluded} symbol{} skipped by --exclude)", if num_excluded == 1 {""} else {"s"});
+    }
+
+    if num_duplicates > 0 {
+        println!("({num_duplicates} duplicate symbol{} skipped by --dedup-window)", if num_duplicates == 1 {""} else {"s"});
+    }
+
+    if let Some(nearest) = checker.nearest_hash_tracker.as_ref().and_then(|tracker| tracker.best()) {
+        println!("(Nearest hash: {} ({:08x}) is {} away from {:08x})",
+            nearest.candidate, nearest.candidate_hash, nearest.distance, nearest.db_hash);
+    }
+
+    if newly_found_syms.is_empty() {
+        println!("No new matches {symbols_checked_str}.");
+    } else {
+        println!();
+        println!("{empty:!>width$}", empty = "", width = 70);  // ("!" * 70)
+
+        println!("Found {} new match{} {}!",
+            newly_found_syms.len(),
+            if newly_found_syms.len() == 1 {""} else {"es"},
+            symbols_checked_str);
+
+        let mut summary = TextSink::stdout();
+        for found in &newly_found_syms {
+            summary.push(found);
+        }
+
+        println!("{empty:!>width$}", empty = "", width = 70);  // ("!" * 70)
+        println!();
+    }
+
+    let stats = SearchStats{
+        num_new_matches: newly_found_syms.len(),
+        num_candidates: next_i - start_index,
+        num_hashed: num_hashed + counters.num_hashed,
+        num_demangled,
+        demangle_time,
+        query_time: query_start_time.elapsed(),
+    };
+
+    if stats.query_time.as_secs_f64() > 10.0 || settings.verbose {
+        let demangle_rate_str = if stats.num_demangled > 0 {
+            format!(", at {:0.0}/sec", stats.demangle_rate())
+        } else {
+            String::new()
+        };
+        println!("(Query executed in {:0.3} seconds: {:0.0} symbols/sec; {} demangled{demangle_rate_str})",
+            stats.query_time.as_secs_f64(), stats.candidate_rate(), stats.num_demangled);
+    }
+
+    stats
+}
+
+
+/// Loads the symbol database from a single symbol map. If `stream` is
+/// set, the map is assumed to be in Dolphin format and is parsed
+/// incrementally, to save memory.
+pub fn load_symbol_database_from_path(path: &Path, stream: bool, verbose: bool) -> Result<SymbolDatabase, Box<dyn Error>> {
+    load_symbol_database_from_paths(&[path.to_owned()], stream, verbose)
+}
+
+
+/// Loads the symbol database from several symbol maps, merged together.
+/// Each symbol is tagged with the file name of the map it came from.
+pub fn load_symbol_database_from_paths(paths: &[PathBuf], stream: bool, verbose: bool) -> Result<SymbolDatabase, Box<dyn Error>> {
+    let mut db = SymbolDatabase::default();
+    for path in paths {
+        let mut file_name = "<unknown>";
+        if let Some(name) = path.file_name() {
+            if let Some(name) = name.to_str() {
+                file_name = name;
+            }
+        }
+        let file_name = file_name;
+
+        if stream {
+            db.merge_from_reader(BufReader::new(File::open(path)?), SymbolMapFormat::Dolphin, file_name)?;
+        } else {
+            db.merge_map(&load_symbol_map_from_path(path)?, file_name);
+        }
+
+        if verbose {
+            // (Later maps are merged into the earlier ones, so their
+            // symbols can't be counted separately)
+            let total_len = db.num_symbols();
+            let unk_len = db.num_unknown_symbols();
+            let percent_unknown = (unk_len as f64) / (total_len as f64) * 100.0;
+            if db.sources.len() == 1 {
+                println!("Loaded {total_len} symbols from {file_name} ({unk_len} ({percent_unknown:0.3}%) unknown).");
+            } else {
+                println!("Merged in {file_name}: {total_len} symbols in total ({unk_len} ({percent_unknown:0.3}%) unknown).");
+            }
+        }
+    }
+
+    if verbose {
+        if let Some(warning) = demangler_mismatch_warning(&db.find_demangler_mismatches(DEMANGLER_CHECK_SAMPLES, demangle)) {
+            println!("{warning}");
+        }
+        println!();
+    }
+
+    Ok(db)
+}
+
+
+/// A note saying which symbol map a match came from, if the database
+/// was merged from more than one.
+fn match_source_note(db: &SymbolDatabase, entry: &SymbolDatabaseEntry) -> Option<String> {
+    if db.sources.len() < 2 {
+        return None;
+    }
+    db.source_name(entry).map(|name| format!("(Found {:08x} in {name})", entry.address))
+}
+
+
+/// Loads the confirmed addresses file into the database, if it exists.
+pub fn load_confirmed_addresses_from_path(db: &mut SymbolDatabase, path: &Path) -> Result<(), Box<dyn Error>> {
+    match File::open(path) {
+        Ok(file) => db.load_confirmed_addresses(BufReader::new(file)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+
+/// Marks an address as confirmed, both in the database and in the
+/// confirmed addresses file.
+fn process_confirm_command(arg: &str, db: &mut SymbolDatabase, path: &Path) -> Result<(), Box<dyn Error>> {
+    let address = parse_address(arg).ok_or_else(|| format!("invalid address \"{arg}\""))?;
+    if !db.entries().any(|entry| entry.address == address && entry.mangled_name.is_none()) {
+        println!("(Note: there's no unknown symbol at {address:08x} in the database.)");
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{address:08x}")?;
+    db.confirmed_addresses.insert(address);
+    println!("Confirmed {address:08x}.");
+    Ok(())
+}
+
+
+/// Describes the database's contents.
+fn describe_db_stats(db: &SymbolDatabase) -> Vec<String> {
+    let (total, unknown) = (db.num_symbols(), db.num_unknown_symbols());
+    let unknown_percent = if total == 0 {0.0} else {(unknown as f64) / (total as f64) * 100.0};
+    vec![
+        format!("Symbols: {total}"),
+        format!("Unknown: {unknown} ({unknown_percent:0.3}%)"),
+        format!("Confirmed by hand: {}", db.confirmed_addresses.len()),
+        format!("Distinct mangled hashes: {}", db.num_distinct_mangled_hashes()),
+        format!("Most symbols sharing a mangled hash: {}", db.largest_bucket_size()),
+    ]
+}
+
+
+/// Describes the symbol at an address, or the one before it if there
+/// isn't one there.
+fn describe_address(db: &SymbolDatabase, address: u32) -> String {
+    let describe = |address: u32, name: &str| {
+        if parse_hashname(name).is_none() {
+            format!("{address:08x} | {name}")
+        } else if db.confirmed_addresses.contains(&address) {
+            format!("{address:08x} | unknown, but confirmed ({name})")
+        } else {
+            format!("{address:08x} | unknown ({name})")
+        }
+    };
+
+    if let Some(name) = db.name_at(address) {
+        describe(address, name)
+    } else if let Some((before, name)) = db.symbol_before(address) {
+        format!("No symbol at {address:08x}. The nearest one before it is:\n{}", describe(before, name))
+    } else {
+        format!("No symbol at or before {address:08x}.")
+    }
+}
+
+
+/// Resumes the search saved in a checkpoint file. Returns the number of
+/// new matches.
+fn process_resume_command(path: &Path, line_number: usize, db: &mut SymbolDatabase, settings: &Settings, word_lists: &mut WordListCache, sinks: &mut [Box<dyn MatchSink>]) -> Result<usize, Box<dyn Error>> {
+    let checkpoint = Checkpoint::load(path)?;
+    if preprocess_pattern(&checkpoint.pattern, settings, word_lists) != checkpoint.expanded_pattern {
+        return Err(format!(
+            "\"{}\" doesn't expand to the same thing it did when the checkpoint was saved (have word lists or shorthands changed?)",
+            checkpoint.pattern).into());
+    }
+    println!("Resuming \"{}\"...", checkpoint.pattern);
+    Ok(process_line_as_pattern_from(&checkpoint.pattern, line_number, checkpoint.index, db, settings, word_lists, sinks))
+}
+
+
+/// Returns a warning to show if any known symbols failed the demangler
+/// check.
+fn demangler_mismatch_warning(mismatches: &[String]) -> Option<String> {
+    let first = mismatches.first()?;
+    Some(format!(
+        "WARNING: the demangler disagrees with the symbol map about {} known symbol{} (such as \"{first}\"), \
+        so demangled hashes probably won't match. Is the demangler out of date?",
+        mismatches.len(),
+        if mismatches.len() == 1 {""} else {"s"}))
+}
+
+
+/// What to do after running a command.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum CommandOutcome {
+    Continue{num_new_matches: usize},
+    Quit,
+}
+
+
+/// Runs one line of input (a command or a pattern). `line_number` is
+/// where the line was in its batch (1-based), or 0 if it wasn't in one.
+pub fn run_command(line: &str, line_number: usize, db: &mut SymbolDatabase, settings: &mut Settings, word_lists: &mut WordListCache, sinks: &mut [Box<dyn MatchSink>], args: &Args) -> Result<CommandOutcome, Box<dyn Error>> {
+    let mut num_new_matches = 0;
+    if line == "q" || line == "quit" || line == "e" || line == "exit" {
+        return Ok(CommandOutcome::Quit);
+    } else if line == "reload wordlists" {
+        word_lists.clear();
+        println!("Word lists will be re-read when they're next used.");
+    } else if line == "r" || line == "reload" {
+        *db = load_symbol_database_from_paths(&args.symbol_map_paths, args.stream_map, true)?;
+        load_confirmed_addresses_from_path(db, Path::new(CONFIRMED_ADDRESSES_PATH))?;
+    } else if line == "escapes on" {
+        println!("Backslash-escaping enabled.");
+        settings.escaping_enabled = true;
+    } else if line == "escapes off" {
+        println!("Backslash-escaping disabled.");
+        settings.escaping_enabled = false;
+    } else if let Some(arg) = line.strip_prefix("maxexpand ") {
+        match arg.trim().parse() {
+            Ok(0) => {
+                println!("Patterns will never ask for confirmation.");
+                settings.max_expansions = 0;
+            },
+            Ok(max_expansions) => {
+                println!("Patterns with more than {max_expansions} expansions will ask for confirmation first.");
+                settings.max_expansions = max_expansions;
+            },
+            Err(_) => println!("Usage: maxexpand <number> (0 to never ask)"),
+        }
+    } else if line == "match demangled-only" {
+        println!("Matching on demangled hashes only (every symbol will be demangled, so this is slower).");
+        settings.demangled_only = true;
+    } else if line == "match both" {
+        println!("Matching on both mangled and demangled hashes.");
+        settings.demangled_only = false;
+    } else if line == "sample boundaries" {
+        println!("Showing the first, middle and last few symbols of each search.");
+        settings.echo_sampling = EchoSampling::Boundaries;
+    } else if line == "sample periodic" {
+        println!("Showing the first few symbols of each search, and a sample of the rest.");
+        settings.echo_sampling = EchoSampling::Periodic;
+    } else if line == "stats" {
+        for line in describe_db_stats(db) {
+            println!("{line}");
+        }
+    } else if line == "db-alphabet" {
+        for line in describe_db_alphabet(&db.known_name_alphabet()) {
+            println!("{line}");
+        }
+    } else if line == "hash" || line.starts_with("hash ") {
+        for line in describe_hashes(line["hash".len()..].trim()) {
+            println!("{line}");
+        }
+    } else if let Some(arg) = line.strip_prefix("demangle ") {
+        match demangle(arg.trim()) {
+            Ok(demangled) => println!("{demangled}"),
+            Err(e) => println!("Couldn't demangle {}: {e}", arg.trim()),
+        }
+    } else if let Some(arg) = line.strip_prefix("unhash ") {
+        match unhash(arg.trim()) {
+            Ok(hash) => println!("{hash:08x}"),
+            Err(e) => println!("Usage: unhash <hex hash> <suffix> ({e})"),
+        }
+    } else if let Some(arg) = line.strip_prefix("hash-trace ") {
+        print_hash_trace(arg);
+    } else if let Some(arg) = line.strip_prefix("preview ") {
+        process_preview_command(arg, settings, word_lists);
+    } else if let Some(arg) = line.strip_prefix("graph ") {
+        process_graph_command(arg, settings, word_lists);
+    } else if let Some(arg) = line.strip_prefix("verify-roundtrip ") {
+        if let Err(e) = process_verify_roundtrip_command(Path::new(arg.trim())) {
+            println!("Couldn't verify {}: {e}", arg.trim());
+        }
+    } else if let Some(arg) = line.strip_prefix("addr ") {
+        match parse_address(arg.trim()) {
+            Some(address) => println!("{}", describe_address(db, address)),
+            None => println!("Invalid address \"{}\"", arg.trim()),
+        }
+    } else if let Some(arg) = line.strip_prefix("confirm ") {
+        if let Err(e) = process_confirm_command(arg.trim(), db, Path::new(CONFIRMED_ADDRESSES_PATH)) {
+            println!("Couldn't confirm {}: {e}", arg.trim());
+        }
+    } else if let Some(arg) = line.strip_prefix("resume ") {
+        match process_resume_command(Path::new(arg.trim()), line_number, db, settings, word_lists, sinks) {
+            Ok(count) => num_new_matches = count,
+            Err(e) => println!("Couldn't resume from {}: {e}", arg.trim()),
+        }
+    } else if let Some(arg) = line.strip_prefix("fuzz ") {
+        num_new_matches = process_fuzz_command(arg.trim(), &MatchOrigin{pattern: line, line: line_number}, db, settings, sinks);
+    } else {
+        num_new_matches = process_line_as_pattern(line, line_number, db, settings, word_lists, sinks);
+    }
+    Ok(CommandOutcome::Continue{num_new_matches})
+}
+
+
+/// Runs numbered lines non-interactively, for run_batch() and
+/// run_batch_file().
+fn run_numbered_lines<I: Iterator<Item = (usize, String)>>(lines: I, patterns_only: bool, db: &mut SymbolDatabase, settings: &mut Settings, word_lists: &mut WordListCache, sinks: &mut [Box<dyn MatchSink>], args: &Args) -> Result<usize, Box<dyn Error>> {
+    let mut total_new_matches = 0;
+    for (line_number, line) in lines {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        println!("sym> {line}");
+        if patterns_only {
+            total_new_matches += process_line_as_pattern(&line, line_number, db, settings, word_lists, sinks);
+            continue;
+        }
+        match run_command(&line, line_number, db, settings, word_lists, sinks, args)? {
+            CommandOutcome::Continue{num_new_matches} => total_new_matches += num_new_matches,
+            CommandOutcome::Quit => break,
+        }
+    }
+    Ok(total_new_matches)
+}
+
+
+/// Runs each line as a command, non-interactively, or just as a pattern
+/// if `patterns_only` is set (so that nothing can be mistaken for a
+/// command). Blank lines and lines starting with "#" are skipped.
+/// Returns the total number of new matches.
+pub fn run_batch<I: IntoIterator<Item = String>>(lines: I, patterns_only: bool, db: &mut SymbolDatabase, settings: &mut Settings, word_lists: &mut WordListCache, sinks: &mut [Box<dyn MatchSink>], args: &Args) -> Result<usize, Box<dyn Error>> {
+    let lines = lines.into_iter().enumerate().map(|(i, line)| (i + 1, line));
+    run_numbered_lines(lines, patterns_only, db, settings, word_lists, sinks, args)
+}
+
+
+/// Same as run_batch(), but for the lines of a file, starting from line
+/// args.start_line.
+pub fn run_batch_file(path: &Path, patterns_only: bool, db: &mut SymbolDatabase, settings: &mut Settings, word_lists: &mut WordListCache, sinks: &mut [Box<dyn MatchSink>], args: &Args) -> Result<usize, Box<dyn Error>> {
+    let lines = BufReader::new(File::open(path)?).lines().collect::<Result<Vec<String>, _>>()?;
+    let lines = lines.into_iter().enumerate().map(|(i, line)| (i + 1, line)).skip(args.start_line - 1);
+    run_numbered_lines(lines, patterns_only, db, settings, word_lists, sinks, args)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::RefCell;
+    use std::fs;
+    use std::rc::Rc;
+
+    use djb2_utils::hash_djb2_default;
+    use nvidia_demangle::DEFAULT_BUF_SIZE;
+
+    use crate::args::default_word_list_dirs;
+    use crate::sinks::CollectingSink;
+    use crate::test_support::{make_temp_dir, make_unknown_db, test_settings};
+
+    #[test]
+    fn test_hash_trace() {
+        let trace = hash_trace(b"mario");
+        assert_eq!(trace.len(), 5);
+        assert_eq!(trace[0], hash_djb2_default(b"m"));
+        assert_eq!(trace[2], hash_djb2_default(b"mar"));
+        assert_eq!(*trace.last().unwrap(), hash_djb2_default(b"mario"));
+    }
+
+    #[test]
+    fn test_describe_hashes() {
+        let mangled = "construct__10dWmActor_cFv";
+        let demangled = demangle(mangled).unwrap();
+        assert_eq!(describe_hashes(mangled), vec![
+            format!("{:08x} | {mangled}", hash_djb2_default(mangled.as_bytes())),
+            format!("{:08x} | {demangled} (demangled)", hash_djb2_default(demangled.as_bytes())),
+        ]);
+        assert_eq!(describe_hashes("someData"), vec![format!("{:08x} | someData", hash_djb2_default(b"someData"))]);
+        assert_eq!(describe_hashes(""), vec![format!("{DJB2_HASH_SEED:08x} | (empty string, so this is just the seed)")]);
+    }
+
+    #[test]
+    fn test_unhash() {
+        let hash = hash_djb2_default(b"construct__10dWmActor_cFv");
+        assert_eq!(unhash(&format!("{hash:08x} __10dWmActor_cFv")), Ok(hash_djb2_default(b"construct")));
+        assert_eq!(unhash(&format!("0x{hash:x} construct__10dWmActor_cFv")), Ok(DJB2_HASH_SEED));
+        assert_eq!(unhash(&format!("{hash:08x}")), Ok(hash));
+        assert!(unhash("xyz Fv").is_err());
+        assert!(unhash("123456789 Fv").is_err());
+    }
+
+    #[test]
+    fn test_hash_trace_empty() {
+        assert!(hash_trace(b"").is_empty());
+    }
+
+    #[test]
+    fn test_demangle_candidate_threshold() {
+        let mut out = "leftover".to_owned();
+        assert!(!demangle_candidate("a__Fv", 6, DEFAULT_BUF_SIZE, &mut out));
+        assert!(demangle_candidate("ab__Fv", 6, DEFAULT_BUF_SIZE, &mut out));
+        assert_eq!(out, demangle("ab__Fv").unwrap_or_else(|_| "ERROR".to_string()));
+        assert!(demangle_candidate("", 0, DEFAULT_BUF_SIZE, &mut out));
+    }
+
+    #[test]
+    fn test_demangle_buffer_warning() {
+        let iter = brace_expand_iter("{a,bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb}__10dWmActor_cFv", false).unwrap();
+        let max_len = max_candidate_length("", iter.max_expansion_length());
+        assert_eq!(max_len, 66);
+        assert!(demangle_buffer_warning(max_len, 64).is_some());
+        assert!(demangle_buffer_warning(max_len, 132).is_some());
+        assert!(demangle_buffer_warning(max_len, DEFAULT_BUF_SIZE).is_none());
+
+        // Length prefixes only grow things once their contents reach
+        // 1000 bytes
+        assert_eq!(max_candidate_length("P[a]P[b]", 999), 999);
+        assert_eq!(max_candidate_length("P[a]P[b]", 1000), 1002);
+    }
+
+    #[test]
+    fn test_exclude() {
+        assert!(is_excluded("construct__10dWmActor_cFv", &["Fv".to_owned()]));
+        assert!(!is_excluded("construct__10dWmActor_cFi", &["Fv".to_owned()]));
+
+        let pattern = "construct__10dWmActor_cF{v,i}";
+        let mut settings = test_settings();
+        let mut db = make_unknown_db(&["construct__10dWmActor_cFv", "construct__10dWmActor_cFi"]);
+        settings.excluded_substrings = vec!["Fv".to_owned()];
+        assert_eq!(search_pattern(pattern, &mut db, &settings, &mut []), 1);
+
+        let mut db = make_unknown_db(&["construct__10dWmActor_cFv", "construct__10dWmActor_cFi"]);
+        settings.excluded_substrings = Vec::new();
+        assert_eq!(search_pattern(pattern, &mut db, &settings, &mut []), 2);
+    }
+
+    #[test]
+    fn test_confirmed_addresses_are_known() {
+        let pattern = "construct__10dWmActor_cF{v,i}";
+        let names = ["construct__10dWmActor_cFv", "construct__10dWmActor_cFi"];
+        let dir = make_temp_dir("confirmed_addresses_are_known");
+        let path = dir.join("confirmed.txt");
+
+        let mut db = make_unknown_db(&names);
+        load_confirmed_addresses_from_path(&mut db, &path).unwrap();
+        assert!(db.confirmed_addresses.is_empty());
+        process_confirm_command("4", &mut db, &path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "00000004\n");
+
+        // Only the unconfirmed one is new
+        let found = Rc::new(RefCell::new(Vec::new()));
+        let mut sinks: Vec<Box<dyn MatchSink>> = vec![Box::new(CollectingSink::new(found.clone()))];
+        assert_eq!(search_pattern(pattern, &mut db, &test_settings(), &mut sinks), 1);
+        assert_eq!(found.borrow().len(), 1);
+        assert_eq!(found.borrow()[0].address, 0);
+
+        // And that persists
+        let mut db = make_unknown_db(&names);
+        load_confirmed_addresses_from_path(&mut db, &path).unwrap();
+        assert_eq!(search_pattern(pattern, &mut db, &test_settings(), &mut []), 1);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_demangler_mismatch_warning() {
+        assert_eq!(demangler_mismatch_warning(&[]), None);
+
+        // (A second map naming the symbol gives something to check the
+        // placeholder's demangled hash against)
+        let mut db = make_unknown_db(&["construct__10dWmActor_cFv"]);
+        db.insert_from_source(0, "construct__10dWmActor_cFv", 1);
+        assert_eq!(demangler_mismatch_warning(&db.find_demangler_mismatches(DEMANGLER_CHECK_SAMPLES, demangle)), None);
+
+        let other_demangler = |name: &str| demangle(name).map(|s| s.to_uppercase());
+        let warning = demangler_mismatch_warning(&db.find_demangler_mismatches(DEMANGLER_CHECK_SAMPLES, other_demangler));
+        assert!(warning.unwrap().contains("1 known symbol (such as \"construct__10dWmActor_cFv\")"));
+    }
+
+    #[test]
+    fn test_needs_confirmation() {
+        assert!(!needs_confirmation(Some(1000), 1000));
+        assert!(needs_confirmation(Some(1001), 1000));
+        assert!(needs_confirmation(None, 1000));
+        assert!(!needs_confirmation(None, 0));
+        assert!(!needs_confirmation(Some(usize::MAX), 0));
+    }
+
+    #[test]
+    fn test_large_pattern_gate() {
+        let names = ["construct__10dWmActor_cFv"];
+        let pattern = "construct__10dWmActor_cF{v,i,f}";
+        let mut settings = test_settings();
+        settings.max_expansions = 2;
+
+        // With nobody to ask, it's skipped
+        assert_eq!(process_line_as_pattern(pattern, 1, &mut make_unknown_db(&names), &settings, &mut WordListCache::new(), &mut []), 0);
+
+        settings.confirm_large_patterns = Some(|_| false);
+        assert_eq!(process_line_as_pattern(pattern, 1, &mut make_unknown_db(&names), &settings, &mut WordListCache::new(), &mut []), 0);
+
+        settings.confirm_large_patterns = Some(|_| true);
+        assert_eq!(process_line_as_pattern(pattern, 1, &mut make_unknown_db(&names), &settings, &mut WordListCache::new(), &mut []), 1);
+
+        // Small enough patterns never ask
+        settings.confirm_large_patterns = Some(|_| panic!("asked"));
+        settings.max_expansions = 3;
+        assert_eq!(process_line_as_pattern(pattern, 1, &mut make_unknown_db(&names), &settings, &mut WordListCache::new(), &mut []), 1);
+        settings.max_expansions = 0;
+        assert_eq!(process_line_as_pattern(pattern, 1, &mut make_unknown_db(&names), &settings, &mut WordListCache::new(), &mut []), 1);
+    }
+
+    #[test]
+    fn test_demangle_count() {
+        let mut settings = test_settings();
+        let names = ["construct__10dWmActor_cFv"];
+
+        // Small queries echo (and so demangle) every candidate
+        let stats = search_pattern_with_stats("construct__10dWmActor_cF{v,i,f}", &MatchOrigin{pattern: "", line: 0}, &mut make_unknown_db(&names), &settings, &mut []);
+        assert_eq!((stats.num_new_matches, stats.num_candidates, stats.num_demangled), (1, 3, 3));
+
+        // Beyond that, only candidates matching a mangled hash are
+        // demangled
+        let pattern = "construct__10dWmActor_cF{v,{0..99}}";
+        let stats = search_pattern_with_stats(pattern, &MatchOrigin{pattern, line: 0}, &mut make_unknown_db(&names), &settings, &mut []);
+        assert_eq!((stats.num_new_matches, stats.num_candidates), (1, 101));
+        assert_eq!(stats.num_demangled, ONLY_ECHO_FIRST);
+
+        settings.min_demangle_length = 100;
+        let stats = search_pattern_with_stats(pattern, &MatchOrigin{pattern, line: 0}, &mut make_unknown_db(&names), &settings, &mut []);
+        assert_eq!((stats.num_new_matches, stats.num_demangled), (0, 0));
+        assert_eq!(stats.demangle_time, Duration::ZERO);
+    }
+
+    #[test]
+    fn test_inverse_prune() {
+        let mut settings = test_settings();
+        let names = ["f42y__10dWmActor_cFv"];
+        let pattern = "{a,b,c,d,e,f,g,h}{1..100}{x,y,z}__10dWmActor_cFv";
+
+        let brute_force = search_pattern_with_stats(pattern, &MatchOrigin{pattern, line: 0}, &mut make_unknown_db(&names), &settings, &mut []);
+        assert_eq!((brute_force.num_new_matches, brute_force.num_hashed), (1, 2400));
+
+        settings.inverse_prune = true;
+        let pruned = search_pattern_with_stats(pattern, &MatchOrigin{pattern, line: 0}, &mut make_unknown_db(&names), &settings, &mut []);
+        assert_eq!(pruned.num_new_matches, 1);
+        // 8 prefixes forwards, the suffix's 1 + 3 + 300 backwards, 100 + 3
+        // + 1 to follow the match, and then the match itself
+        assert_eq!(pruned.num_hashed, 8 + 304 + 104 + 1);
+
+        // Length prefixes change candidates after they're expanded, so
+        // these fall back to brute force
+        let pattern = "{a,b}__P[dWmActor_c]Fv";
+        let stats = search_pattern_with_stats(pattern, &MatchOrigin{pattern, line: 0}, &mut make_unknown_db(&names), &settings, &mut []);
+        assert_eq!(stats.num_hashed, 2);
+    }
+
+    /// A sink that records what's in a checkpoint file whenever a match
+    /// comes in.
+    struct CheckpointSpySink {
+        path: PathBuf,
+        seen: Rc<RefCell<Vec<Option<Checkpoint>>>>,
+    }
+
+    impl MatchSink for CheckpointSpySink {
+        fn push(&mut self, _found: &FoundMatch) {
+            self.seen.borrow_mut().push(Checkpoint::load(&self.path).ok());
+        }
+    }
+
+    #[test]
+    fn test_checkpoint_and_resume() {
+        let dir = make_temp_dir("checkpoint_and_resume");
+        let path = dir.join("checkpoint.txt");
+        // (At indices 12, 778 and 1805)
+        let names = ["a5x__10dWmActor_cFv", "c60y__10dWmActor_cFv", "g2z__10dWmActor_cFv"];
+        let pattern = "{a,b,c,d,e,f,g,h}{1..100}{x,y,z}__10dWmActor_cFv";
+        let settings = Settings{checkpoint_path: Some(path.clone()), checkpoint_interval: 500, ..test_settings()};
+
+        let run = |start_index| {
+            let found = Rc::new(RefCell::new(Vec::new()));
+            let checkpoints = Rc::new(RefCell::new(Vec::new()));
+            let mut sinks: Vec<Box<dyn MatchSink>> = vec![
+                Box::new(CollectingSink::new(found.clone())),
+                Box::new(CheckpointSpySink{path: path.clone(), seen: checkpoints.clone()}),
+            ];
+            process_line_as_pattern_from(pattern, 0, start_index, &mut make_unknown_db(&names), &settings, &mut WordListCache::new(), &mut sinks);
+            let found: Vec<String> = found.borrow().iter().map(|found| found.mangled_name.clone()).collect();
+            let checkpoints = checkpoints.borrow().clone();
+            (found, checkpoints)
+        };
+
+        let (all_found, checkpoints) = run(0);
+        assert_eq!(all_found, names);
+        let indices: Vec<Option<usize>> = checkpoints.iter().map(|checkpoint| checkpoint.as_ref().map(|checkpoint| checkpoint.index)).collect();
+        assert_eq!(indices, [None, Some(500), Some(1500)]);
+        // It's removed once the search finishes
+        assert!(!path.exists());
+
+        // "Interrupted" at index 500
+        let checkpoint = checkpoints[1].clone().unwrap();
+        assert_eq!(checkpoint.pattern, pattern);
+        checkpoint.save(&path).unwrap();
+        let found = Rc::new(RefCell::new(Vec::new()));
+        let mut sinks: Vec<Box<dyn MatchSink>> = vec![Box::new(CollectingSink::new(found.clone()))];
+        let num_new_matches = process_resume_command(&path, 0, &mut make_unknown_db(&names), &settings, &mut WordListCache::new(), &mut sinks).unwrap();
+        assert_eq!(num_new_matches, 2);
+        let found: Vec<String> = found.borrow().iter().map(|found| found.mangled_name.clone()).collect();
+        assert_eq!(found, all_found[1..]);
+        assert_eq!(run(500).0, all_found[1..]);
+
+        // The pattern has to expand the same way it did before
+        Checkpoint{expanded_pattern: "{a,b}".to_owned(), ..checkpoint}.save(&path).unwrap();
+        assert!(process_resume_command(&path, 0, &mut make_unknown_db(&names), &settings, &mut WordListCache::new(), &mut []).is_err());
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_demangled_only() {
+        // The database has the demangled hash of "construct__10dWmActor_cFv",
+        // but a different mangled hash (as if the arguments were mangled
+        // differently)
+        let demangled = demangle("construct__10dWmActor_cFv").unwrap();
+        let mut db = SymbolDatabase::new(&HashMap::from([
+            (0x80004024, format!("hashname_12345678_{:08x}", hash_djb2_default(demangled.as_bytes()))),
+        ]));
+        let pattern = "{construct,destruct}__10dWmActor_cFv";
+
+        let mut settings = test_settings();
+        assert_eq!(search_pattern(pattern, &mut db, &settings, &mut []), 0);
+
+        settings.demangled_only = true;
+        let found = Rc::new(RefCell::new(Vec::new()));
+        let mut sinks: Vec<Box<dyn MatchSink>> = vec![Box::new(CollectingSink::new(found.clone()))];
+        assert_eq!(search_pattern(pattern, &mut db, &settings, &mut sinks), 1);
+        assert_eq!(found.borrow()[0].address, 0x80004024);
+        assert_eq!(found.borrow()[0].mangled_name, "construct__10dWmActor_cFv");
+
+        // Same on multiple threads
+        settings.threads = 2;
+        assert_eq!(search_pattern(pattern, &mut db, &settings, &mut []), 1);
+    }
+
+    #[test]
+    fn test_completed_ranges() {
+        let mut completed = CompletedRanges::new(10);
+        assert_eq!(completed.complete(20..30), 10);
+        assert_eq!(completed.complete(40..50), 10);
+        assert_eq!(completed.complete(10..20), 30);
+        assert_eq!(completed.complete(30..40), 50);
+    }
+
+    #[test]
+    fn test_parallel_checkpoints() {
+        let dir = make_temp_dir("parallel_checkpoints");
+        let path = dir.join("checkpoint.txt");
+        let names = ["a5x__10dWmActor_cFv", "c60y__10dWmActor_cFv", "g2z__10dWmActor_cFv"];
+        let pattern = "{a,b,c,d,e,f,g,h}{1..100}{x,y,z}__10dWmActor_cFv";
+        let settings = Settings{checkpoint_path: Some(path.clone()), checkpoint_interval: 100, threads: 2, ..test_settings()};
+
+        let found = Rc::new(RefCell::new(Vec::new()));
+        let checkpoints = Rc::new(RefCell::new(Vec::new()));
+        let mut sinks: Vec<Box<dyn MatchSink>> = vec![
+            Box::new(CollectingSink::new(found.clone())),
+            Box::new(CheckpointSpySink{path: path.clone(), seen: checkpoints.clone()}),
+        ];
+        assert_eq!(process_line_as_pattern(pattern, 0, &mut make_unknown_db(&names), &settings, &mut WordListCache::new(), &mut sinks), 3);
+        // Wherever a checkpoint was, everything before it was done
+        for checkpoint in checkpoints.borrow().iter().flatten() {
+            assert_eq!(checkpoint.expanded_pattern, pattern);
+            assert!(checkpoint.index < 2400);
+        }
+        // It's removed once the search finishes
+        assert!(!path.exists());
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_describe_db_stats() {
+        let db = SymbolDatabase::new(&HashMap::from([
+            (0x80004000, "construct__10dWmActor_cFv".to_owned()),
+            (0x80004024, "hashname_0a6729dd_1b07e645".to_owned()),
+        ]));
+        assert_eq!(describe_db_stats(&db), vec![
+            "Symbols: 2",
+            "Unknown: 1 (50.000%)",
+            "Confirmed by hand: 0",
+            "Distinct mangled hashes: 2",
+            "Most symbols sharing a mangled hash: 1",
+        ]);
+        assert_eq!(describe_db_stats(&SymbolDatabase::default())[1], "Unknown: 0 (0.000%)");
+    }
+
+    #[test]
+    fn test_describe_address() {
+        let mut db = SymbolDatabase::new(&HashMap::from([
+            (0x80004000, "construct__10dWmActor_cFv".to_owned()),
+            (0x80004024, "hashname_0a6729dd_1b07e645".to_owned()),
+        ]));
+        assert_eq!(describe_address(&db, 0x80004000), "80004000 | construct__10dWmActor_cFv");
+        assert_eq!(describe_address(&db, 0x80004024), "80004024 | unknown (hashname_0a6729dd_1b07e645)");
+        assert_eq!(describe_address(&db, 0x80004010), "No symbol at 80004010. The nearest one before it is:\n80004000 | construct__10dWmActor_cFv");
+        assert_eq!(describe_address(&db, 0x80000000), "No symbol at or before 80000000.");
+
+        db.confirmed_addresses.insert(0x80004024);
+        assert_eq!(describe_address(&db, 0x80004024), "80004024 | unknown, but confirmed (hashname_0a6729dd_1b07e645)");
+    }
+
+    #[test]
+    fn test_describe_db_alphabet() {
+        assert_eq!(describe_db_alphabet(&BTreeSet::new()), vec!["No known symbol names in the database."]);
+        assert_eq!(describe_db_alphabet(&BTreeSet::from(*b"_aZ<\t")), vec![
+            "5 distinct bytes: \\x09<Z_a",
+            "Lowest: \\x09 (09), highest: a (61)",
+            "Outside the usual mangled-name alphabet: \\x09 <",
+        ]);
+    }
+
+    #[test]
+    fn test_strip_namespace() {
+        let mut settings = test_settings();
+        let names = ["construct__10dWmActor_cFv"];
+        let pattern = "construct__Q2{2ns,2xy}10dWmActor_cFv";
+        assert_eq!(search_pattern(pattern, &mut make_unknown_db(&names), &settings, &mut []), 0);
+
+        settings.strip_namespace = Some("ab".to_owned());
+        assert_eq!(search_pattern(pattern, &mut make_unknown_db(&names), &settings, &mut []), 0);
+        settings.strip_namespace = Some("ns".to_owned());
+        assert_eq!(search_pattern(pattern, &mut make_unknown_db(&names), &settings, &mut []), 1);
+    }
+
+    #[test]
+    fn test_dedup_window() {
+        let mut settings = test_settings();
+        let names = ["construct__10dWmActor_cFv"];
+
+        // Adjacent duplicates
+        let pattern = "construct__10dWmActor_cF{,}v";
+        assert_eq!(search_pattern(pattern, &mut make_unknown_db(&names), &settings, &mut []), 2);
+        settings.dedup_window = 1;
+        assert_eq!(search_pattern(pattern, &mut make_unknown_db(&names), &settings, &mut []), 1);
+
+        // Duplicates further apart than the window
+        let pattern = "{construct,destruct,construct}__10dWmActor_cFv";
+        assert_eq!(search_pattern(pattern, &mut make_unknown_db(&names), &settings, &mut []), 2);
+        settings.dedup_window = 2;
+        assert_eq!(search_pattern(pattern, &mut make_unknown_db(&names), &settings, &mut []), 1);
+    }
+
+    #[test]
+    fn test_preview_sample_indices() {
+        assert_eq!(preview_sample_indices(100, 3, 1, 2), vec![0, 1, 2, 50, 98, 99]);
+        assert_eq!(preview_sample_indices(5, 3, 1, 3), vec![0, 1, 2, 3, 4]);
+        assert_eq!(preview_sample_indices(0, 3, 1, 3), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_preview_includes_last_expansion() {
+        let pattern = "P[{a,b}{1..500}]{c,d{e,f}}";
+        let all: Vec<String> = brace_expand_iter(pattern, false).unwrap()
+            .map(|mut s| {
+                apply_square_bracket_length_prefix_substitution(&mut s);
+                s
+            })
+            .collect();
+        let samples = preview_samples(&brace_expand_iter(pattern, false).unwrap());
+
+        assert_eq!(samples.first(), Some(&(0, all[0].clone())));
+        assert_eq!(samples.last(), Some(&(all.len() - 1, all.last().unwrap().clone())));
+        assert_eq!(samples.last().unwrap().1, "4b500df");
+        for (i, s) in &samples {
+            assert_eq!(s, &all[*i]);
+        }
+    }
+
+    #[test]
+    fn test_boundary_echo_sampling() {
+        let iter = brace_expand_iter("{a..z}{0..9}{0..9}", false).unwrap();
+        let sampler = EchoSampler::new(EchoSampling::Boundaries, iter.num_expansions());
+        let echoed: Vec<String> = iter.enumerate()
+            .filter(|(i, _)| sampler.should_echo(*i, 0))
+            .map(|(_, s)| s)
+            .collect();
+        assert_eq!(echoed.len(), ONLY_ECHO_FIRST + ECHO_MIDDLE + ECHO_LAST);
+        assert_eq!(echoed[0], "a00");
+        assert_eq!(echoed.last().unwrap(), "z99");
+
+        // Periodic sampling only gets there by chance
+        let sampler = EchoSampler::new(EchoSampling::Periodic, 2600);
+        assert!(sampler.should_echo(0, 0));
+        assert!(!sampler.should_echo(2599, 0));
+    }
+
+    #[test]
+    fn test_length_prefix_substitution() {
+        let dir = make_temp_dir("length_prefix_substitution");
+        fs::write(dir.join("names.txt"), "ab\ncdef\n").unwrap();
+        let settings = Settings{word_list_dirs: vec![dir.clone()], ..test_settings()};
+        let expand = |pattern: &str| -> Vec<String> {
+            let line = preprocess_pattern(pattern, &settings, &mut WordListCache::new());
+            brace_expand_iter(&line, false).unwrap().map(|mut s| {
+                apply_square_bracket_length_prefix_substitution(&mut s);
+                s
+            }).collect()
+        };
+
+        // Prefixes count the words, not the "W[...]"
+        assert_eq!(expand("P[W[names]]Fv"), vec!["2abFv", "4cdefFv"]);
+        assert_eq!(expand("P[xW[names]]"), vec!["3xab", "5xcdef"]);
+        // Nested prefixes count the inner prefix's digits too
+        assert_eq!(expand("P[abP[cd]]"), vec!["5ab2cd"]);
+        assert_eq!(expand("P[P[W[names]]x]"), vec!["42abx", "64cdefx"]);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_length_prefix_groups() {
+        let apply = |s: &str| {
+            let mut s = s.to_owned();
+            apply_square_bracket_length_prefix_substitution(&mut s);
+            s
+        };
+
+        // Siblings each get their own length
+        assert_eq!(apply("aP[x]bP[yy]c"), "a1xb2yyc");
+        assert_eq!(apply("P[abcdefghij]P[]"), "10abcdefghij0");
+        // Nested
+        assert_eq!(apply("P[aP[b]]"), "3a1b");
+        assert_eq!(apply("P[P[ab]P[c]]d"), "52ab1cd");
+        // Unmatched brackets are left alone
+        assert_eq!(apply("a]P[b]c]"), "a]1bc]");
+        assert_eq!(apply("P[aP[b]"), "P[a1b");
+        assert_eq!(apply("no prefixes]"), "no prefixes]");
+    }
+
+    #[test]
+    fn test_char_classes() {
+        let expand = |pattern: &str| -> Vec<String> {
+            let line = preprocess_pattern(pattern, &Settings{escaping_enabled: true, ..test_settings()}, &mut WordListCache::new());
+            brace_expand_iter(&line, true).unwrap().map(|mut s| {
+                apply_square_bracket_length_prefix_substitution(&mut s);
+                s
+            }).collect()
+        };
+
+        assert_eq!(expand("[a-c]x"), vec!["ax", "bx", "cx"]);
+        assert_eq!(expand("[a-bX0-1_]"), vec!["a", "b", "X", "0", "1", "_"]);
+        assert_eq!(expand("[-a,]"), vec!["-", "a", ","]);
+        assert_eq!(expand("[aba]"), vec!["a", "b"]);
+        assert_eq!(expand("P[[x-y]z]"), vec!["2xz", "2yz"]);
+        assert_eq!(expand("a[?[0-1]]"), vec!["a", "a0", "a1"]);
+
+        let apply = |s: &str| {
+            let mut s = s.to_owned();
+            apply_char_class_substitution(&mut s);
+            s
+        };
+        assert_eq!(apply("P[ab]W[list]"), "P[ab]W[list]");
+        assert_eq!(apply("[z-a][]x]"), "[z-a][]x]");
+        assert_eq!(apply("[[a]]"), "[{a}]");
+    }
+
+    #[test]
+    fn test_case_permutations() {
+        let expand = |pattern: &str| -> Vec<String> {
+            let line = preprocess_pattern(pattern, &test_settings(), &mut WordListCache::new());
+            brace_expand_iter(&line, false).unwrap().collect()
+        };
+
+        assert_eq!(expand("C[ab]"), vec!["ab", "aB", "Ab", "AB"]);
+        assert_eq!(expand("xC[M_1]"), vec!["xm_1", "xM_1"]);
+        assert_eq!(expand("C[a]C[b]").len(), 4);
+
+        let mut s = "C[mario".to_owned();
+        apply_case_permutation_substitution(&mut s);
+        assert_eq!(s, "C[mario");
+        let mut s = "C[mario]".to_owned();
+        apply_case_permutation_substitution(&mut s);
+        assert_eq!(s, "{m,M}{a,A}{r,R}{i,I}{o,O}");
+
+        // Classes inside are expanded first
+        assert_eq!(expand("C[[a-b]x]"), vec!["ax", "aX", "Ax", "AX", "bx", "bX", "Bx", "BX"]);
+        // A "C" right before a class makes it a case permutation, unless
+        // it's separated from it
+        assert_eq!(expand("C[a-b]"), vec!["a-b", "a-B", "A-b", "A-B"]);
+        assert_eq!(expand("{C}[a-b]"), vec!["Ca", "Cb"]);
+        // Other square brackets inside aren't supported
+        let mut s = "C[P[ab]]C[c]".to_owned();
+        apply_case_permutation_substitution(&mut s);
+        assert_eq!(s, "C[P[ab]]{c,C}");
+        let mut s = "C[C[a]".to_owned();
+        apply_case_permutation_substitution(&mut s);
+        assert_eq!(s, "C[{a,A}");
+    }
+
+    #[test]
+    fn test_optional_groups() {
+        let expand = |pattern: &str| -> Vec<String> {
+            let line = preprocess_pattern(pattern, &test_settings(), &mut WordListCache::new());
+            brace_expand_iter(&line, false).unwrap().map(|mut s| {
+                apply_square_bracket_length_prefix_substitution(&mut s);
+                s
+            }).collect()
+        };
+
+        assert_eq!(expand("foo[?_bar]"), vec!["foo", "foo_bar"]);
+        assert_eq!(expand("foo[?_bar[?_baz]]"), vec!["foo", "foo_bar", "foo_bar_baz"]);
+        assert_eq!(expand("[?a][?b]"), vec!["", "b", "a", "ab"]);
+        assert_eq!(expand("[?P[ab]]x"), vec!["x", "2abx"]);
+
+        let mut s = "a[b]c".to_owned();
+        apply_square_bracket_optional_substitution(&mut s);
+        assert_eq!(s, "a[b]c");
+    }
+
+    #[test]
+    fn test_word_list_dir_precedence() {
+        let base = make_temp_dir("word_list_dir_precedence");
+        let local = base.join("local");
+        let shared = base.join("shared");
+        fs::create_dir_all(&local).unwrap();
+        fs::create_dir_all(&shared).unwrap();
+        fs::write(local.join("foo.txt"), "local1\nlocal2\n").unwrap();
+        fs::write(shared.join("foo.txt"), "shared\n").unwrap();
+        fs::write(shared.join("bar.txt"), "bar\n").unwrap();
+
+        let dirs = vec![local.clone(), shared.clone()];
+        assert_eq!(find_word_list_file("foo.txt", &dirs), Some(local.join("foo.txt")));
+        assert_eq!(find_word_list_file("bar.txt", &dirs), Some(shared.join("bar.txt")));
+        assert_eq!(find_word_list_file("baz.txt", &dirs), None);
+
+        let mut s = "aW[foo]bW[bar]".to_owned();
+        apply_square_bracket_word_list_substitution(&mut s, &mut WordListCache::new(), &dirs, false);
+        assert_eq!(s, "a{local1,local2}b{bar}");
+
+        fs::remove_dir_all(&base).ok();
+    }
+
+    #[test]
+    fn test_word_list_dir_from_env() {
+        // (The current directory isn't the temp dir, so this only works
+        // if the directory is really used)
+        let dir = make_temp_dir("word_list_dir_from_env");
+        fs::write(dir.join("envlist.txt"), "one\ntwo\n").unwrap();
+        let settings = Settings{word_list_dirs: default_word_list_dirs(Some(dir.clone().into_os_string())), ..test_settings()};
+        assert_eq!(preprocess_pattern("W[envlist]__Fv", &settings, &mut WordListCache::new()), "{one,two}__Fv");
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_word_list_cache() {
+        let dir = make_temp_dir("word_list_cache");
+        fs::write(dir.join("foo.txt"), "a\nb\n").unwrap();
+        let dirs = vec![dir.clone()];
+        let mut word_lists = WordListCache::new();
+
+        let mut s = "W[foo]x".to_owned();
+        apply_square_bracket_word_list_substitution(&mut s, &mut word_lists, &dirs, false);
+        assert_eq!(s, "{a,b}x");
+
+        // The second time, it doesn't need the file anymore
+        fs::remove_file(dir.join("foo.txt")).unwrap();
+        let mut s = "yW[foo]".to_owned();
+        apply_square_bracket_word_list_substitution(&mut s, &mut word_lists, &dirs, false);
+        assert_eq!(s, "y{a,b}");
+
+        word_lists.clear();
+        assert_eq!(word_lists.get("foo.txt", &dirs, false), None);
+
+        // A line that isn't valid UTF-8 doesn't cut the list short
+        fs::write(dir.join("bad.txt"), b"a\n\xffb\nc\n").unwrap();
+        assert_eq!(word_lists.get("bad.txt", &dirs, false).unwrap(), ["a", "\u{fffd}b", "c"]);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+}
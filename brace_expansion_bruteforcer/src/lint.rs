@@ -0,0 +1,136 @@
+use brace_expand_2::brace_expand_iter;
+
+
+/// Returns true for bytes that can appear in mangled names (or in the
+/// "P[...]" length prefixes that get substituted later).
+fn is_mangled_name_byte(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || b"_<>,-[]".contains(&c)
+}
+
+
+/// Counts braces that are opened but never closed.
+fn count_unclosed_braces(pattern: &str, escape: bool) -> usize {
+    let mut depth: usize = 0;
+    let mut is_escape_seq = false;
+    for c in pattern.chars() {
+        if is_escape_seq {
+            is_escape_seq = false;
+        } else if escape && c == '\\' {
+            is_escape_seq = true;
+        } else if c == '{' {
+            depth += 1;
+        } else if c == '}' {
+            depth = depth.saturating_sub(1);
+        }
+    }
+    depth
+}
+
+
+/// Finds words in the pattern that look like shorthands (e.g. "END4"),
+/// but aren't.
+fn find_unknown_shorthands(pattern: &str, shorthand_names: &[&str]) -> Vec<String> {
+    let mut unknown = Vec::new();
+    for name in shorthand_names {
+        let alpha_prefix = name.trim_end_matches(|c: char| c.is_ascii_digit());
+        if alpha_prefix.len() == name.len() {
+            continue;
+        }
+
+        for (idx, _) in pattern.match_indices(alpha_prefix) {
+            let digits_len = pattern[idx + alpha_prefix.len()..]
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(pattern.len() - idx - alpha_prefix.len());
+            let word = &pattern[idx..idx + alpha_prefix.len() + digits_len];
+            if digits_len > 0 && !shorthand_names.contains(&word) && !unknown.iter().any(|w| w == word) {
+                unknown.push(word.to_owned());
+            }
+        }
+    }
+    unknown
+}
+
+
+/// Looks for likely mistakes in a pattern, for when it didn't match
+/// anything. `raw` is the line as the user typed it, and `processed` is
+/// the pattern after shorthand and word-list substitution. Returns a
+/// list of suggestions (empty if nothing looks wrong).
+pub fn lint_pattern(raw: &str, processed: &str, escape: bool, shorthand_names: &[&str]) -> Vec<String> {
+    let mut suggestions = Vec::new();
+
+    if raw.trim().contains(char::is_whitespace) {
+        suggestions.push("The pattern contains spaces, which are ignored. Mangled names never contain spaces -- is this a demangled name?".to_owned());
+    }
+
+    let unclosed = count_unclosed_braces(processed, escape);
+    if unclosed > 0 {
+        suggestions.push(format!("{unclosed} brace{} opened but never closed.", if unclosed == 1 {" was"} else {"s were"}));
+    }
+
+    for word in find_unknown_shorthands(raw, shorthand_names) {
+        suggestions.push(format!("\"{word}\" isn't a known shorthand (known: {}).", shorthand_names.join(", ")));
+    }
+
+    if let Ok(iter) = brace_expand_iter(processed, escape) {
+        let unexpected: Vec<String> = iter.alphabet().into_iter()
+            .filter(|c| !is_mangled_name_byte(*c))
+            .map(|c| format!("{:?}", c as char))
+            .collect();
+        if !unexpected.is_empty() {
+            suggestions.push(format!("The pattern can produce characters that never appear in mangled names: {}.", unexpected.join(", ")));
+        }
+
+        if iter.num_expansions() > 0 && iter.common_suffix().ends_with("__") {
+            suggestions.push("Every candidate ends with \"__\", so none of them has a class name or argument list.".to_owned());
+        }
+    }
+
+    suggestions
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHORTHANDS: &[&str] = &["END1", "END2", "END3"];
+
+    #[test]
+    fn test_clean_pattern() {
+        assert!(lint_pattern("construct__10dWmActor_cF{v,i}", "construct__10dWmActor_cF{v,i}", false, SHORTHANDS).is_empty());
+    }
+
+    #[test]
+    fn test_stray_space() {
+        let suggestions = lint_pattern("construct__10dWmActor_c F{v,i}", "construct__10dWmActor_cF{v,i}", false, SHORTHANDS);
+        assert_eq!(suggestions.len(), 1);
+        assert!(suggestions[0].contains("spaces"));
+    }
+
+    #[test]
+    fn test_unclosed_brace() {
+        let suggestions = lint_pattern("a{b,{c,d}", "a{b,{c,d}", false, SHORTHANDS);
+        assert_eq!(suggestions, vec!["1 brace was opened but never closed."]);
+        assert!(!lint_pattern("a\\{b", "a\\{b", true, SHORTHANDS).iter().any(|s| s.contains("never closed")));
+    }
+
+    #[test]
+    fn test_unknown_shorthand() {
+        let suggestions = lint_pattern("fooEND4", "fooEND4", false, SHORTHANDS);
+        assert_eq!(suggestions, vec!["\"END4\" isn't a known shorthand (known: END1, END2, END3)."]);
+        assert!(lint_pattern("fooEND2", "foo", false, SHORTHANDS).is_empty());
+    }
+
+    #[test]
+    fn test_unexpected_characters() {
+        let suggestions = lint_pattern("a::b{(,)}", "a::b{(,)}", false, SHORTHANDS);
+        assert_eq!(suggestions, vec!["The pattern can produce characters that never appear in mangled names: '(', ')', ':'."]);
+    }
+
+    #[test]
+    fn test_missing_suffix() {
+        let suggestions = lint_pattern("{a,b}__", "{a,b}__", false, SHORTHANDS);
+        assert_eq!(suggestions.len(), 1);
+        assert!(suggestions[0].contains("__"));
+    }
+}
@@ -1,364 +1,62 @@
-use std::collections::HashMap;
+use std::cell::RefCell;
 use std::error::Error;
-use std::fs::{File, OpenOptions};
-use std::io::{BufReader, BufRead, Write};
+use std::io::{self, IsTerminal};
 use std::path::Path;
-use std::time::Instant;
+use std::rc::Rc;
 
-use brace_expand_2::brace_expand_iter;
-use djb2_utils::{hash_djb2, DJB2_HASH_SEED};
-use lazy_static::lazy_static;
-use nvidia_demangle::demangle;
-use regex::Regex;
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
-use symbol_map_formats::{BasicSymbolMap, load_symbol_map_from_path};
 
+use nvidia_demangle::DEFAULT_BUF_SIZE;
 
-const ONLY_ECHO_FIRST: usize = 50;
-const ECHO_INTERVAL: usize = 2_000_000;
-const ECHO_INTERVAL_MAX_FUDGE: usize = 100;
+use brace_expansion_bruteforcer::args::{Args, WORD_LIST_DIRS_ENV_VAR};
+use brace_expansion_bruteforcer::checkpoint::DEFAULT_CHECKPOINT_INTERVAL;
+use brace_expansion_bruteforcer::sinks::{format_batch_report, CollectingSink, JsonLinesSink, LogFormat, MatchSink, PatternLogSink};
+use brace_expansion_bruteforcer::{load_confirmed_addresses_from_path, load_symbol_database_from_paths, make_pattern_shorthands, run_batch, run_batch_file, run_command, confirm_on_stdin, CommandOutcome, EchoSampling, Settings, WordListCache, CONFIRMED_ADDRESSES_PATH, DEFAULT_MAX_EXPANSIONS};
 
 
-fn make_pattern_shorthands() -> HashMap<String, String> {
-    let primitives = "{,P,R}{,C}{{,U,S}{c,s,i,l},f,b}";
-    HashMap::from([
-        ("END1".to_owned(), format!("{{,C}}F{{v,{primitives}}}", )),
-        ("END2".to_owned(), format!("{{,C}}F{{v,{primitives},{primitives}{primitives}}}")),
-        ("END3".to_owned(), format!("{{,C}}F{{v,{primitives},{primitives}{primitives},{primitives}{primitives}{primitives}}}")),
-    ])
-}
-
-
-#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
-struct SymbolDatabaseEntry {
-    pub address: u32,
-    pub mangled_hash: u32,
-    pub demangled_hash: u32,
-    pub mangled_name: Option<String>,
-}
-
-
-#[derive(Clone, Eq, PartialEq, Debug)]
-struct SymbolDatabase {
-    /// mangled hash -> demangled hash -> symbols with those hashes.
-    /// Organized this way so we can efficiently check if there are any
-    /// symbols matching a mangled hash, so we can skip demangling
-    /// altogether if not.
-    pub contents: HashMap<u32, HashMap<u32, Vec<SymbolDatabaseEntry>>>,
-}
-
-
-impl SymbolDatabase {
-    fn new(basic_map: &BasicSymbolMap) -> Self {
-        lazy_static! {
-            static ref HASHNAME_REGEX: Regex = Regex::new(concat!(
-                r"^",                 // (start of string)
-                r"hashname_",         // "hashname_"
-                r"([a-fA-F0-9]{8})",  // hex number
-                r"_",                 // underscore
-                r"([a-fA-F0-9]{8})",  // hex number
-            )).unwrap();
-        }
-
-        let mut map: HashMap<u32, HashMap<u32, Vec<SymbolDatabaseEntry>>> = HashMap::new();
-        for (address, name) in basic_map.iter() {
-            let (mangled_hash, demangled_hash, mangled_name) = if let Some(caps) = HASHNAME_REGEX.captures(name) {
-                let mangled_hash = caps.get(1).unwrap().as_str();
-                let demangled_hash = caps.get(2).unwrap().as_str();
-
-                // These are guaranteed to succeed because the regex
-                // only allows hex digits for them
-                (u32::from_str_radix(mangled_hash, 16).unwrap(),
-                 u32::from_str_radix(demangled_hash, 16).unwrap(),
-                 None)
-            } else {
-                (hash_djb2(name.as_bytes(), DJB2_HASH_SEED),
-                 hash_djb2(demangle(name).unwrap_or_else(|_| "ERROR".to_owned()).as_bytes(), DJB2_HASH_SEED),
-                 Some(name.clone()))
-            };
-
-            map.entry(mangled_hash).or_default().entry(demangled_hash).or_default().push(SymbolDatabaseEntry{
-                address: *address,
-                mangled_hash,
-                demangled_hash,
-                mangled_name,
-            });
-        }
-        Self{contents: map}
-    }
-
-    fn unknown_contents(&self) -> HashMap<u32, HashMap<u32, Vec<SymbolDatabaseEntry>>> {
-        let mut new_map: HashMap<u32, HashMap<u32, Vec<SymbolDatabaseEntry>>> = HashMap::new();
-        for (mangled_hash, sub_map) in self.contents.iter() {
-            for (demangled_hash, entries) in sub_map.iter() {
-                for entry in entries {
-                    if entry.mangled_name.is_none() {
-                        new_map.entry(*mangled_hash).or_default().entry(*demangled_hash).or_default().push(entry.clone());
-                    }
-                }
-            }
-        }
-        new_map
-    }
-}
-
-
-/// Replaces any "P[" "]" pairs with length prefixes, in-place.
-fn apply_square_bracket_length_prefix_substitution(s: &mut String) {
-    // We search for "P[" in reverse and "]" forward, instead
-    // of the other way around, because we have to process
-    // these from innermost to outermost if they're nested
-    // (or else we'll insert incorrect length values)
-
-    // TODO: it should be possible to optimize this further (go over
-    // the string in one pass instead of multiple)
-
-    while let Some(open_bracket_byte_idx) = s.find("P[") {
-        if let Some(close_bracket_byte_idx) = s.rfind(']') {
-            let substring_length = close_bracket_byte_idx - open_bracket_byte_idx - 2;
-            s.remove(close_bracket_byte_idx);
-            s.replace_range(
-                open_bracket_byte_idx..open_bracket_byte_idx+2,
-                &substring_length.to_string());
-        } else {
-            // TODO: um...?
-            break;
-        }
-    }
-}
-
-
-/// Replaces any "W[" "]" pairs with word lists, in-place.
-fn apply_square_bracket_word_list_substitution(s: &mut String) {
-    while let Some(open_bracket_byte_idx) = s.find("W[") {
-        if let Some(close_bracket_byte_idx) = s[open_bracket_byte_idx+2..].find(']') {
-            let close_bracket_byte_idx = open_bracket_byte_idx + 2 + close_bracket_byte_idx;
-            let word_list_name = &s[open_bracket_byte_idx+2..close_bracket_byte_idx];
-            let mut word_list_name = word_list_name.to_owned();
-            word_list_name.push_str(".txt");
-
-            let mut word_list_pattern = "{".to_owned();
-            if let Ok(file) = File::open(&word_list_name) {
-                for word in BufReader::new(file).lines().flatten() {
-                    word_list_pattern.push_str(&word.replace("\\", "\\\\").replace(",", "\\,").replace("{", "\\{").replace("}", "\\}"));
-                    word_list_pattern.push(',');
-                }
-                word_list_pattern.replace_range(word_list_pattern.len()-1..word_list_pattern.len(), "}");
-
-                s.replace_range(
-                    open_bracket_byte_idx..close_bracket_byte_idx+1,
-                    &word_list_pattern);
-            } else {
-                println!("WARNING: Couldn't open {word_list_name}");
-                break;
-            }
-        } else {
-            // TODO: um...?
-            break;
-        }
-    }
-}
-
-
-/// Applies the global pattern-shorthand replacements and returns a new
-/// String.
-fn apply_pattern_shorthands(s: &str) -> String {
-    let s = s.to_owned();
-    make_pattern_shorthands().iter().fold(s, |acc, kv| acc.replace(kv.0, kv.1))
-}
-
-
-fn process_line_as_pattern(line: &str, db: &mut SymbolDatabase, escaping_enabled: bool) {
-    let unknown_db_contents = db.unknown_contents();
-
-    let mut line = apply_pattern_shorthands(line);
-    apply_square_bracket_word_list_substitution(&mut line);
-    line.retain(|c| !c.is_whitespace());
-    let line = line;
-
-    let iter = brace_expand_iter(&line, escaping_enabled);
-    if let Err(e) = iter {
-        println!("Parsing failure: {:?}", e);
-        return;
-    }
-    let mut iter = iter.unwrap();
-    let num_expansions = iter.num_expansions();
-
-    if num_expansions > ONLY_ECHO_FIRST {
-        println!("Checking {num_expansions} symbols...");
-    }
-
-    let query_start_time = Instant::now();
-
-    let mut sym_mangled = String::with_capacity(iter.max_expansion_length());
-    let mut next_i = 0;
-    let mut echo_interval_fudge = 0;
-    let mut newly_found_syms = Vec::new();
-    while iter.next_into(&mut sym_mangled) {
-        // (doing it this way so we can safely `continue` in the middle
-        // of this loop if we want to)
-        let i = next_i;
-        next_i += 1;
-
-        let mut force_echo = i < ONLY_ECHO_FIRST || (i + echo_interval_fudge) % ECHO_INTERVAL == 0;
-
-        apply_square_bracket_length_prefix_substitution(&mut sym_mangled);
-
-        let hash_mangled = hash_djb2(sym_mangled.as_bytes(), DJB2_HASH_SEED);
-
-        // if forcing echo, use the full db so we can report "known"
-        // symbols -- otherwise, we're not going to report those anyway,
-        // so use the smaller version to be more efficient
-        let matching_mangled_db = if force_echo {
-            db.contents.get(&hash_mangled)
-        } else {
-            unknown_db_contents.get(&hash_mangled)
-        };
-
-        // Important optimization
-        if matching_mangled_db.is_none() && !force_echo {
-            continue;
-        }
-
-        let sym_demangled = demangle(&sym_mangled).unwrap_or_else(|_| "ERROR".to_string());
-        let hash_demangled = hash_djb2(sym_demangled.as_bytes(), DJB2_HASH_SEED);
-
-        let mut status = "";
-        let mut new_unknown_syms = None;
-        if let Some(matching_mangled_db) = matching_mangled_db {
-            if let Some(matching_both_db) = matching_mangled_db.get(&hash_demangled) {
-                // There are symbols matching both of the hashes. Pick out the ones with unknown names
-                let thing: Vec<&SymbolDatabaseEntry> = matching_both_db.iter().filter(|sym| sym.mangled_name.is_none()).collect();
-                if thing.is_empty() {
-                    status = "(known)";
-                } else {
-                    new_unknown_syms = Some(thing);
-                    force_echo = true;
-                    status = "!!!!!!!!!!!!!!!!! ";  // (18 "!" + 1 " ")
-                }
-            }
-        }
-
-        if new_unknown_syms.is_none() && !force_echo {
-            continue;
-        }
-
-        println!("{hash_mangled:08x}_{hash_demangled:08x} | {sym_mangled}");
-        println!("{status:^18}| {sym_demangled}");
-
-        if i == ONLY_ECHO_FIRST - 1 {
-            println!("For performance, only the first {ONLY_ECHO_FIRST} symbols are displayed (above), plus a small sample of the rest (below):");
-        }
-
-        if force_echo {
-            // We add a bit of jitter to the echo interval because
-            // otherwise it can end up being a multiple of some
-            // sub-pattern in the brace-expansion output sequence, which
-            // causes us to only show some types of outputs and not a
-            // more representative sample.
-            echo_interval_fudge += 1;
-            if echo_interval_fudge > ECHO_INTERVAL_MAX_FUDGE {
-                echo_interval_fudge = 0;
-            }
-        }
-
-        if let Some(new_unknown_syms) = new_unknown_syms {
-            println!("{empty:^>width$}", empty = "", width = 70);  // ("^" * 70)
-
-            for matching_sym in new_unknown_syms {
-                newly_found_syms.push((matching_sym.clone(), sym_mangled.clone(), sym_demangled.clone()));
-
-                if let Ok(mut file) = OpenOptions::new()
-                        .create(true)
-                        .append(true)
-                        .open("positive_symbol_log.txt") {
-                    writeln!(file, "{sym_mangled}").ok();
-                }
-            }
-        }
-    }
-
-    let symbols_checked_str = format!("({} symbol{} checked)",
-        num_expansions,
-        if num_expansions == 1 {""} else {"s"});
-
-    if newly_found_syms.is_empty() {
-        println!("No new matches {symbols_checked_str}.");
-    } else {
-        println!();
-        println!("{empty:!>width$}", empty = "", width = 70);  // ("!" * 70)
-
-        println!("Found {} new match{} {}!",
-            newly_found_syms.len(),
-            if newly_found_syms.len() == 1 {""} else {"es"},
-            symbols_checked_str);
-
-        for (sym, name_mangled, name_demangled) in &newly_found_syms {
-            println!("{:08x} | {:<40} | {}", sym.address, name_mangled, name_demangled);
-        }
-
-        println!("{empty:!>width$}", empty = "", width = 70);  // ("!" * 70)
-        println!();
-    }
-
-    let query_time = (Instant::now() - query_start_time).as_secs_f64();
-    if query_time > 10.0 {
-        println!("(Query executed in {query_time:0.3} seconds)");
-    }
-}
-
-
-fn load_symbol_database_from_path(path: &Path, verbose: bool) -> Result<SymbolDatabase, Box<dyn Error>> {
-    let symbol_list = load_symbol_map_from_path(path)?;
-
-    if verbose {
-        let mut file_name = "<unknown>";
-        if let Some(name) = path.file_name() {
-            if let Some(name) = name.to_str() {
-                file_name = name;
-            }
-        }
-        let file_name = file_name;
-
-        let total_len = symbol_list.len();
-        let unk_len = symbol_list.iter().filter(|item| item.1.starts_with("hashname_")).count();
-
-        println!("Loaded {} symbols from {} ({} ({:0.3}%) unknown).",
-            total_len, file_name, unk_len, (unk_len as f64) / (total_len as f64) * 100.0);
-        println!();
-    }
-
-    Ok(SymbolDatabase::new(&symbol_list))
-}
-
-
-fn main() -> Result<(), Box<dyn Error>> {
-    let mut rl = Editor::<()>::new()?;
-    rl.load_history("history.txt").ok();
-
-    let first_arg = std::env::args().nth(1);
-    if first_arg.is_none() {
-        println!("Specify the path to the symbol map as the first argument");
-        return Ok(());
-    }
-    let first_arg = first_arg.unwrap();
-    let symbol_map_path = Path::new(&first_arg);
-
-    let mut db = load_symbol_database_from_path(symbol_map_path, true)?;
-
+fn print_help() {
     println!("Commands:");
     println!("- Ctrl+C / Ctrl+D / q / quit / e / exit: exit");
-    println!("- r / reload: reload the symbol database");
+    println!("- r / reload: reload the symbol database (all of the symbol maps)");
+    println!("- reload wordlists: re-read word list files the next time they're used (they're cached otherwise)");
     println!("- escapes on / escapes off: enable/disable backslash escapes in patterns (turned OFF by default).");
     println!("    - Enabling lets you include literal braces and commas in patterns, but also means you have to escape any literal backslashes.");
+    println!("- stats: show statistics about the symbol database");
+    println!("- maxexpand <n>: ask for confirmation before searching patterns with more than n expansions (default {DEFAULT_MAX_EXPANSIONS}; 0 to never ask)");
+    println!("- match demangled-only / match both: match symbols on just their demangled hashes, or on both hashes (the default)");
+    println!("- sample boundaries / sample periodic: during searches, also show the last few symbols and a few from the middle, or a periodic sample (the default)");
+    println!("- db-alphabet: list the bytes used in known symbol names in the database");
+    println!("- hash <string>: print the djb2 hash of the string (and of its demangled form, if it looks mangled)");
+    println!("- demangle <symbol>: print the demangled form of a symbol name");
+    println!("- unhash <hex hash> <suffix>: undo the suffix from the hash, giving the hash everything before the suffix must have");
+    println!("- hash-trace <string>: print the running djb2 hash state after each byte of the string");
+    println!("- preview <pattern>: show the first and last few symbols a pattern produces, and some from the middle, without searching");
+    println!("- graph <pattern>: print the structure of the pattern as a Graphviz DOT graph");
+    println!("- verify-roundtrip <path>: check that a Dolphin symbol map can be written back out without losing anything");
+    println!("- addr <address>: show the symbol at this (hex) address, or the nearest one before it");
+    println!("- confirm <address>: mark the symbol at this (hex) address as confirmed, so matches for it aren't reported as new anymore (saved to {CONFIRMED_ADDRESSES_PATH})");
+    println!("- resume <file>: continue a search from a checkpoint file saved with --checkpoint");
+    println!("- fuzz <symbol>: check every symbol within one insertion, deletion or substitution of the given one");
     println!("- (anything else): run as a bruteforce pattern");
     println!();
     println!("Pattern format:");
     println!("- Curly braces (\"{{a,b,c}}\") expand to multiple strings (\"a\", \"b\", \"c\").");
     println!("    - Empty elements are OK: \"{{a,b,}}\" -> \"a\", \"b\", \"\".");
+    println!("- Numeric ranges (\"{{8..11}}\") expand to each number in the range (\"8\", \"9\", \"10\", \"11\").");
+    println!("    - \"0x\" and \"0b\" prefixes (\"{{0x0e..0x11}}\") give zero-padded hex or binary (\"0e\", \"0f\", \"10\", \"11\").");
+    println!("    - A step can be added at the end (\"{{1..10..3}}\" -> \"1\", \"4\", \"7\", \"10\").");
+    println!("- Character ranges (\"{{a..e}}\") expand to each letter in the range, in either direction.");
+    println!("- A \"#\" at the very end (\"abc#\") appends a checksum digit: the sum of the other bytes, mod 10 (\"abc4\").");
+    println!("    - \"#%N\" at the end sums mod N instead (\"abc#%256\" -> \"abc38\"), and \"#d\" sums just the digits, mod 10 (\"a1b2#d\" -> \"a1b23\").");
+    println!("    - So a pattern can't end with a literal \"#\", \"#d\" or \"#%N\" unless backslash-escapes are enabled and the \"#\" is escaped (\"abc\\#\").");
     println!("- \"P\" + square brackets (\"P[abc]\") will be replaced by a length prefix (\"3abc\").");
+    println!("- \"C\" + square brackets (\"C[ab]\") will expand to every capitalization of the contents (\"ab\", \"aB\", \"Ab\", \"AB\").");
+    println!("    - Character classes inside are expanded first (\"C[[a-b]x]\"). A \"C\" right before a character class always means this, so write a literal one as \"{{C}}[a-c]\".");
+    println!("- Square brackets with a character class (\"[a-cX_]\") expand to each of the characters (\"a\", \"b\", \"c\", \"X\", \"_\"), like in a regex.");
+    println!("- Square brackets starting with \"?\" (\"a[?bc]\") are optional: they're shorthand for \"{{,bc}}\" (\"a\", \"abc\"). They can be nested.");
     println!("- \"W\" + square brackets (\"W[abc]\") will expand to the contents of word list file \"abc.txt\" (one word per line).");
+    println!("    - Word lists are looked up in the --wordlist-dir directories (in the order given), or the ones in {WORD_LIST_DIRS_ENV_VAR} (separated like PATH), or the current directory if there are neither.");
     println!("    - Commas, braces and backslashes will be escaped, so this is best used with backslash-escapes enabled.");
     println!("- Linebreaks and other whitespace are removed/ignored");
     println!("- You can use the following shorthand aliases to easily search for symbols with common signatures:");
@@ -369,31 +67,128 @@ fn main() -> Result<(), Box<dyn Error>> {
         println!("    - \"{key}\": \"{value}\"");
     }
     println!();
+    println!("Command-line options:");
+    println!("- (paths): the symbol maps to load; if there are several, they're merged, and matches say which map they came from");
+    println!("- --batch <file>: run each line of the file as a command, then exit");
+    println!("- --start-line <n>: with --batch or --patterns, skip to line n of the file (to resume an interrupted run)");
+    println!("- --patterns <file>: run each line of the file as a pattern (never as a command), then exit; blank lines and lines starting with \"#\" are skipped");
+    println!("- --pattern <pattern>: run the pattern (or command), then exit (can be used multiple times, and combined with --batch and --patterns)");
+    println!("    - In both cases, the exit code is 1 if no new matches were found, or 0 otherwise.");
+    println!("- --escapes: start with backslash-escapes enabled (like \"escapes on\")");
+    println!("- --pattern-log <file>: also log found symbols to this file, as patterns that can be re-run with --batch");
+    println!("- --log-format <text|csv|json>: format of the main match log ({}, {} or {}); csv and json include the hashes", LogFormat::Text.log_path(), LogFormat::Csv.log_path(), LogFormat::Json.log_path());
+    println!("- --json-matches <file>: also log found symbols to this file, as JSON objects (one per line)");
+    println!("- --min-demangle-length <n>: don't demangle candidates shorter than this (they can't match)");
+    println!("- --demangle-buf <n>: initial size of the buffer demangled names are written into (default {DEFAULT_BUF_SIZE}); it grows if needed");
+    println!("- --exclude <substring>: skip candidates containing this (can be used multiple times)");
+    println!("- --dedup-window <n>: skip candidates that are the same as one of the last n (distinct) ones");
+    println!("- --strip-namespace <ns>: remove a leading \"ns::\" (or its mangled equivalent) from each candidate before hashing it");
+    println!("- --inverse-prune: work backwards from the unknown symbols' hashes to skip candidates that can't match, instead of checking every one");
+    println!("    - Only the matches are shown. Patterns using length prefixes (or --strip-namespace) are still checked the normal way.");
+    println!("- --threads <n>: search on n threads (default 1); symbols are shown out of order, and --dedup-window and --nearest-hash need 1 thread");
+    println!("- --max-expansions <n>: in the REPL, ask for confirmation before searching patterns with more than n expansions (default {DEFAULT_MAX_EXPANSIONS}); otherwise, skip them with an error (no limit by default). 0 means no limit.");
+    println!("- --checkpoint <file>: save progress to this file during long (single-threaded) searches, for the \"resume\" command");
+    println!("- --checkpoint-interval <n>: with --checkpoint, save progress every n symbols (default {DEFAULT_CHECKPOINT_INTERVAL})");
+    println!("- --nearest-hash: after each query, report how close the nearest candidate came to an unknown symbol's hash");
+    println!("- --stream-map: parse the (Dolphin-format) symbol map incrementally, to reduce memory usage for very large maps");
+    println!();
+}
+
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            println!("{e}");
+            return Ok(());
+        }
+    };
+
+    let mut db = load_symbol_database_from_paths(&args.symbol_map_paths, args.stream_map, true)?;
+    load_confirmed_addresses_from_path(&mut db, Path::new(CONFIRMED_ADDRESSES_PATH))?;
+
+    let mut settings = Settings{
+        escaping_enabled: args.escapes,
+        word_list_dirs: args.word_list_dirs.clone(),
+        min_demangle_length: args.min_demangle_length,
+        demangle_buf_size: args.demangle_buf_size,
+        excluded_substrings: args.excluded_substrings.clone(),
+        report_nearest_hash: args.report_nearest_hash,
+        dedup_window: args.dedup_window,
+        strip_namespace: args.strip_namespace.clone(),
+        inverse_prune: args.inverse_prune,
+        threads: args.threads,
+        // (The REPL sets this again below)
+        max_expansions: args.max_expansions.unwrap_or(0),
+        confirm_large_patterns: None,
+        demangled_only: false,
+        echo_sampling: EchoSampling::Periodic,
+        checkpoint_path: args.checkpoint_path.clone(),
+        checkpoint_interval: args.checkpoint_interval,
+        verbose: args.verbose,
+    };
+
+    let mut word_lists = WordListCache::new();
+
+    let mut sinks: Vec<Box<dyn MatchSink>> = vec![args.log_format.log_sink()?];
+    if let Some(path) = &args.pattern_log_path {
+        sinks.push(Box::new(PatternLogSink::new(path)));
+    }
+    if let Some(path) = &args.json_matches_path {
+        sinks.push(Box::new(JsonLinesSink::append_to(path)?));
+    }
+
+    if args.batch_path.is_some() || args.patterns_path.is_some() || !args.patterns.is_empty() {
+        let all_matches = Rc::new(RefCell::new(Vec::new()));
+        sinks.push(Box::new(CollectingSink::new(all_matches.clone())));
+
+        let mut total_new_matches = 0;
+        if let Some(batch_path) = &args.batch_path {
+            total_new_matches += run_batch_file(batch_path, false, &mut db, &mut settings, &mut word_lists, &mut sinks, &args)?;
+        }
+        if let Some(patterns_path) = &args.patterns_path {
+            let num_new_matches = run_batch_file(patterns_path, true, &mut db, &mut settings, &mut word_lists, &mut sinks, &args)?;
+            println!("Ran the patterns from {}: {num_new_matches} new matches.", patterns_path.display());
+            total_new_matches += num_new_matches;
+        }
+        total_new_matches += run_batch(args.patterns.clone(), false, &mut db, &mut settings, &mut word_lists, &mut sinks, &args)?;
+
+        if !all_matches.borrow().is_empty() {
+            println!("Matches by pattern:");
+            print!("{}", format_batch_report(&all_matches.borrow()));
+        }
+
+        // Like grep, exit with 1 if nothing was found, so scripts can
+        // tell the difference
+        if total_new_matches == 0 {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    print_help();
 
-    let mut escaping_enabled: bool = false;
+    settings.max_expansions = args.max_expansions.unwrap_or(DEFAULT_MAX_EXPANSIONS);
+    // Only ask for confirmation if someone's there to answer
+    if io::stdin().is_terminal() {
+        settings.confirm_large_patterns = Some(confirm_on_stdin);
+    }
+
+    let mut rl = Editor::<()>::new()?;
+    rl.load_history("history.txt").ok();
 
     loop {
         let readline = rl.readline("sym> ");
         match readline {
             Ok(line) => {
                 rl.add_history_entry(line.as_str());
-                if line == "q" || line == "quit" || line == "e" || line == "exit" {
+                // It's a good idea to flush the history here, since
+                // otherwise, if the pattern is particularly long
+                // and the user decides to Ctrl+C it, they'd lose
+                // that history entry
+                rl.append_history("history.txt")?;
+                if let CommandOutcome::Quit = run_command(&line, 0, &mut db, &mut settings, &mut word_lists, &mut sinks, &args)? {
                     break
-                } else if line == "r" || line == "reload" {
-                    db = load_symbol_database_from_path(symbol_map_path, true)?;
-                } else if line == "escapes on" {
-                    println!("Backslash-escaping enabled.");
-                    escaping_enabled = true;
-                } else if line == "escapes off" {
-                    println!("Backslash-escaping disabled.");
-                    escaping_enabled = false;
-                } else {
-                    // It's a good idea to flush the history here, since
-                    // otherwise, if the pattern is particularly long
-                    // and the user decides to Ctrl+C it, they'd lose
-                    // that history entry
-                    rl.append_history("history.txt")?;
-                    process_line_as_pattern(&line, &mut db, escaping_enabled);
                 }
             },
             Err(ReadlineError::Interrupted) => {
@@ -0,0 +1,66 @@
+/// Splits a length-prefixed name ("10dWmActor_c") off the start of a
+/// string, returning the name and the rest of the string.
+fn split_length_prefixed(s: &str) -> Option<(&str, &str)> {
+    let num_digits = s.bytes().take_while(u8::is_ascii_digit).count();
+    let len: usize = s[..num_digits].parse().ok()?;
+    let end = num_digits.checked_add(len)?;
+    if end > s.len() || !s.is_char_boundary(end) {
+        return None;
+    }
+    Some((&s[num_digits..end], &s[end..]))
+}
+
+
+/// Removes `namespace` from the start of a symbol name's qualifiers, if
+/// it's there. This understands CodeWarrior-mangled names (so
+/// "construct__Q22ns10dWmActor_cFv" and "func__2nsFv" become
+/// "construct__10dWmActor_cFv" and "func__Fv"), and also plain
+/// demangled-style "ns::" prefixes. Returns None if the name isn't in
+/// that namespace.
+pub fn strip_namespace(name: &str, namespace: &str) -> Option<String> {
+    if let Some(rest) = name.strip_prefix(namespace).and_then(|rest| rest.strip_prefix("::")) {
+        return Some(rest.to_owned());
+    }
+
+    // The qualifiers start after the first "__" that isn't at the very
+    // beginning (to allow for names like "__ct")
+    let qualifiers_start = name.get(1..)?.find("__")? + 3;
+    let (base, qualifiers) = name.split_at(qualifiers_start);
+
+    if let Some(rest) = qualifiers.strip_prefix('Q') {
+        let count = rest.chars().next()?.to_digit(10)?;
+        let (first, rest) = split_length_prefixed(&rest[1..])?;
+        if first != namespace || count < 2 {
+            return None;
+        }
+        if count == 2 {
+            // No need for a Q prefix with only one name left
+            Some(format!("{base}{rest}"))
+        } else {
+            Some(format!("{base}Q{}{rest}", count - 1))
+        }
+    } else {
+        let (first, rest) = split_length_prefixed(qualifiers)?;
+        (first == namespace).then(|| format!("{base}{rest}"))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_namespace() {
+        assert_eq!(strip_namespace("construct__Q22ns10dWmActor_cFv", "ns").as_deref(), Some("construct__10dWmActor_cFv"));
+        assert_eq!(strip_namespace("__ct__Q33EGG2ns7TBufferFv", "EGG").as_deref(), Some("__ct__Q22ns7TBufferFv"));
+        assert_eq!(strip_namespace("func__2nsFv", "ns").as_deref(), Some("func__Fv"));
+        assert_eq!(strip_namespace("ns::dWmActor_c::construct(void)", "ns").as_deref(), Some("dWmActor_c::construct(void)"));
+
+        assert_eq!(strip_namespace("construct__Q22ms10dWmActor_cFv", "ns"), None);
+        assert_eq!(strip_namespace("construct__10dWmActor_cFv", "ns"), None);
+        assert_eq!(strip_namespace("func__3nssFv", "ns"), None);
+        assert_eq!(strip_namespace("func__99nsFv", "ns"), None);
+        assert_eq!(strip_namespace("__ct", "ns"), None);
+    }
+}
@@ -0,0 +1,118 @@
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+
+/// How many candidates are checked between looks at the clock.
+const CHECK_INTERVAL: usize = 1 << 16;
+/// Shortest time between redraws of the bar.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(250);
+const BAR_WIDTH: usize = 30;
+
+
+/// How far through `total` candidates a search at `index` is, from 0 to
+/// 1.
+pub fn progress_fraction(index: usize, total: usize) -> f64 {
+    if total == 0 {
+        1.0
+    } else {
+        (index as f64 / total as f64).min(1.0)
+    }
+}
+
+
+/// Formats a duration as "H:MM:SS".
+fn format_eta(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+
+/// Formats one line of progress: a bar, the percentage, the rate, and
+/// the estimated time remaining. `elapsed` is how long it took to get
+/// from `start_index` to `index`.
+pub fn format_progress(start_index: usize, index: usize, total: usize, elapsed: Duration) -> String {
+    let fraction = progress_fraction(index, total);
+    let filled = (fraction * BAR_WIDTH as f64) as usize;
+    let rate = (index - start_index) as f64 / elapsed.as_secs_f64();
+    let eta = if rate > 0.0 && rate.is_finite() {
+        format_eta(Duration::from_secs_f64(total.saturating_sub(index) as f64 / rate))
+    } else {
+        "?".to_owned()
+    };
+    format!("[{}{}] {:5.1}% ({:0.0} symbols/sec, ETA {eta})",
+        "#".repeat(filled), "-".repeat(BAR_WIDTH - filled), fraction * 100.0, rate)
+}
+
+
+/// A progress bar for a long search, drawn on one line of stdout. It
+/// has to be cleared before anything else is printed, and is redrawn
+/// at the next update.
+pub struct ProgressBar {
+    start_index: usize,
+    total: usize,
+    start_time: Instant,
+    last_draw: Instant,
+    /// Length of the line currently drawn (0 if it's cleared)
+    drawn_len: usize,
+}
+
+
+impl ProgressBar {
+    pub fn new(start_index: usize, total: usize) -> Self {
+        let now = Instant::now();
+        Self{start_index, total, start_time: now, last_draw: now, drawn_len: 0}
+    }
+
+    /// Called with the index of each candidate. This is cheap, since it
+    /// only redraws every so often.
+    pub fn update(&mut self, index: usize) {
+        if index == self.start_index || !(index - self.start_index).is_multiple_of(CHECK_INTERVAL) {
+            return;
+        }
+        if self.last_draw.elapsed() < REDRAW_INTERVAL {
+            return;
+        }
+        self.last_draw = Instant::now();
+
+        let line = format_progress(self.start_index, index, self.total, self.start_time.elapsed());
+        // (Padded, in case the previous line was longer)
+        print!("\r{line:<width$}", width = self.drawn_len);
+        io::stdout().flush().ok();
+        self.drawn_len = line.len();
+    }
+
+    /// Erases the bar, if it's drawn.
+    pub fn clear(&mut self) {
+        if self.drawn_len > 0 {
+            print!("\r{empty:width$}\r", empty = "", width = self.drawn_len);
+            io::stdout().flush().ok();
+            self.drawn_len = 0;
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress_fraction() {
+        assert_eq!(progress_fraction(0, 200), 0.0);
+        assert_eq!(progress_fraction(50, 200), 0.25);
+        assert_eq!(progress_fraction(200, 200), 1.0);
+        assert_eq!(progress_fraction(300, 200), 1.0);
+        assert_eq!(progress_fraction(0, 0), 1.0);
+    }
+
+    #[test]
+    fn test_format_progress() {
+        // Half done, at 100/sec, with 100 to go
+        assert_eq!(format_progress(0, 100, 200, Duration::from_secs(1)),
+            "[###############---------------]  50.0% (100 symbols/sec, ETA 0:00:01)");
+        // Resumed from 100, at 10/sec, with 3600 to go
+        assert_eq!(format_progress(100, 110, 3710, Duration::from_secs(1)),
+            "[------------------------------]   3.0% (10 symbols/sec, ETA 0:06:00)");
+        assert!(format_progress(0, 0, 200, Duration::ZERO).ends_with("ETA ?)"));
+    }
+}
@@ -0,0 +1,451 @@
+use std::cell::RefCell;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use brace_expand_2::escape_for_pattern;
+use djb2_utils::{hash_djb2, DJB2_HASH_SEED};
+
+
+/// A newly discovered symbol name.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct FoundMatch {
+    pub address: u32,
+    pub mangled_name: String,
+    pub demangled_name: String,
+    /// The command that found this match, as it was entered
+    pub source_pattern: String,
+    /// Which line of the batch the command was on (1-based), or 0 if it
+    /// wasn't part of a batch
+    pub line: usize,
+}
+
+
+impl FoundMatch {
+    pub fn mangled_hash(&self) -> u32 {
+        hash_djb2(self.mangled_name.as_bytes(), DJB2_HASH_SEED)
+    }
+
+    pub fn demangled_hash(&self) -> u32 {
+        hash_djb2(self.demangled_name.as_bytes(), DJB2_HASH_SEED)
+    }
+}
+
+
+/// Formats that the main match log can be written in.
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug, Default)]
+pub enum LogFormat {
+    /// Just the mangled names, one per line
+    #[default]
+    Text,
+    /// CSV, with a header row
+    Csv,
+    /// One JSON object per line
+    Json,
+}
+
+impl LogFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "text" => Some(Self::Text),
+            "csv" => Some(Self::Csv),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+
+    /// Where the main match log goes in this format.
+    pub fn log_path(self) -> &'static str {
+        match self {
+            Self::Text => "positive_symbol_log.txt",
+            Self::Csv => "positive_symbol_log.csv",
+            Self::Json => "positive_symbol_log.jsonl",
+        }
+    }
+
+    /// Makes a sink that appends to the main match log in this format.
+    pub fn log_sink(self) -> io::Result<Box<dyn MatchSink>> {
+        Ok(match self {
+            Self::Text => Box::new(FileSink::new(self.log_path())),
+            Self::Csv => Box::new(CsvSink::append_to(self.log_path())?),
+            Self::Json => Box::new(JsonLinesSink::append_to(self.log_path())?),
+        })
+    }
+}
+
+
+/// Somewhere that found matches get reported to. Sinks are best-effort:
+/// a sink that fails to write shouldn't stop the search.
+pub trait MatchSink {
+    fn push(&mut self, found: &FoundMatch);
+}
+
+
+/// Sends a match to every sink, in order.
+pub fn push_to_all(sinks: &mut [Box<dyn MatchSink>], found: &FoundMatch) {
+    for sink in sinks.iter_mut() {
+        sink.push(found);
+    }
+}
+
+
+/// Writes each match as a human-readable table row.
+pub struct TextSink<W: Write> {
+    writer: W,
+}
+
+impl TextSink<io::Stdout> {
+    pub fn stdout() -> Self {
+        Self::new(io::stdout())
+    }
+}
+
+impl<W: Write> TextSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self{writer}
+    }
+}
+
+impl<W: Write> MatchSink for TextSink<W> {
+    fn push(&mut self, found: &FoundMatch) {
+        writeln!(self.writer, "{:08x} | {:<40} | {}",
+            found.address, found.mangled_name, found.demangled_name).ok();
+    }
+}
+
+
+/// Appends the mangled name of each match to a file, one per line. The
+/// file is opened for each match, so it can be moved or deleted while
+/// the program is running.
+pub struct FileSink {
+    path: PathBuf,
+}
+
+impl FileSink {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self{path: path.as_ref().to_owned()}
+    }
+}
+
+impl MatchSink for FileSink {
+    fn push(&mut self, found: &FoundMatch) {
+        if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(&self.path) {
+            writeln!(file, "{}", found.mangled_name).ok();
+        }
+    }
+}
+
+
+/// Appends each match to a file as a pattern that matches only that
+/// name. The file starts with an "escapes on" command so that it can be
+/// re-run as-is with --batch.
+pub struct PatternLogSink {
+    path: PathBuf,
+}
+
+impl PatternLogSink {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self{path: path.as_ref().to_owned()}
+    }
+}
+
+impl MatchSink for PatternLogSink {
+    fn push(&mut self, found: &FoundMatch) {
+        append_to_pattern_log(&self.path, &found.mangled_name).ok();
+    }
+}
+
+
+fn append_to_pattern_log(path: &Path, name: &str) -> io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    if file.metadata()?.len() == 0 {
+        writeln!(file, "escapes on")?;
+    }
+    writeln!(file, "{}", escape_for_pattern(name))
+}
+
+
+/// Escapes a string for use in a JSON string literal (not including the
+/// surrounding quotes).
+fn escape_json(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+
+/// Quotes a CSV field if it needs it (if it contains a comma, quote or
+/// line break).
+fn escape_csv(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
+
+
+/// Writes each match as a CSV row: address, mangled name, demangled
+/// name, and their hashes. A header row is written first, unless the
+/// output already has some rows in it.
+pub struct CsvSink<W: Write> {
+    writer: W,
+    header_written: bool,
+}
+
+impl CsvSink<File> {
+    /// Appends to the file at the given path, creating it if needed.
+    pub fn append_to<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let header_written = file.metadata()?.len() > 0;
+        Ok(Self{writer: file, header_written})
+    }
+}
+
+impl<W: Write> CsvSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self{writer, header_written: false}
+    }
+}
+
+impl<W: Write> MatchSink for CsvSink<W> {
+    fn push(&mut self, found: &FoundMatch) {
+        if !self.header_written {
+            self.header_written = writeln!(self.writer, "address,mangled,demangled,mangled_hash,demangled_hash").is_ok();
+        }
+        writeln!(self.writer, "{:08x},{},{},{:08x},{:08x}",
+            found.address, escape_csv(&found.mangled_name), escape_csv(&found.demangled_name),
+            found.mangled_hash(), found.demangled_hash()).ok();
+        self.writer.flush().ok();
+    }
+}
+
+
+/// Writes each match as a JSON object on its own line.
+pub struct JsonLinesSink<W: Write> {
+    writer: W,
+}
+
+impl JsonLinesSink<File> {
+    /// Appends to the file at the given path, creating it if needed.
+    pub fn append_to<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::new(OpenOptions::new().create(true).append(true).open(path)?))
+    }
+}
+
+impl<W: Write> JsonLinesSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self{writer}
+    }
+}
+
+impl<W: Write> MatchSink for JsonLinesSink<W> {
+    fn push(&mut self, found: &FoundMatch) {
+        writeln!(self.writer, "{{\"address\": {}, \"mangled\": \"{}\", \"demangled\": \"{}\", \"mangled_hash\": \"{:08x}\", \"demangled_hash\": \"{:08x}\", \"pattern\": \"{}\", \"line\": {}}}",
+            found.address, escape_json(&found.mangled_name), escape_json(&found.demangled_name),
+            found.mangled_hash(), found.demangled_hash(),
+            escape_json(&found.source_pattern), found.line).ok();
+        self.writer.flush().ok();
+    }
+}
+
+
+/// Keeps every match in a shared list, so they can be reported on
+/// after a batch finishes.
+pub struct CollectingSink {
+    matches: Rc<RefCell<Vec<FoundMatch>>>,
+}
+
+impl CollectingSink {
+    pub fn new(matches: Rc<RefCell<Vec<FoundMatch>>>) -> Self {
+        Self{matches}
+    }
+}
+
+impl MatchSink for CollectingSink {
+    fn push(&mut self, found: &FoundMatch) {
+        self.matches.borrow_mut().push(found.clone());
+    }
+}
+
+
+/// Formats a report of matches grouped by the command that found them,
+/// in the order the commands were run.
+pub fn format_batch_report(matches: &[FoundMatch]) -> String {
+    let mut groups: Vec<(usize, &str, Vec<&FoundMatch>)> = Vec::new();
+    for found in matches {
+        match groups.iter_mut().find(|(line, pattern, _)| *line == found.line && *pattern == found.source_pattern) {
+            Some((_, _, group)) => group.push(found),
+            None => groups.push((found.line, &found.source_pattern, vec![found])),
+        }
+    }
+
+    let mut report = String::new();
+    for (line, pattern, group) in groups {
+        report.push_str(&format!("Line {line}: {pattern} ({} match{})\n",
+            group.len(), if group.len() == 1 {""} else {"es"}));
+        for found in group {
+            report.push_str(&format!("    {:08x} | {:<40} | {}\n",
+                found.address, found.mangled_name, found.demangled_name));
+        }
+    }
+    report
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use brace_expand_2::brace_expand_iter;
+
+    use crate::test_support::make_temp_dir;
+
+    fn sample_matches() -> Vec<FoundMatch> {
+        vec![
+            FoundMatch{
+                address: 0x80001234,
+                mangled_name: "construct__10dWmActor_cFv".to_owned(),
+                demangled_name: "dWmActor_c::construct(void)".to_owned(),
+                source_pattern: "construct__P[dWmActor_c]END1".to_owned(),
+                line: 1,
+            },
+            FoundMatch{
+                address: 0x80005678,
+                mangled_name: "__ct__Q23EGG12TBuffer<a,b>Fv".to_owned(),
+                demangled_name: "EGG::TBuffer<a, b>::TBuffer(void)".to_owned(),
+                source_pattern: "__ct__Q23EGG12TBuffer<{a,b},{a,b}>Fv".to_owned(),
+                line: 3,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_push_to_all() {
+        let first = Rc::new(RefCell::new(Vec::new()));
+        let second = Rc::new(RefCell::new(Vec::new()));
+        let mut sinks: Vec<Box<dyn MatchSink>> = vec![
+            Box::new(CollectingSink::new(first.clone())),
+            Box::new(CollectingSink::new(second.clone())),
+        ];
+
+        for found in sample_matches() {
+            push_to_all(&mut sinks, &found);
+        }
+
+        assert_eq!(*first.borrow(), sample_matches());
+        assert_eq!(*second.borrow(), sample_matches());
+    }
+
+    #[test]
+    fn test_text_sink() {
+        let mut sink = TextSink::new(Vec::new());
+        sink.push(&sample_matches()[0]);
+        assert_eq!(String::from_utf8(sink.writer).unwrap(),
+            "80001234 | construct__10dWmActor_cFv                | dWmActor_c::construct(void)\n");
+    }
+
+    #[test]
+    fn test_json_lines_sink() {
+        let mut sink = JsonLinesSink::new(Vec::new());
+        sink.push(&FoundMatch{
+            address: 16,
+            mangled_name: "a\"b\\c".to_owned(),
+            demangled_name: "x\ny".to_owned(),
+            source_pattern: "a{\"}b".to_owned(),
+            line: 2,
+        });
+        let expected = format!(
+            "{{\"address\": 16, \"mangled\": \"a\\\"b\\\\c\", \"demangled\": \"x\\ny\", \"mangled_hash\": \"{:08x}\", \"demangled_hash\": \"{:08x}\", \"pattern\": \"a{{\\\"}}b\", \"line\": 2}}\n",
+            hash_djb2(b"a\"b\\c", DJB2_HASH_SEED), hash_djb2(b"x\ny", DJB2_HASH_SEED));
+        assert_eq!(String::from_utf8(sink.writer).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_csv_sink() {
+        let mut sink = CsvSink::new(Vec::new());
+        for found in sample_matches() {
+            sink.push(&found);
+        }
+        let samples = sample_matches();
+        assert_eq!(String::from_utf8(sink.writer).unwrap(), format!("\
+address,mangled,demangled,mangled_hash,demangled_hash
+80001234,construct__10dWmActor_cFv,dWmActor_c::construct(void),{:08x},{:08x}
+80005678,\"__ct__Q23EGG12TBuffer<a,b>Fv\",\"EGG::TBuffer<a, b>::TBuffer(void)\",{:08x},{:08x}
+",
+            samples[0].mangled_hash(), samples[0].demangled_hash(), samples[1].mangled_hash(), samples[1].demangled_hash()));
+        assert_eq!(samples[0].mangled_hash(), hash_djb2(b"construct__10dWmActor_cFv", DJB2_HASH_SEED));
+
+        assert_eq!(escape_csv("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn test_log_format() {
+        assert_eq!(LogFormat::from_name("csv"), Some(LogFormat::Csv));
+        assert_eq!(LogFormat::from_name("json"), Some(LogFormat::Json));
+        assert_eq!(LogFormat::from_name("text"), Some(LogFormat::Text));
+        assert_eq!(LogFormat::from_name("xml"), None);
+        assert_eq!(LogFormat::default().log_path(), "positive_symbol_log.txt");
+    }
+
+    #[test]
+    fn test_batch_report() {
+        let matches = Rc::new(RefCell::new(Vec::new()));
+        let mut sink = CollectingSink::new(matches.clone());
+        let mut samples = sample_matches();
+        samples.push(FoundMatch{address: 0x80000010, ..samples[0].clone()});
+        for found in &samples {
+            sink.push(found);
+        }
+
+        assert_eq!(format_batch_report(&matches.borrow()), "\
+Line 1: construct__P[dWmActor_c]END1 (2 matches)
+    80001234 | construct__10dWmActor_cFv                | dWmActor_c::construct(void)
+    80000010 | construct__10dWmActor_cFv                | dWmActor_c::construct(void)
+Line 3: __ct__Q23EGG12TBuffer<{a,b},{a,b}>Fv (1 match)
+    80005678 | __ct__Q23EGG12TBuffer<a,b>Fv             | EGG::TBuffer<a, b>::TBuffer(void)
+");
+    }
+
+    #[test]
+    fn test_pattern_log_round_trip() {
+        let dir = make_temp_dir("sinks_pattern_log");
+        let path = dir.join("log.txt");
+        let names = ["__ct__Q23EGG12TBuffer<a,b>Fv", "a{b}\\c"];
+
+        let mut sink = PatternLogSink::new(&path);
+        for name in names {
+            sink.push(&FoundMatch{
+                address: 0,
+                mangled_name: name.to_owned(),
+                demangled_name: String::new(),
+                source_pattern: String::new(),
+                line: 0,
+            });
+        }
+
+        let contents = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "escapes on");
+        for (line, name) in lines[1..].iter().zip(names) {
+            let expansions: Vec<String> = brace_expand_iter(line, true).unwrap().collect();
+            assert_eq!(expansions, vec![name]);
+        }
+
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,74 @@
+//! Helpers shared by the unit tests and the integration tests (which
+//! get them through the "test-support" feature).
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use djb2_utils::hash_djb2_default;
+use nvidia_demangle::{demangle, DEFAULT_BUF_SIZE};
+
+use crate::database::SymbolDatabase;
+use crate::{EchoSampling, Settings};
+
+
+/// Creates a fresh, empty temporary directory unique to this test. The
+/// caller removes it when it's done.
+pub fn make_temp_dir(test_name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("brace_expansion_bruteforcer_{}_{test_name}", std::process::id()));
+    fs::remove_dir_all(&dir).ok();
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+
+/// The "hashname_" placeholder a symbol map would have for a symbol
+/// whose name isn't known.
+pub fn hashname_placeholder(name: &str) -> String {
+    let demangled = demangle(name).unwrap_or_else(|_| "ERROR".to_string());
+    format!("hashname_{:08x}_{:08x}", hash_djb2_default(name.as_bytes()), hash_djb2_default(demangled.as_bytes()))
+}
+
+
+/// Makes a database with "hashname_" placeholders for the given
+/// symbol names, so they're all unknown.
+pub fn make_unknown_db(names: &[&str]) -> SymbolDatabase {
+    let mut db = SymbolDatabase::default();
+    for (i, name) in names.iter().enumerate() {
+        db.insert(i as u32 * 4, &hashname_placeholder(name));
+    }
+    db
+}
+
+
+/// Writes a Dolphin symbol map with one known symbol (at 0x80004000)
+/// and one unknown one (at 0x80004024, really `unknown_name`).
+pub fn write_fixture_map(path: &Path, unknown_name: &str) {
+    fs::write(path, format!("\
+.text section layout
+  00000000 000024 80004000  4 known__Fv
+  00000024 000010 80004024  4 {}
+", hashname_placeholder(unknown_name))).unwrap();
+}
+
+
+pub fn test_settings() -> Settings {
+    Settings{
+        escaping_enabled: false,
+        word_list_dirs: vec![PathBuf::from(".")],
+        min_demangle_length: 0,
+        demangle_buf_size: DEFAULT_BUF_SIZE,
+        excluded_substrings: Vec::new(),
+        report_nearest_hash: false,
+        dedup_window: 0,
+        strip_namespace: None,
+        inverse_prune: false,
+        threads: 1,
+        max_expansions: 0,
+        confirm_large_patterns: None,
+        demangled_only: false,
+        echo_sampling: EchoSampling::Periodic,
+        checkpoint_path: None,
+        checkpoint_interval: 0,
+        verbose: false,
+    }
+}
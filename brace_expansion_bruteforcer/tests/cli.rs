@@ -0,0 +1,68 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+use brace_expansion_bruteforcer::test_support::{make_temp_dir, write_fixture_map};
+use nvidia_demangle::demangle;
+
+
+/// Creates a fresh temporary directory containing a symbol map with one
+/// known symbol and one unknown one (whose real name is `unknown_name`).
+fn make_temp_map(test_name: &str, unknown_name: &str) -> PathBuf {
+    let dir = make_temp_dir(test_name);
+    write_fixture_map(&dir.join("test.map"), unknown_name);
+    dir
+}
+
+
+/// Runs the bruteforcer in `dir` on its test map, with the given
+/// --pattern arguments.
+fn run_patterns(dir: &PathBuf, patterns: &[&str]) -> Output {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_brace_expansion_bruteforcer"));
+    command.current_dir(dir).arg("test.map");
+    for pattern in patterns {
+        command.arg("--pattern").arg(pattern);
+    }
+    command.output().unwrap()
+}
+
+
+#[test]
+fn test_patterns_with_match() {
+    let dir = make_temp_map("patterns_with_match", "construct__10dWmActor_cFv");
+
+    let output = run_patterns(&dir, &["a{b,c}", "construct__10dWmActor_cF{i,v}"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(stdout.contains("sym> a{b,c}"));
+    assert!(stdout.contains("Found 1 new match"));
+    assert_eq!(fs::read_to_string(dir.join("positive_symbol_log.txt")).unwrap(), "construct__10dWmActor_cFv\n");
+
+    fs::remove_dir_all(&dir).ok();
+}
+
+
+#[test]
+fn test_patterns_without_match() {
+    let dir = make_temp_map("patterns_without_match", "construct__10dWmActor_cFv");
+
+    let output = run_patterns(&dir, &["a{b,c}", "construct__10dWmActor_cF{i,f}"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(!dir.join("positive_symbol_log.txt").exists());
+
+    fs::remove_dir_all(&dir).ok();
+}
+
+
+#[test]
+fn test_demangle_command() {
+    let dir = make_temp_map("demangle_command", "construct__10dWmActor_cFv");
+
+    let output = run_patterns(&dir, &["demangle construct__10dWmActor_cFv"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.lines().any(|line| line == demangle("construct__10dWmActor_cFv").unwrap()), "{stdout}");
+    // (It's not a search, so there are no new matches)
+    assert_eq!(output.status.code(), Some(1));
+
+    fs::remove_dir_all(&dir).ok();
+}
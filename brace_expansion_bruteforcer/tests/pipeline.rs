@@ -0,0 +1,158 @@
+use std::cell::RefCell;
+use std::fs;
+use std::rc::Rc;
+
+use brace_expansion_bruteforcer::database::SymbolDatabase;
+use brace_expansion_bruteforcer::sinks::{CollectingSink, FoundMatch, MatchSink};
+use brace_expansion_bruteforcer::args::Args;
+use brace_expansion_bruteforcer::test_support::{hashname_placeholder, make_temp_dir, test_settings, write_fixture_map};
+use brace_expansion_bruteforcer::{load_symbol_database_from_path, process_line_as_pattern, run_batch, run_batch_file, Settings, WordListCache};
+use symbol_map_formats::BasicSymbolMap;
+use nvidia_demangle::demangle;
+
+
+const UNKNOWN_NAME: &str = "construct__10dWmActor_cFv";
+
+
+/// Writes a Dolphin symbol map with one known symbol and one unknown
+/// one (really UNKNOWN_NAME), and loads it the same way main() does.
+fn load_fixture_db(test_name: &str) -> SymbolDatabase {
+    let dir = make_temp_dir(test_name);
+
+    let path = dir.join("fixture.map");
+    write_fixture_map(&path, UNKNOWN_NAME);
+
+    let db = load_symbol_database_from_path(&path, false, false).unwrap();
+    fs::remove_dir_all(&dir).ok();
+    db
+}
+
+
+#[test]
+fn test_pattern_finds_fixture_symbol() {
+    let mut db = load_fixture_db("finds_fixture_symbol");
+    assert_eq!(db.num_unknown_symbols(), 1);
+
+    let found = Rc::new(RefCell::new(Vec::new()));
+    let mut sinks: Vec<Box<dyn MatchSink>> = vec![Box::new(CollectingSink::new(found.clone()))];
+
+    // Exercises the length-prefix substitution and the END1 shorthand
+    let pattern = "{construct,destruct}__P[dWmActor_c]END1";
+    let num_new_matches = process_line_as_pattern(pattern, 0, &mut db, &test_settings(), &mut WordListCache::new(), &mut sinks);
+    assert_eq!(num_new_matches, 1);
+    assert_eq!(*found.borrow(), vec![FoundMatch{
+        address: 0x24,
+        mangled_name: UNKNOWN_NAME.to_owned(),
+        demangled_name: demangle(UNKNOWN_NAME).unwrap(),
+        source_pattern: pattern.to_owned(),
+        line: 0,
+    }]);
+}
+
+
+#[test]
+fn test_pattern_without_match() {
+    let mut db = load_fixture_db("without_match");
+
+    let found = Rc::new(RefCell::new(Vec::new()));
+    let mut sinks: Vec<Box<dyn MatchSink>> = vec![Box::new(CollectingSink::new(found.clone()))];
+
+    let num_new_matches = process_line_as_pattern("construct__10dWmActor_cF{i,f}", 0, &mut db, &test_settings(), &mut WordListCache::new(), &mut sinks);
+    assert_eq!(num_new_matches, 0);
+    assert!(found.borrow().is_empty());
+    assert_eq!(db.num_unknown_symbols(), 1);
+}
+
+
+#[test]
+fn test_batch_attributes_matches_to_their_pattern() {
+    let mut db = load_fixture_db("batch_attribution");
+
+    let found = Rc::new(RefCell::new(Vec::new()));
+    let mut sinks: Vec<Box<dyn MatchSink>> = vec![Box::new(CollectingSink::new(found.clone()))];
+    let args = Args::parse(["fixture.map".to_owned()]).unwrap();
+
+    let lines = vec![
+        "construct__10dWmActor_cF{i,f}".to_owned(),
+        "construct__10dWmActor_cF{i,v}".to_owned(),
+    ];
+    let total_new_matches = run_batch(lines, false, &mut db, &mut test_settings(), &mut WordListCache::new(), &mut sinks, &args).unwrap();
+    assert_eq!(total_new_matches, 1);
+
+    let matches = found.borrow();
+    assert_eq!(matches.len(), 1);
+    assert_eq!(matches[0].mangled_name, UNKNOWN_NAME);
+    assert_eq!(matches[0].source_pattern, "construct__10dWmActor_cF{i,v}");
+    assert_eq!(matches[0].line, 2);
+}
+
+
+#[test]
+fn test_batch_start_line() {
+    let mut db = load_fixture_db("batch_start_line");
+
+    let dir = make_temp_dir("start_line");
+    let path = dir.join("patterns.txt");
+    // Every line finds the same symbol, so the matches show which lines ran
+    let lines: Vec<String> = (1..=5).map(|i| format!("construct__10dWmActor_cF{{v,{i}}}")).collect();
+    fs::write(&path, lines.join("\n")).unwrap();
+
+    let found = Rc::new(RefCell::new(Vec::new()));
+    let mut sinks: Vec<Box<dyn MatchSink>> = vec![Box::new(CollectingSink::new(found.clone()))];
+    let args = Args::parse(["fixture.map", "--batch", path.to_str().unwrap(), "--start-line", "3"].map(str::to_owned)).unwrap();
+    let total_new_matches = run_batch_file(&path, false, &mut db, &mut test_settings(), &mut WordListCache::new(), &mut sinks, &args).unwrap();
+    fs::remove_dir_all(&dir).ok();
+
+    assert_eq!(total_new_matches, 3);
+    let found_lines: Vec<usize> = found.borrow().iter().map(|found| found.line).collect();
+    assert_eq!(found_lines, vec![3, 4, 5]);
+    assert_eq!(found.borrow()[0].source_pattern, lines[2]);
+}
+
+
+#[test]
+fn test_patterns_file() {
+    let mut db = load_fixture_db("patterns_file");
+
+    let dir = make_temp_dir("patterns_file");
+    let path = dir.join("patterns.txt");
+    // "quit" is just a pattern here, and the checksum "#" at the end of
+    // the last pattern isn't a comment
+    fs::write(&path, "# overnight run\n\nquit\nconstruct__10dWmActor_cF{i,v}\n  # {construct,destruct}__10dWmActor_cFv\nconstruct__10dWmActor_cF#\n").unwrap();
+
+    let found = Rc::new(RefCell::new(Vec::new()));
+    let mut sinks: Vec<Box<dyn MatchSink>> = vec![Box::new(CollectingSink::new(found.clone()))];
+    let args = Args::parse(["fixture.map", "--patterns", path.to_str().unwrap()].map(str::to_owned)).unwrap();
+    let total_new_matches = run_batch_file(&path, true, &mut db, &mut test_settings(), &mut WordListCache::new(), &mut sinks, &args).unwrap();
+    fs::remove_dir_all(&dir).ok();
+
+    assert_eq!(total_new_matches, 1);
+    let found_lines: Vec<usize> = found.borrow().iter().map(|found| found.line).collect();
+    assert_eq!(found_lines, vec![4]);
+}
+
+
+#[test]
+fn test_parallel_search_matches_serial() {
+    // Unknown symbols scattered through the pattern's expansions (one at
+    // each end, so that ranges' boundaries are exercised)
+    let names = ["a1x__10dWmActor_cFv", "d57y__10dWmActor_cFv", "h100z__10dWmActor_cFv", "e3x__10dWmActor_cFv"];
+    let map: BasicSymbolMap = names.iter().enumerate().map(|(i, name)| (i as u32 * 4, hashname_placeholder(name))).collect();
+    let pattern = "{a,b,c,d,e,f,g,h}{1..100}{x,y,z}__10dWmActor_cFv";
+
+    let search = |threads| {
+        let found = Rc::new(RefCell::new(Vec::new()));
+        let mut sinks: Vec<Box<dyn MatchSink>> = vec![Box::new(CollectingSink::new(found.clone()))];
+        let settings = Settings{threads, ..test_settings()};
+        let num_new_matches = process_line_as_pattern(pattern, 0, &mut SymbolDatabase::new(&map), &settings, &mut WordListCache::new(), &mut sinks);
+        // (Matches from different threads can arrive in any order)
+        let mut found = found.borrow().clone();
+        found.sort_by_key(|found| found.address);
+        (num_new_matches, found)
+    };
+
+    let serial = search(1);
+    assert_eq!(serial.0, names.len());
+    assert_eq!(search(4), serial);
+    assert_eq!(search(3), serial);
+}
@@ -1,10 +1,20 @@
 //! Nvidia ALF hash functions.
 
+use std::hash::Hasher;
+
 
 /// The initial seed used for djb2 hashes.
 pub const DJB2_HASH_SEED: u32 = 0x1505;
 
 
+/// The multiplicative inverse of 33 mod 2^32, used to undo the
+/// `hash * 33` step when un-hashing. It exists because 33 is odd (and so
+/// coprime to 2^32), and can be derived with the extended Euclidean
+/// algorithm, or by Newton's iteration `x = x * (2 - 33 * x)` starting
+/// from x = 33 (each step doubles the number of correct low bits).
+pub const DJB2_MULT_INVERSE: u32 = 1041204193;
+
+
 /// Calculate the djb2 hash of a bytestring, with configurable starting
 /// seed. (Use hash_djb2_default() if you want to start with the default
 /// seed.)
@@ -28,14 +38,94 @@ pub fn hash_djb2_default(s: &[u8]) -> u32 {
 }
 
 
+/// The initial seed used for 64-bit djb2 hashes. (It's the same value
+/// as the 32-bit seed, just widened.)
+pub const DJB2_U64_HASH_SEED: u64 = DJB2_HASH_SEED as u64;
+
+
+/// Calculate the 64-bit djb2 hash of a bytestring, with configurable
+/// starting seed. This is the same algorithm as hash_djb2(), just with
+/// 64-bit arithmetic, so it collides much less often. (Note that its low
+/// 32 bits are exactly hash_djb2() with the truncated seed.)
+#[allow(dead_code)]
+#[inline(always)]
+pub fn hash_djb2_u64(s: &[u8], seed: u64) -> u64 {
+    let mut hash = seed;
+    for c in s {
+        hash = hash.overflowing_mul(33).0 ^ (*c as u64);
+    }
+    hash
+}
+
+
+/// Calculate the 64-bit djb2 hash of a bytestring, starting with the
+/// default 64-bit seed.
+#[allow(dead_code)]
+#[inline(always)]
+pub fn hash_djb2_u64_default(s: &[u8]) -> u64 {
+    hash_djb2_u64(s, DJB2_U64_HASH_SEED)
+}
+
+
+/// Calculate the djb2 hashes of many bytestrings at once, with
+/// configurable starting seed. `out` is cleared and then filled with
+/// the hashes, in the same order as the inputs.
+#[allow(dead_code)]
+pub fn hash_djb2_batch(inputs: &[&[u8]], seed: u32, out: &mut Vec<u32>) {
+    out.clear();
+    out.reserve(inputs.len());
+    out.extend(inputs.iter().map(|s| hash_djb2(s, seed)));
+}
+
+
+/// Extend the djb2 hash of some prefix with more bytes, without needing
+/// the prefix itself.
+#[allow(dead_code)]
+#[inline(always)]
+pub fn hash_djb2_append(prefix_hash: u32, suffix: &[u8]) -> u32 {
+    hash_djb2(suffix, prefix_hash)
+}
+
+
+/// Calculate 33^n (mod 2^32), by exponentiation by squaring.
+///
+/// This is the factor that appending n bytes multiplies a hash by,
+/// ignoring the bytes' own contributions: `hash_djb2(&[0; n], h)` is
+/// exactly `h * pow33(n)`. For nonzero bytes it's only an approximation,
+/// since each byte is XORed in rather than added, and that doesn't
+/// commute with the later multiplications.
+#[allow(dead_code)]
+pub fn pow33(mut n: usize) -> u32 {
+    let mut result: u32 = 1;
+    let mut base: u32 = 33;
+    while n > 0 {
+        if n & 1 != 0 {
+            result = result.wrapping_mul(base);
+        }
+        base = base.wrapping_mul(base);
+        n >>= 1;
+    }
+    result
+}
+
+
+/// Like hash_djb2_batch(), but hashes the strings in parallel. The
+/// results are in the same order as the inputs.
+#[cfg(feature = "rayon")]
+#[allow(dead_code)]
+pub fn hash_djb2_par(inputs: &[&[u8]], seed: u32) -> Vec<u32> {
+    use rayon::prelude::*;
+    inputs.par_iter().map(|s| hash_djb2(s, seed)).collect()
+}
+
+
 /// "Undo" a suffix off of a djb2 hash value.
 #[allow(dead_code)]
 #[inline(always)]
 pub fn invhash_djb2(s: &[u8], seed: u32) -> u32 {
     let mut hash = seed;
     for c in s.iter().rev() {
-        // Magic number: multiplicative inverse of 33 mod 32
-        hash = (hash ^ (*c as u32)).overflowing_mul(1041204193).0;
+        hash = (hash ^ (*c as u32)).overflowing_mul(DJB2_MULT_INVERSE).0;
     }
     hash
 }
@@ -55,8 +145,7 @@ pub fn invhash_djb2_int(mut value: usize, seed: u32, length_seed: usize) -> (u32
     let mut length = length_seed;
     loop {
         // Magic number "48": ASCII value of '0'
-        // Other magic number: multiplicative inverse of 33 mod 32
-        hash = (hash ^ (((value % 10) + 48) as u32)).overflowing_mul(1041204193).0;
+        hash = (hash ^ (((value % 10) + 48) as u32)).overflowing_mul(DJB2_MULT_INVERSE).0;
         value /= 10;
         length += 1;
         if value == 0 { break; }
@@ -65,6 +154,133 @@ pub fn invhash_djb2_int(mut value: usize, seed: u32, length_seed: usize) -> (u32
 }
 
 
+/// Like invhash_djb2_int(), but for a number written in any radix from 2
+/// to 16 (using lowercase digits "0-9a-f"). For radix 16 this is
+/// equivalent to
+/// `(invhash_djb2(format!("{value:x}").as_bytes(), seed), length_seed + format!("{value:x}").len())`
+#[allow(dead_code)]
+#[inline(always)]
+pub fn invhash_djb2_radix(mut value: usize, radix: u32, seed: u32, length_seed: usize) -> (u32, usize) {
+    debug_assert!((2..=16).contains(&radix), "radix must be between 2 and 16");
+    let radix = radix as usize;
+    let mut hash = seed;
+    let mut length = length_seed;
+    loop {
+        let digit = b"0123456789abcdef"[value % radix];
+        hash = (hash ^ (digit as u32)).overflowing_mul(DJB2_MULT_INVERSE).0;
+        value /= radix;
+        length += 1;
+        if value == 0 { break; }
+    }
+    (hash, length)
+}
+
+
+/// Search for a suffix of bytes from `alphabet` (at most `max_len` of
+/// them) that takes a hash from `prefix_hash` to `target_hash`, i.e.
+/// `hash_djb2(suffix, prefix_hash) == target_hash`. Shorter suffixes are
+/// tried first, and the first one found is returned.
+///
+/// Only the first n-1 bytes of each length-n candidate are enumerated:
+/// the last byte is solved for directly (by undoing it off of the
+/// target), so each length costs alphabet^(n-1) steps rather than
+/// alphabet^n.
+#[allow(dead_code)]
+pub fn find_suffix(prefix_hash: u32, target_hash: u32, alphabet: &[u8], max_len: usize) -> Option<Vec<u8>> {
+    if prefix_hash == target_hash {
+        return Some(Vec::new());
+    }
+
+    let mut in_alphabet = [false; 256];
+    for c in alphabet {
+        in_alphabet[*c as usize] = true;
+    }
+
+    fn search(hash: u32, target_hash: u32, alphabet: &[u8], in_alphabet: &[bool; 256], remaining: usize, suffix: &mut Vec<u8>) -> bool {
+        if remaining == 1 {
+            // The last byte must be whatever undoes the difference
+            let last = (hash.overflowing_mul(33).0 ^ target_hash) as usize;
+            if last < 256 && in_alphabet[last] {
+                suffix.push(last as u8);
+                return true;
+            }
+            return false;
+        }
+
+        for c in alphabet {
+            suffix.push(*c);
+            if search(hash_djb2(&[*c], hash), target_hash, alphabet, in_alphabet, remaining - 1, suffix) {
+                return true;
+            }
+            suffix.pop();
+        }
+        false
+    }
+
+    let mut suffix = Vec::with_capacity(max_len);
+    for len in 1..=max_len {
+        if search(prefix_hash, target_hash, alphabet, &in_alphabet, len, &mut suffix) {
+            return Some(suffix);
+        }
+    }
+    None
+}
+
+
+/// Incremental djb2 hasher, for input that arrives piecewise. Feeding
+/// it a bytestring in any number of chunks gives the same result as
+/// hash_djb2() on the whole thing.
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug)]
+pub struct Djb2Hasher {
+    state: u32,
+}
+
+impl Djb2Hasher {
+    /// Create a hasher starting from an arbitrary seed (or from a state
+    /// previously snapshotted with state()).
+    pub fn with_seed(seed: u32) -> Self {
+        Self{state: seed}
+    }
+
+    /// The current hash value.
+    pub fn state(&self) -> u32 {
+        self.state
+    }
+
+    /// Feed a single byte into the hash.
+    #[inline(always)]
+    pub fn push(&mut self, c: u8) {
+        self.state = self.state.overflowing_mul(33).0 ^ (c as u32);
+    }
+
+    /// Feed a bytestring into the hash.
+    #[inline(always)]
+    pub fn update(&mut self, s: &[u8]) {
+        for c in s {
+            self.push(*c);
+        }
+    }
+}
+
+impl Default for Djb2Hasher {
+    fn default() -> Self {
+        Self::with_seed(DJB2_HASH_SEED)
+    }
+}
+
+impl Hasher for Djb2Hasher {
+    /// Same as update().
+    fn write(&mut self, bytes: &[u8]) {
+        self.update(bytes);
+    }
+
+    /// The current hash value, widened to u64.
+    fn finish(&self) -> u64 {
+        self.state as u64
+    }
+}
+
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -85,6 +301,86 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_hash_djb2_u64() -> Result<()> {
+        assert_eq!(hash_djb2_u64(b"", 0x123456789abcdef0), 0x123456789abcdef0);
+        assert_eq!(hash_djb2_u64(b"mario", 0x12345678), 0x2a76f13f55d800);
+        assert_eq!(hash_djb2_u64(b"mario", 0x12345678) as u32, hash_djb2(b"mario", 0x12345678));
+        Ok(())
+    }
+
+    #[test]
+    fn test_hash_djb2_u64_default() -> Result<()> {
+        assert_eq!(hash_djb2_u64_default(b""), DJB2_U64_HASH_SEED);
+        assert_eq!(hash_djb2_u64_default(b"mario"), 0x310a6729dd);
+        assert_eq!(hash_djb2_u64_default(b"mario") as u32, hash_djb2_default(b"mario"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_hash_djb2_batch() -> Result<()> {
+        let inputs: [&[u8]; 4] = [b"mario", b"", b"luigi", b"mario"];
+        let mut out = vec![1, 2, 3, 4, 5, 6];
+        hash_djb2_batch(&inputs, 0x12345678, &mut out);
+        assert_eq!(out, inputs.iter().map(|s| hash_djb2(s, 0x12345678)).collect::<Vec<u32>>());
+        assert_eq!(out[0], 0x3f55d800);
+        assert_eq!(out[1], 0x12345678);
+        Ok(())
+    }
+
+    #[test]
+    fn test_hash_djb2_batch_empty() -> Result<()> {
+        let mut out = vec![1, 2, 3];
+        hash_djb2_batch(&[], DJB2_HASH_SEED, &mut out);
+        assert!(out.is_empty());
+        Ok(())
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_hash_djb2_par() -> Result<()> {
+        let strings: Vec<String> = (0..500).map(|i| format!("sym_{i}__{}", "x".repeat(i % 17))).collect();
+        let inputs: Vec<&[u8]> = strings.iter().map(|s| s.as_bytes()).collect();
+        let mut serial = Vec::new();
+        hash_djb2_batch(&inputs, DJB2_HASH_SEED, &mut serial);
+        assert_eq!(hash_djb2_par(&inputs, DJB2_HASH_SEED), serial);
+        Ok(())
+    }
+
+    #[test]
+    fn test_hash_djb2_append() -> Result<()> {
+        let prefix_hash = hash_djb2(b"foo", DJB2_HASH_SEED);
+        assert_eq!(hash_djb2_append(prefix_hash, b"bar"), hash_djb2(b"foobar", DJB2_HASH_SEED));
+        assert_eq!(hash_djb2_append(prefix_hash, b""), prefix_hash);
+        Ok(())
+    }
+
+    #[test]
+    fn test_pow33() -> Result<()> {
+        assert_eq!(pow33(0), 1);
+        assert_eq!(pow33(1), 33);
+        assert_eq!(pow33(2), 1089);
+        let mut expected: u32 = 1;
+        for n in 0..100 {
+            assert_eq!(pow33(n), expected);
+            assert_eq!(hash_djb2(&vec![0; n], 0x12345678), 0x12345678u32.wrapping_mul(pow33(n)));
+            expected = expected.wrapping_mul(33);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_djb2_mult_inverse() -> Result<()> {
+        assert_eq!(33u32.overflowing_mul(DJB2_MULT_INVERSE).0, 1);
+
+        let mut x: u32 = 33;
+        for _ in 0..5 {
+            x = x.wrapping_mul(2u32.wrapping_sub(33u32.wrapping_mul(x)));
+        }
+        assert_eq!(x, DJB2_MULT_INVERSE);
+        Ok(())
+    }
+
     #[test]
     fn test_invhash_djb2() -> Result<()> {
         assert_eq!(invhash_djb2(b"", 0x12345678), 0x12345678);
@@ -105,4 +401,88 @@ mod tests {
         assert_eq!(invhash_djb2_int(101, 0x12345678, 1), (invhash_djb2(b"101", 0x12345678), 4));
         Ok(())
     }
+
+    #[test]
+    fn test_invhash_djb2_radix() -> Result<()> {
+        for value in [0, 1, 9, 10, 11, 99, 100, 101, 12345] {
+            assert_eq!(invhash_djb2_radix(value, 10, 0x12345678, 3), invhash_djb2_int(value, 0x12345678, 3));
+        }
+        for value in [0, 1, 9, 0xa, 0xf, 0x10, 0xff, 0x100, 0xdead] {
+            let digits = format!("{value:x}");
+            assert_eq!(invhash_djb2_radix(value, 16, 0x12345678, 1),
+                (invhash_djb2(digits.as_bytes(), 0x12345678), digits.len() + 1));
+        }
+        assert_eq!(invhash_djb2_radix(5, 2, 0x12345678, 0), (invhash_djb2(b"101", 0x12345678), 3));
+        Ok(())
+    }
+
+    #[test]
+    #[should_panic]
+    #[cfg(debug_assertions)]
+    fn test_invhash_djb2_radix_rejects_bad_radix() {
+        invhash_djb2_radix(10, 17, DJB2_HASH_SEED, 0);
+    }
+
+    #[test]
+    fn test_find_suffix() -> Result<()> {
+        let alphabet = b"abcdefghijklmnopqrstuvwxyz_";
+        let prefix_hash = hash_djb2_default(b"construct__");
+        let target_hash = hash_djb2_default(b"construct__abc");
+
+        let suffix = find_suffix(prefix_hash, target_hash, alphabet, 3).unwrap();
+        assert!(suffix.len() <= 3);
+        assert!(suffix.iter().all(|c| alphabet.contains(c)));
+        assert_eq!(hash_djb2(&suffix, prefix_hash), target_hash);
+
+        assert_eq!(find_suffix(prefix_hash, prefix_hash, alphabet, 3), Some(Vec::new()));
+        assert_eq!(find_suffix(prefix_hash, target_hash, alphabet, 2), None);
+        assert_eq!(find_suffix(prefix_hash, target_hash, alphabet, 0), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_djb2_hasher() -> Result<()> {
+        let mut hasher = Djb2Hasher::default();
+        assert_eq!(hasher.state(), DJB2_HASH_SEED);
+        hasher.update(b"mar");
+        hasher.push(b'i');
+        hasher.update(b"o");
+        assert_eq!(hasher.state(), hash_djb2_default(b"mario"));
+
+        let mut hasher = Djb2Hasher::with_seed(0x12345678);
+        hasher.update(b"mario");
+        assert_eq!(hasher.state(), 0x3f55d800);
+        Ok(())
+    }
+
+    #[test]
+    fn test_djb2_hasher_chunk_boundaries() -> Result<()> {
+        let s = b"construct__10dWmActor_cFUsP7dBase_c";
+        for first_split in 0..=s.len() {
+            for second_split in first_split..=s.len() {
+                let mut hasher = Djb2Hasher::with_seed(0x12345678);
+                hasher.write(&s[..first_split]);
+                hasher.write(&s[first_split..second_split]);
+                hasher.write(&s[second_split..]);
+                assert_eq!(hasher.state(), hash_djb2(s, 0x12345678));
+                assert_eq!(hasher.finish(), hash_djb2(s, 0x12345678) as u64);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_djb2_hasher_snapshot_restore() -> Result<()> {
+        let mut hasher = Djb2Hasher::default();
+        hasher.write(b"construct__");
+        let snapshot = hasher.state();
+
+        hasher.write(b"Fv");
+        assert_eq!(hasher.state(), hash_djb2_default(b"construct__Fv"));
+
+        let mut hasher = Djb2Hasher::with_seed(snapshot);
+        hasher.write(b"Fi");
+        assert_eq!(hasher.state(), hash_djb2_default(b"construct__Fi"));
+        Ok(())
+    }
 }
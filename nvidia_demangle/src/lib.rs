@@ -1,6 +1,8 @@
 extern crate libc;
 extern crate nvidia_demangle_sys;
 
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::ffi::{CStr, CString};
 use std::str;
 use std::error::Error;
@@ -8,31 +10,222 @@ use std::error::Error;
 use libc::c_char;
 
 
-pub fn demangle_with_buf_size(s: &str, buf_size: usize) -> Result<String, Box<dyn Error>> {
-    let input = CString::new(s)?;
-    let mut output_vec: Vec<c_char> = vec![0; buf_size];
+/// The buffer size demangle() starts with.
+pub const DEFAULT_BUF_SIZE: usize = 1024;
+
+/// The largest the buffer is allowed to grow to. Demangled names that
+/// don't fit in this are an error.
+pub const MAX_BUF_SIZE: usize = 1 << 16;
+
+
+/// A demangler that reuses the same output buffer for every call, to
+/// avoid allocating one per name when demangling lots of them. If a
+/// demangled name doesn't fit, the buffer is doubled (up to
+/// MAX_BUF_SIZE) and the name is demangled again.
+#[derive(Clone, Debug)]
+pub struct Demangler {
+    buf: Vec<c_char>,
+}
+
+impl Demangler {
+    pub fn new() -> Self {
+        Self::with_buf_size(DEFAULT_BUF_SIZE)
+    }
+
+    /// (The C side needs room for at least the null terminator, so this
+    /// is at least 1.)
+    pub fn with_buf_size(buf_size: usize) -> Self {
+        Self{buf: vec![0; buf_size.max(1)]}
+    }
+
+    pub fn buf_size(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Grows the buffer to at least `buf_size` (it never shrinks).
+    pub fn reserve(&mut self, buf_size: usize) {
+        if self.buf.len() < buf_size {
+            self.buf.resize(buf_size, 0);
+        }
+    }
+
+    /// Demangles `s`, replacing the contents of `out` with the result.
+    pub fn demangle_into(&mut self, s: &str, out: &mut String) -> Result<(), Box<dyn Error>> {
+        let input = CString::new(s)?;
+
+        loop {
+            let output_str = unsafe {
+                nvidia_demangle_sys::demangle(
+                    self.buf.as_mut_ptr(),
+                    self.buf.len().try_into()?,
+                    input.as_ptr());
+
+                CStr::from_ptr(self.buf.as_ptr())
+            };
+
+            // The C side copies as much as fits and then null-terminates,
+            // so if that filled the whole buffer, the name was (probably)
+            // truncated
+            if output_str.to_bytes().len() + 1 < self.buf.len() {
+                out.clear();
+                out.push_str(output_str.to_str()?);
+                return Ok(());
+            } else if self.buf.len() >= MAX_BUF_SIZE {
+                return Err(format!("demangled name is longer than the maximum buffer size ({MAX_BUF_SIZE} bytes)").into());
+            }
+
+            let new_size = self.buf.len().saturating_mul(2).min(MAX_BUF_SIZE);
+            self.buf.resize(new_size, 0);
+        }
+    }
+
+    pub fn demangle(&mut self, s: &str) -> Result<String, Box<dyn Error>> {
+        let mut out = String::new();
+        self.demangle_into(s, &mut out)?;
+        Ok(out)
+    }
+}
+
+impl Default for Demangler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+/// A Demangler that remembers the results for up to `capacity` names,
+/// for when the same names come up over and over. Once it's full, the
+/// whole cache is cleared. (Errors aren't cached.)
+///
+/// This isn't thread-safe -- use one per thread.
+#[derive(Clone, Debug)]
+pub struct DemangleCache {
+    demangler: Demangler,
+    cache: HashMap<String, String>,
+    capacity: usize,
+    hits: usize,
+}
+
+impl DemangleCache {
+    pub fn new(capacity: usize) -> Self {
+        Self{demangler: Demangler::new(), cache: HashMap::new(), capacity, hits: 0}
+    }
 
-    unsafe {
-        let ptr = input.into_raw();
+    pub fn demangle_cached(&mut self, s: &str) -> Result<String, Box<dyn Error>> {
+        if let Some(demangled) = self.cache.get(s) {
+            self.hits += 1;
+            return Ok(demangled.clone());
+        }
 
-        nvidia_demangle_sys::demangle(
-            output_vec.as_mut_ptr(),
-            buf_size.try_into()?,
-            ptr);
+        let demangled = self.demangler.demangle(s)?;
+        if self.cache.len() >= self.capacity {
+            self.cache.clear();
+        }
+        if self.capacity > 0 {
+            self.cache.insert(s.to_owned(), demangled.clone());
+        }
+        Ok(demangled)
+    }
 
-        let output_str = CStr::from_ptr(output_vec.as_ptr());
+    /// How many calls to demangle_cached() were answered from the cache.
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
 
-        // prevent memory leak -- see https://doc.rust-lang.org/stable/alloc/ffi/struct.CString.html#method.into_raw
-        let _ = CString::from_raw(ptr);
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
 
-        Ok(output_str.to_str()?.to_owned())
+    pub fn is_empty(&self) -> bool {
+        self.cache.is_empty()
     }
 }
 
 
-const DEFAULT_BUF_SIZE: usize = 1024;
+thread_local! {
+    static DEMANGLER: RefCell<Demangler> = RefCell::new(Demangler::new());
+}
+
+
+/// Demangles `s` into `out` (replacing its contents) with this
+/// thread's Demangler, after growing its buffer to at least `buf_size`.
+/// Nothing is allocated unless `out` or the buffer has to grow, so this
+/// is the one to use for demangling lots of names.
+pub fn demangle_into_with_buf_size(s: &str, buf_size: usize, out: &mut String) -> Result<(), Box<dyn Error>> {
+    DEMANGLER.with(|demangler| {
+        let mut demangler = demangler.borrow_mut();
+        demangler.reserve(buf_size);
+        demangler.demangle_into(s, out)
+    })
+}
+
+
+pub fn demangle_with_buf_size(s: &str, buf_size: usize) -> Result<String, Box<dyn Error>> {
+    let mut out = String::new();
+    demangle_into_with_buf_size(s, buf_size, &mut out)?;
+    Ok(out)
+}
+
+
 pub fn demangle(s: &str) -> Result<String, Box<dyn Error>> {
-    demangle_with_buf_size(s, DEFAULT_BUF_SIZE)
+    DEMANGLER.with(|demangler| demangler.borrow_mut().demangle(s))
+}
+
+
+/// Demangles several names in order, reusing the same buffer for all of
+/// them.
+pub fn demangle_batch(inputs: &[&str]) -> Vec<Result<String, Box<dyn Error>>> {
+    DEMANGLER.with(|demangler| {
+        let mut demangler = demangler.borrow_mut();
+        inputs.iter().map(|s| demangler.demangle(s)).collect()
+    })
+}
+
+
+/// Like demangle_batch(), but demangles the names in parallel. The C
+/// demangler has no mutable global state, so it's safe to call from
+/// several threads at once; each thread uses its own buffer. The results
+/// are in the same order as the inputs.
+#[cfg(feature = "rayon")]
+pub fn demangle_par(inputs: &[&str]) -> Vec<Result<String, Box<dyn Error>>> {
+    use rayon::prelude::*;
+
+    // (Box<dyn Error> isn't Send, so errors cross threads as strings)
+    let results: Vec<Result<String, String>> = inputs.par_iter()
+        .map(|s| demangle(s).map_err(|e| e.to_string()))
+        .collect();
+    results.into_iter().map(|result| result.map_err(Into::into)).collect()
+}
+
+
+/// Like demangle(), but returns None if the name couldn't be demangled:
+/// that is, if demangling fails, gives back the input unchanged, or gives
+/// an empty string for a non-empty input.
+pub fn try_demangle(s: &str) -> Option<String> {
+    demangle(s).ok().filter(|demangled| !demangled.is_empty() && demangled != s)
+}
+
+
+/// Cheaply guesses whether a string looks like a mangled name (such as
+/// "construct__10dWmActor_cFv"): a base name, then "__" (not counting a
+/// leading one, as in "__ct__..."), then a class name length, a "Q"
+/// qualified name, or an "F" argument list. Names with spaces,
+/// parentheses or "::" in them are assumed to be demangled already.
+pub fn is_mangled(s: &str) -> bool {
+    if s.contains(|c: char| c.is_whitespace() || c == '(' || c == ')' || c == ':') {
+        return false;
+    }
+
+    let Some(qualifiers_start) = s.get(1..).and_then(|rest| rest.find("__")).map(|i| i + 3) else {
+        return false;
+    };
+    let mut encoding = s[qualifiers_start..].chars();
+    match encoding.next() {
+        Some('F') => true,
+        Some('Q') => encoding.next().is_some_and(|c| c.is_ascii_digit()),
+        Some(c) => c.is_ascii_digit(),
+        None => false,
+    }
 }
 
 
@@ -47,6 +240,127 @@ mod tests {
         assert_eq!(&demangle(m).unwrap(), d);
     }
 
+    #[test]
+    fn test_demangler() {
+        let mut demangler = Demangler::new();
+        let mut out = "leftover".to_owned();
+        for m in [
+            "construct__10dWmActor_cFUsP7dBase_cUlPC7mVec3_cPC7mAng3_c",
+            "holdSound__18NMSndObjectCmn<12>FUlRCQ34nw4r4math4VEC2Ul",
+            "",
+        ] {
+            demangler.demangle_into(m, &mut out).unwrap();
+            assert_eq!(out, demangle(m).unwrap());
+        }
+
+        let mut small = Demangler::with_buf_size(8);
+        assert_eq!(small.buf_size(), 8);
+        assert_eq!(small.demangle("construct__10dWmActor_cFv").unwrap(),
+            demangle_with_buf_size("construct__10dWmActor_cFv", 8).unwrap());
+    }
+
+    #[test]
+    fn test_demangle_cache() {
+        let m = "construct__10dWmActor_cFUsP7dBase_cUlPC7mVec3_cPC7mAng3_c";
+        let mut cache = DemangleCache::new(2);
+        assert_eq!(cache.demangle_cached(m).unwrap(), demangle(m).unwrap());
+        assert_eq!(cache.hits(), 0);
+        assert_eq!(cache.demangle_cached(m).unwrap(), demangle(m).unwrap());
+        assert_eq!(cache.hits(), 1);
+
+        // Filling it up clears it
+        cache.demangle_cached("func__Fv").unwrap();
+        assert_eq!(cache.len(), 2);
+        cache.demangle_cached("other__Fv").unwrap();
+        assert_eq!(cache.len(), 1);
+        cache.demangle_cached(m).unwrap();
+        assert_eq!(cache.hits(), 1);
+
+        let mut disabled = DemangleCache::new(0);
+        disabled.demangle_cached(m).unwrap();
+        disabled.demangle_cached(m).unwrap();
+        assert_eq!(disabled.hits(), 0);
+        assert!(disabled.is_empty());
+    }
+
+    #[test]
+    fn test_demangle_batch() {
+        let m = "construct__10dWmActor_cFUsP7dBase_cUlPC7mVec3_cPC7mAng3_c";
+        let results = demangle_batch(&[m, "", "nul\0byte__Fv"]);
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].as_ref().unwrap(), &demangle(m).unwrap());
+        assert_eq!(results[1].as_ref().unwrap(), "");
+        assert!(results[2].is_err());
+        assert!(demangle_batch(&[]).is_empty());
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_demangle_par() {
+        let m = "construct__10dWmActor_cFUsP7dBase_cUlPC7mVec3_cPC7mAng3_c";
+        let mut inputs = vec![m; 300];
+        inputs.push("nul\0byte__Fv");
+        inputs.push("func__Fv");
+        let results = demangle_par(&inputs);
+        assert_eq!(results.len(), inputs.len());
+        assert!(results[..300].iter().all(|result| result.as_ref().unwrap() == &demangle(m).unwrap()));
+        assert!(results[300].is_err());
+        assert_eq!(results[301].as_ref().unwrap(), &demangle("func__Fv").unwrap());
+    }
+
+    #[test]
+    fn test_try_demangle() {
+        let m = "construct__10dWmActor_cFUsP7dBase_cUlPC7mVec3_cPC7mAng3_c";
+        assert_eq!(try_demangle(m), Some(demangle(m).unwrap()));
+
+        assert_eq!(try_demangle("someData"), None);
+        assert_eq!(try_demangle(""), None);
+        assert_eq!(try_demangle("nul\0byte__Fv"), None);
+    }
+
+    #[test]
+    fn test_is_mangled() {
+        let m = "construct__10dWmActor_cFUsP7dBase_cUlPC7mVec3_cPC7mAng3_c";
+        assert!(is_mangled(m));
+        assert!(!is_mangled(&demangle(m).unwrap()));
+
+        assert!(is_mangled("func__Fv"));
+        assert!(is_mangled("__ct__10dWmActor_cFv"));
+        assert!(is_mangled("__dt__Q23EGG7TBufferFv"));
+        assert!(is_mangled("mInstance__10dWmActor_c"));
+
+        assert!(!is_mangled("dWmActor_c::construct(...)"));
+        assert!(!is_mangled("hashname_0a6729dd_0a6729dd"));
+        assert!(!is_mangled("__start"));
+        assert!(!is_mangled("foo__bar"));
+        assert!(!is_mangled("foo__Qx"));
+        assert!(!is_mangled("foo__"));
+        assert!(!is_mangled(""));
+    }
+
+    #[test]
+    fn test_grow_buffer() {
+        let m = "construct__10dWmActor_cFUsP7dBase_cUlPC7mVec3_cPC7mAng3_c";
+        let mut demangler = Demangler::with_buf_size(4);
+        assert_eq!(demangler.demangle(m).unwrap(), demangle_with_buf_size(m, MAX_BUF_SIZE).unwrap());
+        assert!(demangler.buf_size() > 4);
+        assert_eq!(demangle_with_buf_size(m, 4).unwrap(), demangle(m).unwrap());
+
+        let mut demangler = Demangler::with_buf_size(16);
+        demangler.reserve(8);
+        assert_eq!(demangler.buf_size(), 16);
+        demangler.reserve(32);
+        assert_eq!(demangler.buf_size(), 32);
+
+        let mut out = "leftover".to_owned();
+        demangle_into_with_buf_size(m, 4, &mut out).unwrap();
+        assert_eq!(out, demangle(m).unwrap());
+
+        // (The buffer can't grow past MAX_BUF_SIZE)
+        let long = format!("{}__Fv", "a".repeat(MAX_BUF_SIZE));
+        assert!(Demangler::new().demangle(&long).is_err());
+    }
+
     #[test]
     fn test_broken_symbol() {
         let m = "holdSound__18NMSndObjectCmn<12>FUlRCQ34nw4r4math4VEC2Ul";
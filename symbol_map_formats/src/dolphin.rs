@@ -1,20 +1,50 @@
 use std::collections::HashMap;
 use std::error::Error;
-use std::io::{BufReader, BufRead, Seek, Read, Write};
+use std::io::{BufReader, BufRead, Cursor, Lines, Seek, Read, Write};
 
 use lazy_static::lazy_static;
 use regex::Regex;
 
-use crate::SymbolMap;
+use crate::{BasicSymbolMap, SymbolMap};
 
 
 #[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
 pub struct DolphinSymbolMapSymbol {
-    physical_address: u32,
+    pub(crate) physical_address: u32,
     size: u32,
     virtual_address: u32,
+    /// Only present in some maps
+    dol_offset: Option<u32>,
     alignment: u32,
-    name: String,
+    pub(crate) name: String,
+}
+
+impl DolphinSymbolMapSymbol {
+    /// The "starting address" column (the address used by to_hashmap()).
+    pub fn physical_address(&self) -> u32 {
+        self.physical_address
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn virtual_address(&self) -> u32 {
+        self.virtual_address
+    }
+
+    /// The symbol's offset in the DOL file, if the map has that column.
+    pub fn dol_offset(&self) -> Option<u32> {
+        self.dol_offset
+    }
+
+    pub fn alignment(&self) -> u32 {
+        self.alignment
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
 }
 
 #[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
@@ -23,90 +53,206 @@ pub struct DolphinSymbolMapSection {
     symbols: Vec<DolphinSymbolMapSymbol>,
 }
 
+impl DolphinSymbolMapSection {
+    /// The section name, such as ".text".
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn symbols(&self) -> &[DolphinSymbolMapSymbol] {
+        &self.symbols
+    }
+}
+
 #[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
 pub struct DolphinSymbolMap {
     sections: Vec<DolphinSymbolMapSection>,
 }
 
 
-impl SymbolMap for DolphinSymbolMap {
-    const IS_LOADABLE: bool = true;
-    const PREFERRED_EXTENSION: Option<&'static str> = None;
+/// What DolphinSymbolMap::merge() does when both maps have a symbol at
+/// the same address.
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug)]
+pub enum MergePolicy {
+    /// Keep this map's symbol
+    KeepExisting,
+    /// Replace it with the other map's symbol
+    Overwrite,
+    /// Take the other map's name if this map's is a "hashname_"
+    /// placeholder and the other one's isn't
+    PreferNamed,
+}
 
-    fn load<SR: Seek + Read>(file: SR) -> Result<Self, Box<dyn Error>> {
-        lazy_static! {
-            static ref SECTION_HEADER_REGEX: Regex = Regex::new(concat!(
-                r"(\S+)",            // ".text"
-                r" section layout",  // " section layout"
-            )).unwrap();
-            static ref SYMBOL_LINE_REGEX: Regex = Regex::new(concat!(
-                r"^",                       // (start of string)
-                r"\s*",                     // optional leading whitespace
-                r"(?P<phys>[a-fA-F0-9]+)",  // hex number
-                r"\s+",                     // whitespace
-                r"(?P<size>[a-fA-F0-9]+)",  // hex number
-                r"\s+",                     // whitespace
-                r"(?P<virt>[a-fA-F0-9]+)",  // hex number
-                r"\s+",                     // whitespace
-                // ---- Begin optional field ----
-                r"(?:",                     // non-capturing group
-                r"(?P<dol>[a-fA-F0-9]+)",   // hex number
-                r"\s+",                     // whitespace
-                r")??",                     // Zero or one repetitions, non-greedy
-                // ---- End optional field ----
-                r"(?P<align>\d+)",          // decimal number
-                r"\s+",                     // whitespace
-                r"(?P<name>\S+)",           // symbol name
-            )).unwrap();
+
+/// Whether a symbol name is a "hashname_xxxxxxxx_yyyyyyyy" placeholder
+/// for an unknown name.
+fn is_hashname_placeholder(name: &str) -> bool {
+    lazy_static! {
+        static ref HASHNAME_REGEX: Regex = Regex::new(r"^hashname_[a-fA-F0-9]{8}_[a-fA-F0-9]{8}$").unwrap();
+    }
+    HASHNAME_REGEX.is_match(name)
+}
+
+
+/// Strips `count` whitespace-separated numeric columns from the start of
+/// a line. Returns None if the line doesn't begin with that many.
+fn strip_leading_columns(line: &str, count: usize) -> Option<&str> {
+    let mut line = line;
+    for _ in 0..count {
+        line = line.trim_start();
+        let column_end = line.find(char::is_whitespace)?;
+        if !line[..column_end].bytes().all(|c| c.is_ascii_hexdigit()) {
+            return None;
         }
+        line = &line[column_end..];
+    }
+    Some(line)
+}
 
-        let mut sections = Vec::new();
-        let mut current_section = None;
 
-        for line in BufReader::new(file).lines().flatten() {
-            let line = line.trim_start();
-            if line.is_empty() {
-                continue;
+/// A line in a Dolphin map that we care about.
+enum DolphinMapLine {
+    SectionHeader(String),
+    Symbol(DolphinSymbolMapSymbol),
+}
+
+
+/// Parses a line of a Dolphin map, skipping `leading_columns` extra
+/// numeric columns at the start of symbol lines. Returns None for any
+/// other kinds of lines (blank lines, column headers, etc).
+fn parse_line(line: &str, leading_columns: usize) -> Option<DolphinMapLine> {
+    lazy_static! {
+        static ref SECTION_HEADER_REGEX: Regex = Regex::new(concat!(
+            r"(\S+)",            // ".text"
+            r" section layout",  // " section layout"
+        )).unwrap();
+        static ref SYMBOL_LINE_REGEX: Regex = Regex::new(concat!(
+            r"^",                       // (start of string)
+            r"\s*",                     // optional leading whitespace
+            r"(?P<phys>[a-fA-F0-9]+)",  // hex number
+            r"\s+",                     // whitespace
+            r"(?P<size>[a-fA-F0-9]+)",  // hex number
+            r"\s+",                     // whitespace
+            r"(?P<virt>[a-fA-F0-9]+)",  // hex number
+            r"\s+",                     // whitespace
+            // ---- Begin optional field ----
+            r"(?:",                     // non-capturing group
+            r"(?P<dol>[a-fA-F0-9]+)",   // hex number
+            r"\s+",                     // whitespace
+            r")?",                      // Zero or one repetitions
+            // ---- End optional field ----
+            r"(?P<align>\d+)",          // decimal number
+            r"\s+",                     // whitespace
+            r"(?P<name>\S+)",           // symbol name
+        )).unwrap();
+    }
+
+    let line = line.trim_start();
+    if line.is_empty() {
+        return None;
+    }
+
+    if let Some(caps) = SECTION_HEADER_REGEX.captures(line) {
+        let section_name = caps.get(1).unwrap().as_str();
+        Some(DolphinMapLine::SectionHeader(section_name.to_owned()))
+
+    } else if let Some(caps) = strip_leading_columns(line, leading_columns).and_then(|line| SYMBOL_LINE_REGEX.captures(line)) {
+        let physical_address = caps.name("phys").unwrap().as_str();
+        let size = caps.name("size").unwrap().as_str();
+        let virtual_address = caps.name("virt").unwrap().as_str();
+        let dol_offset = caps.name("dol").map(|dol| dol.as_str());
+        let alignment = caps.name("align").unwrap().as_str();
+        let name = caps.name("name").unwrap().as_str();
+
+        // These are guaranteed to succeed because the regex
+        // only allows hex digits for them
+        let physical_address = u32::from_str_radix(physical_address, 16).unwrap();
+        let size = u32::from_str_radix(size, 16).unwrap();
+        let virtual_address = u32::from_str_radix(virtual_address, 16).unwrap();
+        let dol_offset = dol_offset.map(|dol| u32::from_str_radix(dol, 16).unwrap());
+        let alignment: u32 = alignment.parse().unwrap();  // (base 10)
+
+        Some(DolphinMapLine::Symbol(DolphinSymbolMapSymbol{
+            physical_address,
+            size,
+            virtual_address,
+            dol_offset,
+            alignment,
+            name: name.to_owned(),
+        }))
+
+    } else {
+        None
+    }
+}
+
+
+fn symbol_outside_section_error(symbol: &DolphinSymbolMapSymbol) -> Box<dyn Error> {
+    format!("{} at {:08x} doesn't belong to any section", symbol.name, symbol.physical_address).into()
+}
+
+
+/// Iterator over the symbols in a Dolphin map, which reads and parses
+/// them one line at a time instead of loading the whole map at once.
+pub struct DolphinSymbolStream<R: BufRead> {
+    lines: Lines<R>,
+    leading_columns: usize,
+    in_section: bool,
+}
+
+impl<R: BufRead> Iterator for DolphinSymbolStream<R> {
+    type Item = Result<DolphinSymbolMapSymbol, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(e) => return Some(Err(e.into())),
+            };
+
+            match parse_line(&line, self.leading_columns) {
+                Some(DolphinMapLine::SectionHeader(_)) => self.in_section = true,
+                Some(DolphinMapLine::Symbol(symbol)) => {
+                    if self.in_section {
+                        return Some(Ok(symbol));
+                    } else {
+                        return Some(Err(symbol_outside_section_error(&symbol)));
+                    }
+                },
+                None => {},
             }
+        }
+    }
+}
 
-            if let Some(caps) = SECTION_HEADER_REGEX.captures(line) {
-                let section_name = caps.get(1).unwrap().as_str();
 
-                if let Some(sec) = current_section {
-                    sections.push(sec);
-                }
-                current_section = Some(DolphinSymbolMapSection{
-                    name: section_name.to_owned(),
-                    symbols: Vec::new(),
-                });
-
-            } else if let Some(caps) = SYMBOL_LINE_REGEX.captures(line) {
-                let physical_address = caps.name("phys").unwrap().as_str();
-                let size = caps.name("size").unwrap().as_str();
-                let virtual_address = caps.name("virt").unwrap().as_str();
-                // The regex accomodates for an optional dol_offset
-                // field here, but we don't actually parse it
-                let alignment = caps.name("align").unwrap().as_str();
-                let name = caps.name("name").unwrap().as_str();
-
-                // These are guaranteed to succeed because the regex
-                // only allows hex digits for them
-                let physical_address = u32::from_str_radix(physical_address, 16).unwrap();
-                let size = u32::from_str_radix(size, 16).unwrap();
-                let virtual_address = u32::from_str_radix(virtual_address, 16).unwrap();
-                let alignment: u32 = alignment.parse().unwrap();  // (base 10)
-
-                if let Some(sec) = current_section.as_mut() {
-                    sec.symbols.push(DolphinSymbolMapSymbol{
-                        physical_address,
-                        size,
-                        virtual_address,
-                        alignment,
-                        name: name.to_owned(),
+impl DolphinSymbolMap {
+    /// Like load(), but for maps whose symbol lines are prefixed with
+    /// some number of extra numeric columns (such as a scope or file
+    /// index) before the usual ones. Those columns are skipped.
+    pub fn load_with_leading_columns<SR: Seek + Read>(file: SR, leading_columns: usize) -> Result<Self, Box<dyn Error>> {
+        let mut sections = Vec::new();
+        let mut current_section = None;
+
+        for line in BufReader::new(file).lines() {
+            match parse_line(&line?, leading_columns) {
+                Some(DolphinMapLine::SectionHeader(section_name)) => {
+                    if let Some(sec) = current_section {
+                        sections.push(sec);
+                    }
+                    current_section = Some(DolphinSymbolMapSection{
+                        name: section_name,
+                        symbols: Vec::new(),
                     });
-                } else {
-                    return Err(format!("{name} at {physical_address:08x} doesn't belong to any section").into());
-                }
+                },
+                Some(DolphinMapLine::Symbol(symbol)) => {
+                    if let Some(sec) = current_section.as_mut() {
+                        sec.symbols.push(symbol);
+                    } else {
+                        return Err(symbol_outside_section_error(&symbol));
+                    }
+                },
+                None => {},
             }
         }
 
@@ -117,6 +263,204 @@ impl SymbolMap for DolphinSymbolMap {
         Ok(DolphinSymbolMap{sections})
     }
 
+    /// Like load(), but also returns a warning for each symbol at the
+    /// same physical address as an earlier one. The map itself keeps all
+    /// of them, but to_hashmap() only keeps the last.
+    pub fn load_with_warnings<SR: Seek + Read>(file: SR) -> Result<(Self, Vec<String>), Box<dyn Error>> {
+        let map = Self::load(file)?;
+
+        let mut warnings = Vec::new();
+        let mut seen: HashMap<u32, &str> = HashMap::new();
+        for symbol in map.symbols() {
+            if let Some(previous) = seen.insert(symbol.physical_address, &symbol.name) {
+                warnings.push(format!(
+                    "duplicate address {:08x}: \"{}\" and \"{}\"",
+                    symbol.physical_address, previous, symbol.name));
+            }
+        }
+
+        Ok((map, warnings))
+    }
+
+    /// Builds a map with a single ".text" section from {address: name},
+    /// with the symbols in address order. Since there's no other
+    /// information, each symbol's virtual address is the same as its
+    /// physical one, its size is 0 and its alignment is 4.
+    pub fn from_hashmap(map: &BasicSymbolMap) -> Self {
+        let mut symbols: Vec<DolphinSymbolMapSymbol> = map.iter()
+            .map(|(address, name)| DolphinSymbolMapSymbol{
+                physical_address: *address,
+                size: 0,
+                virtual_address: *address,
+                dol_offset: None,
+                alignment: 4,
+                name: name.clone(),
+            })
+            .collect();
+        symbols.sort();
+        Self{sections: vec![DolphinSymbolMapSection{name: ".text".to_owned(), symbols}]}
+    }
+
+    /// Iterates over the symbols in a map (across all sections) without
+    /// loading the whole thing into memory. Like
+    /// load_with_leading_columns(), this can skip extra leading columns.
+    pub fn stream_symbols<R: BufRead>(reader: R, leading_columns: usize) -> DolphinSymbolStream<R> {
+        DolphinSymbolStream{lines: reader.lines(), leading_columns, in_section: false}
+    }
+
+    pub fn sections(&self) -> &[DolphinSymbolMapSection] {
+        &self.sections
+    }
+
+    /// All of the symbols in the map, section by section, in file order.
+    /// (Symbols don't know which section they're in -- use
+    /// sections() or section_of() for that.)
+    pub fn symbols(&self) -> impl Iterator<Item = &DolphinSymbolMapSymbol> {
+        self.sections.iter().flat_map(|section| &section.symbols)
+    }
+
+    /// The name of the section containing a symbol at this virtual
+    /// address, if any. (Physical addresses are relative to the start of
+    /// each section, so they can't be used for this.)
+    pub fn section_of(&self, address: u32) -> Option<&str> {
+        self.sections.iter()
+            .find(|section| section.symbols.iter().any(|symbol| symbol.virtual_address == address))
+            .map(|section| section.name.as_str())
+    }
+
+    /// Finds pairs of symbols in the same section whose
+    /// [physical_address, physical_address + size) ranges overlap, which
+    /// usually means the map is corrupt or was misparsed. Only symbols
+    /// that are next to each other in address order are compared.
+    pub fn find_overlaps(&self) -> Vec<(DolphinSymbolMapSymbol, DolphinSymbolMapSymbol)> {
+        let mut overlaps = Vec::new();
+        for section in &self.sections {
+            let mut symbols: Vec<&DolphinSymbolMapSymbol> = section.symbols.iter().collect();
+            symbols.sort_by_key(|symbol| symbol.physical_address);
+            for pair in symbols.windows(2) {
+                let end = u64::from(pair[0].physical_address) + u64::from(pair[0].size);
+                if end > u64::from(pair[1].physical_address) {
+                    overlaps.push((pair[0].clone(), pair[1].clone()));
+                }
+            }
+        }
+        overlaps
+    }
+
+    /// Adds another map's symbols to this one, matching them up by
+    /// physical address within sections of the same name. Symbols at new
+    /// addresses are inserted in address order, and sections that only
+    /// exist in `other` are added at the end. `policy` decides what
+    /// happens to symbols at addresses that both maps have.
+    pub fn merge(&mut self, other: &DolphinSymbolMap, policy: MergePolicy) {
+        for other_section in &other.sections {
+            let Some(section) = self.sections.iter_mut().find(|section| section.name == other_section.name) else {
+                self.sections.push(other_section.clone());
+                continue;
+            };
+
+            for other_symbol in &other_section.symbols {
+                match section.symbols.iter_mut().find(|symbol| symbol.physical_address == other_symbol.physical_address) {
+                    Some(symbol) => match policy {
+                        MergePolicy::KeepExisting => {},
+                        MergePolicy::Overwrite => *symbol = other_symbol.clone(),
+                        MergePolicy::PreferNamed => {
+                            if is_hashname_placeholder(&symbol.name) && !is_hashname_placeholder(&other_symbol.name) {
+                                symbol.name = other_symbol.name.clone();
+                            }
+                        },
+                    },
+                    None => {
+                        let i = section.symbols.partition_point(|symbol| symbol.physical_address < other_symbol.physical_address);
+                        section.symbols.insert(i, other_symbol.clone());
+                    },
+                }
+            }
+        }
+    }
+
+    /// Loads a map, writes it back out to an in-memory buffer, and
+    /// compares the section header and symbol lines that were written
+    /// with the ones in the original file. Returns a description of every
+    /// line that came out differently (so an empty list means the map
+    /// round-trips losslessly). Other lines, like column headings, aren't
+    /// compared.
+    pub fn verify_roundtrip<SR: Seek + Read>(mut file: SR) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut original = Vec::new();
+        file.read_to_end(&mut original)?;
+        let map = Self::load(Cursor::new(&original))?;
+
+        let mut written = Cursor::new(Vec::new());
+        map.write(&mut written)?;
+
+        let original = String::from_utf8_lossy(&original);
+        let written = String::from_utf8_lossy(written.get_ref());
+        let original_lines = map_content_lines(&original);
+        let written_lines = map_content_lines(&written);
+
+        let mut differences = Vec::new();
+        if original_lines.len() != written_lines.len() {
+            differences.push(format!("{} lines became {}", original_lines.len(), written_lines.len()));
+        }
+        for ((line_number, original_line), (_, written_line)) in original_lines.iter().zip(&written_lines) {
+            if original_line != written_line {
+                differences.push(format!("line {line_number}: \"{original_line}\" was written as \"{written_line}\""));
+            }
+        }
+        Ok(differences)
+    }
+}
+
+
+/// The section header and symbol lines in the text of a map, with their
+/// (1-based) line numbers and without trailing whitespace.
+fn map_content_lines(text: &str) -> Vec<(usize, &str)> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| parse_line(line, 0).is_some())
+        .map(|(i, line)| (i + 1, line.trim_end()))
+        .collect()
+}
+
+
+fn write_section<W: Write>(file: &mut W, section: &DolphinSymbolMapSection) -> Result<(), Box<dyn Error>> {
+    writeln!(file, "{} section layout", section.name)?;
+    if section.symbols.iter().any(|symbol| symbol.dol_offset.is_some()) {
+        writeln!(file, "  Starting        Virtual  File")?;
+        writeln!(file, "  address  Size   address  offset")?;
+        writeln!(file, "  ---------------------------------")?;
+    } else {
+        writeln!(file, "  Starting        Virtual")?;
+        writeln!(file, "  address  Size   address")?;
+        writeln!(file, "  -----------------------")?;
+    }
+
+    for symbol in &section.symbols {
+        write!(file, "  {:08x} {:06x} {:08x}", symbol.physical_address, symbol.size, symbol.virtual_address)?;
+        if let Some(dol_offset) = symbol.dol_offset {
+            write!(file, " {dol_offset:08x}")?;
+        }
+        writeln!(file, " {:>2} {}", symbol.alignment, symbol.name)?;
+    }
+    writeln!(file)?;
+    Ok(())
+}
+
+
+impl SymbolMap for DolphinSymbolMap {
+    const IS_LOADABLE: bool = true;
+    const PREFERRED_EXTENSION: Option<&'static str> = None;
+
+    fn load<SR: Seek + Read>(file: SR) -> Result<Self, Box<dyn Error>> {
+        Self::load_with_leading_columns(file, 0)
+    }
+
+    /// Since load() skips lines it doesn't recognize, this requires at
+    /// least one section header.
+    fn looks_like_format(&self) -> bool {
+        !self.sections.is_empty()
+    }
+
     fn to_hashmap(&self) -> HashMap<u32, String> {
         let mut map = HashMap::new();
         for section in &self.sections {
@@ -127,7 +471,326 @@ impl SymbolMap for DolphinSymbolMap {
         map
     }
 
-    fn write<SW: Seek + Write>(&self, _: SW) -> Result<(), Box<dyn Error>> {
-        Err("not yet implemented".into())
+    fn write<SW: Seek + Write>(&self, mut file: SW) -> Result<(), Box<dyn Error>> {
+        for section in &self.sections {
+            write_section(&mut file, section)?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_MAP: &str = "\
+.text section layout
+  Starting        Virtual
+  address  Size   address
+  -----------------------
+  00000000 000024 80004000  4 __start
+  00000024 000010 80004024  4 hashname_0a6729dd_0a6729dd
+.data section layout
+  00000000 000008 80400000  8 someData
+";
+
+    const SAMPLE_MAP_WITH_DOL_OFFSETS: &str = "\
+.text section layout
+  Starting        Virtual  File
+  address  Size   address  offset
+  ---------------------------------
+  00000000 000024 80004000 00000100  4 __start
+  00000024 000010 80004024 00000124  4 hashname_0a6729dd_0a6729dd
+
+.data section layout
+  Starting        Virtual  File
+  address  Size   address  offset
+  ---------------------------------
+  00000000 000008 80400000 000003a0  8 someData
+
+";
+
+    const SAMPLE_MAP_WITH_INDEX_COLUMN: &str = "\
+.text section layout
+  1 00000000 000024 80004000  4 __start
+  2 00000024 000010 80004024  4 hashname_0a6729dd_0a6729dd
+.data section layout
+  17 00000000 000008 80400000  8 someData
+";
+
+    #[test]
+    fn test_load() {
+        let map = DolphinSymbolMap::load(Cursor::new(SAMPLE_MAP)).unwrap();
+        assert_eq!(map.sections.len(), 2);
+        assert_eq!(map.sections[0].name, ".text");
+        assert_eq!(map.sections[0].symbols[1], DolphinSymbolMapSymbol{
+            physical_address: 0x24,
+            size: 0x10,
+            virtual_address: 0x80004024,
+            dol_offset: None,
+            alignment: 4,
+            name: "hashname_0a6729dd_0a6729dd".to_owned(),
+        });
+        assert_eq!(map.to_hashmap(), HashMap::from([
+            (0x00, "someData".to_owned()),
+            (0x24, "hashname_0a6729dd_0a6729dd".to_owned()),
+        ]));
+    }
+
+    #[test]
+    fn test_symbol_accessors() {
+        let map = DolphinSymbolMap::load(Cursor::new(SAMPLE_MAP)).unwrap();
+        let names: Vec<&str> = map.symbols().map(DolphinSymbolMapSymbol::name).collect();
+        assert_eq!(names, vec!["__start", "hashname_0a6729dd_0a6729dd", "someData"]);
+
+        let symbol = map.symbols().find(|symbol| symbol.name() == "someData").unwrap();
+        assert_eq!(symbol.size(), 8);
+        assert_eq!(symbol.virtual_address(), 0x80400000);
+        assert_eq!(symbol.alignment(), 8);
+        assert_eq!(map.section_of(symbol.virtual_address()), Some(".data"));
+        assert_eq!(map.section_of(0x80004024), Some(".text"));
+        // (Physical addresses don't count)
+        assert_eq!(map.section_of(0x24), None);
+        assert_eq!(map.section_of(0x1234), None);
+
+        assert_eq!(map.sections()[1].name(), ".data");
+        assert_eq!(map.sections()[1].symbols()[0].name(), "someData");
+    }
+
+    #[test]
+    fn test_load_dol_offsets() {
+        let map = DolphinSymbolMap::load(Cursor::new(SAMPLE_MAP_WITH_DOL_OFFSETS)).unwrap();
+        assert_eq!(map.sections[0].symbols[0].dol_offset, Some(0x100));
+        assert_eq!(map.sections[0].symbols[0].alignment, 4);
+        assert_eq!(map.sections[0].symbols[0].name, "__start");
+        assert_eq!(map.sections[1].symbols[0].dol_offset(), Some(0x3a0));
+
+        let map = DolphinSymbolMap::load(Cursor::new(SAMPLE_MAP)).unwrap();
+        assert!(map.sections.iter().flat_map(|section| &section.symbols).all(|symbol| symbol.dol_offset().is_none()));
+    }
+
+    #[test]
+    fn test_dol_offsets_preserved_per_line() {
+        // A section can mix 4-field and 5-field lines
+        let sample = "\
+.text section layout
+  00000000 000024 80004000 00000100  4 __start
+  00000024 000010 80004024  4 noOffset
+";
+        let map = DolphinSymbolMap::load(Cursor::new(sample)).unwrap();
+        assert_eq!(map.sections[0].symbols[0].dol_offset(), Some(0x100));
+        assert_eq!(map.sections[0].symbols[1].dol_offset(), None);
+        assert_eq!(DolphinSymbolMap::verify_roundtrip(Cursor::new(sample)).unwrap(), Vec::<String>::new());
+
+        // Maps without DOL offsets don't gain any
+        let mut written = Cursor::new(Vec::new());
+        DolphinSymbolMap::load(Cursor::new(SAMPLE_MAP)).unwrap().write(&mut written).unwrap();
+        let written = String::from_utf8(written.into_inner()).unwrap();
+        assert!(written.contains("\n  00000024 000010 80004024  4 hashname_0a6729dd_0a6729dd\n"));
+        assert!(!written.contains("offset"));
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        for sample in [SAMPLE_MAP, SAMPLE_MAP_WITH_DOL_OFFSETS] {
+            assert_eq!(DolphinSymbolMap::verify_roundtrip(Cursor::new(sample)).unwrap(), Vec::<String>::new());
+
+            let map = DolphinSymbolMap::load(Cursor::new(sample)).unwrap();
+
+            let mut written = Cursor::new(Vec::new());
+            map.write(&mut written).unwrap();
+            let reloaded = DolphinSymbolMap::load(Cursor::new(written.get_ref())).unwrap();
+            assert_eq!(reloaded, map);
+        }
+
+        let mut written = Cursor::new(Vec::new());
+        DolphinSymbolMap::load(Cursor::new(SAMPLE_MAP_WITH_DOL_OFFSETS)).unwrap().write(&mut written).unwrap();
+        assert_eq!(String::from_utf8(written.into_inner()).unwrap(), SAMPLE_MAP_WITH_DOL_OFFSETS);
+    }
+
+    #[test]
+    fn test_roundtrip_edge_cases() {
+        let map = DolphinSymbolMap{sections: vec![
+            DolphinSymbolMapSection{name: ".init".to_owned(), symbols: Vec::new()},
+            DolphinSymbolMapSection{name: ".text".to_owned(), symbols: vec![
+                DolphinSymbolMapSymbol{
+                    physical_address: 0,
+                    size: 0x1234567,
+                    virtual_address: 0x80004000,
+                    dol_offset: None,
+                    alignment: 32,
+                    name: "big__Fv".to_owned(),
+                },
+                DolphinSymbolMapSymbol{
+                    physical_address: 0xffffffff,
+                    size: 0,
+                    virtual_address: 0,
+                    dol_offset: None,
+                    alignment: 128,
+                    name: "zero".to_owned(),
+                },
+            ]},
+        ]};
+        let mut written = Cursor::new(Vec::new());
+        map.write(&mut written).unwrap();
+        assert_eq!(DolphinSymbolMap::load(Cursor::new(written.get_ref())).unwrap(), map);
+        assert_eq!(DolphinSymbolMap::verify_roundtrip(Cursor::new(written.get_ref())).unwrap(), Vec::<String>::new());
+        let written = String::from_utf8(written.into_inner()).unwrap();
+        assert!(written.contains("\n  00000000 1234567 80004000 32 big__Fv\n"));
+        assert!(written.contains("\n  ffffffff 000000 00000000 128 zero\n"));
+    }
+
+    #[test]
+    fn test_load_with_warnings() {
+        let text = "\
+.text section layout
+  00000000 000024 80004000  4 __start
+  00000024 000010 80004024  4 hashname_0a6729dd_0a6729dd
+";
+        let (_, warnings) = DolphinSymbolMap::load_with_warnings(Cursor::new(text)).unwrap();
+        assert!(warnings.is_empty());
+
+        let duplicated = format!("{text}  00000024 000010 80004024  4 construct__10dWmActor_cFv\n");
+        let (map, warnings) = DolphinSymbolMap::load_with_warnings(Cursor::new(duplicated)).unwrap();
+        assert_eq!(map.symbols().count(), 3);
+        assert_eq!(warnings, [
+            "duplicate address 00000024: \"hashname_0a6729dd_0a6729dd\" and \"construct__10dWmActor_cFv\"",
+        ]);
+
+        // (Addresses are compared across sections too, since to_hashmap()
+        // would collapse those as well)
+        let (_, warnings) = DolphinSymbolMap::load_with_warnings(Cursor::new(SAMPLE_MAP)).unwrap();
+        assert_eq!(warnings, ["duplicate address 00000000: \"__start\" and \"someData\""]);
+    }
+
+    #[test]
+    fn test_from_hashmap() {
+        let symbols = BasicSymbolMap::from([
+            (0x80004024, "construct__10dWmActor_cFv".to_owned()),
+            (0x80004000, "__start".to_owned()),
+            (0x80004010, "hashname_0a6729dd_0a6729dd".to_owned()),
+        ]);
+        let map = DolphinSymbolMap::from_hashmap(&symbols);
+        assert_eq!(map.sections.len(), 1);
+        assert_eq!(map.sections[0].name, ".text");
+        let addresses: Vec<u32> = map.symbols().map(DolphinSymbolMapSymbol::physical_address).collect();
+        assert_eq!(addresses, [0x80004000, 0x80004010, 0x80004024]);
+
+        let mut written = Cursor::new(Vec::new());
+        map.write(&mut written).unwrap();
+        written.rewind().unwrap();
+        let reloaded = DolphinSymbolMap::load(written).unwrap();
+        assert_eq!(reloaded, map);
+        assert_eq!(reloaded.to_hashmap(), symbols);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_find_overlaps() {
+        assert!(DolphinSymbolMap::load(Cursor::new(SAMPLE_MAP)).unwrap().find_overlaps().is_empty());
+
+        let map = DolphinSymbolMap::load(Cursor::new("\
+.text section layout
+  00000000 000024 80004000  4 __start
+  00000024 000010 80004024  4 adjacent
+  00000030 000008 80004030  4 overlapping
+.data section layout
+  00000000 000008 80400000  8 someData
+")).unwrap();
+        let overlaps = map.find_overlaps();
+        assert_eq!(overlaps.len(), 1);
+        assert_eq!(overlaps[0].0.name(), "adjacent");
+        assert_eq!(overlaps[0].1.name(), "overlapping");
+    }
+
+    #[test]
+    fn test_merge() {
+        let base = DolphinSymbolMap::load(Cursor::new(SAMPLE_MAP)).unwrap();
+        let other = DolphinSymbolMap::load(Cursor::new("\
+.text section layout
+  00000010 000014 80004010  4 middle__Fv
+  00000024 000010 80004024  8 construct__10dWmActor_cFv
+.bss section layout
+  00000000 000004 80500000  4 someBss
+")).unwrap();
+
+        let mut map = base.clone();
+        map.merge(&other, MergePolicy::KeepExisting);
+        let names: Vec<&str> = map.symbols().map(DolphinSymbolMapSymbol::name).collect();
+        assert_eq!(names, vec!["__start", "middle__Fv", "hashname_0a6729dd_0a6729dd", "someData", "someBss"]);
+        assert_eq!(map.sections.len(), 3);
+        assert_eq!(map.section_of(0x80004010), Some(".text"));
+
+        let mut map = base.clone();
+        map.merge(&other, MergePolicy::Overwrite);
+        assert_eq!(map.sections[0].symbols[2], other.sections[0].symbols[1]);
+
+        // PreferNamed replaces the placeholder's name (but nothing else)
+        let mut map = base.clone();
+        map.merge(&other, MergePolicy::PreferNamed);
+        assert_eq!(map.sections[0].symbols[2].name(), "construct__10dWmActor_cFv");
+        assert_eq!(map.sections[0].symbols[2].alignment(), 4);
+
+        // ...but never the other way around
+        let mut reversed = other.clone();
+        reversed.merge(&base, MergePolicy::PreferNamed);
+        assert_eq!(reversed.sections[0].symbols[2].name(), "construct__10dWmActor_cFv");
+
+        assert!(is_hashname_placeholder("hashname_0a6729dd_0a6729dd"));
+        assert!(!is_hashname_placeholder("hashname_0a6729dd"));
+        assert!(!is_hashname_placeholder("construct__10dWmActor_cFv"));
+    }
+
+    #[test]
+    fn test_verify_roundtrip_differences() {
+        // (With the first alignment column 1 space wide instead of 2)
+        let sample = "\
+.text section layout
+  00000000 000024 80004000 4 __start
+  00000024 000010 80004024  4 hashname_0a6729dd_0a6729dd
+.data section layout
+  00000000 000008 80400000  8 someData
+";
+        assert_eq!(DolphinSymbolMap::verify_roundtrip(Cursor::new(sample)).unwrap(), vec![
+            "line 2: \"  00000000 000024 80004000 4 __start\" was written as \"  00000000 000024 80004000  4 __start\"",
+        ]);
+
+        // Maps that can't be loaded at all are errors
+        assert!(DolphinSymbolMap::verify_roundtrip(Cursor::new("  00000000 000024 80004000  4 __start\n")).is_err());
+    }
+
+    #[test]
+    fn test_load_with_leading_columns() {
+        let expected = DolphinSymbolMap::load(Cursor::new(SAMPLE_MAP)).unwrap();
+        let map = DolphinSymbolMap::load_with_leading_columns(Cursor::new(SAMPLE_MAP_WITH_INDEX_COLUMN), 1).unwrap();
+        assert_eq!(map, expected);
+        assert_eq!(map.sections[1].symbols[0].physical_address, 0);
+        assert_eq!(map.sections[1].symbols[0].virtual_address, 0x80400000);
+        assert_eq!(map.sections[1].symbols[0].name, "someData");
+    }
+
+    #[test]
+    fn test_load_invalid_utf8() {
+        // A bad line partway through is an error, not the end of the map
+        let mut sample = SAMPLE_MAP.as_bytes().to_vec();
+        let i = sample.iter().position(|c| *c == b'_').unwrap();
+        sample[i] = 0xff;
+        assert!(DolphinSymbolMap::load(Cursor::new(sample)).is_err());
+    }
+
+    #[test]
+    fn test_stream_symbols() {
+        let map = DolphinSymbolMap::load(Cursor::new(SAMPLE_MAP)).unwrap();
+        let streamed: Vec<DolphinSymbolMapSymbol> = DolphinSymbolMap::stream_symbols(Cursor::new(SAMPLE_MAP), 0)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let loaded: Vec<DolphinSymbolMapSymbol> = map.sections.into_iter().flat_map(|sec| sec.symbols).collect();
+        assert_eq!(streamed, loaded);
+    }
+
+    #[test]
+    fn test_stream_symbols_outside_section() {
+        let mut stream = DolphinSymbolMap::stream_symbols(Cursor::new("  00000000 000024 80004000  4 __start\n"), 0);
+        assert!(stream.next().unwrap().is_err());
+    }
+}
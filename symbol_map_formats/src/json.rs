@@ -0,0 +1,84 @@
+use std::error::Error;
+use std::io::{Seek, Read, Write};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{BasicSymbolMap, SymbolMap};
+
+
+#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Serialize, Deserialize)]
+pub struct JsonSymbolMapRecord {
+    pub address: u32,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u32>,
+}
+
+
+/// A JSON array of {"address", "name", "size" (optional)} objects.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct JsonSymbolMap {
+    pub records: Vec<JsonSymbolMapRecord>,
+}
+
+
+impl JsonSymbolMap {
+    /// Same as load(), but from any reader (JSON doesn't need seeking).
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self, Box<dyn Error>> {
+        Ok(Self{records: serde_json::from_reader(reader)?})
+    }
+}
+
+
+impl SymbolMap for JsonSymbolMap {
+    const IS_LOADABLE: bool = true;
+    const PREFERRED_EXTENSION: Option<&'static str> = Some("json");
+
+    fn load<SR: Seek + Read>(file: SR) -> Result<Self, Box<dyn Error>> {
+        Self::from_reader(file)
+    }
+
+    fn to_hashmap(&self) -> BasicSymbolMap {
+        self.records.iter().map(|record| (record.address, record.name.clone())).collect()
+    }
+
+    fn write<SW: Seek + Write>(&self, file: SW) -> Result<(), Box<dyn Error>> {
+        serde_json::to_writer_pretty(file, &self.records)?;
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    #[test]
+    fn test_roundtrip() {
+        let map = JsonSymbolMap::load(Cursor::new(r#"[
+            {"address": 2147500032, "name": "__start", "size": 36},
+            {"address": 36, "name": "construct__10dWmActor_cFv"}
+        ]"#)).unwrap();
+        assert_eq!(map.records[0].size, Some(36));
+        assert_eq!(map.records[1].size, None);
+        assert_eq!(map.to_hashmap(), BasicSymbolMap::from([
+            (0x80004000, "__start".to_owned()),
+            (0x24, "construct__10dWmActor_cFv".to_owned()),
+        ]));
+
+        let mut written = Cursor::new(Vec::new());
+        map.write(&mut written).unwrap();
+        let written = written.into_inner();
+        assert!(!String::from_utf8_lossy(&written).contains("null"));
+        assert_eq!(JsonSymbolMap::load(Cursor::new(written)).unwrap(), map);
+    }
+
+    #[test]
+    fn test_autodetect() {
+        let dolphin = ".text section layout\n  00000000 000024 80004000  4 __start\n";
+        assert!(!JsonSymbolMap::autodetect(Cursor::new(dolphin)));
+        assert!(JsonSymbolMap::autodetect(Cursor::new("[]")));
+    }
+}
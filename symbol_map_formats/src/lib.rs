@@ -1,16 +1,25 @@
 pub mod dolphin;
+#[cfg(feature = "json")]
+pub mod json;
+pub mod nm;
 
 use std::collections::HashMap;
 use std::error::Error;
 use std::fs::File;
-use std::io::{Seek, Read, Write};
+use std::io::{BufRead, Seek, Read, Write};
 use std::path::Path;
 
 use crate::dolphin::DolphinSymbolMap;
+#[cfg(feature = "json")]
+use crate::json::JsonSymbolMap;
+use crate::nm::NmSymbolMap;
 
 
 pub type BasicSymbolMap = HashMap<u32, String>;
 
+/// Iterator over (address, name) pairs, parsed one at a time.
+pub type SymbolStream<'a> = Box<dyn Iterator<Item = Result<(u32, String), Box<dyn Error>>> + 'a>;
+
 
 /// Trait representing any symbol map type. Since their semantics vary a
 /// lot, this just provides a common baseline of functionality common to
@@ -36,27 +45,154 @@ pub trait SymbolMap where Self: Sized {
     /// Writes to a file
     fn write<SW: Seek + Write>(&self, file: SW) -> Result<(), Box<dyn Error>>;
 
+    /// Whether a map that loaded successfully really looks like it's in
+    /// this format. Formats whose load() accepts almost anything (even an
+    /// empty file) should check for something only they'd have, so that
+    /// autodetect() doesn't claim files in other formats.
+    fn looks_like_format(&self) -> bool {
+        true
+    }
+
+    /// Loads the file if it's in the symbol map format, or returns None
+    /// if it isn't (see autodetect()). Also makes a best-effort attempt to
+    /// rewind it to the beginning.
+    fn load_if_detected<SR: Seek + Read>(mut file: SR) -> Option<Self> {
+        file.rewind().ok()?;
+        let res = Self::load(&mut file);
+        file.rewind().ok()?;
+        res.ok().filter(Self::looks_like_format)
+    }
+
     /// Tries to detect if the file is in the symbol map format or not.
     /// Also makes a best-effort attempt to rewind it to the beginning.
-    fn autodetect<SR: Seek + Read>(mut file: SR) -> bool {
-        if file.rewind().is_err() {
-            return false;
+    fn autodetect<SR: Seek + Read>(file: SR) -> bool {
+        Self::load_if_detected(file).is_some()
+    }
+}
+
+
+/// The symbol map formats supported by this crate.
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug)]
+pub enum SymbolMapFormat {
+    Dolphin,
+    Nm,
+    #[cfg(feature = "json")]
+    Json,
+}
+
+
+impl SymbolMapFormat {
+    /// All of the formats, in the order autodetection tries them.
+    pub const ALL: &'static [Self] = &[
+        Self::Dolphin,
+        Self::Nm,
+        #[cfg(feature = "json")]
+        Self::Json,
+    ];
+
+    /// The format's name ("dolphin", "nm", etc).
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Dolphin => "dolphin",
+            Self::Nm => "nm",
+            #[cfg(feature = "json")]
+            Self::Json => "json",
         }
-        let res = Self::load(&mut file);
-        if file.rewind().is_err() {
-            return false;
+    }
+
+    /// Loads the file as {address: name} if it's in this format (see
+    /// SymbolMap::load_if_detected()).
+    pub fn load_if_detected<SR: Seek + Read>(self, file: SR) -> Option<BasicSymbolMap> {
+        match self {
+            Self::Dolphin => DolphinSymbolMap::load_if_detected(file).map(|map| map.to_hashmap()),
+            Self::Nm => NmSymbolMap::load_if_detected(file).map(|map| map.to_hashmap()),
+            #[cfg(feature = "json")]
+            Self::Json => JsonSymbolMap::load_if_detected(file).map(|map| map.to_hashmap()),
+        }
+    }
+
+    /// Iterates over the (address, name) pairs in a symbol map, reading
+    /// them one at a time instead of loading the whole map into memory
+    /// (except for JSON, which is read all at once).
+    pub fn stream_symbols<'a, R: BufRead + 'a>(self, reader: R) -> SymbolStream<'a> {
+        match self {
+            Self::Dolphin => Box::new(DolphinSymbolMap::stream_symbols(reader, 0)
+                .map(|symbol| symbol.map(|symbol| (symbol.physical_address, symbol.name)))),
+            Self::Nm => Box::new(NmSymbolMap::stream_symbols(reader)),
+            #[cfg(feature = "json")]
+            Self::Json => match JsonSymbolMap::from_reader(reader) {
+                Ok(map) => Box::new(map.records.into_iter().map(|record| Ok((record.address, record.name)))),
+                Err(e) => Box::new(std::iter::once(Err(e))),
+            },
         }
-        res.is_ok()
     }
 }
 
 
-pub fn load_symbol_map_from_file<SR: Seek + Read>(mut file: SR) -> Result<BasicSymbolMap, Box<dyn Error>> {
+/// The differences between two symbol maps. Everything is sorted by
+/// address.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct SymbolMapDiff {
+    /// Addresses only in the new map
+    pub added: Vec<u32>,
+    /// Addresses only in the old map
+    pub removed: Vec<u32>,
+    /// (address, old name, new name) for addresses whose name changed
+    pub renamed: Vec<(u32, String, String)>,
+}
+
+
+/// Compares two symbol maps.
+pub fn diff(old: &BasicSymbolMap, new: &BasicSymbolMap) -> SymbolMapDiff {
+    let mut result = SymbolMapDiff::default();
+    for (address, old_name) in old {
+        match new.get(address) {
+            None => result.removed.push(*address),
+            Some(new_name) if new_name != old_name => result.renamed.push((*address, old_name.clone(), new_name.clone())),
+            Some(_) => {},
+        }
+    }
+    result.added = new.keys().filter(|address| !old.contains_key(address)).copied().collect();
+
+    result.added.sort_unstable();
+    result.removed.sort_unstable();
+    result.renamed.sort_unstable();
+    result
+}
+
+
+/// Returns the name of the format the file is in ("dolphin", "nm", etc),
+/// or None if it isn't in any of them.
+pub fn detect_format<SR: Seek + Read>(mut file: SR) -> Option<&'static str> {
+    SymbolMapFormat::ALL.iter()
+        .find(|format| format.load_if_detected(&mut file).is_some())
+        .map(|format| format.name())
+}
+
+
+/// Whether a file has nothing but whitespace in it. Also rewinds it.
+fn is_blank<SR: Seek + Read>(file: &mut SR) -> Result<bool, Box<dyn Error>> {
+    file.rewind()?;
+    let mut contents = Vec::new();
+    file.read_to_end(&mut contents)?;
     file.rewind()?;
-    if let Ok(map) = DolphinSymbolMap::load(file) {
-        return Ok(map.to_hashmap());
+    Ok(contents.iter().all(u8::is_ascii_whitespace))
+}
+
+
+/// Loads a symbol map in whichever format it's in. (A blank file isn't
+/// in any format, but is loaded as an empty map.)
+pub fn load_symbol_map_from_file<SR: Seek + Read>(mut file: SR) -> Result<BasicSymbolMap, Box<dyn Error>> {
+    for format in SymbolMapFormat::ALL {
+        if let Some(map) = format.load_if_detected(&mut file) {
+            return Ok(map);
+        }
+    }
+    if is_blank(&mut file)? {
+        return Ok(BasicSymbolMap::new());
     }
-    Err("couldn't load symbol map file".into())
+    let names: Vec<&str> = SymbolMapFormat::ALL.iter().map(|format| format.name()).collect();
+    Err(format!("couldn't load symbol map file (tried formats: {})", names.join(", ")).into())
 }
 
 
@@ -70,7 +206,49 @@ where P: AsRef<Path> {
 mod tests {
     use super::*;
 
+    use std::io::Cursor;
+
+    #[test]
+    fn test_load_symbol_map_from_file() {
+        let dolphin = ".text section layout\n  00000000 000024 80004000  4 __start\n";
+        assert_eq!(load_symbol_map_from_file(Cursor::new(dolphin)).unwrap(), BasicSymbolMap::from([(0, "__start".to_owned())]));
+
+        let nm = "80004000 T __start\n";
+        assert_eq!(load_symbol_map_from_file(Cursor::new(nm)).unwrap(), BasicSymbolMap::from([(0x80004000, "__start".to_owned())]));
+
+        let error = load_symbol_map_from_file(Cursor::new("neither\n")).unwrap_err();
+        assert!(error.to_string().starts_with("couldn't load symbol map file (tried formats: dolphin, nm"), "{error}");
+        assert_eq!(load_symbol_map_from_file(Cursor::new(" \n\n")).unwrap(), BasicSymbolMap::new());
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = BasicSymbolMap::from([
+            (0x10, "__start".to_owned()),
+            (0x24, "hashname_0a6729dd_1b07e645".to_owned()),
+            (0x30, "removed__Fv".to_owned()),
+            (0x08, "alsoRemoved".to_owned()),
+        ]);
+        let new = BasicSymbolMap::from([
+            (0x10, "__start".to_owned()),
+            (0x24, "construct__10dWmActor_cFv".to_owned()),
+            (0x40, "added__Fv".to_owned()),
+            (0x00, "alsoAdded".to_owned()),
+        ]);
+        assert_eq!(diff(&old, &new), SymbolMapDiff{
+            added: vec![0x00, 0x40],
+            removed: vec![0x08, 0x30],
+            renamed: vec![(0x24, "hashname_0a6729dd_1b07e645".to_owned(), "construct__10dWmActor_cFv".to_owned())],
+        });
+        assert_eq!(diff(&new, &new), SymbolMapDiff::default());
+    }
+
     #[test]
-    fn it_works() {
+    fn test_detect_format() {
+        let dolphin = ".text section layout\n  00000000 000024 80004000  4 __start\n";
+        assert_eq!(detect_format(Cursor::new(dolphin)), Some("dolphin"));
+        assert_eq!(detect_format(Cursor::new("80004000 T __start\n")), Some("nm"));
+        assert_eq!(detect_format(Cursor::new(b"\x8f\x00random\xffblob {,}\n")), None);
+        assert_eq!(detect_format(Cursor::new("")), None);
     }
 }
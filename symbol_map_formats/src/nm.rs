@@ -0,0 +1,166 @@
+use std::error::Error;
+use std::io::{BufReader, BufRead, Seek, Read, Write};
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::{BasicSymbolMap, SymbolMap};
+
+
+/// A plain `nm`-style symbol map, with one "address type name" line per
+/// symbol (such as "80004000 T __start").
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct NmSymbolMap {
+    symbols: BasicSymbolMap,
+}
+
+
+/// Parses one line of an nm map. Returns None for a blank line or an
+/// undefined symbol (which has no address, like "U foo"), and an error
+/// for any other line that isn't a symbol.
+fn parse_line(line: &str) -> Option<Result<(u32, String), Box<dyn Error>>> {
+    lazy_static! {
+        static ref SYMBOL_LINE_REGEX: Regex = Regex::new(concat!(
+            r"^",                 // (start of string)
+            r"([0-9a-fA-F]+)",    // hex number
+            r"\s+",               // whitespace
+            r"[a-zA-Z]",          // symbol type
+            r"\s+",               // whitespace
+            r"(\S+)",             // symbol name
+            r"$",                 // (end of string)
+        )).unwrap();
+        static ref UNDEFINED_SYMBOL_LINE_REGEX: Regex = Regex::new(concat!(
+            r"^",                 // (start of string)
+            r"[Uuvw]",            // undefined (or weak) symbol type
+            r"\s+",               // whitespace
+            r"\S+",               // symbol name
+            r"$",                 // (end of string)
+        )).unwrap();
+    }
+
+    let line = line.trim();
+    if line.is_empty() || UNDEFINED_SYMBOL_LINE_REGEX.is_match(line) {
+        return None;
+    }
+    let Some(caps) = SYMBOL_LINE_REGEX.captures(line) else {
+        return Some(Err(format!("not an nm symbol line: \"{line}\"").into()));
+    };
+    Some(u32::from_str_radix(caps.get(1).unwrap().as_str(), 16)
+        .map(|address| (address, caps.get(2).unwrap().as_str().to_owned()))
+        .map_err(Into::into))
+}
+
+
+impl NmSymbolMap {
+    pub fn new(symbols: BasicSymbolMap) -> Self {
+        Self{symbols}
+    }
+
+    /// Iterates over the (address, name) pairs in a map without loading
+    /// the whole thing into memory.
+    pub fn stream_symbols<R: BufRead>(reader: R) -> impl Iterator<Item = Result<(u32, String), Box<dyn Error>>> {
+        reader.lines().filter_map(|line| match line {
+            Ok(line) => parse_line(&line),
+            Err(e) => Some(Err(e.into())),
+        })
+    }
+}
+
+
+impl SymbolMap for NmSymbolMap {
+    const IS_LOADABLE: bool = true;
+    const PREFERRED_EXTENSION: Option<&'static str> = None;
+
+    /// Blank lines are skipped, but any other line that isn't a symbol
+    /// is an error (so that autodetect() doesn't accept other formats).
+    fn load<SR: Seek + Read>(file: SR) -> Result<Self, Box<dyn Error>> {
+        let symbols = Self::stream_symbols(BufReader::new(file)).collect::<Result<_, _>>()?;
+        Ok(Self{symbols})
+    }
+
+    /// Requires at least one symbol, so that empty files aren't detected
+    /// as nm maps.
+    fn looks_like_format(&self) -> bool {
+        !self.symbols.is_empty()
+    }
+
+    fn to_hashmap(&self) -> BasicSymbolMap {
+        self.symbols.clone()
+    }
+
+    /// Symbols are written in address order, all with type "T".
+    fn write<SW: Seek + Write>(&self, mut file: SW) -> Result<(), Box<dyn Error>> {
+        let mut symbols: Vec<(&u32, &String)> = self.symbols.iter().collect();
+        symbols.sort();
+        for (address, name) in symbols {
+            writeln!(file, "{address:08x} T {name}")?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    const SAMPLE_MAP: &str = "\
+80004000 T __start
+80004024 t hashname_0a6729dd_0a6729dd
+
+80400000 D someData
+";
+
+    #[test]
+    fn test_load() {
+        let map = NmSymbolMap::load(Cursor::new(SAMPLE_MAP)).unwrap();
+        assert_eq!(map.to_hashmap(), BasicSymbolMap::from([
+            (0x80004000, "__start".to_owned()),
+            (0x80004024, "hashname_0a6729dd_0a6729dd".to_owned()),
+            (0x80400000, "someData".to_owned()),
+        ]));
+
+        // Undefined symbols don't have addresses, so they're skipped
+        let map = NmSymbolMap::load(Cursor::new("         U memcpy\n80004000 T __start\n         w weakRef\n")).unwrap();
+        assert_eq!(map.to_hashmap(), BasicSymbolMap::from([(0x80004000, "__start".to_owned())]));
+
+        assert!(NmSymbolMap::load(Cursor::new("80004000 T\n")).is_err());
+        assert!(NmSymbolMap::load(Cursor::new("T __start\n")).is_err());
+        assert!(NmSymbolMap::load(Cursor::new(".text section layout\n")).is_err());
+        assert!(NmSymbolMap::load(Cursor::new("800040000 T tooBig\n")).is_err());
+    }
+
+    #[test]
+    fn test_stream_symbols() {
+        let streamed: BasicSymbolMap = NmSymbolMap::stream_symbols(Cursor::new(SAMPLE_MAP)).collect::<Result<_, _>>().unwrap();
+        assert_eq!(streamed, NmSymbolMap::load(Cursor::new(SAMPLE_MAP)).unwrap().to_hashmap());
+
+        let mut stream = NmSymbolMap::stream_symbols(Cursor::new("80004000 T __start
+bad line
+"));
+        assert_eq!(stream.next().unwrap().unwrap(), (0x80004000, "__start".to_owned()));
+        assert!(stream.next().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_write() {
+        let map = NmSymbolMap::new(BasicSymbolMap::from([
+            (0x80400000, "someData".to_owned()),
+            (0x24, "construct__10dWmActor_cFv".to_owned()),
+        ]));
+        let mut written = Cursor::new(Vec::new());
+        map.write(&mut written).unwrap();
+        assert_eq!(String::from_utf8(written.into_inner()).unwrap(),
+            "00000024 T construct__10dWmActor_cFv\n80400000 T someData\n");
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let map = NmSymbolMap::load(Cursor::new(SAMPLE_MAP)).unwrap();
+        let mut written = Cursor::new(Vec::new());
+        map.write(&mut written).unwrap();
+        assert_eq!(NmSymbolMap::load(Cursor::new(written.into_inner())).unwrap(), map);
+    }
+}